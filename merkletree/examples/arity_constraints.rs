@@ -0,0 +1,49 @@
+//! Print the constraints of a membership proof in JubJub trees of arity 2, 4
+//! and 8 that hold the same number of leaves:
+//!
+//!     cargo run --release --example arity_constraints
+use ark_crypto_primitives::crh::CRHScheme;
+use ark_ed_on_bls12_381::Fq;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::ConstraintSystem;
+use merkle_groth::constraints::{ConfigGadget, DigestVar, LeafParamVar, InnerParamVar, PathVar};
+use merkle_groth::jubjub::*;
+use merkle_groth::{Config, MerkleTree};
+
+/// The constraints of allocating a path and verifying a 128-byte leaf with it,
+/// in a tree of `P` with `2^log_capacity` leaves.
+fn membership_constraints<P: Config, PG: ConfigGadget<P, Fq>>(log_capacity: u32) -> usize
+where
+    P::LeafHash: CRHScheme<Parameters = LeafHashParams>,
+    P::InnerHash: CRHScheme<Parameters = LeafHashParams>,
+{
+    let mut rng = ark_std::test_rng();
+    let leaf_params = P::LeafHash::setup(&mut rng).unwrap();
+    let inner_params = P::InnerHash::setup(&mut rng).unwrap();
+    let depth = (log_capacity / P::ARITY.trailing_zeros()) as usize;
+    let tree = MerkleTree::<P>::blank(&leaf_params, &inner_params, depth).unwrap();
+    assert_eq!(tree.num_leaves(), 1 << log_capacity);
+    let path = tree.generate_proof(tree.num_leaves() - 1).unwrap();
+
+    let cs = ConstraintSystem::<Fq>::new_ref();
+    let leaf_params = LeafParamVar::<P, Fq, PG>::new_constant(cs.clone(), &leaf_params).unwrap();
+    let inner_params = InnerParamVar::<P, Fq, PG>::new_constant(cs.clone(), &inner_params).unwrap();
+    let root = DigestVar::<P, Fq, PG>::new_input(cs.clone(), || Ok(tree.root())).unwrap();
+    let leaf = UInt8::new_witness_vec(cs.clone(), &[0u8; 128]).unwrap();
+    let start = cs.num_constraints();
+    let path = PathVar::<P, Fq, PG>::new_witness(cs.clone(), || Ok(path)).unwrap();
+    let _is_member = path.verify_membership(&leaf_params, &inner_params, &root, &leaf).unwrap();
+    cs.num_constraints() - start
+}
+
+fn main() {
+    println!("leaves    arity 2    arity 4    arity 8");
+    for log_capacity in [6, 12, 18] {
+        println!(
+            "2^{log_capacity:<6} {:>9}  {:>9}  {:>9}",
+            membership_constraints::<JubJubMerkleTreeParams, JubJubMerkleTreeParamsVar>(log_capacity),
+            membership_constraints::<JubJub4aryMerkleTreeParams, JubJub4aryMerkleTreeParamsVar>(log_capacity),
+            membership_constraints::<JubJub8aryMerkleTreeParams, JubJub8aryMerkleTreeParamsVar>(log_capacity),
+        );
+    }
+}
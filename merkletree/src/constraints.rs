@@ -0,0 +1,311 @@
+//! Gadgets for the Merkle trees of this crate.
+use crate::multiproof::{self, MultiPath};
+use crate::{bits_per_level, Config, Path};
+use ark_crypto_primitives::crh::CRHSchemeGadget;
+use ark_ff::PrimeField;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{Namespace, SynthesisError};
+use ark_std::borrow::Borrow;
+use derivative::Derivative;
+
+/// The gadgets of the hashes of a tree with configuration `P`.
+pub trait ConfigGadget<P: Config, F: PrimeField> {
+    type LeafHash: CRHSchemeGadget<P::LeafHash, F, InputVar = [UInt8<F>]>;
+    type InnerHash: CRHSchemeGadget<
+        P::InnerHash,
+        F,
+        InputVar = [UInt8<F>],
+        OutputVar = <Self::LeafHash as CRHSchemeGadget<P::LeafHash, F>>::OutputVar,
+    >;
+}
+
+/// The digest of a node of a tree with configuration `P`, in the circuit.
+pub type DigestVar<P, F, PG> =
+    <<PG as ConfigGadget<P, F>>::LeafHash as CRHSchemeGadget<<P as Config>::LeafHash, F>>::OutputVar;
+pub type LeafParamVar<P, F, PG> =
+    <<PG as ConfigGadget<P, F>>::LeafHash as CRHSchemeGadget<<P as Config>::LeafHash, F>>::ParametersVar;
+pub type InnerParamVar<P, F, PG> =
+    <<PG as ConfigGadget<P, F>>::InnerHash as CRHSchemeGadget<<P as Config>::InnerHash, F>>::ParametersVar;
+
+/// Hash the digests of the `ARITY` children of an inner node.
+///
+/// The children are encoded with `to_non_unique_bytes`: an encoding that
+/// differs from the canonical one differs as a hash input, so a prover who
+/// could exploit it could also find a collision of the inner hash.
+pub fn hash_children<P: Config, F: PrimeField, PG: ConfigGadget<P, F>>(
+    inner_hash_params: &InnerParamVar<P, F, PG>,
+    children: &[DigestVar<P, F, PG>],
+) -> Result<DigestVar<P, F, PG>, SynthesisError> {
+    assert_eq!(children.len(), P::ARITY);
+    let mut input = Vec::new();
+    for child in children {
+        input.extend(child.to_non_unique_bytes()?);
+    }
+    PG::InnerHash::evaluate(inner_hash_params, &input)
+}
+
+/// For each position among `arity` children, whether it is the position whose
+/// little-endian bits are `position`.
+fn position_flags<F: PrimeField>(position: &[Boolean<F>], arity: usize) -> Result<Vec<Boolean<F>>, SynthesisError> {
+    (0..arity)
+        .map(|j| {
+            let bits = position
+                .iter()
+                .enumerate()
+                .map(|(i, bit)| if (j >> i) & 1 == 1 { bit.clone() } else { bit.not() })
+                .collect::<Vec<_>>();
+            Boolean::kary_and(&bits)
+        })
+        .collect()
+}
+
+/// The children of a node: `siblings` with `node` inserted at the position
+/// whose flag in `is_at` is set.
+fn insert_node<F: PrimeField, V: CondSelectGadget<F>>(
+    siblings: &[V],
+    is_at: &[Boolean<F>],
+    node: &V,
+) -> Result<Vec<V>, SynthesisError> {
+    let arity = is_at.len();
+    (0..arity)
+        .map(|j| {
+            // Left of the node, child `j` is sibling `j`; right of it, sibling `j - 1`.
+            let sibling = if j == 0 {
+                siblings[0].clone()
+            } else if j == arity - 1 {
+                siblings[j - 1].clone()
+            } else {
+                let is_after = Boolean::kary_or(&is_at[j + 1..])?;
+                V::conditionally_select(&is_after, &siblings[j], &siblings[j - 1])?
+            };
+            V::conditionally_select(&is_at[j], node, &sibling)
+        })
+        .collect()
+}
+
+/// A membership proof for a single leaf, in the circuit.
+#[derive(Derivative)]
+#[derivative(Clone(bound = "P: Config, F: PrimeField, PG: ConfigGadget<P, F>"))]
+pub struct PathVar<P: Config, F: PrimeField, PG: ConfigGadget<P, F>> {
+    /// The little-endian bits of the leaf index, `log2(ARITY)` bits per level
+    /// from the leaves up.
+    pub leaf_index_bits: Vec<Boolean<F>>,
+    /// For each level from the leaves up, the digests of the `ARITY - 1`
+    /// siblings of the path's node at that level, in order.
+    pub siblings: Vec<Vec<DigestVar<P, F, PG>>>,
+}
+
+impl<P: Config, F: PrimeField, PG: ConfigGadget<P, F>> PathVar<P, F, PG> {
+    /// The root of the tree in which this path leads to a leaf with digest `leaf_digest`.
+    pub fn root_from_leaf_digest(
+        &self,
+        inner_hash_params: &InnerParamVar<P, F, PG>,
+        leaf_digest: DigestVar<P, F, PG>,
+    ) -> Result<DigestVar<P, F, PG>, SynthesisError> {
+        let bits = bits_per_level::<P>();
+        self.siblings
+            .iter()
+            .zip(self.leaf_index_bits.chunks(bits))
+            .try_fold(leaf_digest, |node, (siblings, position)| {
+                let is_at = position_flags(position, P::ARITY)?;
+                let children = insert_node(siblings, &is_at, &node)?;
+                hash_children::<P, F, PG>(inner_hash_params, &children)
+            })
+    }
+
+    /// The root of the tree in which this path leads to `leaf`.
+    pub fn calculate_root(
+        &self,
+        leaf_hash_params: &LeafParamVar<P, F, PG>,
+        inner_hash_params: &InnerParamVar<P, F, PG>,
+        leaf: &[UInt8<F>],
+    ) -> Result<DigestVar<P, F, PG>, SynthesisError> {
+        let leaf_digest = PG::LeafHash::evaluate(leaf_hash_params, leaf)?;
+        self.root_from_leaf_digest(inner_hash_params, leaf_digest)
+    }
+
+    /// Whether `leaf` is at this path's index in the tree with root `root`.
+    pub fn verify_membership(
+        &self,
+        leaf_hash_params: &LeafParamVar<P, F, PG>,
+        inner_hash_params: &InnerParamVar<P, F, PG>,
+        root: &DigestVar<P, F, PG>,
+        leaf: &[UInt8<F>],
+    ) -> Result<Boolean<F>, SynthesisError> {
+        self.calculate_root(leaf_hash_params, inner_hash_params, leaf)?
+            .is_eq(root)
+    }
+
+    /// Replace `old_leaf` by `new_leaf` at this path's index. Returns whether
+    /// `old_leaf` is in the tree with root `old_root`, and the root of the tree
+    /// with `new_leaf` instead. Both roots are computed along the same siblings,
+    /// so the rest of the tree is unchanged by construction.
+    #[allow(clippy::type_complexity)]
+    pub fn update_leaf(
+        &self,
+        leaf_hash_params: &LeafParamVar<P, F, PG>,
+        inner_hash_params: &InnerParamVar<P, F, PG>,
+        old_root: &DigestVar<P, F, PG>,
+        old_leaf: &[UInt8<F>],
+        new_leaf: &[UInt8<F>],
+    ) -> Result<(Boolean<F>, DigestVar<P, F, PG>), SynthesisError> {
+        let mut old_node = PG::LeafHash::evaluate(leaf_hash_params, old_leaf)?;
+        let mut new_node = PG::LeafHash::evaluate(leaf_hash_params, new_leaf)?;
+        for (siblings, position) in self.siblings.iter().zip(self.leaf_index_bits.chunks(bits_per_level::<P>())) {
+            let is_at = position_flags(position, P::ARITY)?;
+            old_node = hash_children::<P, F, PG>(inner_hash_params, &insert_node(siblings, &is_at, &old_node)?)?;
+            new_node = hash_children::<P, F, PG>(inner_hash_params, &insert_node(siblings, &is_at, &new_node)?)?;
+        }
+        Ok((old_node.is_eq(old_root)?, new_node))
+    }
+}
+
+impl<P: Config, F: PrimeField, PG: ConfigGadget<P, F>> AllocVar<Path<P>, F> for PathVar<P, F, PG> {
+    fn new_variable<T: Borrow<Path<P>>>(
+        cs: impl Into<Namespace<F>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        let ns = cs.into();
+        let cs = ns.cs();
+        f().and_then(|path| {
+            let path = path.borrow();
+            let num_bits = path.siblings.len() * bits_per_level::<P>();
+            let leaf_index_bits = (0..num_bits)
+                .map(|i| Boolean::new_variable(cs.clone(), || Ok((path.leaf_index >> i) & 1 == 1), mode))
+                .collect::<Result<Vec<_>, _>>()?;
+            let siblings = path
+                .siblings
+                .iter()
+                .map(|level| Vec::new_variable(cs.clone(), || Ok(level.clone()), mode))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(Self {
+                leaf_index_bits,
+                siblings,
+            })
+        })
+    }
+}
+
+/// A membership proof for several leaves, in the circuit. The leaf indices
+/// determine which nodes are hashed together, so they are constants of the
+/// circuit rather than witnesses: a circuit that uses a `MultiPathVar` is
+/// specific to the set of leaves it opens, and cannot serve batches that touch
+/// other leaves.
+#[derive(Derivative)]
+#[derivative(Clone(bound = "P: Config, F: PrimeField, PG: ConfigGadget<P, F>"))]
+pub struct MultiPathVar<P: Config, F: PrimeField, PG: ConfigGadget<P, F>> {
+    /// The indices of the leaves, in increasing order.
+    pub leaf_indices: Vec<usize>,
+    /// The number of levels above the leaves.
+    pub depth: usize,
+    /// The digests of the siblings that are not ancestors of any of the leaves,
+    /// in the order of `MultiPath::siblings`.
+    pub siblings: Vec<DigestVar<P, F, PG>>,
+}
+
+impl<P: Config, F: PrimeField, PG: ConfigGadget<P, F>> MultiPathVar<P, F, PG> {
+    fn leaf_digests(
+        &self,
+        leaf_hash_params: &LeafParamVar<P, F, PG>,
+        leaves: &[Vec<UInt8<F>>],
+    ) -> Result<Vec<DigestVar<P, F, PG>>, SynthesisError> {
+        if leaves.len() != self.leaf_indices.len() {
+            return Err(SynthesisError::Unsatisfiable);
+        }
+        leaves
+            .iter()
+            .map(|leaf| PG::LeafHash::evaluate(leaf_hash_params, leaf))
+            .collect()
+    }
+
+    /// The root of the tree in which this multiproof leads to `leaves`, in the
+    /// order of `self.leaf_indices`.
+    pub fn calculate_root(
+        &self,
+        leaf_hash_params: &LeafParamVar<P, F, PG>,
+        inner_hash_params: &InnerParamVar<P, F, PG>,
+        leaves: &[Vec<UInt8<F>>],
+    ) -> Result<DigestVar<P, F, PG>, SynthesisError> {
+        let leaf_digests = self.leaf_digests(leaf_hash_params, leaves)?;
+        let shape = multiproof::shape::<P>(&self.leaf_indices, self.depth);
+        multiproof::fold(&shape, leaf_digests, &self.siblings, |children| {
+            hash_children::<P, F, PG>(inner_hash_params, children)
+        })
+    }
+
+    /// Whether `leaves` are at `self.leaf_indices` in the tree with root `root`.
+    pub fn verify_membership(
+        &self,
+        leaf_hash_params: &LeafParamVar<P, F, PG>,
+        inner_hash_params: &InnerParamVar<P, F, PG>,
+        root: &DigestVar<P, F, PG>,
+        leaves: &[Vec<UInt8<F>>],
+    ) -> Result<Boolean<F>, SynthesisError> {
+        self.calculate_root(leaf_hash_params, inner_hash_params, leaves)?
+            .is_eq(root)
+    }
+
+    /// Replace `old_leaves` by `new_leaves` at `self.leaf_indices`, in one pass
+    /// over the nodes above the leaves. Returns whether `old_leaves` are in the
+    /// tree with root `old_root`, and the root of the tree with `new_leaves`
+    /// instead, which shares all siblings with the old tree.
+    ///
+    /// The leaf indices are distinct, so each leaf is updated at most once;
+    /// successive updates of the same leaf need one call each, chaining the
+    /// returned root into the next.
+    #[allow(clippy::type_complexity)]
+    pub fn update_leaves(
+        &self,
+        leaf_hash_params: &LeafParamVar<P, F, PG>,
+        inner_hash_params: &InnerParamVar<P, F, PG>,
+        old_root: &DigestVar<P, F, PG>,
+        old_leaves: &[Vec<UInt8<F>>],
+        new_leaves: &[Vec<UInt8<F>>],
+    ) -> Result<(Boolean<F>, DigestVar<P, F, PG>), SynthesisError> {
+        let leaf_digests = self
+            .leaf_digests(leaf_hash_params, old_leaves)?
+            .into_iter()
+            .zip(self.leaf_digests(leaf_hash_params, new_leaves)?)
+            .collect();
+        let siblings = self
+            .siblings
+            .iter()
+            .map(|sibling| (sibling.clone(), sibling.clone()))
+            .collect::<Vec<_>>();
+        let shape = multiproof::shape::<P>(&self.leaf_indices, self.depth);
+        let (computed_old_root, new_root) = multiproof::fold(&shape, leaf_digests, &siblings, |children| {
+            let (old, new): (Vec<_>, Vec<_>) = children.iter().cloned().unzip();
+            Ok::<_, SynthesisError>((
+                hash_children::<P, F, PG>(inner_hash_params, &old)?,
+                hash_children::<P, F, PG>(inner_hash_params, &new)?,
+            ))
+        })?;
+        Ok((computed_old_root.is_eq(old_root)?, new_root))
+    }
+}
+
+impl<P: Config, F: PrimeField, PG: ConfigGadget<P, F>> AllocVar<MultiPath<P>, F> for MultiPathVar<P, F, PG> {
+    fn new_variable<T: Borrow<MultiPath<P>>>(
+        cs: impl Into<Namespace<F>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        let ns = cs.into();
+        let cs = ns.cs();
+        f().and_then(|multipath| {
+            let multipath = multipath.borrow();
+            let shape = multiproof::shape::<P>(&multipath.leaf_indices, multipath.depth);
+            if !multiproof::indices_are_valid::<P>(&multipath.leaf_indices, multipath.depth)
+                || multipath.siblings.len() != multiproof::num_siblings(&shape)
+            {
+                return Err(SynthesisError::Unsatisfiable);
+            }
+            Ok(Self {
+                leaf_indices: multipath.leaf_indices.clone(),
+                depth: multipath.depth,
+                siblings: Vec::new_variable(cs, || Ok(multipath.siblings.clone()), mode)?,
+            })
+        })
+    }
+}
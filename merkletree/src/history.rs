@@ -0,0 +1,149 @@
+//! Earlier versions of a tree. A `MerkleTreeHistory` journals the nodes that
+//! change between versions, so the roots and paths of every version remain
+//! available for the cost of the changed nodes only.
+use crate::{path_from_nodes, Config, Digest, MerkleTree, Path};
+use ark_crypto_primitives::Error;
+use derivative::Derivative;
+use std::collections::HashMap;
+
+/// The versions in which a node changed, in increasing order, each with the
+/// node's digest from that version on.
+type NodeChanges<P> = Vec<(usize, Digest<P>)>;
+
+/// The committed versions of a tree. Version 0 is the tree the history starts
+/// from, and each `commit` adds the next version.
+#[derive(Derivative)]
+#[derivative(Clone(bound = "P: Config"))]
+pub struct MerkleTreeHistory<P: Config> {
+    /// The digests of the nodes of each level in version 0, from the leaves up.
+    initial: Vec<Vec<Digest<P>>>,
+    /// The changes of each node that changed after version 0, keyed by level
+    /// and position.
+    changes: HashMap<(usize, usize), NodeChanges<P>>,
+    latest_version: usize,
+}
+
+impl<P: Config> MerkleTreeHistory<P> {
+    /// A history whose version 0 is `tree`.
+    pub fn new(tree: &MerkleTree<P>) -> Self {
+        Self {
+            initial: tree.levels.clone(),
+            changes: HashMap::new(),
+            latest_version: 0,
+        }
+    }
+
+    /// The number of levels above the leaves.
+    pub fn depth(&self) -> usize {
+        self.initial.len() - 1
+    }
+
+    /// The most recently committed version.
+    pub fn latest_version(&self) -> usize {
+        self.latest_version
+    }
+
+    /// The digest of the node at `position` of `level` in `version`.
+    fn node(&self, version: usize, level: usize, position: usize) -> &Digest<P> {
+        self.changes
+            .get(&(level, position))
+            .and_then(|changes| {
+                let num_earlier = changes.partition_point(|(changed_in, _)| *changed_in <= version);
+                num_earlier.checked_sub(1).map(|i| &changes[i].1)
+            })
+            .unwrap_or(&self.initial[level][position])
+    }
+
+    /// Record `tree` as the next version, and return the indices of the leaves
+    /// that differ from the latest version, in increasing order.
+    ///
+    /// Only the subtrees whose roots differ are visited: equal digests imply
+    /// equal subtrees, as the hashes are collision-resistant.
+    pub fn commit(&mut self, tree: &MerkleTree<P>) -> Result<Vec<usize>, Error> {
+        if tree.depth() != self.depth() {
+            return Err("the tree does not have the depth of the history".into());
+        }
+        let version = self.latest_version + 1;
+        let mut changed_leaves = Vec::new();
+        let mut pending = vec![(self.depth(), 0)];
+        while let Some((level, position)) = pending.pop() {
+            let digest = &tree.levels[level][position];
+            if digest == self.node(self.latest_version, level, position) {
+                continue;
+            }
+            self.changes
+                .entry((level, position))
+                .or_default()
+                .push((version, digest.clone()));
+            if level == 0 {
+                changed_leaves.push(position);
+            } else {
+                let first_child = position * P::ARITY;
+                pending.extend((first_child..first_child + P::ARITY).map(|child| (level - 1, child)));
+            }
+        }
+        self.latest_version = version;
+        changed_leaves.sort_unstable();
+        Ok(changed_leaves)
+    }
+
+    /// The root of `version`, if it has been committed.
+    pub fn root_at(&self, version: usize) -> Option<Digest<P>> {
+        (version <= self.latest_version).then(|| self.node(version, self.depth(), 0).clone())
+    }
+
+    /// A membership proof for the leaf at `index` in `version`.
+    pub fn generate_proof_at(&self, version: usize, index: usize) -> Result<Path<P>, Error> {
+        if version > self.latest_version {
+            return Err("the version has not been committed".into());
+        }
+        if index >= self.initial[0].len() {
+            return Err("leaf index out of range".into());
+        }
+        Ok(path_from_nodes(self.depth(), index, |level, position| {
+            self.node(version, level, position).clone()
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jubjub::*;
+    use ark_crypto_primitives::crh::CRHScheme;
+
+    #[test]
+    fn history_answers_roots_and_proofs_of_every_version() {
+        let mut rng = ark_std::test_rng();
+        let leaf_crh_params = LeafHash::setup(&mut rng).unwrap();
+        let inner_crh_params = InnerHash::setup(&mut rng).unwrap();
+        let mut tree = JubJubMerkleTree::blank(&leaf_crh_params, &inner_crh_params, 3).unwrap();
+        let mut history = MerkleTreeHistory::new(&tree);
+        let mut versions = vec![tree.clone()];
+
+        tree.update(3, &[1u8; 8]).unwrap();
+        assert_eq!(history.commit(&tree).unwrap(), vec![3]);
+        versions.push(tree.clone());
+        tree.update(5, &[2u8; 8]).unwrap();
+        tree.update(3, &[3u8; 8]).unwrap();
+        assert_eq!(history.commit(&tree).unwrap(), vec![3, 5]);
+        versions.push(tree.clone());
+        assert!(history.commit(&tree).unwrap().is_empty());
+        versions.push(tree.clone());
+        assert_eq!(history.latest_version(), 3);
+
+        for (version, tree) in versions.iter().enumerate() {
+            assert_eq!(history.root_at(version), Some(tree.root()));
+            for index in 0..tree.num_leaves() {
+                assert_eq!(history.generate_proof_at(version, index).unwrap(), tree.generate_proof(index).unwrap());
+            }
+        }
+        let path = history.generate_proof_at(1, 3).unwrap();
+        assert!(path.verify(&leaf_crh_params, &inner_crh_params, &history.root_at(1).unwrap(), [1u8; 8].as_slice()).unwrap());
+        assert_eq!(history.root_at(4), None);
+        assert!(history.generate_proof_at(4, 0).is_err());
+        assert!(history.generate_proof_at(0, 8).is_err());
+        let other = JubJubMerkleTree::blank(&leaf_crh_params, &inner_crh_params, 2).unwrap();
+        assert!(history.commit(&other).is_err());
+    }
+}
@@ -0,0 +1,354 @@
+//! Merkle trees over the JubJub curve with arity 2, 4 or 8, whose leaves and
+//! inner nodes are hashed with Pedersen hashes. Leaves have up to 1024 bits, and
+//! an inner node hashes the 512-bit encodings of its children. The digests are
+//! curve points, whose coordinates lie in the scalar field of BLS12-381.
+//!
+//! All configurations share the leaf hash and the digests. The parameters of
+//! every hash have the type `pedersen::Parameters<JubJub>`, but each hash needs
+//! parameters sampled for its own input size.
+use crate::constraints::{ConfigGadget, PathVar};
+use crate::{Config, MerkleTree, Path};
+use ark_crypto_primitives::crh::{pedersen, CRHScheme, CRHSchemeGadget};
+use ark_ed_on_bls12_381::{constraints::EdwardsVar, EdwardsProjective as JubJub, Fq};
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Window4x256;
+impl pedersen::Window for Window4x256 {
+    const WINDOW_SIZE: usize = 4;
+    const NUM_WINDOWS: usize = 256;
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Window4x512;
+impl pedersen::Window for Window4x512 {
+    const WINDOW_SIZE: usize = 4;
+    const NUM_WINDOWS: usize = 512;
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Window4x1024;
+impl pedersen::Window for Window4x1024 {
+    const WINDOW_SIZE: usize = 4;
+    const NUM_WINDOWS: usize = 1024;
+}
+
+pub type LeafHash = pedersen::CRH<JubJub, Window4x256>;
+pub type LeafHashGadget = pedersen::constraints::CRHGadget<JubJub, EdwardsVar, Window4x256>;
+pub type InnerHash = pedersen::CRH<JubJub, Window4x256>;
+pub type InnerHashGadget = pedersen::constraints::CRHGadget<JubJub, EdwardsVar, Window4x256>;
+
+pub type Inner4aryHash = pedersen::CRH<JubJub, Window4x512>;
+pub type Inner4aryHashGadget = pedersen::constraints::CRHGadget<JubJub, EdwardsVar, Window4x512>;
+pub type Inner8aryHash = pedersen::CRH<JubJub, Window4x1024>;
+pub type Inner8aryHashGadget = pedersen::constraints::CRHGadget<JubJub, EdwardsVar, Window4x1024>;
+
+#[derive(Clone)]
+pub struct JubJubMerkleTreeParams;
+impl Config for JubJubMerkleTreeParams {
+    const ARITY: usize = 2;
+    type LeafHash = LeafHash;
+    type InnerHash = InnerHash;
+}
+
+pub struct JubJubMerkleTreeParamsVar;
+impl ConfigGadget<JubJubMerkleTreeParams, Fq> for JubJubMerkleTreeParamsVar {
+    type LeafHash = LeafHashGadget;
+    type InnerHash = InnerHashGadget;
+}
+
+#[derive(Clone)]
+pub struct JubJub4aryMerkleTreeParams;
+impl Config for JubJub4aryMerkleTreeParams {
+    const ARITY: usize = 4;
+    type LeafHash = LeafHash;
+    type InnerHash = Inner4aryHash;
+}
+
+pub struct JubJub4aryMerkleTreeParamsVar;
+impl ConfigGadget<JubJub4aryMerkleTreeParams, Fq> for JubJub4aryMerkleTreeParamsVar {
+    type LeafHash = LeafHashGadget;
+    type InnerHash = Inner4aryHashGadget;
+}
+
+#[derive(Clone)]
+pub struct JubJub8aryMerkleTreeParams;
+impl Config for JubJub8aryMerkleTreeParams {
+    const ARITY: usize = 8;
+    type LeafHash = LeafHash;
+    type InnerHash = Inner8aryHash;
+}
+
+pub struct JubJub8aryMerkleTreeParamsVar;
+impl ConfigGadget<JubJub8aryMerkleTreeParams, Fq> for JubJub8aryMerkleTreeParamsVar {
+    type LeafHash = LeafHashGadget;
+    type InnerHash = Inner8aryHashGadget;
+}
+
+pub type JubJubMerkleTree = MerkleTree<JubJubMerkleTreeParams>;
+pub type JubJubPath = Path<JubJubMerkleTreeParams>;
+pub type JubJubPathVar = PathVar<JubJubMerkleTreeParams, Fq, JubJubMerkleTreeParamsVar>;
+pub type LeafHashParams = <LeafHash as CRHScheme>::Parameters;
+pub type InnerHashParams = <InnerHash as CRHScheme>::Parameters;
+pub type LeafHashParamsVar = <LeafHashGadget as CRHSchemeGadget<LeafHash, Fq>>::ParametersVar;
+pub type InnerHashParamsVar = <InnerHashGadget as CRHSchemeGadget<InnerHash, Fq>>::ParametersVar;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constraints::DigestVar;
+    use ark_bls12_381::Bls12_381;
+    use ark_crypto_primitives::crh::TwoToOneCRHScheme;
+    use ark_crypto_primitives::merkle_tree::{self, ByteDigestConverter};
+    use ark_crypto_primitives::snark::SNARK;
+    use ark_ec::AffineRepr;
+    use ark_ff::ToConstraintField;
+    use ark_r1cs_std::prelude::*;
+    use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystem, ConstraintSystemRef, SynthesisError};
+    use ark_std::rand::{prelude::StdRng, SeedableRng};
+
+    type Root = <LeafHash as CRHScheme>::Output;
+    type RootVar = DigestVar<JubJubMerkleTreeParams, Fq, JubJubMerkleTreeParamsVar>;
+
+    fn leaves() -> Vec<Vec<u8>> {
+        [1u8, 2, 3, 10, 9, 17, 70, 45].iter().map(|i| vec![*i; 30]).collect()
+    }
+
+    /// Generate a merkle tree, its constraints, and test its constraints
+    #[test]
+    fn good_merkle_tree_test() {
+        let leaves = (0..4u8).map(|i| vec![i; 30]).collect::<Vec<_>>();
+        let mut rng = ark_std::test_rng();
+
+        let leaf_crh_params = LeafHash::setup(&mut rng).unwrap();
+        let inner_crh_params = InnerHash::setup(&mut rng).unwrap();
+
+        let tree = JubJubMerkleTree::new(&leaf_crh_params, &inner_crh_params, leaves.iter().map(Vec::as_slice)).unwrap();
+        let root = tree.root();
+        for (i, leaf) in leaves.iter().enumerate() {
+            let cs = ConstraintSystem::<Fq>::new_ref();
+            let proof = tree.generate_proof(i).unwrap();
+            assert!(proof
+                .verify(&leaf_crh_params, &inner_crh_params, &root, leaf.as_slice())
+                .unwrap());
+
+            // Allocate Merkle Tree Root
+            let root = RootVar::new_witness(ark_relations::ns!(cs, "new_digest"), || Ok(root)).unwrap();
+
+            let constraints_from_digest = cs.num_constraints();
+            println!("constraints from digest: {}", constraints_from_digest);
+
+            // Allocate Parameters for CRH
+            let leaf_crh_params_var =
+                LeafHashParamsVar::new_constant(ark_relations::ns!(cs, "leaf_crh_parameter"), &leaf_crh_params)
+                    .unwrap();
+            let inner_crh_params_var =
+                InnerHashParamsVar::new_constant(ark_relations::ns!(cs, "inner_crh_parameter"), &inner_crh_params)
+                    .unwrap();
+
+            let constraints_from_params = cs.num_constraints() - constraints_from_digest;
+            println!("constraints from parameters: {}", constraints_from_params);
+
+            // Allocate Leaf
+            let leaf_g = UInt8::new_input_vec(cs.clone(), leaf).unwrap();
+
+            let constraints_from_leaf = cs.num_constraints() - constraints_from_params - constraints_from_digest;
+            println!("constraints from leaf: {}", constraints_from_leaf);
+
+            // Allocate Merkle Tree Path
+            let cw = JubJubPathVar::new_witness(ark_relations::ns!(cs, "new_witness"), || Ok(&proof)).unwrap();
+
+            let constraints_from_path =
+                cs.num_constraints() - constraints_from_params - constraints_from_digest - constraints_from_leaf;
+            println!("constraints from path: {}", constraints_from_path);
+
+            assert!(cs.is_satisfied().unwrap());
+            assert!(cw
+                .verify_membership(&leaf_crh_params_var, &inner_crh_params_var, &root, &leaf_g)
+                .unwrap()
+                .value()
+                .unwrap());
+            let setup_constraints =
+                constraints_from_leaf + constraints_from_digest + constraints_from_params + constraints_from_path;
+            println!("number of constraints: {}", cs.num_constraints() - setup_constraints);
+
+            assert!(cs.is_satisfied().unwrap(), "verification constraints not satisfied");
+        }
+    }
+
+    /// The binary trees hash exactly like arkworks' trees with Pedersen hashes.
+    #[test]
+    fn binary_tree_matches_arkworks() {
+        struct ArkworksParams;
+        impl merkle_tree::Config for ArkworksParams {
+            type Leaf = [u8];
+            type LeafDigest = <LeafHash as CRHScheme>::Output;
+            type LeafInnerDigestConverter = ByteDigestConverter<Self::LeafDigest>;
+            type InnerDigest = <pedersen::TwoToOneCRH<JubJub, Window4x256> as TwoToOneCRHScheme>::Output;
+            type LeafHash = LeafHash;
+            type TwoToOneHash = pedersen::TwoToOneCRH<JubJub, Window4x256>;
+        }
+
+        let mut rng = ark_std::test_rng();
+        let leaf_crh_params = LeafHash::setup(&mut rng).unwrap();
+        let inner_crh_params = InnerHash::setup(&mut rng).unwrap();
+        let leaves = leaves();
+
+        let mut tree = JubJubMerkleTree::new(&leaf_crh_params, &inner_crh_params, leaves.iter().map(Vec::as_slice)).unwrap();
+        let mut arkworks_tree =
+            merkle_tree::MerkleTree::<ArkworksParams>::new(&leaf_crh_params, &inner_crh_params, leaves.clone()).unwrap();
+        assert_eq!(tree.root(), arkworks_tree.root());
+
+        tree.update(5, &[7u8; 30]).unwrap();
+        arkworks_tree.update(5, &[7u8; 30]).unwrap();
+        assert_eq!(tree.root(), arkworks_tree.root());
+        assert!(tree
+            .generate_proof(5)
+            .unwrap()
+            .verify(&leaf_crh_params, &inner_crh_params, &arkworks_tree.root(), [7u8; 30].as_slice())
+            .unwrap());
+    }
+
+    #[test]
+    fn blank_tree_updates_like_a_full_tree() {
+        let mut rng = ark_std::test_rng();
+        let leaf_crh_params = LeafHash::setup(&mut rng).unwrap();
+        let inner_crh_params = InnerHash::setup(&mut rng).unwrap();
+        let leaves = leaves();
+
+        let mut tree = JubJubMerkleTree::blank(&leaf_crh_params, &inner_crh_params, 3).unwrap();
+        assert_eq!(tree.num_leaves(), 8);
+        for (i, leaf) in leaves.iter().enumerate() {
+            tree.update(i, leaf).unwrap();
+        }
+        let full_tree =
+            JubJubMerkleTree::new(&leaf_crh_params, &inner_crh_params, leaves.iter().map(Vec::as_slice)).unwrap();
+        assert_eq!(tree.root(), full_tree.root());
+
+        assert!(tree.update(8, &leaves[0]).is_err());
+        assert!(tree.generate_proof(8).is_err());
+        assert!(!tree
+            .generate_proof(4)
+            .unwrap()
+            .verify(&leaf_crh_params, &inner_crh_params, &tree.root(), leaves[3].as_slice())
+            .unwrap());
+    }
+
+    #[derive(Clone)]
+    pub struct MerkleTreeCircuit {
+        // These are constants that will be embedded into the circuit
+        pub leaf_crh_params: LeafHashParams,
+        pub inner_crh_params: InnerHashParams,
+
+        // This is the public input to the circuit.
+        pub root: Root,
+
+        // These are the private witnesses to the circuit.
+        pub leaf: u8,
+        pub authentication_path: JubJubPath,
+    }
+
+    impl ConstraintSynthesizer<Fq> for MerkleTreeCircuit {
+        fn generate_constraints(self, cs: ConstraintSystemRef<Fq>) -> Result<(), SynthesisError> {
+            // First, we allocate the public inputs
+            let root = RootVar::new_input(ark_relations::ns!(cs, "new_digest"), || Ok(self.root))?;
+
+            let leaf = UInt8::new_witness(ark_relations::ns!(cs, "leaf_var"), || Ok(self.leaf))?;
+
+            // Then, we allocate the public parameters as constants:
+            let leaf_crh_params =
+                LeafHashParamsVar::new_constant(ark_relations::ns!(cs, "leaf_crh_parameter"), &self.leaf_crh_params)?;
+            let inner_crh_params = InnerHashParamsVar::new_constant(
+                ark_relations::ns!(cs, "inner_crh_parameter"),
+                &self.inner_crh_params,
+            )?;
+
+            // Finally, we allocate our path as a private witness variable:
+            let path = JubJubPathVar::new_witness(ark_relations::ns!(cs, "new_witness"), || {
+                Ok(self.authentication_path)
+            })?;
+
+            let leaf_bytes = vec![leaf; 30];
+
+            let is_member = path.verify_membership(&leaf_crh_params, &inner_crh_params, &root, &leaf_bytes)?;
+
+            is_member.enforce_equal(&Boolean::TRUE)?;
+
+            Ok(())
+        }
+    }
+
+    fn circuit_for_fifth_leaf(rng: &mut impl ark_std::rand::Rng) -> (MerkleTreeCircuit, Root) {
+        // First, let's sample the public parameters for the hash functions:
+        let leaf_crh_params = LeafHash::setup(rng).unwrap();
+        let inner_crh_params = InnerHash::setup(rng).unwrap();
+
+        // Next, let's construct our tree.
+        let tree =
+            JubJubMerkleTree::new(&leaf_crh_params, &inner_crh_params, leaves().iter().map(Vec::as_slice)).unwrap();
+
+        // Now, let's try to generate a membership proof for the 5th item, i.e. 9.
+        let proof = tree.generate_proof(4).unwrap(); // we're 0-indexing!
+
+        // First, let's get the root we want to verify against:
+        let root = tree.root();
+
+        let circuit = MerkleTreeCircuit {
+            // constants
+            leaf_crh_params,
+            inner_crh_params,
+
+            // public input
+            root,
+
+            // witnesses
+            leaf: 9u8,
+            authentication_path: proof,
+        };
+        (circuit, root)
+    }
+
+    #[test]
+    fn merkle_tree_constraints_correctness() {
+        let (circuit, _) = circuit_for_fifth_leaf(&mut ark_std::test_rng());
+
+        // Next, let's make the circuit!
+        let cs = ConstraintSystem::<Fq>::new_ref();
+        circuit.generate_constraints(cs.clone()).unwrap();
+        // Let's check whether the constraint system is satisfied
+        let is_satisfied = cs.is_satisfied().unwrap();
+        if !is_satisfied {
+            // If it isn't, find out the offending constraint.
+            println!("{:?}", cs.which_is_unsatisfied());
+        }
+        assert!(is_satisfied);
+    }
+
+    #[test]
+    fn merkle_tree_rejects_wrong_leaf() {
+        let (mut circuit, _) = circuit_for_fifth_leaf(&mut ark_std::test_rng());
+        circuit.leaf = 17;
+
+        let cs = ConstraintSystem::<Fq>::new_ref();
+        circuit.generate_constraints(cs.clone()).unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+    }
+
+    #[test]
+    fn merkle_tree_groth_correctness() {
+        let mut rng = StdRng::seed_from_u64(0u64);
+        let (circuit, root) = circuit_for_fifth_leaf(&mut rng);
+
+        let (pk, vk) = ark_groth16::Groth16::<Bls12_381>::circuit_specific_setup(circuit.clone(), &mut rng).unwrap();
+
+        let pvk = ark_groth16::prepare_verifying_key(&vk);
+
+        let public_input = root.into_group().to_field_elements().unwrap();
+
+        let groth_proof =
+            ark_groth16::Groth16::<Bls12_381>::create_random_proof_with_reduction(circuit, &pk, &mut rng).unwrap();
+
+        let a = ark_groth16::Groth16::<Bls12_381>::verify_proof(&pvk, &groth_proof, &public_input).unwrap();
+        println!("verification result: {}", a);
+        assert!(a);
+    }
+}
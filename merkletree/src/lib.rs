@@ -1,306 +1,341 @@
-use ark_crypto_primitives::merkle_tree::{MerkleTree, ByteDigestConverter, DigestConverter, Path, Config, constraints::{BytesVarDigestConverter, ConfigGadget, PathVar}};
-use ark_crypto_primitives::crh::{pedersen, TwoToOneCRHScheme, TwoToOneCRHSchemeGadget, CRHScheme, CRHSchemeGadget};
-use ark_relations::{lc, r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError, ConstraintSystem}};
-use ark_ed_on_bls12_381::{constraints::EdwardsVar, EdwardsProjective as JubJub, Fq};
-use ark_std::rand::{prelude::StdRng, SeedableRng, RngCore};
-use ark_crypto_primitives::snark::SNARK;
-use ark_ff::ToConstraintField;
-use ark_r1cs_std::prelude::*;
-use ark_bls12_381::Bls12_381;
+//! Merkle trees whose inner nodes have `Config::ARITY` children, generic over the
+//! hash of the leaves and the hash of the inner nodes. The gadgets are in
+//! [`constraints`], and [`jubjub`] instantiates the trees with Pedersen hashes
+//! over JubJub.
+//!
+//! A leaf's digest is `LeafHash(leaf)`, and an inner node's digest is
+//! `InnerHash(d_0 || ... || d_{ARITY-1})`, where `d_j` is the uncompressed
+//! encoding of the digest of its `j`-th child. Leaf `i` lies below the `j`-th
+//! child of its ancestor at level `l` (counting from the leaves), where `j` is
+//! the `l`-th base-`ARITY` digit of `i`. For `ARITY = 2` this is the hashing of
+//! arkworks' `MerkleTree` with a `ByteDigestConverter`.
+use ark_crypto_primitives::crh::CRHScheme;
+use ark_crypto_primitives::Error;
+use ark_serialize::CanonicalSerialize;
+use ark_std::borrow::Borrow;
 use derivative::Derivative;
-use ark_ec::AffineRepr;
 
-#[allow(unused_imports)]
-
-#[derive(Clone)]
-pub struct Window4x256;
-impl pedersen::Window for Window4x256 {
-    const WINDOW_SIZE: usize = 4;
-    const NUM_WINDOWS: usize = 256;
+pub mod constraints;
+pub mod history;
+pub mod jubjub;
+pub mod multiproof;
+
+/// The hashes and the arity of a Merkle tree.
+pub trait Config {
+    /// The number of children of an inner node. It has to be a power of two.
+    const ARITY: usize;
+    /// The hash from a leaf to its digest.
+    type LeafHash: CRHScheme<Input = [u8]>;
+    /// The hash from the encoded digests of `ARITY` children to their parent's digest.
+    type InnerHash: CRHScheme<Input = [u8], Output = <Self::LeafHash as CRHScheme>::Output>;
 }
 
-pub type LeafH = pedersen::CRH<JubJub, Window4x256>;
-pub type LeafHG = pedersen::constraints::CRHGadget<JubJub, EdwardsVar, Window4x256>;
-
-pub type CompressH = pedersen::TwoToOneCRH<JubJub, Window4x256>;
-pub type CompressHG = pedersen::constraints::TwoToOneCRHGadget<JubJub, EdwardsVar, Window4x256>;
-
-pub type LeafVar<ConstraintF> = [UInt8<ConstraintF>];
-
-#[derive(Clone)]
-pub struct JubJubMerkleTreeParams;
-
-impl Config for JubJubMerkleTreeParams {
-    type Leaf = [u8];
-    type LeafDigest = <LeafH as CRHScheme>::Output;
-    type LeafInnerDigestConverter = ByteDigestConverter<Self::LeafDigest>;
-
-    type InnerDigest = <CompressH as TwoToOneCRHScheme>::Output;
-    type LeafHash = LeafH;
-    type TwoToOneHash = CompressH;
+/// The digest of a node of a tree with configuration `P`.
+pub type Digest<P> = <<P as Config>::LeafHash as CRHScheme>::Output;
+pub type LeafParam<P> = <<P as Config>::LeafHash as CRHScheme>::Parameters;
+pub type InnerParam<P> = <<P as Config>::InnerHash as CRHScheme>::Parameters;
+
+/// The number of leaf-index bits consumed by each level of a tree with configuration `P`.
+pub fn bits_per_level<P: Config>() -> usize {
+    assert!(
+        P::ARITY >= 2 && P::ARITY.is_power_of_two(),
+        "the arity has to be a power of two"
+    );
+    P::ARITY.trailing_zeros() as usize
 }
 
-type ConstraintF = Fq;
-pub struct JubJubMerkleTreeParamsVar;
-impl ConfigGadget<JubJubMerkleTreeParams, ConstraintF> for JubJubMerkleTreeParamsVar {
-    type Leaf = LeafVar<ConstraintF>;
-    type LeafDigest = <LeafHG as CRHSchemeGadget<LeafH, ConstraintF>>::OutputVar;
-    type LeafInnerConverter = BytesVarDigestConverter<Self::LeafDigest, ConstraintF>;
-    type InnerDigest =
-    <CompressHG as TwoToOneCRHSchemeGadget<CompressH, ConstraintF>>::OutputVar;
-    type LeafHash = LeafHG;
-    type TwoToOneHash = CompressHG;
+/// The position of the ancestor at `level` of leaf `index` among its siblings.
+fn position_at_level<P: Config>(index: usize, level: usize) -> usize {
+    (index >> (level * bits_per_level::<P>())) & (P::ARITY - 1)
 }
 
-pub type JubJubMerkleTree = MerkleTree<JubJubMerkleTreeParams>;
+/// The children of a node: `siblings` with `node` inserted at `position`.
+fn insert_node<P: Config>(siblings: &[Digest<P>], position: usize, node: Digest<P>) -> Vec<Digest<P>> {
+    let mut children = siblings.to_vec();
+    children.insert(position, node);
+    children
+}
 
-/// Generate a merkle tree, its constraints, and test its constraints
-#[test]
-fn good_merkle_tree_test() {
-    let mut leaves = Vec::new();
-    for i in 0..4u8 {
-        let input = vec![i; 30];
-        leaves.push(input);
+/// Hash the digests of the `ARITY` children of an inner node.
+pub fn hash_children<P: Config>(
+    inner_hash_params: &InnerParam<P>,
+    children: &[Digest<P>],
+) -> Result<Digest<P>, Error> {
+    assert_eq!(children.len(), P::ARITY);
+    let mut input = Vec::new();
+    for child in children {
+        child.serialize_uncompressed(&mut input)?;
     }
-    let mut rng = ark_std::test_rng();
-
-    let leaf_crh_params = <LeafH as CRHScheme>::setup(&mut rng).unwrap();
-    let two_to_one_crh_params = <CompressH as TwoToOneCRHScheme>::setup(&mut rng).unwrap();
-
-    let tree = JubJubMerkleTree::new(&leaf_crh_params, &two_to_one_crh_params, leaves.clone()).unwrap();
-    let root = tree.root();
-    for (i, leaf) in leaves.iter().enumerate() {
-        let cs = ConstraintSystem::<Fq>::new_ref();
-        let proof = tree.generate_proof(i).unwrap();
-        assert!(proof
-            .verify(
-                &leaf_crh_params,
-                &two_to_one_crh_params,
-                &root,
-                leaf.as_slice()
-            )
-            .unwrap());
-
-        // Allocate Merkle Tree Root
-        let root = <LeafHG as CRHSchemeGadget<LeafH, _>>::OutputVar::new_witness(
-            ark_relations::ns!(cs, "new_digest"),
-            || {Ok(root)},
-        )
-        .unwrap();
-
-        let constraints_from_digest = cs.num_constraints();
-        println!("constraints from digest: {}", constraints_from_digest);
-
-        // Allocate Parameters for CRH
-        let leaf_crh_params_var =
-            <LeafHG as CRHSchemeGadget<LeafH, _>>::ParametersVar::new_constant(
-                ark_relations::ns!(cs, "leaf_crh_parameter"),
-                &leaf_crh_params,
-            )
-                .unwrap();
-        let two_to_one_crh_params_var =
-            <CompressHG as TwoToOneCRHSchemeGadget<CompressH, _>>::ParametersVar::new_constant(
-                ark_relations::ns!(cs, "two_to_one_crh_parameter"),
-                &two_to_one_crh_params,
-            )
-                .unwrap();
-
-        let constraints_from_params = cs.num_constraints() - constraints_from_digest;
-        println!("constraints from parameters: {}", constraints_from_params);
-
-        // Allocate Leaf
-        let leaf_g = UInt8::new_input_vec(cs.clone(), leaf).unwrap();
-
-        let constraints_from_leaf =
-            cs.num_constraints() - constraints_from_params - constraints_from_digest;
-        println!("constraints from leaf: {}", constraints_from_leaf);
-
-        // Allocate Merkle Tree Path
-        let cw: PathVar<JubJubMerkleTreeParams, Fq, JubJubMerkleTreeParamsVar> =
-            PathVar::new_witness(ark_relations::ns!(cs, "new_witness"), || Ok(&proof)).unwrap();
-
-        let constraints_from_path = cs.num_constraints()
-            - constraints_from_params
-            - constraints_from_digest
-            - constraints_from_leaf;
-        println!("constraints from path: {}", constraints_from_path);
-
-        assert!(cs.is_satisfied().unwrap());
-        assert!(cw
-            .verify_membership(
-                &leaf_crh_params_var,
-                &two_to_one_crh_params_var,
-                &root,
-                &leaf_g,
-            )
-            .unwrap()
-            .value()
-            .unwrap());
-        let setup_constraints = constraints_from_leaf
-            + constraints_from_digest
-            + constraints_from_params
-            + constraints_from_path;
-        println!(
-            "number of constraints: {}",
-            cs.num_constraints() - setup_constraints
-        );
+    P::InnerHash::evaluate(inner_hash_params, input.as_slice())
+}
 
-        assert!(
-            cs.is_satisfied().unwrap(),
-            "verification constraints not satisfied"
-        );
+/// The path to leaf `index` in a tree with `depth` levels above the leaves,
+/// whose node at `position` of `level` has digest `node(level, position)`.
+fn path_from_nodes<P: Config>(depth: usize, index: usize, node: impl Fn(usize, usize) -> Digest<P>) -> Path<P> {
+    let siblings = (0..depth)
+        .map(|level| {
+            let node_index = index >> (level * bits_per_level::<P>());
+            let first_child = node_index - node_index % P::ARITY;
+            (first_child..first_child + P::ARITY)
+                .filter(|&position| position != node_index)
+                .map(|position| node(level, position))
+                .collect()
+        })
+        .collect();
+    Path {
+        leaf_index: index,
+        siblings,
     }
 }
 
-#[derive(Clone)]
-pub struct MerkleTreeCircuit {
-    // These are constants that will be embedded into the circuit
-    pub leaf_crh_params: <LeafH as CRHScheme>::Parameters,
-    pub two_to_one_crh_params: <CompressH as TwoToOneCRHScheme>::Parameters,
-
-    // This is the public input to the circuit.
-    pub root: <CompressH as TwoToOneCRHScheme>::Output,
-    
-    // These are the private witnesses to the circuit.
-    pub leaf: u8,
-    pub authentication_path: Path<JubJubMerkleTreeParams>,
+/// A membership proof for a single leaf.
+#[derive(Derivative)]
+#[derivative(Clone(bound = "P: Config"), Debug(bound = "P: Config"), PartialEq(bound = "P: Config"))]
+pub struct Path<P: Config> {
+    /// The index of the leaf.
+    pub leaf_index: usize,
+    /// For each level from the leaves up, the digests of the `ARITY - 1`
+    /// siblings of the path's node at that level, in order.
+    pub siblings: Vec<Vec<Digest<P>>>,
 }
 
-impl ConstraintSynthesizer<ConstraintF> for MerkleTreeCircuit {
-    fn generate_constraints(
-        self,
-        cs: ConstraintSystemRef<ConstraintF>,
-    ) -> Result<(), SynthesisError> {
-        // First, we allocate the public inputs
-        let root = <LeafHG as CRHSchemeGadget<LeafH, _>>::OutputVar::new_input(ark_relations::ns!(cs, "new_digest"), || Ok(self.root))?;
-
-        let leaf = UInt8::new_witness(ark_relations::ns!(cs, "leaf_var"), || Ok(self.leaf))?;
-
-        // Then, we allocate the public parameters as constants:
-        let leaf_crh_params = <LeafHG as CRHSchemeGadget<LeafH, _>>::ParametersVar::new_constant(ark_relations::ns!(cs, "leaf_crh_parameter"), &self.leaf_crh_params)?;
-        let two_to_one_crh_params = <CompressHG as TwoToOneCRHSchemeGadget<CompressH, _>>::ParametersVar::new_constant(ark_relations::ns!(cs, "two_to_one_crh_parameter"), &self.two_to_one_crh_params)?;
-
-        // Finally, we allocate our path as a private witness variable:
-        let path: PathVar<JubJubMerkleTreeParams, Fq, JubJubMerkleTreeParamsVar> = PathVar::new_witness(ark_relations::ns!(cs, "new_witness"), || Ok(self.authentication_path))?;
-
-        let leaf_bytes = vec![leaf; 30];
-
-        let is_member = 
-            path.verify_membership(
-            &leaf_crh_params,
-            &two_to_one_crh_params,
-            &root,
-            &leaf_bytes,
-        )?;
+impl<P: Config> Path<P> {
+    /// The root of the tree in which this path leads to a leaf with digest `leaf_digest`.
+    pub fn root_from_leaf_digest(
+        &self,
+        inner_hash_params: &InnerParam<P>,
+        leaf_digest: Digest<P>,
+    ) -> Result<Digest<P>, Error> {
+        self.siblings
+            .iter()
+            .enumerate()
+            .try_fold(leaf_digest, |node, (level, siblings)| {
+                let position = position_at_level::<P>(self.leaf_index, level);
+                hash_children::<P>(inner_hash_params, &insert_node::<P>(siblings, position, node))
+            })
+    }
 
-        is_member.enforce_equal(&Boolean::TRUE)?;
+    /// The root of the tree in which this path leads to `leaf`.
+    pub fn calculate_root<L: Borrow<[u8]>>(
+        &self,
+        leaf_hash_params: &LeafParam<P>,
+        inner_hash_params: &InnerParam<P>,
+        leaf: L,
+    ) -> Result<Digest<P>, Error> {
+        let leaf_digest = P::LeafHash::evaluate(leaf_hash_params, leaf.borrow())?;
+        self.root_from_leaf_digest(inner_hash_params, leaf_digest)
+    }
 
-        Ok(())
+    /// Check that `leaf` is at `self.leaf_index` in the tree with root `root`.
+    pub fn verify<L: Borrow<[u8]>>(
+        &self,
+        leaf_hash_params: &LeafParam<P>,
+        inner_hash_params: &InnerParam<P>,
+        root: &Digest<P>,
+        leaf: L,
+    ) -> Result<bool, Error> {
+        Ok(self.calculate_root(leaf_hash_params, inner_hash_params, leaf)? == *root)
     }
 }
 
-#[test]
-fn merkle_tree_constraints_correctness() {
-    let mut rng = ark_std::test_rng();
-
-    // First, let's sample the public parameters for the hash functions:
-    let leaf_crh_params = <LeafH as CRHScheme>::setup(&mut rng).unwrap();
-    let two_to_one_crh_params = <CompressH as TwoToOneCRHScheme>::setup(&mut rng).unwrap();
-
-    // Next, let's construct our tree.
-    let mut leaves = Vec::new();
-    leaves.push(vec![1u8; 30]);
-    leaves.push(vec![2u8; 30]);
-    leaves.push(vec![3u8; 30]);
-    leaves.push(vec![10u8; 30]);
-    leaves.push(vec![9u8; 30]);
-    leaves.push(vec![17u8; 30]);
-    leaves.push(vec![70u8; 30]);
-    leaves.push(vec![45u8; 30]);
-    let tree = JubJubMerkleTree::new(&leaf_crh_params, &two_to_one_crh_params, leaves).unwrap();
-
-    // Now, let's try to generate a membership proof for the 5th item, i.e. 9.
-    let proof = tree.generate_proof(4).unwrap(); // we're 0-indexing!
-
-    // First, let's get the root we want to verify against:
-    let root = tree.root();
-
-    let circuit = MerkleTreeCircuit {
-        // constants
-        leaf_crh_params,
-        two_to_one_crh_params,
-
-        // public input
-        root,
-
-        // witnesses
-        leaf: 9u8,
-        authentication_path: proof,
-    };
-
-    // Next, let's make the circuit!
-    let cs = ConstraintSystem::<Fq>::new_ref();
-    circuit.generate_constraints(cs.clone()).unwrap();
-    // Let's check whether the constraint system is satisfied
-    let is_satisfied = cs.is_satisfied().unwrap();
-    if !is_satisfied {
-        // If it isn't, find out the offending constraint.
-        println!("{:?}", cs.which_is_unsatisfied());
-    }
-    println!("Ya we got here");
-    assert!(is_satisfied);
+/// A Merkle tree with `ARITY^depth` leaves that stores the digests of all of its nodes.
+#[derive(Derivative)]
+#[derivative(Clone(bound = "P: Config"))]
+pub struct MerkleTree<P: Config> {
+    leaf_hash_params: LeafParam<P>,
+    inner_hash_params: InnerParam<P>,
+    /// The digests of the nodes of each level, from the leaves up to the root.
+    levels: Vec<Vec<Digest<P>>>,
 }
 
-#[test]
-fn merkle_tree_groth_correctness() {
-    let mut rng = StdRng::seed_from_u64(0u64);
+impl<P: Config> MerkleTree<P> {
+    /// A tree with `ARITY^depth` leaves, all of which have the default digest
+    /// rather than the digest of some leaf.
+    pub fn blank(
+        leaf_hash_params: &LeafParam<P>,
+        inner_hash_params: &InnerParam<P>,
+        depth: usize,
+    ) -> Result<Self, Error> {
+        // All nodes of a level are equal, so hash once per level.
+        let mut node = Digest::<P>::default();
+        let mut levels = Vec::with_capacity(depth + 1);
+        for level in 0..=depth {
+            let width = P::ARITY.pow((depth - level) as u32);
+            levels.push(vec![node.clone(); width]);
+            if level < depth {
+                node = hash_children::<P>(inner_hash_params, &vec![node; P::ARITY])?;
+            }
+        }
+        Ok(Self {
+            leaf_hash_params: leaf_hash_params.clone(),
+            inner_hash_params: inner_hash_params.clone(),
+            levels,
+        })
+    }
 
-    // First, let's sample the public parameters for the hash functions:
-    let leaf_crh_params = <LeafH as CRHScheme>::setup(&mut rng).unwrap();
-    let two_to_one_crh_params = <CompressH as TwoToOneCRHScheme>::setup(&mut rng).unwrap();
+    /// A tree with the given leaves, whose number has to be a power of `ARITY`.
+    pub fn new<L: Borrow<[u8]>>(
+        leaf_hash_params: &LeafParam<P>,
+        inner_hash_params: &InnerParam<P>,
+        leaves: impl IntoIterator<Item = L>,
+    ) -> Result<Self, Error> {
+        let leaf_digests = leaves
+            .into_iter()
+            .map(|leaf| P::LeafHash::evaluate(leaf_hash_params, leaf.borrow()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut depth = 0;
+        while P::ARITY.pow(depth as u32) < leaf_digests.len() {
+            depth += 1;
+        }
+        if P::ARITY.pow(depth as u32) != leaf_digests.len() {
+            return Err("the number of leaves has to be a power of the arity".into());
+        }
+        let mut levels = vec![leaf_digests];
+        for _ in 0..depth {
+            let parents = levels
+                .last()
+                .unwrap()
+                .chunks(P::ARITY)
+                .map(|children| hash_children::<P>(inner_hash_params, children))
+                .collect::<Result<Vec<_>, _>>()?;
+            levels.push(parents);
+        }
+        Ok(Self {
+            leaf_hash_params: leaf_hash_params.clone(),
+            inner_hash_params: inner_hash_params.clone(),
+            levels,
+        })
+    }
 
-    // Next, let's construct our tree.
-    let mut leaves = Vec::new();
-    leaves.push(vec![1u8; 30]);
-    leaves.push(vec![2u8; 30]);
-    leaves.push(vec![3u8; 30]);
-    leaves.push(vec![10u8; 30]);
-    leaves.push(vec![9u8; 30]);
-    leaves.push(vec![17u8; 30]);
-    leaves.push(vec![70u8; 30]);
-    leaves.push(vec![45u8; 30]);
-    let tree = JubJubMerkleTree::new(&leaf_crh_params, &two_to_one_crh_params, leaves).unwrap();
+    /// The number of levels above the leaves.
+    pub fn depth(&self) -> usize {
+        self.levels.len() - 1
+    }
 
-    // Now, let's try to generate a membership proof for the 5th item, i.e. 9.
-    let proof = tree.generate_proof(4).unwrap(); 
+    /// The number of leaves.
+    pub fn num_leaves(&self) -> usize {
+        self.levels[0].len()
+    }
 
-    // First, let's get the root we want to verify against:
-    let root = tree.root();
+    /// The digest of the root.
+    pub fn root(&self) -> Digest<P> {
+        self.levels[self.depth()][0].clone()
+    }
 
-    let circuit = MerkleTreeCircuit {
-        // constants
-        leaf_crh_params,
-        two_to_one_crh_params,
+    /// The digest of the leaf at `index`.
+    pub fn leaf_digest(&self, index: usize) -> Option<&Digest<P>> {
+        self.levels[0].get(index)
+    }
 
-        // public input
-        root,
+    fn check_index(&self, index: usize) -> Result<(), Error> {
+        if index < self.num_leaves() {
+            Ok(())
+        } else {
+            Err("leaf index out of range".into())
+        }
+    }
 
-        // witnesses
-        leaf: 9u8,
-        authentication_path: proof,
-    };
+    /// A membership proof for the leaf at `index`.
+    pub fn generate_proof(&self, index: usize) -> Result<Path<P>, Error> {
+        self.check_index(index)?;
+        Ok(path_from_nodes(self.depth(), index, |level, position| {
+            self.levels[level][position].clone()
+        }))
+    }
 
-    let (pk, vk) = {
-        ark_groth16::Groth16::<Bls12_381>::circuit_specific_setup(circuit.clone(), &mut rng).unwrap()
-    };
+    /// Replace the leaf at `index` by `new_leaf`, and rehash its ancestors.
+    pub fn update(&mut self, index: usize, new_leaf: &[u8]) -> Result<(), Error> {
+        self.check_index(index)?;
+        let leaf_digest = P::LeafHash::evaluate(&self.leaf_hash_params, new_leaf)?;
+        self.update_leaf_digest(index, leaf_digest)
+    }
 
-    let pvk = ark_groth16::prepare_verifying_key(&vk);
+    /// Replace the digest of the leaf at `index` by `leaf_digest`, and rehash its ancestors.
+    pub fn update_leaf_digest(&mut self, index: usize, leaf_digest: Digest<P>) -> Result<(), Error> {
+        self.check_index(index)?;
+        self.levels[0][index] = leaf_digest;
+        let mut node_index = index;
+        for level in 1..self.levels.len() {
+            node_index /= P::ARITY;
+            let first_child = node_index * P::ARITY;
+            let parent = hash_children::<P>(
+                &self.inner_hash_params,
+                &self.levels[level - 1][first_child..first_child + P::ARITY],
+            )?;
+            self.levels[level][node_index] = parent;
+        }
+        Ok(())
+    }
+}
 
-    let public_input = tree.root().into_group().to_field_elements().unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constraints::{DigestVar, PathVar};
+    use crate::jubjub::{Inner4aryHash, LeafHash, JubJub4aryMerkleTreeParams as Arity4, JubJub4aryMerkleTreeParamsVar as Arity4Var};
+    use ark_crypto_primitives::crh::pedersen;
+    use ark_ed_on_bls12_381::{EdwardsProjective as JubJub, Fq};
+    use ark_r1cs_std::prelude::*;
+    use ark_relations::r1cs::ConstraintSystem;
+
+    #[test]
+    fn four_ary_paths_verify_natively_and_in_the_circuit() {
+        let mut rng = ark_std::test_rng();
+        let leaf_params = LeafHash::setup(&mut rng).unwrap();
+        let params = Inner4aryHash::setup(&mut rng).unwrap();
+        let leaves = (0..16u8).map(|i| vec![i; 8]).collect::<Vec<_>>();
+        let mut tree = MerkleTree::<Arity4>::new(&leaf_params, &params, leaves.clone()).unwrap();
+        assert_eq!(tree.depth(), 2);
+        assert!(MerkleTree::<Arity4>::new(&leaf_params, &params, leaves[..8].to_vec()).is_err());
+
+        tree.update(9, &[42u8; 8]).unwrap();
+        let root = tree.root();
+        for index in [0, 6, 9, 15] {
+            let leaf = if index == 9 { vec![42u8; 8] } else { leaves[index].clone() };
+            let path = tree.generate_proof(index).unwrap();
+            assert!(path.verify(&leaf_params, &params, &root, leaf.as_slice()).unwrap());
+            assert!(!path.verify(&leaf_params, &params, &root, [1u8; 8].as_slice()).unwrap());
+
+            let cs = ConstraintSystem::<Fq>::new_ref();
+            let leaf_params_var = pedersen::constraints::CRHParametersVar::<JubJub, _>::new_constant(cs.clone(), &leaf_params).unwrap();
+            let params_var = pedersen::constraints::CRHParametersVar::<JubJub, _>::new_constant(cs.clone(), &params).unwrap();
+            let root_var = DigestVar::<Arity4, Fq, Arity4Var>::new_input(cs.clone(), || Ok(root)).unwrap();
+            let leaf_var = UInt8::new_witness_vec(cs.clone(), &leaf).unwrap();
+            let path_var = PathVar::<Arity4, Fq, Arity4Var>::new_witness(cs.clone(), || Ok(&path)).unwrap();
+            assert_eq!(path_var.leaf_index_bits.len(), 4);
+            path_var
+                .verify_membership(&leaf_params_var, &params_var, &root_var, &leaf_var)
+                .unwrap()
+                .enforce_equal(&Boolean::TRUE)
+                .unwrap();
+            assert!(cs.is_satisfied().unwrap());
+        }
+    }
 
-    let groth_proof = ark_groth16::Groth16::<Bls12_381>::create_random_proof_with_reduction(circuit,&pk,&mut rng).unwrap();
-    
-    let a = ark_groth16::Groth16::<Bls12_381>::verify_proof(&pvk,&groth_proof,&public_input).unwrap();
-    println!("verification result: {}",a);
-    assert!(a);
-}
\ No newline at end of file
+    #[test]
+    fn path_gadget_updates_a_leaf_along_its_siblings() {
+        let mut rng = ark_std::test_rng();
+        let leaf_params = LeafHash::setup(&mut rng).unwrap();
+        let params = Inner4aryHash::setup(&mut rng).unwrap();
+        let leaves = (0..16u8).map(|i| vec![i; 8]).collect::<Vec<_>>();
+        let mut tree = MerkleTree::<Arity4>::new(&leaf_params, &params, leaves.clone()).unwrap();
+        let old_root = tree.root();
+        let path = tree.generate_proof(6).unwrap();
+        tree.update(6, &[42u8; 8]).unwrap();
+
+        for (old_leaf, is_member) in [(leaves[6].clone(), true), (vec![1u8; 8], false)] {
+            let cs = ConstraintSystem::<Fq>::new_ref();
+            let leaf_params_var = pedersen::constraints::CRHParametersVar::<JubJub, _>::new_constant(cs.clone(), &leaf_params).unwrap();
+            let params_var = pedersen::constraints::CRHParametersVar::<JubJub, _>::new_constant(cs.clone(), &params).unwrap();
+            let old_root_var = DigestVar::<Arity4, Fq, Arity4Var>::new_input(cs.clone(), || Ok(old_root)).unwrap();
+            let old_leaf_var = UInt8::new_witness_vec(cs.clone(), &old_leaf).unwrap();
+            let new_leaf_var = UInt8::new_witness_vec(cs.clone(), &[42u8; 8]).unwrap();
+            let path_var = PathVar::<Arity4, Fq, Arity4Var>::new_witness(cs.clone(), || Ok(&path)).unwrap();
+            let (was_member, new_root) = path_var
+                .update_leaf(&leaf_params_var, &params_var, &old_root_var, &old_leaf_var, &new_leaf_var)
+                .unwrap();
+            assert_eq!(was_member.value().unwrap(), is_member);
+            assert_eq!(new_root.value().unwrap(), tree.root());
+        }
+    }
+}
@@ -0,0 +1,311 @@
+//! Membership proofs for several leaves of one tree, which share the siblings
+//! that single paths would repeat. A sibling is only included if it is not
+//! itself the ancestor of a proven leaf, so a multiproof of leaves in the same
+//! subtree is much smaller than their paths.
+use crate::{hash_children, Config, Digest, InnerParam, LeafParam, MerkleTree};
+use ark_crypto_primitives::crh::CRHScheme;
+use ark_crypto_primitives::Error;
+use ark_std::borrow::Borrow;
+use derivative::Derivative;
+
+/// Where a child of an inner node of a multiproof comes from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Child {
+    /// The node at this index among the nodes known at the child's level.
+    Known(usize),
+    /// The next sibling of the multiproof.
+    Sibling,
+}
+
+/// For each level from the leaves up, for each inner node with a known child,
+/// where each of its children comes from. The nodes of a level are known in
+/// the order of their indices, starting with the leaves at `leaf_indices`.
+pub(crate) fn shape<P: Config>(leaf_indices: &[usize], depth: usize) -> Vec<Vec<Vec<Child>>> {
+    let mut indices = leaf_indices.to_vec();
+    let mut levels = Vec::with_capacity(depth);
+    for _ in 0..depth {
+        let mut parents = Vec::new();
+        let mut parent_indices = Vec::new();
+        let mut k = 0;
+        while k < indices.len() {
+            let parent = indices[k] / P::ARITY;
+            let children = (0..P::ARITY)
+                .map(|j| {
+                    if indices.get(k) == Some(&(parent * P::ARITY + j)) {
+                        k += 1;
+                        Child::Known(k - 1)
+                    } else {
+                        Child::Sibling
+                    }
+                })
+                .collect();
+            parents.push(children);
+            parent_indices.push(parent);
+        }
+        levels.push(parents);
+        indices = parent_indices;
+    }
+    levels
+}
+
+/// Whether `leaf_indices` are the increasing indices of at least one leaf of a
+/// tree of depth `depth`.
+pub(crate) fn indices_are_valid<P: Config>(leaf_indices: &[usize], depth: usize) -> bool {
+    leaf_indices.windows(2).all(|pair| pair[0] < pair[1])
+        && leaf_indices.last().is_some_and(|last| *last < P::ARITY.pow(depth as u32))
+}
+
+/// The number of siblings of a multiproof with this shape.
+pub(crate) fn num_siblings(shape: &[Vec<Vec<Child>>]) -> usize {
+    shape.iter().flatten().flatten().filter(|child| **child == Child::Sibling).count()
+}
+
+/// Hash the known `leaves` up to the root of a multiproof with this shape. The
+/// number of siblings has to match the shape, and there has to be a leaf.
+pub(crate) fn fold<T: Clone, E>(
+    shape: &[Vec<Vec<Child>>],
+    leaves: Vec<T>,
+    siblings: &[T],
+    mut hash: impl FnMut(&[T]) -> Result<T, E>,
+) -> Result<T, E> {
+    let mut siblings = siblings.iter();
+    let mut nodes = leaves;
+    for level in shape {
+        nodes = level
+            .iter()
+            .map(|children| {
+                let children = children
+                    .iter()
+                    .map(|child| match child {
+                        Child::Known(k) => nodes[*k].clone(),
+                        Child::Sibling => siblings.next().expect("the shape fixes the number of siblings").clone(),
+                    })
+                    .collect::<Vec<_>>();
+                hash(&children)
+            })
+            .collect::<Result<_, _>>()?;
+    }
+    Ok(nodes.swap_remove(0))
+}
+
+/// A membership proof for several leaves of a tree.
+#[derive(Derivative)]
+#[derivative(Clone(bound = "P: Config"), Debug(bound = "P: Config"), PartialEq(bound = "P: Config"))]
+pub struct MultiPath<P: Config> {
+    /// The indices of the leaves, in increasing order.
+    pub leaf_indices: Vec<usize>,
+    /// The number of levels above the leaves.
+    pub depth: usize,
+    /// The digests of the siblings that are not ancestors of any of the leaves,
+    /// level by level from the leaves up, and in the order of their indices
+    /// within a level.
+    pub siblings: Vec<Digest<P>>,
+}
+
+impl<P: Config> MultiPath<P> {
+    /// The root of the tree in which this multiproof leads to leaves with
+    /// digests `leaf_digests`, in the order of `self.leaf_indices`.
+    pub fn root_from_leaf_digests(
+        &self,
+        inner_hash_params: &InnerParam<P>,
+        leaf_digests: Vec<Digest<P>>,
+    ) -> Result<Digest<P>, Error> {
+        if !indices_are_valid::<P>(&self.leaf_indices, self.depth) {
+            return Err("the leaf indices have to be increasing and in range".into());
+        }
+        if leaf_digests.len() != self.leaf_indices.len() {
+            return Err("a multiproof needs one leaf per index".into());
+        }
+        let shape = shape::<P>(&self.leaf_indices, self.depth);
+        if self.siblings.len() != num_siblings(&shape) {
+            return Err("the multiproof has the wrong number of siblings".into());
+        }
+        fold(&shape, leaf_digests, &self.siblings, |children| {
+            hash_children::<P>(inner_hash_params, children)
+        })
+    }
+
+    /// The root of the tree in which this multiproof leads to `leaves`, in the
+    /// order of `self.leaf_indices`. Computing it for the updated leaves gives
+    /// the root of the tree after the update.
+    pub fn calculate_root<L: Borrow<[u8]>>(
+        &self,
+        leaf_hash_params: &LeafParam<P>,
+        inner_hash_params: &InnerParam<P>,
+        leaves: impl IntoIterator<Item = L>,
+    ) -> Result<Digest<P>, Error> {
+        let leaf_digests = leaves
+            .into_iter()
+            .map(|leaf| P::LeafHash::evaluate(leaf_hash_params, leaf.borrow()))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.root_from_leaf_digests(inner_hash_params, leaf_digests)
+    }
+
+    /// Check that `leaves` are at `self.leaf_indices` in the tree with root `root`.
+    pub fn verify<L: Borrow<[u8]>>(
+        &self,
+        leaf_hash_params: &LeafParam<P>,
+        inner_hash_params: &InnerParam<P>,
+        root: &Digest<P>,
+        leaves: impl IntoIterator<Item = L>,
+    ) -> Result<bool, Error> {
+        Ok(self.calculate_root(leaf_hash_params, inner_hash_params, leaves)? == *root)
+    }
+}
+
+impl<P: Config> MerkleTree<P> {
+    /// A membership proof for the leaves at `indices`, which are sorted and
+    /// deduplicated into the proof's `leaf_indices`.
+    pub fn generate_multiproof(&self, indices: &[usize]) -> Result<MultiPath<P>, Error> {
+        let mut leaf_indices = indices.to_vec();
+        leaf_indices.sort_unstable();
+        leaf_indices.dedup();
+        if leaf_indices.is_empty() {
+            return Err("a multiproof needs a leaf".into());
+        }
+        if leaf_indices.iter().any(|index| *index >= self.num_leaves()) {
+            return Err("leaf index out of range".into());
+        }
+        // Walk up the shape like `fold`, but collect the missing children
+        // instead of hashing.
+        let shape = shape::<P>(&leaf_indices, self.depth());
+        let mut siblings = Vec::new();
+        let mut indices = leaf_indices.clone();
+        for (level, parents) in shape.iter().enumerate() {
+            let mut parent_indices = Vec::with_capacity(parents.len());
+            for children in parents {
+                let parent = children
+                    .iter()
+                    .find_map(|child| match child {
+                        Child::Known(k) => Some(indices[*k] / P::ARITY),
+                        Child::Sibling => None,
+                    })
+                    .expect("every parent in the shape has a known child");
+                for (j, child) in children.iter().enumerate() {
+                    if *child == Child::Sibling {
+                        siblings.push(self.levels[level][parent * P::ARITY + j].clone());
+                    }
+                }
+                parent_indices.push(parent);
+            }
+            indices = parent_indices;
+        }
+        Ok(MultiPath {
+            leaf_indices,
+            depth: self.depth(),
+            siblings,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constraints::{DigestVar, MultiPathVar};
+    use crate::jubjub::*;
+    use ark_ed_on_bls12_381::Fq;
+    use ark_r1cs_std::prelude::*;
+    use ark_relations::r1cs::ConstraintSystem;
+
+    #[test]
+    fn multiproofs_share_siblings() {
+        let mut rng = ark_std::test_rng();
+        let leaf_crh_params = LeafHash::setup(&mut rng).unwrap();
+        let inner_crh_params = InnerHash::setup(&mut rng).unwrap();
+        let leaves = (0..16u8).map(|i| vec![i; 30]).collect::<Vec<_>>();
+        let mut tree = JubJubMerkleTree::new(&leaf_crh_params, &inner_crh_params, leaves.clone()).unwrap();
+
+        let multipath = tree.generate_multiproof(&[5, 4, 12, 5]).unwrap();
+        assert_eq!(multipath.leaf_indices, vec![4, 5, 12]);
+        // Leaf 13, then nodes 3 and 7 one level up, then nodes 0 and 2; 4 and 5
+        // are siblings, and the subtrees of 4 and 12 meet at the root.
+        assert_eq!(multipath.siblings.len(), 5);
+        let proven = [&leaves[4], &leaves[5], &leaves[12]].map(|leaf| leaf.as_slice());
+        assert!(multipath
+            .verify(&leaf_crh_params, &inner_crh_params, &tree.root(), proven)
+            .unwrap());
+        let wrong = [&leaves[4], &leaves[6], &leaves[12]].map(|leaf| leaf.as_slice());
+        assert!(!multipath
+            .verify(&leaf_crh_params, &inner_crh_params, &tree.root(), wrong)
+            .unwrap());
+        assert!(multipath
+            .verify(&leaf_crh_params, &inner_crh_params, &tree.root(), proven[..2].iter().copied())
+            .is_err());
+
+        // The root for the updated leaves is the root of the updated tree.
+        let updated = [[1u8; 30], [2u8; 30], [3u8; 30]];
+        let new_root = multipath
+            .calculate_root(&leaf_crh_params, &inner_crh_params, updated.iter().map(|leaf| leaf.as_slice()))
+            .unwrap();
+        for (index, leaf) in multipath.leaf_indices.iter().zip(&updated) {
+            tree.update(*index, leaf).unwrap();
+        }
+        assert_eq!(new_root, tree.root());
+
+        assert!(tree.generate_multiproof(&[]).is_err());
+        assert!(tree.generate_multiproof(&[16]).is_err());
+        assert!(tree.generate_multiproof(&(0..16).collect::<Vec<_>>()).unwrap().siblings.is_empty());
+    }
+
+    #[test]
+    fn multiproof_gadget_updates_leaves_in_one_pass() {
+        type RootVar = DigestVar<JubJubMerkleTreeParams, Fq, JubJubMerkleTreeParamsVar>;
+        type MultiPathJubJubVar = MultiPathVar<JubJubMerkleTreeParams, Fq, JubJubMerkleTreeParamsVar>;
+
+        let mut rng = ark_std::test_rng();
+        let leaf_crh_params = LeafHash::setup(&mut rng).unwrap();
+        let inner_crh_params = InnerHash::setup(&mut rng).unwrap();
+        let leaves = (0..16u8).map(|i| vec![i; 30]).collect::<Vec<_>>();
+        let mut tree = JubJubMerkleTree::new(&leaf_crh_params, &inner_crh_params, leaves.clone()).unwrap();
+        let indices = [4, 5, 6, 7];
+        let old_root = tree.root();
+        let multipath = tree.generate_multiproof(&indices).unwrap();
+        let paths = indices.map(|i| tree.generate_proof(i).unwrap());
+        let new_leaves = indices.map(|i| vec![i as u8 + 100; 30]);
+        for (i, leaf) in indices.iter().zip(&new_leaves) {
+            tree.update(*i, leaf).unwrap();
+        }
+
+        let update_with_multiproof = |old_leaves: &[Vec<u8>]| {
+            let cs = ConstraintSystem::<Fq>::new_ref();
+            let leaf_params = LeafHashParamsVar::new_constant(cs.clone(), &leaf_crh_params).unwrap();
+            let inner_params = InnerHashParamsVar::new_constant(cs.clone(), &inner_crh_params).unwrap();
+            let old_root_var = RootVar::new_input(cs.clone(), || Ok(old_root)).unwrap();
+            let multipath_var = MultiPathJubJubVar::new_witness(cs.clone(), || Ok(&multipath)).unwrap();
+            let old_leaves = old_leaves.iter().map(|leaf| UInt8::new_witness_vec(cs.clone(), leaf).unwrap()).collect::<Vec<_>>();
+            let new_leaves = new_leaves.iter().map(|leaf| UInt8::new_witness_vec(cs.clone(), leaf).unwrap()).collect::<Vec<_>>();
+            let start = cs.num_constraints();
+            let (old_leaves_hold, new_root) = multipath_var
+                .update_leaves(&leaf_params, &inner_params, &old_root_var, &old_leaves, &new_leaves)
+                .unwrap();
+            (old_leaves_hold.value().unwrap(), new_root.value().unwrap(), cs.num_constraints() - start)
+        };
+        let (holds, new_root, multiproof_constraints) = update_with_multiproof(&leaves[4..8]);
+        assert!(holds);
+        assert_eq!(new_root, tree.root());
+        let mut tampered = leaves[4..8].to_vec();
+        tampered[2][0] ^= 1;
+        assert!(!update_with_multiproof(&tampered).0);
+
+        // A multiproof needs one leaf per index.
+        let cs = ConstraintSystem::<Fq>::new_ref();
+        let leaf_params = LeafHashParamsVar::new_constant(cs.clone(), &leaf_crh_params).unwrap();
+        let inner_params = InnerHashParamsVar::new_constant(cs.clone(), &inner_crh_params).unwrap();
+        let multipath_var = MultiPathJubJubVar::new_witness(cs.clone(), || Ok(&multipath)).unwrap();
+        let too_few = leaves[4..7].iter().map(|leaf| UInt8::new_witness_vec(cs.clone(), leaf).unwrap()).collect::<Vec<_>>();
+        assert!(multipath_var.calculate_root(&leaf_params, &inner_params, &too_few).is_err());
+
+        // Checking every leaf with a pre-path and a post-path hashes the shared
+        // ancestors once per path.
+        let cs = ConstraintSystem::<Fq>::new_ref();
+        let leaf_params = LeafHashParamsVar::new_constant(cs.clone(), &leaf_crh_params).unwrap();
+        let inner_params = InnerHashParamsVar::new_constant(cs.clone(), &inner_crh_params).unwrap();
+        let start = cs.num_constraints();
+        for (path, leaf) in paths.iter().zip(&leaves[4..8]).chain(paths.iter().zip(&new_leaves)) {
+            let path_var = JubJubPathVar::new_witness(cs.clone(), || Ok(path)).unwrap();
+            let leaf = UInt8::new_witness_vec(cs.clone(), leaf).unwrap();
+            let _root = path_var.calculate_root(&leaf_params, &inner_params, &leaf).unwrap();
+        }
+        assert!(multiproof_constraints * 2 < cs.num_constraints() - start);
+    }
+}
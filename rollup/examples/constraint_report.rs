@@ -0,0 +1,28 @@
+//! Print the number of constraints in each namespace of the rollup circuit, for
+//! a batch of two transactions.
+//!
+//! Run `cargo run --release --example constraint_report [depth]` on two commits
+//! and `diff` the outputs to see where the circuit grew or shrank. `depth`
+//! limits how many namespace levels are reported; by default all are.
+use ark_rollup::rollup::Rollup;
+use ark_simple_payments_scp::account::TokenId;
+use ark_simple_payments_scp::ledger::{Amount, Parameters, State};
+use ark_simple_payments_scp::transaction::Transaction;
+
+fn main() {
+    let depth = std::env::args().nth(1).map(|depth| depth.parse().expect("depth should be a number"));
+
+    let mut rng = ark_std::test_rng();
+    let pp = Parameters::sample(&mut rng);
+    let mut state = State::new(32, &pp);
+    let (alice_id, alice_pk, alice_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+    state.update_balance(alice_id, TokenId(0), Amount(20)).unwrap();
+    let (bob_id, _, _) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+    let tx = Transaction::create(&pp, alice_id, bob_id, TokenId(0), Amount(5), &alice_sk, &alice_pk, &mut rng);
+    let rollup = Rollup::<2>::with_state_and_transactions(pp, &[tx.clone(), tx], &mut state, true).unwrap();
+
+    let report = rollup.constraint_report().expect("the circuit should synthesize");
+    let report = depth.map_or_else(|| report.clone(), |depth| report.truncated(depth));
+    print!("{report}");
+    println!("{} total", report.total());
+}
@@ -1,16 +1,44 @@
 use crate::ledger::*;
 use crate::ConstraintF;
 use ark_ed_on_bls12_381::{constraints::EdwardsVar, EdwardsProjective};
+use ark_crypto_primitives::crh::CRHSchemeGadget;
 use ark_r1cs_std::bits::{uint8::UInt8, ToBytesGadget};
+use ark_r1cs_std::fields::fp::FpVar;
 use ark_r1cs_std::prelude::*;
 use ark_relations::r1cs::{Namespace, SynthesisError};
 use ark_simple_payments_scp::account::*;
+use ark_simple_payments_scp::signature::eddsa::constraints::SignatureVar as EdDSASignatureVar;
 use ark_simple_payments_scp::signature::schnorr::constraints::*;
 use std::borrow::Borrow;
+use std::cmp::Ordering;
 
 /// Account public key used to verify transaction signatures.
 pub type AccountPublicKeyVar = PublicKeyVar<EdwardsProjective, EdwardsVar>;
 
+/// A signature by an account key; see `AccountSignature`.
+pub enum AccountSignatureVar {
+    Schnorr(SignatureVar<EdwardsProjective, EdwardsVar>),
+    EdDSA(EdDSASignatureVar),
+}
+
+impl AllocVar<AccountSignature, ConstraintF> for AccountSignatureVar {
+    #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
+    fn new_variable<T: Borrow<AccountSignature>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        f().and_then(|signature| match signature.borrow() {
+            AccountSignature::Schnorr(signature) => {
+                SignatureVar::new_variable(cs, || Ok(signature), mode).map(Self::Schnorr)
+            }
+            AccountSignature::EdDSA(signature) => {
+                EdDSASignatureVar::new_variable(cs, || Ok(signature), mode).map(Self::EdDSA)
+            }
+        })
+    }
+}
+
 /// Account identifier. This prototype supports only 256 accounts at a time.
 #[derive(Clone, Debug)]
 pub struct AccountIdVar(pub UInt8<ConstraintF>);
@@ -21,6 +49,18 @@ impl AccountIdVar {
     pub fn to_bytes_le(&self) -> Vec<UInt8<ConstraintF>> {
         vec![self.0.clone()]
     }
+
+    /// The little-endian bits of the index of the leaf holding this account's
+    /// balance of `token`, mirroring `AccountId::leaf_index`.
+    #[tracing::instrument(target = "r1cs", skip(self, token))]
+    pub fn leaf_index_bits_le(
+        &self,
+        token: &TokenIdVar,
+    ) -> Result<Vec<Boolean<ConstraintF>>, SynthesisError> {
+        let mut bits = token.0.to_bits_le()?[..TOKEN_ID_BITS].to_vec();
+        bits.extend(self.0.to_bits_le()?);
+        Ok(bits)
+    }
 }
 
 impl AllocVar<AccountId, ConstraintF> for AccountIdVar {
@@ -34,25 +74,59 @@ impl AllocVar<AccountId, ConstraintF> for AccountIdVar {
     }
 }
 
-/// Information about the account, such as the balance and the associated public key.
+/// Token identifier.
+#[derive(Clone, Debug)]
+pub struct TokenIdVar(pub UInt8<ConstraintF>);
+
+impl TokenIdVar {
+    /// Convert the token identifier to bytes.
+    #[tracing::instrument(target = "r1cs", skip(self))]
+    pub fn to_bytes_le(&self) -> Vec<UInt8<ConstraintF>> {
+        vec![self.0.clone()]
+    }
+
+    /// Whether this is one of the `NUM_TOKENS` tokens supported by the ledger,
+    /// i.e. whether all bits above the low `TOKEN_ID_BITS` are zero.
+    #[tracing::instrument(target = "r1cs", skip(self))]
+    pub fn is_supported(&self) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        Ok(Boolean::kary_or(&self.0.to_bits_le()?[TOKEN_ID_BITS..])?.not())
+    }
+}
+
+impl AllocVar<TokenId, ConstraintF> for TokenIdVar {
+    #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
+    fn new_variable<T: Borrow<TokenId>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        UInt8::new_variable(cs, || f().map(|u| u.borrow().0), mode).map(Self)
+    }
+}
+
+/// Information stored in an `(account, token)` leaf: the public key and the
+/// balance of that token, or a commitment to it; see `AccountInformation`.
 #[derive(Clone)]
 pub struct AccountInformationVar {
     /// The account public key.
     pub public_key: AccountPublicKeyVar,
-    /// The balance associated with this this account.
+    /// The balance of the leaf's token held by this account.
     pub balance: AmountVar,
+    /// The commitment to `balance` that the leaf holds instead, if the ledger
+    /// has confidential amounts.
+    pub balance_commitment: Option<AmountCommitmentVar>,
 }
 
 impl AccountInformationVar {
-    /// Convert the account information to bytes.
+    /// Convert the account information to bytes, matching `AccountInformation::to_bytes_le`.
     #[tracing::instrument(target = "r1cs", skip(self))]
     pub fn to_bytes_le(&self) -> Vec<UInt8<ConstraintF>> {
-        self.public_key
-            .to_bytes()
-            .unwrap()
-            .into_iter()
-            .chain(self.balance.to_bytes_le())
-            .collect()
+        let mut bytes = self.public_key.to_bytes().unwrap();
+        match &self.balance_commitment {
+            Some(balance_commitment) => bytes.extend(balance_commitment.commitment.to_bytes().unwrap()),
+            None => bytes.extend(self.balance.to_bytes_le()),
+        }
+        bytes
     }
 }
 
@@ -69,10 +143,90 @@ impl AllocVar<AccountInformation, ConstraintF> for AccountInformationVar {
             let public_key =
                 AccountPublicKeyVar::new_variable(cs.clone(), || Ok(&info.public_key), mode)?;
             let balance = AmountVar::new_variable(cs.clone(), || Ok(&info.balance), mode)?;
+            let balance_commitment = info
+                .balance_commitment
+                .map(|c| AmountCommitmentVar::new_variable(cs.clone(), || Ok(c), mode))
+                .transpose()?;
             Ok(Self {
                 public_key,
                 balance,
+                balance_commitment,
+            })
+        })
+    }
+}
+
+/// The k-of-n policy controlling a multisig account; see `MultisigPolicy`.
+/// Unused key slots hold the identity.
+pub struct MultisigPolicyVar {
+    /// The number of signatures required to authorize a transfer.
+    pub threshold: UInt8<ConstraintF>,
+    /// The `MAX_MULTISIG_KEYS` key slots.
+    pub public_keys: Vec<AccountPublicKeyVar>,
+}
+
+impl MultisigPolicyVar {
+    /// Convert the policy to bytes, matching `MultisigPolicy::to_bytes_le`.
+    #[tracing::instrument(target = "r1cs", skip(self))]
+    pub fn to_bytes_le(&self) -> Result<Vec<UInt8<ConstraintF>>, SynthesisError> {
+        let mut bytes = vec![self.threshold.clone()];
+        for public_key in &self.public_keys {
+            bytes.extend(public_key.to_bytes()?);
+        }
+        Ok(bytes)
+    }
+
+    /// The key that an account controlled by this policy is registered under.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters))]
+    pub fn account_key(&self, parameters: &crate::ledger::ParametersVar) -> Result<EdwardsVar, SynthesisError> {
+        PolicyHashGadget::evaluate(&parameters.policy_crh_params, &self.to_bytes_le()?)
+    }
+
+    /// Whether at least `threshold` of the used key slots validly signed
+    /// `message`, where `signatures[i]` is the signature of slot `i`.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, message, signatures))]
+    pub fn threshold_met(
+        &self,
+        parameters: &crate::ledger::ParametersVar,
+        message: &[UInt8<ConstraintF>],
+        signatures: &[AccountSignatureVar],
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        let mut count = FpVar::zero();
+        for (public_key, signature) in self.public_keys.iter().zip(signatures) {
+            // Anyone can sign for the identity, so unused slots must not count.
+            let slot_used = public_key.pub_key.is_zero()?.not();
+            let verifies = parameters.verify_signature(public_key, message, signature)?;
+            count += FpVar::from(slot_used.and(&verifies)?);
+        }
+        // Both sides are at most `MAX_MULTISIG_KEYS`, far below the field's
+        // midpoint, as `is_cmp` requires.
+        let threshold = Boolean::le_bits_to_fp_var(&self.threshold.to_bits_le()?)?;
+        count.is_cmp(&threshold, Ordering::Greater, true)
+    }
+}
+
+impl AllocVar<MultisigPolicy, ConstraintF> for MultisigPolicyVar {
+    #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
+    fn new_variable<T: Borrow<MultisigPolicy>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        f().and_then(|policy| {
+            let policy = policy.borrow();
+            let cs = cs.into();
+            let threshold = UInt8::new_variable(cs.clone(), || Ok(policy.threshold), mode)?;
+            let padding = AccountPublicKey::zero();
+            let public_keys = (0..MAX_MULTISIG_KEYS)
+                .map(|i| {
+                    let public_key = policy.public_keys.get(i).unwrap_or(&padding);
+                    AccountPublicKeyVar::new_variable(cs.clone(), || Ok(public_key), mode)
+                })
+                .collect::<Result<_, _>>()?;
+            Ok(Self {
+                threshold,
+                public_keys,
             })
         })
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,460 @@
+//! A model of the L1 side of the rollup, for testing without a chain. The
+//! `L1Bridge` stores the current rollup root, accepts each batch whose proof
+//! verifies under the `Rollup` verifying key, and keeps the deposit and
+//! withdrawal queues.
+//!
+//! Each submission publishes the encoded state diffs of its batch, which the
+//! proof commits to, so that anyone can follow the state; see `da`.
+//!
+//! Deposits and withdrawals are transfers on L2 from and to a bridge account
+//! controlled by the operator; see `Rollup::with_bridge_account`. The circuit
+//! chains the transfers from the bridge account and those to it into two more
+//! public inputs, so a batch proves which pending deposits it credits and
+//! which withdrawals it makes. A withdrawal pays out to the L1 destination
+//! that the sender signed with the transfer. Swaps sign no destination, so
+//! the circuit rejects swaps with the bridge account.
+use crate::account::{AccountIdVar, TokenIdVar};
+use crate::da::{self as da_gadget, DiffCommitmentVar};
+use crate::exit::ExitQueue;
+use crate::ledger::{AmountVar, ParametersVar};
+use crate::ConstraintF;
+use ark_bls12_381::Bls12_381;
+use ark_crypto_primitives::Error;
+use ark_ec::AffineRepr;
+use ark_groth16::{Groth16, Proof, VerifyingKey};
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{SynthesisError, ToConstraintField};
+use ark_simple_payments_scp::account::{AccountId, TokenId};
+use ark_simple_payments_scp::da::{self, DiffCommitment};
+use ark_simple_payments_scp::genesis::Genesis;
+use ark_simple_payments_scp::ledger::{AccRoot, Amount, Parameters};
+use ark_simple_payments_scp::transaction::L1Address;
+use ark_snark::SNARK;
+use std::collections::VecDeque;
+
+/// Funds locked on L1, to be credited to `account` on L2.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Deposit {
+    pub account: AccountId,
+    pub token: TokenId,
+    pub amount: Amount,
+}
+
+impl Deposit {
+    /// Convert the deposit to bytes: the account, the token and the amount.
+    pub fn to_bytes_le(&self) -> Vec<u8> {
+        transfer_bytes(self.account, self.token, self.amount)
+    }
+}
+
+/// Funds that `account` sent to the bridge account on L2, to be paid out to
+/// `destination` on L1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Withdrawal {
+    pub account: AccountId,
+    pub token: TokenId,
+    pub amount: Amount,
+    pub destination: L1Address,
+}
+
+impl Withdrawal {
+    /// Convert the withdrawal to bytes: the account, the token, the amount and
+    /// the destination.
+    pub fn to_bytes_le(&self) -> Vec<u8> {
+        let mut bytes = transfer_bytes(self.account, self.token, self.amount);
+        bytes.extend(self.destination);
+        bytes
+    }
+}
+
+fn transfer_bytes(account: AccountId, token: TokenId, amount: Amount) -> Vec<u8> {
+    let mut bytes = account.to_bytes_le();
+    bytes.extend(token.to_bytes_le());
+    bytes.extend(amount.to_bytes_le());
+    bytes
+}
+
+/// The deposits that a batch credits and the withdrawals that it makes, in the
+/// order of its transfers; see `Rollup::bridge_transfers`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BridgeTransfers {
+    pub deposits: Vec<Deposit>,
+    pub withdrawals: Vec<Withdrawal>,
+}
+
+impl BridgeTransfers {
+    /// Record the transfer of `amount` of `token` from `sender` to `recipient`,
+    /// signed with `destination`: a transfer from `bridge_account` credits a
+    /// deposit, and a transfer to it makes a withdrawal to `destination`. A
+    /// transfer from the bridge account to itself is neither.
+    pub fn record(
+        &mut self,
+        bridge_account: AccountId,
+        sender: AccountId,
+        recipient: AccountId,
+        token: TokenId,
+        amount: Amount,
+        destination: L1Address,
+    ) {
+        match (sender == bridge_account, recipient == bridge_account) {
+            (true, false) => self.deposits.push(Deposit { account: recipient, token, amount }),
+            (false, true) => self.withdrawals.push(Withdrawal { account: sender, token, amount, destination }),
+            _ => {}
+        }
+    }
+
+    /// The commitments to the deposits and to the withdrawals, which are public
+    /// inputs of the `Rollup` circuit.
+    pub fn commitments(&self, parameters: &Parameters) -> (DiffCommitment, DiffCommitment) {
+        (
+            commit(parameters, self.deposits.iter().map(Deposit::to_bytes_le)),
+            commit(parameters, self.withdrawals.iter().map(Withdrawal::to_bytes_le)),
+        )
+    }
+}
+
+/// Chain the encoded deposits or withdrawals `transfers` with `da::chain`,
+/// starting from the identity. All deposits have the same width, and so do all
+/// withdrawals, so distinct lists hash distinct inputs.
+pub fn commit(parameters: &Parameters, transfers: impl IntoIterator<Item = Vec<u8>>) -> DiffCommitment {
+    transfers
+        .into_iter()
+        .fold(DiffCommitment::default(), |commitment, bytes| da::chain(parameters, &commitment, &bytes))
+}
+
+/// The in-circuit counterpart of `BridgeTransfers`, chaining the transfers
+/// from and to the bridge account as the circuit checks them. Whether a
+/// transfer involves the bridge account depends on the witnessed accounts, so
+/// every transfer is hashed into both chains and kept by selection. Without a
+/// bridge account, nothing is recorded and both commitments are the identity.
+pub struct BridgeTransfersVar {
+    bridge_account: Option<UInt8<ConstraintF>>,
+    pub deposits: DiffCommitmentVar,
+    pub withdrawals: DiffCommitmentVar,
+}
+
+impl BridgeTransfersVar {
+    /// Start recording the transfers from and to `bridge_account`, with both
+    /// chains at the identity. The bridge account is a constant of the circuit.
+    pub fn new(bridge_account: Option<AccountId>) -> Self {
+        Self {
+            bridge_account: bridge_account.map(|account| UInt8::constant(account.0)),
+            deposits: DiffCommitmentVar::constant(DiffCommitment::default().into()),
+            withdrawals: DiffCommitmentVar::constant(DiffCommitment::default().into()),
+        }
+    }
+
+    /// Record a transfer that the circuit checks, like `BridgeTransfers::record`.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, sender, recipient, token, amount, destination))]
+    pub fn record(
+        &mut self,
+        parameters: &ParametersVar,
+        sender: &AccountIdVar,
+        recipient: &AccountIdVar,
+        token: &TokenIdVar,
+        amount: &AmountVar,
+        destination: &[UInt8<ConstraintF>],
+    ) -> Result<(), SynthesisError> {
+        let Some(bridge_account) = &self.bridge_account else {
+            return Ok(());
+        };
+        let bytes = |account: &AccountIdVar| {
+            let mut bytes = account.to_bytes_le();
+            bytes.extend(token.to_bytes_le());
+            bytes.extend(amount.to_bytes_le());
+            bytes
+        };
+        let from_bridge = sender.0.is_eq(bridge_account)?;
+        let to_bridge = recipient.0.is_eq(bridge_account)?;
+        let is_deposit = from_bridge.and(&to_bridge.not())?;
+        let credited = da_gadget::chain(parameters, &self.deposits, &bytes(recipient))?;
+        self.deposits = is_deposit.select(&credited, &self.deposits)?;
+        let is_withdrawal = to_bridge.and(&from_bridge.not())?;
+        let mut withdrawal = bytes(sender);
+        withdrawal.extend_from_slice(destination);
+        let withdrawn = da_gadget::chain(parameters, &self.withdrawals, &withdrawal)?;
+        self.withdrawals = is_withdrawal.select(&withdrawn, &self.withdrawals)?;
+        Ok(())
+    }
+
+    /// Whether `account` is not the bridge account. Swaps have to exclude the
+    /// bridge account, since their parties sign no destination for a withdrawal.
+    pub fn excludes(&self, account: &AccountIdVar) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        match &self.bridge_account {
+            Some(bridge_account) => Ok(account.0.is_eq(bridge_account)?.not()),
+            None => Ok(Boolean::TRUE),
+        }
+    }
+}
+
+/// A batch that the operator submits to L1.
+#[derive(Clone, Debug)]
+pub struct BatchSubmission {
+    /// The number of the batch. The first batch after genesis is batch 1.
+    pub batch: usize,
+    /// The root that the batch starts from.
+    pub initial_root: AccRoot,
+    /// The root that the batch ends in.
+    pub final_root: AccRoot,
+    /// The state diffs of the batch, as encoded by `da::encode`.
+    pub state_diffs: Vec<u8>,
+    /// The proof of the `Rollup` circuit for the batch.
+    pub proof: Proof<Bls12_381>,
+    /// The number of pending deposits, oldest first, that the batch credits.
+    pub num_deposits: usize,
+    /// The withdrawals that the batch makes.
+    pub withdrawals: Vec<Withdrawal>,
+}
+
+impl BatchSubmission {
+    /// The public inputs of the `Rollup` circuit, if the batch credits
+    /// `deposits`: the coordinates of the initial root, of the final root, of
+    /// the commitment to the state diffs, and of the commitments to the
+    /// deposits and to the withdrawals. Returns `None` if the state diffs do
+    /// not decode.
+    pub fn public_inputs(&self, parameters: &Parameters, deposits: &[Deposit]) -> Option<Vec<ConstraintF>> {
+        let diff_commitment = da::commit(parameters, &da::decode(&self.state_diffs)?);
+        let transfers = BridgeTransfers {
+            deposits: deposits.to_vec(),
+            withdrawals: self.withdrawals.clone(),
+        };
+        let (deposit_commitment, withdrawal_commitment) = transfers.commitments(parameters);
+        let mut inputs = vec![self.initial_root.x, self.initial_root.y, self.final_root.x, self.final_root.y];
+        for commitment in [diff_commitment, deposit_commitment, withdrawal_commitment] {
+            inputs.extend(commitment.into_group().to_field_elements()?);
+        }
+        Some(inputs)
+    }
+}
+
+/// Why `L1Bridge::submit` rejects a batch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SubmissionRejection {
+    /// The batch has been accepted before.
+    Stale,
+    /// The batch skips batches that have not been accepted yet.
+    OutOfOrder,
+    /// The batch does not start from the current root.
+    WrongInitialRoot,
+    /// The batch credits more deposits than are pending.
+    UnknownDeposits,
+    /// The state diffs are not a valid encoding.
+    MalformedStateDiffs,
+    /// The proof does not prove the batch.
+    InvalidProof,
+}
+
+/// The L1 contract of the rollup.
+pub struct L1Bridge {
+    ledger_params: Parameters,
+    verifying_key: VerifyingKey<Bls12_381>,
+    root: AccRoot,
+    latest_batch: usize,
+    /// Deposits that no batch has credited yet, oldest first.
+    deposits: VecDeque<Deposit>,
+    /// Withdrawals that have not been paid out yet, oldest first.
+    withdrawals: VecDeque<Withdrawal>,
+}
+
+impl L1Bridge {
+    /// A bridge for batches of the ledger with `ledger_params`, proven with the
+    /// `Rollup` circuit's `verifying_key`, starting from `genesis_root`.
+    pub fn new(ledger_params: Parameters, verifying_key: VerifyingKey<Bls12_381>, genesis_root: AccRoot) -> Self {
+        Self {
+            ledger_params,
+            verifying_key,
+            root: genesis_root,
+            latest_batch: 0,
+            deposits: VecDeque::new(),
+            withdrawals: VecDeque::new(),
+        }
+    }
+
+    /// A bridge starting from the root of `genesis`, which the first batch has
+    /// to start from. Fails if `genesis` does not describe a valid ledger.
+    pub fn from_genesis(
+        ledger_params: Parameters,
+        verifying_key: VerifyingKey<Bls12_381>,
+        genesis: &Genesis,
+    ) -> Result<Self, Error> {
+        let genesis_root = genesis.root(&ledger_params)?;
+        Ok(Self::new(ledger_params, verifying_key, genesis_root))
+    }
+
+    /// The root after the latest accepted batch.
+    pub fn root(&self) -> AccRoot {
+        self.root
+    }
+
+    /// The number of the latest accepted batch, or 0 before the first batch.
+    pub fn latest_batch(&self) -> usize {
+        self.latest_batch
+    }
+
+    /// The deposits that no batch has credited yet, oldest first.
+    pub fn pending_deposits(&self) -> &VecDeque<Deposit> {
+        &self.deposits
+    }
+
+    /// Lock the funds of `deposit` until a batch credits them.
+    pub fn deposit(&mut self, deposit: Deposit) {
+        self.deposits.push_back(deposit);
+    }
+
+    /// Accept `submission` as the next batch if its proof verifies for the
+    /// oldest `num_deposits` pending deposits and its withdrawals, dequeue
+    /// those deposits, and queue the withdrawals for payout.
+    pub fn submit(&mut self, submission: BatchSubmission) -> Result<(), SubmissionRejection> {
+        if submission.batch <= self.latest_batch {
+            return Err(SubmissionRejection::Stale);
+        }
+        if submission.batch > self.latest_batch + 1 {
+            return Err(SubmissionRejection::OutOfOrder);
+        }
+        if submission.initial_root != self.root {
+            return Err(SubmissionRejection::WrongInitialRoot);
+        }
+        if submission.num_deposits > self.deposits.len() {
+            return Err(SubmissionRejection::UnknownDeposits);
+        }
+        let deposits = self.deposits.range(..submission.num_deposits).copied().collect::<Vec<_>>();
+        let public_inputs = submission
+            .public_inputs(&self.ledger_params, &deposits)
+            .ok_or(SubmissionRejection::MalformedStateDiffs)?;
+        let verifies = Groth16::<Bls12_381>::verify(&self.verifying_key, &public_inputs, &submission.proof);
+        if !verifies.unwrap_or(false) {
+            return Err(SubmissionRejection::InvalidProof);
+        }
+        self.deposits.drain(..submission.num_deposits);
+        self.withdrawals.extend(submission.withdrawals);
+        self.root = submission.final_root;
+        self.latest_batch = submission.batch;
+        Ok(())
+    }
+
+    /// Pay out the oldest queued withdrawal, if any.
+    pub fn pay_next_withdrawal(&mut self) -> Option<Withdrawal> {
+        self.withdrawals.pop_front()
+    }
+
+    /// Stop accepting batches once the operator has disappeared, and open the
+    /// escape hatch for the current root, with the exit circuit's
+    /// `exit_verifying_key`. Returns the exit queue and the deposits that were
+    /// never credited, which are refunded.
+    pub fn freeze(self, exit_verifying_key: VerifyingKey<Bls12_381>) -> (ExitQueue, Vec<Deposit>) {
+        (ExitQueue::new(exit_verifying_key, self.root), self.deposits.into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rollup::Rollup;
+    use ark_simple_payments_scp::ledger::{Parameters, State};
+    use ark_simple_payments_scp::transaction::Transaction;
+    use ark_std::rand::{rngs::StdRng, RngCore, SeedableRng};
+
+    #[test]
+    fn bridge_snark_accepts_batches_in_order() {
+        let mut rng = StdRng::seed_from_u64(ark_std::test_rng().next_u64());
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (bridge_id, bridge_pk, bridge_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(bridge_id, TokenId(0), Amount(1000)).unwrap();
+        let (alice_id, alice_pk, alice_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let token = TokenId(0);
+
+        let setup_tx = Transaction::create(&pp, bridge_id, alice_id, token, Amount(1), &bridge_sk, &bridge_pk, &mut rng);
+        let setup_circuit = Rollup::<1>::with_state_and_transactions(pp.clone(), &[setup_tx], &mut state.clone(), true)
+            .unwrap()
+            .with_bridge_account(bridge_id);
+        let (pk, vk) = Groth16::<Bls12_381>::circuit_specific_setup(setup_circuit, &mut rng).unwrap();
+        let genesis = state.clone();
+        let mut bridge = L1Bridge::new(pp.clone(), vk, state.root());
+
+        // Batch 1 credits Alice's deposit from the bridge account.
+        let deposit = Deposit { account: alice_id, token, amount: Amount(10) };
+        bridge.deposit(deposit);
+        let credit = Transaction::create(&pp, bridge_id, alice_id, token, deposit.amount, &bridge_sk, &bridge_pk, &mut rng);
+        let rollup = Rollup::<1>::with_state_and_transactions(pp.clone(), &[credit], &mut state, true)
+            .unwrap()
+            .with_bridge_account(bridge_id);
+        assert_eq!(rollup.bridge_transfers().deposits, vec![deposit]);
+        let batch_1 = BatchSubmission {
+            batch: 1,
+            initial_root: rollup.initial_root.unwrap(),
+            final_root: rollup.final_root.unwrap(),
+            state_diffs: da::encode(rollup.state_diffs.as_ref().unwrap()),
+            proof: Groth16::<Bls12_381>::prove(&pk, rollup, &mut rng).unwrap(),
+            num_deposits: 1,
+            withdrawals: Vec::new(),
+        };
+        let too_many_deposits = BatchSubmission { num_deposits: 2, ..batch_1.clone() };
+        assert_eq!(bridge.submit(too_many_deposits), Err(SubmissionRejection::UnknownDeposits));
+        let uncredited = BatchSubmission { num_deposits: 0, ..batch_1.clone() };
+        assert_eq!(bridge.submit(uncredited), Err(SubmissionRejection::InvalidProof));
+        let skipping = BatchSubmission { batch: 2, ..batch_1.clone() };
+        assert_eq!(bridge.submit(skipping), Err(SubmissionRejection::OutOfOrder));
+        assert_eq!(bridge.submit(batch_1.clone()), Ok(()));
+        assert!(bridge.pending_deposits().is_empty());
+        assert_eq!(bridge.submit(batch_1.clone()), Err(SubmissionRejection::Stale));
+
+        // Batch 2 withdraws 4 units that Alice sent to the bridge account.
+        let withdrawal = Withdrawal { account: alice_id, token, amount: Amount(4), destination: [7u8; 20] };
+        let debit = Transaction::create_withdrawal(
+            &pp,
+            alice_id,
+            bridge_id,
+            token,
+            withdrawal.amount,
+            withdrawal.destination,
+            &alice_sk,
+            &alice_pk,
+            &mut rng,
+        );
+        let rollup = Rollup::<1>::with_state_and_transactions(pp.clone(), &[debit], &mut state, true)
+            .unwrap()
+            .with_bridge_account(bridge_id);
+        let batch_2 = BatchSubmission {
+            batch: 2,
+            initial_root: rollup.initial_root.unwrap(),
+            final_root: rollup.final_root.unwrap(),
+            state_diffs: da::encode(rollup.state_diffs.as_ref().unwrap()),
+            proof: Groth16::<Bls12_381>::prove(&pk, rollup, &mut rng).unwrap(),
+            num_deposits: 0,
+            withdrawals: vec![withdrawal],
+        };
+        let wrong_start = BatchSubmission { batch: 2, ..batch_1.clone() };
+        assert_eq!(bridge.submit(wrong_start), Err(SubmissionRejection::WrongInitialRoot));
+        let forged = BatchSubmission { proof: batch_1.proof.clone(), ..batch_2.clone() };
+        assert_eq!(bridge.submit(forged), Err(SubmissionRejection::InvalidProof));
+        let withheld = BatchSubmission { state_diffs: batch_1.state_diffs.clone(), ..batch_2.clone() };
+        assert_eq!(bridge.submit(withheld), Err(SubmissionRejection::InvalidProof));
+        let truncated = BatchSubmission { state_diffs: vec![2], ..batch_2.clone() };
+        assert_eq!(bridge.submit(truncated), Err(SubmissionRejection::MalformedStateDiffs));
+        // The proof fixes the withdrawals and their signed destinations, so the
+        // operator cannot forge them.
+        let inflated = Withdrawal { amount: Amount(400), ..withdrawal };
+        let redirected = Withdrawal { destination: [8u8; 20], ..withdrawal };
+        let forged_withdrawals = [vec![inflated], vec![redirected], vec![withdrawal, withdrawal], Vec::new()];
+        for withdrawals in forged_withdrawals {
+            let forged = BatchSubmission { withdrawals, ..batch_2.clone() };
+            assert_eq!(bridge.submit(forged), Err(SubmissionRejection::InvalidProof));
+        }
+        assert_eq!(bridge.submit(batch_2.clone()), Ok(()));
+        assert_eq!(bridge.latest_batch(), 2);
+        assert_eq!(bridge.root(), state.root());
+        let published = [&batch_1.state_diffs, &batch_2.state_diffs].map(Vec::as_slice);
+        assert_eq!(da::replay(&genesis, published).unwrap().root(), state.root());
+        assert_eq!(bridge.pay_next_withdrawal(), Some(withdrawal));
+        assert_eq!(bridge.pay_next_withdrawal(), None);
+
+        // Once the operator disappears, uncredited deposits are refunded.
+        let late_deposit = Deposit { account: alice_id, token, amount: Amount(3) };
+        bridge.deposit(late_deposit);
+        // The exit circuit is tested in `exit`, so any verifying key will do.
+        let (exit_queue, refunds) = bridge.freeze(VerifyingKey::default());
+        assert_eq!(exit_queue.frozen_root(), state.root());
+        assert_eq!(refunds, vec![late_deposit]);
+    }
+}
@@ -0,0 +1,111 @@
+//! The in-circuit counterpart of `ark_simple_payments_scp::da`: the diffs that
+//! a batch publishes, and the commitment to the last write of each leaf.
+use crate::account::{AccountIdVar, AccountPublicKeyVar, TokenIdVar};
+use crate::ledger::{AmountVar, DiffHashGadget, ParametersVar};
+use crate::ConstraintF;
+use ark_crypto_primitives::crh::CRHSchemeGadget;
+use ark_ed_on_bls12_381::constraints::EdwardsVar;
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::SynthesisError;
+use ark_simple_payments_scp::da::DiffCommitment;
+
+/// The commitment to the diffs of a batch; see `DiffCommitment`.
+pub type DiffCommitmentVar = EdwardsVar;
+
+/// A leaf changed by a batch; see `StateDiff`.
+#[allow(clippy::large_enum_variant)]
+pub enum StateDiffVar {
+    Balance {
+        account: AccountIdVar,
+        token: TokenIdVar,
+        balance: AmountVar,
+    },
+    Key {
+        account: AccountIdVar,
+        public_key: AccountPublicKeyVar,
+    },
+    BalanceCommitment {
+        account: AccountIdVar,
+        token: TokenIdVar,
+        commitment: EdwardsVar,
+    },
+}
+
+impl StateDiffVar {
+    /// Convert the diff to bytes, matching `StateDiff::to_bytes_le`.
+    #[tracing::instrument(target = "r1cs", skip(self))]
+    pub fn to_bytes_le(&self) -> Result<Vec<UInt8<ConstraintF>>, SynthesisError> {
+        match self {
+            StateDiffVar::Balance { account, token, balance } => {
+                let mut bytes = vec![UInt8::constant(0)];
+                bytes.extend(account.to_bytes_le());
+                bytes.extend(token.to_bytes_le());
+                bytes.extend(balance.to_bytes_le());
+                Ok(bytes)
+            }
+            StateDiffVar::Key { account, public_key } => {
+                let mut bytes = vec![UInt8::constant(1)];
+                bytes.extend(account.to_bytes_le());
+                bytes.extend(public_key.to_bytes()?);
+                Ok(bytes)
+            }
+            StateDiffVar::BalanceCommitment { account, token, commitment } => {
+                let mut bytes = vec![UInt8::constant(2)];
+                bytes.extend(account.to_bytes_le());
+                bytes.extend(token.to_bytes_le());
+                bytes.extend(commitment.to_bytes()?);
+                Ok(bytes)
+            }
+        }
+    }
+
+    /// What the diff writes, packed into a field element: the `(account, token)`
+    /// leaf for balance and balance commitment diffs, which overwrite each
+    /// other, and the account for key diffs. Two diffs write the same thing iff
+    /// `StateDiff::is_overwritten_by` holds between them.
+    #[tracing::instrument(target = "r1cs", skip(self))]
+    fn target(&self) -> Result<FpVar<ConstraintF>, SynthesisError> {
+        let (is_key, token, account) = match self {
+            StateDiffVar::Balance { account, token, .. } | StateDiffVar::BalanceCommitment { account, token, .. } => {
+                (Boolean::FALSE, token.0.to_bits_le()?, account)
+            }
+            StateDiffVar::Key { account, .. } => (Boolean::TRUE, vec![Boolean::FALSE; 8], account),
+        };
+        let mut bits = vec![is_key];
+        bits.extend(token);
+        bits.extend(account.0.to_bits_le()?);
+        Boolean::le_bits_to_fp_var(&bits)
+    }
+}
+
+/// Commit to the diffs of `diffs` that no later diff overwrites, in order,
+/// mirroring `da::commit` of `da::last_writes`. Which diffs are overwritten
+/// depends on the witnessed accounts and tokens, so every diff is hashed and
+/// the overwritten ones are skipped by selection.
+#[tracing::instrument(target = "r1cs", skip(parameters, diffs))]
+pub fn commit(parameters: &ParametersVar, diffs: &[StateDiffVar]) -> Result<DiffCommitmentVar, SynthesisError> {
+    let targets = diffs.iter().map(StateDiffVar::target).collect::<Result<Vec<_>, _>>()?;
+    let mut commitment = DiffCommitmentVar::constant(DiffCommitment::default().into());
+    for (i, diff) in diffs.iter().enumerate() {
+        let mut overwritten = Boolean::FALSE;
+        for later in &targets[i + 1..] {
+            overwritten = overwritten.or(&later.is_eq(&targets[i])?)?;
+        }
+        let next = chain(parameters, &commitment, &diff.to_bytes_le()?)?;
+        commitment = overwritten.select(&commitment, &next)?;
+    }
+    Ok(commitment)
+}
+
+/// One step of a hash chain, matching `da::chain`.
+#[tracing::instrument(target = "r1cs", skip(parameters, commitment, bytes))]
+pub fn chain(
+    parameters: &ParametersVar,
+    commitment: &DiffCommitmentVar,
+    bytes: &[UInt8<ConstraintF>],
+) -> Result<DiffCommitmentVar, SynthesisError> {
+    let mut input = commitment.to_bytes()?;
+    input.extend_from_slice(bytes);
+    DiffHashGadget::evaluate(&parameters.diff_crh_params, &input)
+}
@@ -0,0 +1,289 @@
+//! Relate the constraints of the rollup circuit to the operations of a batch:
+//! explain why a batch does not satisfy the circuit (see `Rollup::diagnose`),
+//! and count the constraints of each namespace (see `Rollup::constraint_report`).
+use crate::ConstraintF;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::SynthesisError;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Range;
+
+/// An operation of a batch, in the order in which the circuit applies them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    /// The allocation of the initial and final roots and the diff, deposit and
+    /// withdrawal commitments.
+    PublicInputs,
+    Transaction(usize),
+    Swap(usize),
+    KeyRotation(usize),
+    MultisigTransaction(usize),
+    /// The check that the batch ends in the public final root.
+    FinalRoot,
+    /// The check that the state diffs of the batch lead to the public diff commitment.
+    StateDiffs,
+    /// The check that the transfers from and to the bridge account lead to the
+    /// public deposit and withdrawal commitments.
+    BridgeTransfers,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operation::PublicInputs => write!(f, "the public inputs"),
+            Operation::Transaction(i) => write!(f, "transaction {i}"),
+            Operation::Swap(i) => write!(f, "swap {i}"),
+            Operation::KeyRotation(i) => write!(f, "key rotation {i}"),
+            Operation::MultisigTransaction(i) => write!(f, "multisig transaction {i}"),
+            Operation::FinalRoot => write!(f, "the final root"),
+            Operation::StateDiffs => write!(f, "the state diffs"),
+            Operation::BridgeTransfers => write!(f, "the bridge transfers"),
+        }
+    }
+}
+
+impl Operation {
+    /// The name of the operation's tracing span in the circuit. The public
+    /// inputs, the final root check, the state diffs check and the bridge
+    /// transfers check have no span of their own.
+    pub fn span_name(&self) -> &'static str {
+        match self {
+            Operation::PublicInputs => "public_inputs",
+            Operation::Transaction(_) => "transaction",
+            Operation::Swap(_) => "swap",
+            Operation::KeyRotation(_) => "key_rotation",
+            Operation::MultisigTransaction(_) => "multisig_transaction",
+            Operation::FinalRoot => "final_root",
+            Operation::StateDiffs => "state_diffs",
+            Operation::BridgeTransfers => "bridge_transfers",
+        }
+    }
+
+    /// The namespace of the operation in a `ConstraintReport`, e.g. `transaction[3]`.
+    pub fn namespace(&self) -> String {
+        match self {
+            Operation::Transaction(i)
+            | Operation::Swap(i)
+            | Operation::KeyRotation(i)
+            | Operation::MultisigTransaction(i) => format!("{}[{i}]", self.span_name()),
+            Operation::PublicInputs | Operation::FinalRoot | Operation::StateDiffs | Operation::BridgeTransfers => {
+                self.span_name().to_string()
+            }
+        }
+    }
+}
+
+/// A check that the circuit makes on an operation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Check {
+    /// The operation starts from the root the previous one ended in, or the
+    /// batch ends in the final root.
+    RootChain,
+    /// The signature verifies under the sender's key.
+    Signature,
+    /// The sender's balance covers the amount.
+    Balance,
+    /// The sender's leaf is in the tree before the operation.
+    SenderPath,
+    /// The recipient's leaf is in the tree before the operation.
+    RecipientPath,
+    /// Updating the leaves along their paths leads to the root after the operation.
+    PostRoot,
+    /// All paths open the leaves of the transferred token.
+    LeafIndices,
+    /// The amount and balance commitments open correctly, if the ledger has
+    /// confidential amounts.
+    AmountCommitments,
+    /// The diffs of the batch hash to the diff commitment.
+    DiffCommitment,
+    /// The transfers from and to the bridge account hash to the deposit and
+    /// withdrawal commitments.
+    BridgeTransfers,
+    /// All checks of an operation that are not broken down further.
+    Validity,
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Check::RootChain => "root chain",
+            Check::Signature => "signature",
+            Check::Balance => "balance",
+            Check::SenderPath => "sender path",
+            Check::RecipientPath => "recipient path",
+            Check::PostRoot => "post root",
+            Check::LeafIndices => "leaf indices",
+            Check::AmountCommitments => "amount commitments",
+            Check::DiffCommitment => "diff commitment",
+            Check::BridgeTransfers => "bridge transfers",
+            Check::Validity => "validity",
+        })
+    }
+}
+
+/// The conjunction of `checks`.
+pub fn all_hold(checks: &[(Check, Boolean<ConstraintF>)]) -> Result<Boolean<ConstraintF>, SynthesisError> {
+    Boolean::kary_and(&checks.iter().map(|(_, holds)| holds.clone()).collect::<Vec<_>>())
+}
+
+/// The constraints generated for one operation and the values of its checks.
+#[derive(Clone, Debug)]
+pub struct OperationTrace {
+    pub operation: Operation,
+    /// The indices of the operation's constraints.
+    pub constraints: Range<usize>,
+    /// Whether each check holds for the assignment.
+    pub checks: Vec<(Check, bool)>,
+}
+
+impl OperationTrace {
+    /// Whether the circuit accepts the operation.
+    pub fn holds(&self) -> bool {
+        self.checks.iter().all(|(_, holds)| *holds)
+    }
+
+    /// The checks that do not hold.
+    pub fn failed_checks(&self) -> Vec<Check> {
+        self.checks
+            .iter()
+            .filter(|(_, holds)| !holds)
+            .map(|(check, _)| *check)
+            .collect()
+    }
+}
+
+/// The first unsatisfied constraint of a batch.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnsatisfiedConstraint {
+    /// The index of the constraint.
+    pub index: usize,
+    /// The operation that generated it.
+    pub operation: Operation,
+    /// The checks of that operation that do not hold.
+    pub failed_checks: Vec<Check>,
+}
+
+/// The result of `Rollup::diagnose`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnosis {
+    /// The first unsatisfied constraint, or `None` if the batch satisfies the circuit.
+    pub unsatisfied: Option<UnsatisfiedConstraint>,
+    /// The operations that the native ledger and the circuit judge differently,
+    /// with the native verdict.
+    pub disagreements: Vec<(Operation, bool)>,
+}
+
+impl Diagnosis {
+    /// Compare the circuit's `trace` with the `native_verdicts` of the same
+    /// operations, given the index of the first unsatisfied constraint.
+    pub(crate) fn new(
+        trace: &[OperationTrace],
+        native_verdicts: &[(Operation, bool)],
+        first_unsatisfied: Option<usize>,
+    ) -> Self {
+        let unsatisfied = first_unsatisfied.map(|index| {
+            let operation = trace
+                .iter()
+                .find(|t| t.constraints.contains(&index))
+                .expect("every constraint belongs to an operation");
+            UnsatisfiedConstraint {
+                index,
+                operation: operation.operation,
+                failed_checks: operation.failed_checks(),
+            }
+        });
+        let disagreements = native_verdicts
+            .iter()
+            .filter(|(operation, native_valid)| {
+                trace.iter().any(|t| t.operation == *operation && t.holds() != *native_valid)
+            })
+            .copied()
+            .collect();
+        Self {
+            unsatisfied,
+            disagreements,
+        }
+    }
+}
+
+impl fmt::Display for Diagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.unsatisfied {
+            Some(unsatisfied) => {
+                write!(f, "constraint {} of {} is unsatisfied", unsatisfied.index, unsatisfied.operation)?;
+                if !unsatisfied.failed_checks.is_empty() {
+                    let checks = unsatisfied.failed_checks.iter().map(Check::to_string).collect::<Vec<_>>();
+                    write!(f, "; failed checks: {}", checks.join(", "))?;
+                }
+            }
+            None => write!(f, "all constraints are satisfied")?,
+        }
+        for (operation, native_valid) in &self.disagreements {
+            let (native, circuit) = if *native_valid { ("valid", "invalid") } else { ("invalid", "valid") };
+            write!(f, "\n{operation} is {native} natively but {circuit} in the circuit")?;
+        }
+        Ok(())
+    }
+}
+
+/// The number of constraints generated in each namespace of a batch. A
+/// namespace is keyed by its path of tracing spans, starting with the indexed
+/// operation it belongs to, e.g. `transaction[0]/checks/verify_signature`.
+///
+/// The `Display` output has one `count path` line per namespace, sorted by
+/// path, so that the reports of two commits can be compared with `diff`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConstraintReport(pub BTreeMap<String, usize>);
+
+impl ConstraintReport {
+    /// Count the constraints of `trace`, given the name of every constraint as
+    /// returned by `ConstraintSystemRef::constraint_names`.
+    pub(crate) fn new(trace: &[OperationTrace], constraint_names: &[String]) -> Self {
+        let mut counts = BTreeMap::new();
+        for operation in trace {
+            for name in &constraint_names[operation.constraints.clone()] {
+                // Drop the module paths, the span of `Rollup::synthesize`, and the
+                // span of the operation, which is replaced by its namespace, since
+                // large indices share a span name.
+                let mut spans = name
+                    .split('/')
+                    .filter(|span| !span.is_empty())
+                    .map(|span| span.rsplit("::").next().unwrap_or(span))
+                    .skip(1)
+                    .peekable();
+                spans.next_if(|span| span.starts_with(operation.operation.span_name()));
+                let path = std::iter::once(operation.operation.namespace())
+                    .chain(spans.map(str::to_string))
+                    .collect::<Vec<_>>()
+                    .join("/");
+                *counts.entry(path).or_insert(0) += 1;
+            }
+        }
+        Self(counts)
+    }
+
+    /// The total number of constraints.
+    pub fn total(&self) -> usize {
+        self.0.values().sum()
+    }
+
+    /// Merge every namespace into its ancestor `depth` levels down; with depth 1
+    /// this counts the constraints of each operation.
+    pub fn truncated(&self, depth: usize) -> Self {
+        let mut counts = BTreeMap::new();
+        for (path, count) in &self.0 {
+            let ancestor = path.split('/').take(depth).collect::<Vec<_>>().join("/");
+            *counts.entry(ancestor).or_insert(0) += count;
+        }
+        Self(counts)
+    }
+}
+
+impl fmt::Display for ConstraintReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (path, count) in &self.0 {
+            writeln!(f, "{count} {path}")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,551 @@
+//! Escape hatch for when the operator stops producing batches. Each user can
+//! then withdraw, on L1, the balance of each of their `(account, token)` leaves
+//! in the last finalized root. An `ExitCircuit` proves an `ExitClaim`: the leaf
+//! holds the claimed balance in that root, and the leaf's key signed the claim.
+//! `ExitQueue` models the L1 side, which accepts each leaf's exit only once.
+//!
+//! Multisig accounts are registered under a key that nobody can sign for, so
+//! their exits are authorized like their transfers: by a threshold of the keys
+//! of the policy that hashes to the leaf's key; see `ExitAuthorization`. Both
+//! kinds of exit are proven with the same circuit.
+use crate::account::{AccountIdVar, AccountInformationVar, AccountSignatureVar, MultisigPolicyVar, TokenIdVar};
+use crate::diagnostics::{all_hold, Check};
+use crate::ledger::{path_is_at_index, AccPathVar, AccRootVar, ParametersVar};
+use crate::transaction::signing_payload_bytes;
+use crate::ConstraintF;
+use ark_bls12_381::Bls12_381;
+use ark_ff::PrimeField;
+use ark_groth16::{Groth16, Proof, ProvingKey, VerifyingKey};
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+use ark_simple_payments_scp::account::{
+    AccountId, AccountInformation, AccountPublicKey, AccountSecretKey, AccountSignature, MultisigPolicy, TokenId,
+    MAX_MULTISIG_KEYS,
+};
+use ark_simple_payments_scp::ledger::{AccPath, AccRoot, Amount, Parameters, State};
+use ark_simple_payments_scp::transaction::{L1Address, OperationKind, SigningPayload};
+use ark_snark::SNARK;
+use ark_std::rand::{CryptoRng, Rng, RngCore};
+use std::collections::{HashSet, VecDeque};
+
+/// A claim to withdraw the whole balance of `token` held by `account` in `root`
+/// to `destination` on L1. Its fields are the public inputs of `ExitCircuit`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExitClaim {
+    /// The finalized root that the balance is proven against.
+    pub root: AccRoot,
+    pub account: AccountId,
+    pub token: TokenId,
+    /// The balance of the leaf in `root`.
+    pub amount: Amount,
+    pub destination: L1Address,
+}
+
+impl ExitClaim {
+    /// The payload that the account's key signs to authorize the exit.
+    pub fn signing_payload(&self, parameters: &Parameters) -> SigningPayload {
+        SigningPayload::exit(parameters.domain, self.account, self.token, self.amount, &self.destination)
+    }
+
+    /// The public inputs of the exit circuit: the coordinates of the root,
+    /// followed by the account, the token, the amount and the destination, each
+    /// packed into one field element.
+    pub fn public_inputs(&self) -> Vec<ConstraintF> {
+        vec![
+            self.root.x,
+            self.root.y,
+            ConstraintF::from(self.account.0),
+            ConstraintF::from(self.token.0),
+            ConstraintF::from(self.amount.0),
+            ConstraintF::from_le_bytes_mod_order(&self.destination),
+        ]
+    }
+
+    /// Verify that `proof` proves this claim with the exit circuit's `verifying_key`.
+    pub fn verify(
+        &self,
+        verifying_key: &VerifyingKey<Bls12_381>,
+        proof: &Proof<Bls12_381>,
+    ) -> Result<bool, SynthesisError> {
+        Groth16::<Bls12_381>::verify(verifying_key, &self.public_inputs(), proof)
+    }
+}
+
+/// The signatures on the claim's `SigningPayload::exit` that authorize an exit.
+#[derive(Clone)]
+pub enum ExitAuthorization {
+    /// A signature under the key in the leaf.
+    Key(AccountSignature),
+    /// For a multisig account, signatures of at least `policy.threshold` of
+    /// `policy.public_keys`, where the policy hashes to the key in the leaf.
+    /// `signatures[i]` is the signature of `policy.public_keys[i]`, if that key signed.
+    Multisig {
+        policy: MultisigPolicy,
+        signatures: Vec<Option<AccountSignature>>,
+    },
+}
+
+/// The circuit proving an `ExitClaim`.
+pub struct ExitCircuit {
+    pub ledger_params: Parameters,
+    /// The claim, which is public.
+    pub claim: Option<ExitClaim>,
+    /// The information of the claimed leaf, and its authentication path with
+    /// respect to the claim's root.
+    pub account_info_and_path: Option<(AccountInformation, AccPath)>,
+    /// The signatures authorizing the exit.
+    pub authorization: Option<ExitAuthorization>,
+}
+
+impl ExitCircuit {
+    /// The exit of the whole balance of `token` held by `account` after the
+    /// finalized `batch` to `destination`, signed with the account's
+    /// `secret_key`. Returns `None` if `batch` has not been finalized or the
+    /// account did not exist after it.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new<R: Rng>(
+        ledger_params: &Parameters,
+        state: &State,
+        batch: usize,
+        account: AccountId,
+        token: TokenId,
+        destination: L1Address,
+        secret_key: &AccountSecretKey,
+        rng: &mut R,
+    ) -> Option<Self> {
+        let mut exit = Self::unsigned(ledger_params, state, batch, account, token, destination)?;
+        let message = exit.claim?.signing_payload(ledger_params).to_bytes_le();
+        let public_key = exit.account_info_and_path.as_ref()?.0.public_key;
+        let signature = ledger_params.sign(secret_key, &public_key, &message, rng);
+        exit.authorization = Some(ExitAuthorization::Key(signature));
+        Some(exit)
+    }
+
+    /// Like `new`, but for the multisig account `account` controlled by
+    /// `policy`. The exit is not signed by any key yet; see `sign`.
+    pub fn new_multisig(
+        ledger_params: &Parameters,
+        state: &State,
+        batch: usize,
+        account: AccountId,
+        token: TokenId,
+        destination: L1Address,
+        policy: MultisigPolicy,
+    ) -> Option<Self> {
+        let mut exit = Self::unsigned(ledger_params, state, batch, account, token, destination)?;
+        let signatures = vec![None; policy.public_keys.len()];
+        exit.authorization = Some(ExitAuthorization::Multisig { policy, signatures });
+        Some(exit)
+    }
+
+    /// The exit of the whole balance of the leaf, without an authorization.
+    fn unsigned(
+        ledger_params: &Parameters,
+        state: &State,
+        batch: usize,
+        account: AccountId,
+        token: TokenId,
+        destination: L1Address,
+    ) -> Option<Self> {
+        let root = state.root_at(batch)?;
+        let (account_info, path) = state.generate_proof_at(batch, account, token)?;
+        let claim = ExitClaim {
+            root,
+            account,
+            token,
+            amount: account_info.balance,
+            destination,
+        };
+        Some(Self {
+            ledger_params: ledger_params.clone(),
+            claim: Some(claim),
+            account_info_and_path: Some((account_info, path)),
+            authorization: None,
+        })
+    }
+
+    /// Add the signature of `pk` to a multisig exit. Returns `None` if the exit
+    /// is not a multisig exit or `pk` is not part of its policy.
+    pub fn sign<R: Rng>(&mut self, sk: &AccountSecretKey, pk: &AccountPublicKey, rng: &mut R) -> Option<()> {
+        let message = self.claim?.signing_payload(&self.ledger_params).to_bytes_le();
+        let Some(ExitAuthorization::Multisig { policy, signatures }) = &mut self.authorization else {
+            return None;
+        };
+        let slot = policy.public_keys.iter().position(|key| key == pk)?;
+        signatures.resize(policy.public_keys.len(), None);
+        signatures[slot] = Some(self.ledger_params.sign(sk, pk, &message, rng));
+        Some(())
+    }
+
+    /// The witnesses for both ways of authorizing the exit, so that the circuit
+    /// does not depend on which one is used: the signature under the leaf's
+    /// key, the multisig policy, and a signature for each key slot of the
+    /// policy. The unused way is filled with placeholders that never verify.
+    fn authorization_witnesses(&self) -> Option<(AccountSignature, MultisigPolicy, Vec<AccountSignature>)> {
+        let placeholder = AccountSignature::placeholder(self.ledger_params.signature_scheme);
+        Some(match self.authorization.as_ref()? {
+            ExitAuthorization::Key(signature) => {
+                // No key slot is used, so no signature counts towards the threshold.
+                let policy = MultisigPolicy { threshold: 1, public_keys: Vec::new() };
+                (signature.clone(), policy, vec![placeholder; MAX_MULTISIG_KEYS])
+            }
+            ExitAuthorization::Multisig { policy, signatures } => {
+                let signatures = (0..MAX_MULTISIG_KEYS)
+                    .map(|i| signatures.get(i).cloned().flatten().unwrap_or_else(|| placeholder.clone()))
+                    .collect();
+                (placeholder, policy.clone(), signatures)
+            }
+        })
+    }
+
+    /// Prove this exit with the exit circuit's `proving_key`.
+    pub fn prove<R: RngCore + CryptoRng>(
+        self,
+        proving_key: &ProvingKey<Bls12_381>,
+        rng: &mut R,
+    ) -> Result<Proof<Bls12_381>, SynthesisError> {
+        Groth16::<Bls12_381>::prove(proving_key, self, rng)
+    }
+}
+
+/// Generate the proving and verifying keys of the exit circuit. The circuit only
+/// depends on the ledger parameters and the depth of the account tree, so
+/// `circuit` can be any exit from a ledger with the same parameters and depth.
+pub fn setup<R: RngCore + CryptoRng>(
+    circuit: ExitCircuit,
+    rng: &mut R,
+) -> Result<(ProvingKey<Bls12_381>, VerifyingKey<Bls12_381>), SynthesisError> {
+    Groth16::<Bls12_381>::circuit_specific_setup(circuit, rng)
+}
+
+/// Allocate a public input, and enforce that `bits` are its little-endian bits.
+fn enforce_packed_input(
+    cs: ConstraintSystemRef<ConstraintF>,
+    bits: &[Boolean<ConstraintF>],
+    value: Option<ConstraintF>,
+) -> Result<(), SynthesisError> {
+    let input = FpVar::new_input(cs, || value.ok_or(SynthesisError::AssignmentMissing))?;
+    Boolean::le_bits_to_fp_var(bits)?.enforce_equal(&input)
+}
+
+impl ConstraintSynthesizer<ConstraintF> for ExitCircuit {
+    #[tracing::instrument(target = "r1cs", skip(self, cs))]
+    fn generate_constraints(self, cs: ConstraintSystemRef<ConstraintF>) -> Result<(), SynthesisError> {
+        let ledger_params = ParametersVar::new_constant(
+            ark_relations::ns!(cs, "Ledger parameters"),
+            &self.ledger_params,
+        )?;
+        let claim = self.claim.as_ref();
+        let inputs = claim.map(ExitClaim::public_inputs);
+        let root = AccRootVar::new_input(ark_relations::ns!(cs, "Root"), || {
+            claim.map(|c| c.root).ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let account = AccountIdVar::new_witness(ark_relations::ns!(cs, "Account"), || {
+            claim.map(|c| c.account).ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let token = TokenIdVar::new_witness(ark_relations::ns!(cs, "Token"), || {
+            claim.map(|c| c.token).ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let destination = match claim {
+            Some(claim) => UInt8::new_witness_vec(ark_relations::ns!(cs, "Destination"), &claim.destination)?,
+            None => UInt8::new_witness_vec(ark_relations::ns!(cs, "Destination"), &[None; 20])?,
+        };
+        let account_info = AccountInformationVar::new_witness(ark_relations::ns!(cs, "Account Info"), || {
+            self.account_info_and_path.as_ref().map(|t| t.0).ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let path = AccPathVar::new_witness(ark_relations::ns!(cs, "Path"), || {
+            self.account_info_and_path.as_ref().map(|t| &t.1).ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let authorization = self.authorization_witnesses();
+        let authorization = authorization.as_ref();
+        let signature = AccountSignatureVar::new_witness(ark_relations::ns!(cs, "Signature"), || {
+            authorization.map(|a| &a.0).ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let policy = MultisigPolicyVar::new_witness(ark_relations::ns!(cs, "Multisig Policy"), || {
+            authorization.map(|a| &a.1).ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let policy_signatures = (0..MAX_MULTISIG_KEYS)
+            .map(|i| {
+                AccountSignatureVar::new_witness(ark_relations::ns!(cs, "Multisig Signature"), || {
+                    authorization.map(|a| &a.2[i]).ok_or(SynthesisError::AssignmentMissing)
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // The claim's account, token, amount and destination are public inputs.
+        let destination_bits = destination.to_bits_le()?;
+        for (i, bits) in [
+            account.0.to_bits_le()?,
+            token.0.to_bits_le()?,
+            account_info.balance.0.to_bits_le(),
+            destination_bits,
+        ]
+        .iter()
+        .enumerate()
+        {
+            enforce_packed_input(cs.clone(), bits, inputs.as_ref().map(|inputs| inputs[2 + i]))?;
+        }
+
+        let leaf_exists = path.verify_membership(
+            &ledger_params.leaf_crh_params,
+            &ledger_params.inner_crh_params,
+            &root,
+            account_info.to_bytes_le().as_slice(),
+        )?;
+        let path_at_token_leaf = Boolean::kary_and(&[
+            token.is_supported()?,
+            path_is_at_index(&path, &account.leaf_index_bits_le(&token)?)?,
+        ])?;
+        // If the leaf holds a balance commitment, the claimed amount has to be
+        // the committed balance.
+        let commitment_valid = match (ledger_params.confidential_amounts, &account_info.balance_commitment) {
+            (true, Some(commitment)) => commitment.opens_to(&ledger_params, &account_info.balance)?,
+            (false, None) => Boolean::TRUE,
+            _ => Boolean::FALSE,
+        };
+        // The body matches `SigningPayload::exit`.
+        let mut body = account.to_bytes_le();
+        body.extend(token.to_bytes_le());
+        body.extend(account_info.balance.to_bytes_le());
+        body.extend(destination);
+        let message = signing_payload_bytes(&ledger_params, OperationKind::Exit, body);
+        // Either the leaf's key signed, or the leaf belongs to a multisig
+        // account whose policy keys signed, as in `MultisigTransactionVar::validate`.
+        let key_signed = ledger_params.verify_signature(&account_info.public_key, &message, &signature)?;
+        let policy_signed = policy
+            .account_key(&ledger_params)?
+            .is_eq(&account_info.public_key.pub_key)?
+            .and(&policy.threshold_met(&ledger_params, &message, &policy_signatures)?)?;
+        let sig_verifies = key_signed.or(&policy_signed)?;
+
+        all_hold(&[
+            (Check::SenderPath, leaf_exists),
+            (Check::LeafIndices, path_at_token_leaf),
+            (Check::AmountCommitments, commitment_valid),
+            (Check::Signature, sig_verifies),
+        ])?
+        .enforce_equal(&Boolean::TRUE)
+    }
+}
+
+/// Why `ExitQueue::submit` rejects a claim.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExitRejection {
+    /// The claim is not against the frozen root.
+    WrongRoot,
+    /// The claimed leaf has exited already.
+    AlreadyExited,
+    /// The proof does not prove the claim.
+    InvalidProof,
+}
+
+/// A model of the L1 contract that pays out exits. Once the operator stops
+/// producing batches, the contract freezes the last finalized root, and
+/// accepts one exit per leaf of that root.
+pub struct ExitQueue {
+    verifying_key: VerifyingKey<Bls12_381>,
+    frozen_root: AccRoot,
+    /// The leaves whose exit has been accepted.
+    exited: HashSet<(AccountId, TokenId)>,
+    /// The accepted claims that have not been paid out, oldest first.
+    pending: VecDeque<ExitClaim>,
+}
+
+impl ExitQueue {
+    /// A queue for exits from `frozen_root`, proven with the exit circuit's `verifying_key`.
+    pub fn new(verifying_key: VerifyingKey<Bls12_381>, frozen_root: AccRoot) -> Self {
+        Self {
+            verifying_key,
+            frozen_root,
+            exited: HashSet::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// The root that exits are proven against.
+    pub fn frozen_root(&self) -> AccRoot {
+        self.frozen_root
+    }
+
+    /// Whether the exit of the `(account, token)` leaf has been accepted.
+    pub fn has_exited(&self, account: AccountId, token: TokenId) -> bool {
+        self.exited.contains(&(account, token))
+    }
+
+    /// Queue `claim` for payout if `proof` proves it against the frozen root,
+    /// and the claimed leaf has not exited before.
+    pub fn submit(&mut self, claim: ExitClaim, proof: &Proof<Bls12_381>) -> Result<(), ExitRejection> {
+        if claim.root != self.frozen_root {
+            return Err(ExitRejection::WrongRoot);
+        }
+        if self.has_exited(claim.account, claim.token) {
+            return Err(ExitRejection::AlreadyExited);
+        }
+        if !claim.verify(&self.verifying_key, proof).unwrap_or(false) {
+            return Err(ExitRejection::InvalidProof);
+        }
+        self.exited.insert((claim.account, claim.token));
+        self.pending.push_back(claim);
+        Ok(())
+    }
+
+    /// Pay out the oldest accepted claim, if any.
+    pub fn pay_next(&mut self) -> Option<ExitClaim> {
+        self.pending.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ark_relations::r1cs::ConstraintSystem;
+    use ark_simple_payments_scp::transaction::Transaction;
+    use ark_std::rand::{rngs::StdRng, SeedableRng};
+
+    /// A ledger in which Alice sends 4 of her 10 units to Bob in batch 2.
+    fn ledger_with_two_batches() -> (Parameters, State, AccountId, AccountSecretKey, AccountId) {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, alice_pk, alice_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (bob_id, _, _) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, TokenId(0), Amount(10)).unwrap();
+        state.finalize_batch();
+        let tx = Transaction::create(&pp, alice_id, bob_id, TokenId(0), Amount(4), &alice_sk, &alice_pk, &mut rng);
+        state.apply_transaction(&pp, &tx).unwrap();
+        state.finalize_batch();
+        (pp, state, alice_id, alice_sk, bob_id)
+    }
+
+    /// Register a 2-of-3 multisig vault holding 9 units, in a new batch.
+    fn add_vault(
+        pp: &Parameters,
+        state: &mut State,
+    ) -> (AccountId, MultisigPolicy, Vec<(AccountPublicKey, AccountSecretKey)>) {
+        let mut rng = ark_std::test_rng();
+        let keys = (0..3).map(|_| pp.keygen(&mut rng)).collect::<Vec<_>>();
+        let policy = MultisigPolicy { threshold: 2, public_keys: keys.iter().map(|(pk, _)| *pk).collect() };
+        let vault_id = state.register_multisig(pp, &policy).unwrap();
+        state.update_balance(vault_id, TokenId(0), Amount(9)).unwrap();
+        state.finalize_batch();
+        (vault_id, policy, keys)
+    }
+
+    fn is_satisfied(circuit: ExitCircuit) -> bool {
+        let cs = ConstraintSystem::new_ref();
+        circuit.generate_constraints(cs.clone()).unwrap();
+        cs.is_satisfied().unwrap()
+    }
+
+    #[test]
+    fn exit_circuit_checks_balance_and_key() {
+        let mut rng = ark_std::test_rng();
+        let (pp, state, alice_id, alice_sk, bob_id) = ledger_with_two_batches();
+        let destination = [7u8; 20];
+        for batch in [1, 2] {
+            let exit = ExitCircuit::new(&pp, &state, batch, alice_id, TokenId(0), destination, &alice_sk, &mut rng).unwrap();
+            assert_eq!(exit.claim.unwrap().amount, Amount(if batch == 1 { 10 } else { 6 }));
+            assert!(is_satisfied(exit));
+        }
+
+        // Alice cannot claim more than her balance...
+        let mut exit = ExitCircuit::new(&pp, &state, 2, alice_id, TokenId(0), destination, &alice_sk, &mut rng).unwrap();
+        exit.claim.as_mut().unwrap().amount = Amount(10);
+        assert!(!is_satisfied(exit));
+        // ... or exit with Bob's balance.
+        let exit = ExitCircuit::new(&pp, &state, 2, bob_id, TokenId(0), destination, &alice_sk, &mut rng).unwrap();
+        assert!(!is_satisfied(exit));
+        // The signature covers the destination.
+        let mut exit = ExitCircuit::new(&pp, &state, 2, alice_id, TokenId(0), destination, &alice_sk, &mut rng).unwrap();
+        exit.claim.as_mut().unwrap().destination = [8u8; 20];
+        assert!(!is_satisfied(exit));
+    }
+
+    #[test]
+    fn multisig_exits_need_the_threshold() {
+        let mut rng = ark_std::test_rng();
+        let (pp, mut state, alice_id, alice_sk, _) = ledger_with_two_batches();
+        let (vault_id, policy, keys) = add_vault(&pp, &mut state);
+        let destination = [7u8; 20];
+        let exit = |account, policy: &MultisigPolicy| {
+            ExitCircuit::new_multisig(&pp, &state, 3, account, TokenId(0), destination, policy.clone()).unwrap()
+        };
+
+        // One signature is not enough, two are.
+        let signed_by = |signers: &[usize], rng: &mut _| {
+            let mut vault_exit = exit(vault_id, &policy);
+            for &i in signers {
+                vault_exit.sign(&keys[i].1, &keys[i].0, rng).unwrap();
+            }
+            vault_exit
+        };
+        assert!(!is_satisfied(signed_by(&[0], &mut rng)));
+        let vault_exit = signed_by(&[0, 2], &mut rng);
+        assert_eq!(vault_exit.claim.unwrap().amount, Amount(9));
+        assert!(is_satisfied(vault_exit));
+
+        // Keys outside the policy cannot sign, and a fully signed policy other
+        // than the vault's does not authorize its exit.
+        let (outsider_pk, outsider_sk) = pp.keygen(&mut rng);
+        assert!(exit(vault_id, &policy).sign(&outsider_sk, &outsider_pk, &mut rng).is_none());
+        let other_policy = MultisigPolicy { threshold: 1, public_keys: vec![keys[0].0] };
+        let mut forged = exit(vault_id, &other_policy);
+        forged.sign(&keys[0].1, &keys[0].0, &mut rng).unwrap();
+        assert!(!is_satisfied(forged));
+        // Neither does a single policy key signing as the leaf's key.
+        let single = ExitCircuit::new(&pp, &state, 3, vault_id, TokenId(0), destination, &keys[0].1, &mut rng).unwrap();
+        assert!(!is_satisfied(single));
+
+        // A single-key account cannot exit with a policy, and its exit cannot
+        // collect multisig signatures.
+        let mut alice_exit = exit(alice_id, &other_policy);
+        alice_exit.sign(&keys[0].1, &keys[0].0, &mut rng).unwrap();
+        assert!(!is_satisfied(alice_exit));
+        let mut alice_exit = ExitCircuit::new(&pp, &state, 3, alice_id, TokenId(0), destination, &alice_sk, &mut rng).unwrap();
+        assert!(alice_exit.sign(&keys[0].1, &keys[0].0, &mut rng).is_none());
+    }
+
+    #[test]
+    fn exit_snark_and_queue_prevent_double_exits() {
+        let mut rng = StdRng::seed_from_u64(ark_std::test_rng().next_u64());
+        let (pp, mut state, alice_id, alice_sk, _) = ledger_with_two_batches();
+        let (vault_id, policy, keys) = add_vault(&pp, &mut state);
+        let exit = |batch, rng: &mut StdRng| {
+            ExitCircuit::new(&pp, &state, batch, alice_id, TokenId(0), [7u8; 20], &alice_sk, rng).unwrap()
+        };
+        let (pk, vk) = setup(exit(3, &mut rng), &mut rng).unwrap();
+        let mut queue = ExitQueue::new(vk, state.root_at(3).unwrap());
+
+        // An exit from an earlier batch is rejected, as it may have been spent since.
+        let stale = exit(1, &mut rng);
+        let stale_claim = stale.claim.unwrap();
+        let proof = stale.prove(&pk, &mut rng).unwrap();
+        assert_eq!(queue.submit(stale_claim, &proof), Err(ExitRejection::WrongRoot));
+
+        let exit = exit(3, &mut rng);
+        let claim = exit.claim.unwrap();
+        let proof = exit.prove(&pk, &mut rng).unwrap();
+        let mut inflated = claim;
+        inflated.amount = Amount(10);
+        assert_eq!(queue.submit(inflated, &proof), Err(ExitRejection::InvalidProof));
+        assert!(!queue.has_exited(alice_id, TokenId(0)));
+
+        assert_eq!(queue.submit(claim, &proof), Ok(()));
+        assert!(queue.has_exited(alice_id, TokenId(0)));
+        assert_eq!(queue.submit(claim, &proof), Err(ExitRejection::AlreadyExited));
+        assert_eq!(queue.pay_next(), Some(claim));
+        assert_eq!(queue.pay_next(), None);
+
+        // The vault exits with the same keys, on the signatures of two of its keys.
+        let mut vault_exit =
+            ExitCircuit::new_multisig(&pp, &state, 3, vault_id, TokenId(0), [9u8; 20], policy).unwrap();
+        for (public_key, secret_key) in &keys[1..] {
+            vault_exit.sign(secret_key, public_key, &mut rng).unwrap();
+        }
+        let vault_claim = vault_exit.claim.unwrap();
+        let proof = vault_exit.prove(&pk, &mut rng).unwrap();
+        assert_eq!(queue.submit(vault_claim, &proof), Ok(()));
+        assert_eq!(queue.pay_next(), Some(vault_claim));
+    }
+}
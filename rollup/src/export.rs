@@ -0,0 +1,426 @@
+//! Export of a constraint system to the iden3 binary formats, so that the rollup
+//! circuit can be inspected with circom/snarkjs tooling.
+//!
+//! Both formats are little-endian and consist of a 4-byte magic string, a `u32`
+//! version, a `u32` section count, and the sections. Each section starts with a
+//! `u32` type and a `u64` byte length.
+//!
+//! `.r1cs` (version 1):
+//! * section 1, header: `u32` field size `n8 = 32`, the prime (`n8` bytes),
+//!   `u32` number of wires, `u32` public outputs (always 0), `u32` public inputs,
+//!   `u32` private inputs, `u64` number of labels, `u32` number of constraints;
+//! * section 2, constraints: for each constraint the linear combinations `A`,
+//!   `B` and `C`, each encoded as a `u32` term count followed by `(u32 wire,
+//!   n8-byte coefficient)` pairs;
+//! * section 3, wire-to-label map: one `u64` label per wire.
+//!
+//! `.wtns` (version 2):
+//! * section 1, header: `u32` field size `n8`, the prime, `u32` number of values;
+//! * section 2, witness: one `n8`-byte value per wire.
+//!
+//! Field elements are canonical (non-Montgomery) little-endian integers. Wire 0
+//! is the constant one, followed by the public inputs and then the witness
+//! variables, which is the order used by `ConstraintMatrices`. Every witness
+//! variable counts as a private input, and wire `i` has label `i`.
+use crate::ConstraintF;
+use ark_crypto_primitives::Error;
+use ark_ff::{BigInteger, Field, PrimeField, Zero};
+use ark_relations::r1cs::{
+    ConstraintMatrices, ConstraintSynthesizer, ConstraintSystem, ConstraintSystemRef, Matrix,
+};
+use ark_serialize::CanonicalDeserialize;
+use ark_std::io::{self, Read, Write};
+
+/// The size in bytes of an encoded field element.
+const FIELD_SIZE: u32 = 32;
+
+const R1CS_MAGIC: &[u8; 4] = b"r1cs";
+const R1CS_VERSION: u32 = 1;
+const R1CS_HEADER: u32 = 1;
+const R1CS_CONSTRAINTS: u32 = 2;
+const R1CS_WIRE_TO_LABEL: u32 = 3;
+
+const WTNS_MAGIC: &[u8; 4] = b"wtns";
+const WTNS_VERSION: u32 = 2;
+const WTNS_HEADER: u32 = 1;
+const WTNS_WITNESS: u32 = 2;
+
+/// Synthesize `circuit` and return its constraint matrices together with the
+/// full assignment `(1, public inputs, witnesses)`.
+pub fn synthesize<C: ConstraintSynthesizer<ConstraintF>>(
+    circuit: C,
+) -> Result<(ConstraintMatrices<ConstraintF>, Vec<ConstraintF>), Error> {
+    let cs = ConstraintSystem::new_ref();
+    circuit.generate_constraints(cs.clone())?;
+    matrices_and_assignment(&cs)
+}
+
+/// Finalize `cs` and return its constraint matrices together with the full
+/// assignment `(1, public inputs, witnesses)`.
+pub fn matrices_and_assignment(
+    cs: &ConstraintSystemRef<ConstraintF>,
+) -> Result<(ConstraintMatrices<ConstraintF>, Vec<ConstraintF>), Error> {
+    cs.finalize();
+    let matrices = cs.to_matrices().ok_or("the constraint matrices were not constructed")?;
+    let cs = cs.borrow().ok_or("the constraint system is empty")?;
+    let mut assignment = cs.instance_assignment.clone();
+    assignment.extend_from_slice(&cs.witness_assignment);
+    Ok((matrices, assignment))
+}
+
+/// Synthesize `circuit`, e.g. a `Rollup`, and write its constraints to `r1cs` and
+/// its assignment to `wtns`.
+pub fn export<C: ConstraintSynthesizer<ConstraintF>, W1: Write, W2: Write>(
+    circuit: C,
+    r1cs: W1,
+    wtns: W2,
+) -> Result<(), Error> {
+    let (matrices, assignment) = synthesize(circuit)?;
+    write_r1cs(&matrices, r1cs)?;
+    write_wtns(&assignment, wtns)?;
+    Ok(())
+}
+
+/// Check `A·z ∘ B·z = C·z` for the full assignment `z`.
+pub fn is_satisfied(matrices: &ConstraintMatrices<ConstraintF>, assignment: &[ConstraintF]) -> bool {
+    let num_variables = matrices.num_instance_variables + matrices.num_witness_variables;
+    assignment.len() == num_variables
+        && assignment.first() == Some(&ConstraintF::ONE)
+        && first_unsatisfied(matrices, assignment).is_none()
+}
+
+/// The index of the first constraint that the full `assignment` violates. The
+/// assignment has to contain a value for every variable.
+pub fn first_unsatisfied(matrices: &ConstraintMatrices<ConstraintF>, assignment: &[ConstraintF]) -> Option<usize> {
+    let evaluate = |row: &[(ConstraintF, usize)]| -> ConstraintF {
+        row.iter().map(|(coeff, wire)| *coeff * assignment[*wire]).sum()
+    };
+    (0..matrices.num_constraints)
+        .find(|&i| evaluate(&matrices.a[i]) * evaluate(&matrices.b[i]) != evaluate(&matrices.c[i]))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn to_u32(value: usize) -> io::Result<u32> {
+    u32::try_from(value).map_err(|_| invalid_data("count does not fit in a u32"))
+}
+
+fn write_field_element<W: Write>(writer: &mut W, value: &ConstraintF) -> io::Result<()> {
+    writer.write_all(&value.into_bigint().to_bytes_le())
+}
+
+fn write_prime<W: Write>(writer: &mut W) -> io::Result<()> {
+    writer.write_all(&ConstraintF::MODULUS.to_bytes_le())
+}
+
+/// Write the file header followed by `sections`, each given as `(type, contents)`.
+fn write_file<W: Write>(
+    mut writer: W,
+    magic: &[u8; 4],
+    version: u32,
+    sections: &[(u32, Vec<u8>)],
+) -> io::Result<()> {
+    writer.write_all(magic)?;
+    writer.write_all(&version.to_le_bytes())?;
+    writer.write_all(&to_u32(sections.len())?.to_le_bytes())?;
+    for (section_type, contents) in sections {
+        writer.write_all(&section_type.to_le_bytes())?;
+        writer.write_all(&(contents.len() as u64).to_le_bytes())?;
+        writer.write_all(contents)?;
+    }
+    writer.flush()
+}
+
+fn write_linear_combination(buffer: &mut Vec<u8>, row: &[(ConstraintF, usize)]) -> io::Result<()> {
+    buffer.extend_from_slice(&to_u32(row.len())?.to_le_bytes());
+    for (coeff, wire) in row {
+        buffer.extend_from_slice(&to_u32(*wire)?.to_le_bytes());
+        write_field_element(buffer, coeff)?;
+    }
+    Ok(())
+}
+
+/// Write `matrices` in the `.r1cs` format.
+pub fn write_r1cs<W: Write>(matrices: &ConstraintMatrices<ConstraintF>, writer: W) -> io::Result<()> {
+    let num_wires = matrices.num_instance_variables + matrices.num_witness_variables;
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&FIELD_SIZE.to_le_bytes());
+    write_prime(&mut header)?;
+    header.extend_from_slice(&to_u32(num_wires)?.to_le_bytes());
+    header.extend_from_slice(&0u32.to_le_bytes());
+    header.extend_from_slice(&to_u32(matrices.num_instance_variables - 1)?.to_le_bytes());
+    header.extend_from_slice(&to_u32(matrices.num_witness_variables)?.to_le_bytes());
+    header.extend_from_slice(&(num_wires as u64).to_le_bytes());
+    header.extend_from_slice(&to_u32(matrices.num_constraints)?.to_le_bytes());
+
+    let mut constraints = Vec::new();
+    for i in 0..matrices.num_constraints {
+        write_linear_combination(&mut constraints, &matrices.a[i])?;
+        write_linear_combination(&mut constraints, &matrices.b[i])?;
+        write_linear_combination(&mut constraints, &matrices.c[i])?;
+    }
+
+    let labels = (0..num_wires as u64).flat_map(u64::to_le_bytes).collect();
+
+    write_file(
+        writer,
+        R1CS_MAGIC,
+        R1CS_VERSION,
+        &[
+            (R1CS_HEADER, header),
+            (R1CS_CONSTRAINTS, constraints),
+            (R1CS_WIRE_TO_LABEL, labels),
+        ],
+    )
+}
+
+/// Write the full assignment `(1, public inputs, witnesses)` in the `.wtns` format.
+pub fn write_wtns<W: Write>(assignment: &[ConstraintF], writer: W) -> io::Result<()> {
+    let mut header = Vec::new();
+    header.extend_from_slice(&FIELD_SIZE.to_le_bytes());
+    write_prime(&mut header)?;
+    header.extend_from_slice(&to_u32(assignment.len())?.to_le_bytes());
+
+    let mut witness = Vec::new();
+    for value in assignment {
+        write_field_element(&mut witness, value)?;
+    }
+
+    write_file(
+        writer,
+        WTNS_MAGIC,
+        WTNS_VERSION,
+        &[(WTNS_HEADER, header), (WTNS_WITNESS, witness)],
+    )
+}
+
+/// A cursor over the contents of a section.
+struct SectionReader<'a>(&'a [u8]);
+
+impl SectionReader<'_> {
+    fn bytes(&mut self, len: usize) -> io::Result<&[u8]> {
+        if self.0.len() < len {
+            return Err(invalid_data("section is truncated"));
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    /// Read a canonical field element; values that are not reduced are rejected.
+    fn field_element(&mut self) -> io::Result<ConstraintF> {
+        ConstraintF::deserialize_uncompressed(self.bytes(FIELD_SIZE as usize)?)
+            .map_err(|_| invalid_data("field element is not canonical"))
+    }
+
+    /// Read the field size and the prime, and check that they match `ConstraintF`.
+    fn field(&mut self) -> io::Result<()> {
+        if self.u32()? != FIELD_SIZE || self.bytes(FIELD_SIZE as usize)? != ConstraintF::MODULUS.to_bytes_le() {
+            return Err(invalid_data("file is not over the rollup's field"));
+        }
+        Ok(())
+    }
+
+    fn finish(&self) -> io::Result<()> {
+        if !self.0.is_empty() {
+            return Err(invalid_data("section has trailing bytes"));
+        }
+        Ok(())
+    }
+}
+
+/// Read a file with the given magic and version, and return its sections as
+/// `(type, contents)` in file order.
+fn read_file<R: Read>(mut reader: R, magic: &[u8; 4], version: u32) -> io::Result<Vec<(u32, Vec<u8>)>> {
+    let mut contents = Vec::new();
+    reader.read_to_end(&mut contents)?;
+    let mut file = SectionReader(&contents);
+    if file.bytes(4)? != magic || file.u32()? != version {
+        return Err(invalid_data("unexpected magic string or version"));
+    }
+    let num_sections = file.u32()?;
+    let mut sections = Vec::new();
+    for _ in 0..num_sections {
+        let section_type = file.u32()?;
+        let len = usize::try_from(file.u64()?).map_err(|_| invalid_data("section is too large"))?;
+        sections.push((section_type, file.bytes(len)?.to_vec()));
+    }
+    file.finish()?;
+    Ok(sections)
+}
+
+/// Find the unique section of type `section_type`.
+fn section(sections: &[(u32, Vec<u8>)], section_type: u32) -> io::Result<SectionReader<'_>> {
+    let mut matching = sections.iter().filter(|(t, _)| *t == section_type);
+    match (matching.next(), matching.next()) {
+        (Some((_, contents)), None) => Ok(SectionReader(contents)),
+        (None, _) => Err(invalid_data("missing section")),
+        (Some(_), Some(_)) => Err(invalid_data("duplicate section")),
+    }
+}
+
+fn read_linear_combination(
+    section: &mut SectionReader,
+    num_wires: usize,
+) -> io::Result<Vec<(ConstraintF, usize)>> {
+    let num_terms = section.u32()?;
+    (0..num_terms)
+        .map(|_| {
+            let wire = section.u32()? as usize;
+            if wire >= num_wires {
+                return Err(invalid_data("wire index out of range"));
+            }
+            Ok((section.field_element()?, wire))
+        })
+        .collect()
+}
+
+fn num_non_zero(matrix: &Matrix<ConstraintF>) -> usize {
+    matrix
+        .iter()
+        .flatten()
+        .filter(|(coeff, _)| !coeff.is_zero())
+        .count()
+}
+
+/// Read a `.r1cs` file written by `write_r1cs`. The wire-to-label map is not
+/// returned, but it has to list every wire.
+pub fn read_r1cs<R: Read>(reader: R) -> io::Result<ConstraintMatrices<ConstraintF>> {
+    let sections = read_file(reader, R1CS_MAGIC, R1CS_VERSION)?;
+
+    let mut header = section(&sections, R1CS_HEADER)?;
+    header.field()?;
+    let num_wires = header.u32()? as usize;
+    let num_public_outputs = header.u32()? as usize;
+    let num_public_inputs = header.u32()? as usize;
+    let _num_private_inputs = header.u32()?;
+    let num_labels = header.u64()?;
+    let num_constraints = header.u32()? as usize;
+    header.finish()?;
+    let num_instance_variables = 1 + num_public_outputs + num_public_inputs;
+    if num_instance_variables > num_wires {
+        return Err(invalid_data("more public wires than wires"));
+    }
+
+    let mut constraints = section(&sections, R1CS_CONSTRAINTS)?;
+    let (mut a, mut b, mut c) = (Vec::new(), Vec::new(), Vec::new());
+    for _ in 0..num_constraints {
+        a.push(read_linear_combination(&mut constraints, num_wires)?);
+        b.push(read_linear_combination(&mut constraints, num_wires)?);
+        c.push(read_linear_combination(&mut constraints, num_wires)?);
+    }
+    constraints.finish()?;
+
+    let mut labels = section(&sections, R1CS_WIRE_TO_LABEL)?;
+    for _ in 0..num_wires {
+        if labels.u64()? >= num_labels {
+            return Err(invalid_data("label out of range"));
+        }
+    }
+    labels.finish()?;
+
+    Ok(ConstraintMatrices {
+        num_instance_variables,
+        num_witness_variables: num_wires - num_instance_variables,
+        num_constraints,
+        a_num_non_zero: num_non_zero(&a),
+        b_num_non_zero: num_non_zero(&b),
+        c_num_non_zero: num_non_zero(&c),
+        a,
+        b,
+        c,
+    })
+}
+
+/// Read a `.wtns` file written by `write_wtns`.
+pub fn read_wtns<R: Read>(reader: R) -> io::Result<Vec<ConstraintF>> {
+    let sections = read_file(reader, WTNS_MAGIC, WTNS_VERSION)?;
+
+    let mut header = section(&sections, WTNS_HEADER)?;
+    header.field()?;
+    let num_values = header.u32()?;
+    header.finish()?;
+
+    let mut witness = section(&sections, WTNS_WITNESS)?;
+    let assignment = (0..num_values)
+        .map(|_| witness.field_element())
+        .collect::<io::Result<Vec<_>>>()?;
+    witness.finish()?;
+    Ok(assignment)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rollup::Rollup;
+    use ark_simple_payments_scp::account::TokenId;
+    use ark_simple_payments_scp::ledger::{Amount, Parameters, State};
+    use ark_simple_payments_scp::transaction::Transaction;
+
+    fn single_transfer_rollup() -> Rollup<1> {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, alice_pk, alice_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, TokenId(0), Amount(20)).unwrap();
+        let (bob_id, _, _) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let tx = Transaction::create(&pp, alice_id, bob_id, TokenId(0), Amount(5), &alice_sk, &alice_pk, &mut rng);
+        Rollup::<1>::with_state_and_transactions(pp, &[tx], &mut state, true).unwrap()
+    }
+
+    #[test]
+    fn rollup_round_trips_through_r1cs_and_wtns() {
+        let (matrices, assignment) = synthesize(single_transfer_rollup()).unwrap();
+        assert!(is_satisfied(&matrices, &assignment));
+
+        let (mut r1cs, mut wtns) = (Vec::new(), Vec::new());
+        export(single_transfer_rollup(), &mut r1cs, &mut wtns).unwrap();
+        assert_eq!(&r1cs[..4], b"r1cs");
+        assert_eq!(&wtns[..4], b"wtns");
+
+        let imported_matrices = read_r1cs(&r1cs[..]).unwrap();
+        let imported_assignment = read_wtns(&wtns[..]).unwrap();
+        assert_eq!(imported_matrices, matrices);
+        assert_eq!(imported_assignment, assignment);
+        assert!(is_satisfied(&imported_matrices, &imported_assignment));
+
+        // A single wrong witness value breaks the imported system.
+        let mut bad_assignment = imported_assignment;
+        let last = bad_assignment.len() - 1;
+        bad_assignment[last] += ConstraintF::ONE;
+        assert!(!is_satisfied(&imported_matrices, &bad_assignment));
+    }
+
+    #[test]
+    fn malformed_files_are_rejected() {
+        let assignment = vec![ConstraintF::ONE, ConstraintF::from(7u64)];
+        let mut wtns = Vec::new();
+        write_wtns(&assignment, &mut wtns).unwrap();
+        assert_eq!(read_wtns(&wtns[..]).unwrap(), assignment);
+
+        // Truncated file.
+        assert!(read_wtns(&wtns[..wtns.len() - 1]).is_err());
+        // Wrong magic string.
+        let mut bad_magic = wtns.clone();
+        bad_magic[0] = b'x';
+        assert!(read_wtns(&bad_magic[..]).is_err());
+        // A value that is not reduced modulo the prime.
+        let mut unreduced = wtns.clone();
+        let start = unreduced.len() - FIELD_SIZE as usize;
+        unreduced[start..].copy_from_slice(&ConstraintF::MODULUS.to_bytes_le());
+        assert!(read_wtns(&unreduced[..]).is_err());
+        // A witness file is not a constraint file.
+        assert!(read_r1cs(&wtns[..]).is_err());
+    }
+}
@@ -0,0 +1,416 @@
+//! Following the rollup without sequencing it. A `Follower` rebuilds the
+//! `State` from the operations that the operator publishes with each batch,
+//! replaying them on the native ledger, and checks that each batch ends in the
+//! final root of its verified proof.
+//!
+//! A follower can instead rebuild the state from the state diffs of each
+//! `BatchSubmission`, as `da::replay` does, once the batch's proof verifies
+//! for the commitment to those diffs; see `Follower::ingest_proven`. This
+//! needs no operations, so it also follows ledgers with confidential amounts,
+//! whose transactions only replay with the openings of their amounts.
+use crate::bridge::{BatchSubmission, Deposit};
+use crate::diagnostics::Operation;
+use crate::rollup::Rollup;
+use ark_bls12_381::Bls12_381;
+use ark_crypto_primitives::Error;
+use ark_groth16::{Groth16, VerifyingKey};
+use ark_simple_payments_scp::da;
+use ark_simple_payments_scp::genesis::Genesis;
+use ark_simple_payments_scp::ledger::{AccRoot, Parameters, State};
+use ark_simple_payments_scp::transaction::{KeyRotation, MultisigTransaction, Swap, Transaction};
+use ark_snark::SNARK;
+use std::fmt;
+
+/// The operations of a batch, as published by the operator, and the roots of
+/// its verified proof.
+#[derive(Clone)]
+pub struct PublishedBatch {
+    /// The number of the batch. The first batch after genesis is batch 1.
+    pub batch: usize,
+    /// The initial root of the batch's proof.
+    pub initial_root: AccRoot,
+    /// The final root of the batch's proof.
+    pub final_root: AccRoot,
+    pub transactions: Vec<Transaction>,
+    pub swaps: Vec<Swap>,
+    pub key_rotations: Vec<KeyRotation>,
+    pub multisig_transactions: Vec<MultisigTransaction>,
+}
+
+impl PublishedBatch {
+    /// The data that the operator publishes for `rollup` as batch number `batch`.
+    /// Returns `None` if the rollup lacks its roots or operations.
+    pub fn from_rollup<
+        const NUM_TX: usize,
+        const NUM_SWAPS: usize,
+        const NUM_ROTATIONS: usize,
+        const NUM_MULTISIG_TX: usize,
+    >(
+        batch: usize,
+        rollup: &Rollup<NUM_TX, NUM_SWAPS, NUM_ROTATIONS, NUM_MULTISIG_TX>,
+    ) -> Option<Self> {
+        Some(Self {
+            batch,
+            initial_root: rollup.initial_root?,
+            final_root: rollup.final_root?,
+            transactions: rollup.transactions.clone()?,
+            swaps: rollup.swaps.clone()?,
+            key_rotations: rollup.key_rotations.clone()?,
+            multisig_transactions: rollup.multisig_transactions.clone()?,
+        })
+    }
+}
+
+/// A batch as the L1 bridge accepted it: its submission, which carries the
+/// encoded state diffs and the proof, and the deposits that it credits.
+#[derive(Clone, Debug)]
+pub struct ProvenBatch {
+    pub submission: BatchSubmission,
+    /// The oldest `submission.num_deposits` deposits that were pending when the
+    /// batch was accepted.
+    pub deposits: Vec<Deposit>,
+}
+
+/// Why a `Follower` cannot apply a published batch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Divergence {
+    /// The batch is not the one after the follower's latest batch.
+    UnexpectedBatch { expected: usize, published: usize },
+    /// The batch does not start from the follower's root, which is `computed`.
+    InitialRoot { batch: usize, computed: AccRoot },
+    /// The ledger rejects `operation`, the first offending operation of the batch.
+    Rejected { batch: usize, operation: Operation },
+    /// The state diffs do not decode, or write a leaf that does not exist.
+    MalformedStateDiffs { batch: usize },
+    /// The proof does not verify for the batch's roots, state diffs, deposits
+    /// and withdrawals, so the published diffs are not the ones that were proven.
+    InvalidProof { batch: usize },
+    /// The ledger accepts all operations or diffs of the batch, but they end in
+    /// the `computed` root instead of the final root, so the published
+    /// operations are not the ones that were proven.
+    FinalRoot { batch: usize, computed: AccRoot },
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Divergence::UnexpectedBatch { expected, published } => {
+                write!(f, "expected batch {expected}, but batch {published} was published")
+            }
+            Divergence::InitialRoot { batch, .. } => {
+                write!(f, "batch {batch} does not start from the root of the previous batch")
+            }
+            Divergence::Rejected { batch, operation } => write!(f, "{operation} of batch {batch} is invalid"),
+            Divergence::MalformedStateDiffs { batch } => write!(f, "the state diffs of batch {batch} are malformed"),
+            Divergence::InvalidProof { batch } => write!(f, "the proof of batch {batch} does not verify"),
+            Divergence::FinalRoot { batch, .. } => write!(f, "batch {batch} does not end in its final root"),
+        }
+    }
+}
+
+/// A node that rebuilds the state from published batches.
+pub struct Follower {
+    ledger_params: Parameters,
+    state: State,
+}
+
+impl Follower {
+    /// A follower of the ledger with `ledger_params`, starting from `genesis`.
+    pub fn new(ledger_params: Parameters, genesis: State) -> Self {
+        Self {
+            ledger_params,
+            state: genesis,
+        }
+    }
+
+    /// A follower starting from the ledger described by `genesis`. Fails if
+    /// `genesis` does not describe a valid ledger.
+    pub fn from_genesis(ledger_params: Parameters, genesis: &Genesis) -> Result<Self, Error> {
+        let state = genesis.state(&ledger_params)?;
+        Ok(Self::new(ledger_params, state))
+    }
+
+    /// The state after the latest ingested batch.
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    /// The number of the latest ingested batch, or the batch of the genesis
+    /// state before the first one.
+    pub fn latest_batch(&self) -> usize {
+        self.state.latest_batch()
+    }
+
+    /// Check that batch number `batch` is the next one and starts from the
+    /// follower's root.
+    fn check_start(&self, batch: usize, initial_root: AccRoot) -> Result<(), Divergence> {
+        let expected = self.latest_batch() + 1;
+        if batch != expected {
+            return Err(Divergence::UnexpectedBatch { expected, published: batch });
+        }
+        if initial_root != self.state.root() {
+            return Err(Divergence::InitialRoot {
+                batch,
+                computed: self.state.root(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Replay `batch` in the order in which the circuit applies its operations,
+    /// and finalize it if it ends in its final root. Otherwise the state is left
+    /// as it was, and the divergence is returned.
+    pub fn ingest(&mut self, batch: &PublishedBatch) -> Result<(), Divergence> {
+        self.check_start(batch.batch, batch.initial_root)?;
+        let number = batch.batch;
+
+        let pp = &self.ledger_params;
+        let mut state = self.state.clone();
+        let reject = |operation| Divergence::Rejected { batch: number, operation };
+        for (i, tx) in batch.transactions.iter().enumerate() {
+            state.apply_transaction(pp, tx).ok_or(reject(Operation::Transaction(i)))?;
+        }
+        for (i, swap) in batch.swaps.iter().enumerate() {
+            state.apply_swap(pp, swap).ok_or(reject(Operation::Swap(i)))?;
+        }
+        for (i, rotation) in batch.key_rotations.iter().enumerate() {
+            state.apply_key_rotation(pp, rotation).ok_or(reject(Operation::KeyRotation(i)))?;
+        }
+        for (i, tx) in batch.multisig_transactions.iter().enumerate() {
+            state.apply_multisig_transaction(pp, tx).ok_or(reject(Operation::MultisigTransaction(i)))?;
+        }
+        if state.root() != batch.final_root {
+            return Err(Divergence::FinalRoot {
+                batch: number,
+                computed: state.root(),
+            });
+        }
+        // Replace the state before finalizing, so that the history is not shared.
+        self.state = state;
+        self.state.finalize_batch();
+        Ok(())
+    }
+
+    /// Ingest `batches` in order, stopping at the first divergence. Returns the
+    /// number of the latest ingested batch.
+    pub fn sync<'a>(&mut self, batches: impl IntoIterator<Item = &'a PublishedBatch>) -> Result<usize, Divergence> {
+        for batch in batches {
+            self.ingest(batch)?;
+        }
+        Ok(self.latest_batch())
+    }
+
+    /// Verify the proof of `batch` under the `Rollup` circuit's `verifying_key`,
+    /// apply its state diffs, and finalize it if it ends in its final root.
+    /// Otherwise the state is left as it was, and the divergence is returned.
+    pub fn ingest_proven(
+        &mut self,
+        verifying_key: &VerifyingKey<Bls12_381>,
+        batch: &ProvenBatch,
+    ) -> Result<(), Divergence> {
+        let submission = &batch.submission;
+        self.check_start(submission.batch, submission.initial_root)?;
+        let number = submission.batch;
+
+        // The public inputs include the commitment to the decoded diffs, so a
+        // verifying proof attests that they are the diffs of the proven batch.
+        let public_inputs = submission
+            .public_inputs(&self.ledger_params, &batch.deposits)
+            .ok_or(Divergence::MalformedStateDiffs { batch: number })?;
+        let verifies = Groth16::<Bls12_381>::verify(verifying_key, &public_inputs, &submission.proof);
+        if !verifies.unwrap_or(false) {
+            return Err(Divergence::InvalidProof { batch: number });
+        }
+
+        let diffs = da::decode(&submission.state_diffs).ok_or(Divergence::MalformedStateDiffs { batch: number })?;
+        let mut state = self.state.clone();
+        for diff in &diffs {
+            state
+                .apply_state_diff(diff)
+                .ok_or(Divergence::MalformedStateDiffs { batch: number })?;
+        }
+        if state.root() != submission.final_root {
+            return Err(Divergence::FinalRoot {
+                batch: number,
+                computed: state.root(),
+            });
+        }
+        // Replace the state before finalizing, so that the history is not shared.
+        self.state = state;
+        self.state.finalize_batch();
+        Ok(())
+    }
+
+    /// Ingest `batches` with `ingest_proven` in order, stopping at the first
+    /// divergence. Returns the number of the latest ingested batch.
+    pub fn sync_proven<'a>(
+        &mut self,
+        verifying_key: &VerifyingKey<Bls12_381>,
+        batches: impl IntoIterator<Item = &'a ProvenBatch>,
+    ) -> Result<usize, Divergence> {
+        for batch in batches {
+            self.ingest_proven(verifying_key, batch)?;
+        }
+        Ok(self.latest_batch())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ark_simple_payments_scp::account::TokenId;
+    use ark_simple_payments_scp::da::StateDiff;
+    use ark_simple_payments_scp::genesis::GenesisAccount;
+    use ark_simple_payments_scp::ledger::Amount;
+    use ark_std::rand::{rngs::StdRng, RngCore, SeedableRng};
+
+    #[test]
+    fn follower_replays_batches_and_reports_divergence() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, alice_pk, alice_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, TokenId(0), Amount(20)).unwrap();
+        let (bob_id, bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(bob_id, TokenId(0), Amount(10)).unwrap();
+        let genesis = state.clone();
+
+        // The operator sequences two batches.
+        let mut published = Vec::new();
+        for (batch, amount) in [(1, 5), (2, 3)] {
+            let txs = [
+                Transaction::create(&pp, alice_id, bob_id, TokenId(0), Amount(amount), &alice_sk, &alice_pk, &mut rng),
+                Transaction::create(&pp, bob_id, alice_id, TokenId(0), Amount(1), &bob_sk, &bob_pk, &mut rng),
+            ];
+            let rollup = Rollup::<2>::with_state_and_transactions(pp.clone(), &txs, &mut state, true).unwrap();
+            published.push(PublishedBatch::from_rollup(batch, &rollup).unwrap());
+        }
+
+        // The same genesis, loaded from its file.
+        let genesis_file = Genesis {
+            num_accounts: 32,
+            accounts: vec![
+                GenesisAccount { public_key: alice_pk, balances: vec![20] },
+                GenesisAccount { public_key: bob_pk, balances: vec![10] },
+            ],
+        };
+        let mut follower = Follower::from_genesis(pp.clone(), &genesis_file).unwrap();
+        assert_eq!(follower.state().root(), genesis.root());
+        assert_eq!(published[0].initial_root, genesis_file.root(&pp).unwrap());
+        assert_eq!(
+            follower.ingest(&published[1]),
+            Err(Divergence::UnexpectedBatch { expected: 1, published: 2 })
+        );
+        assert_eq!(follower.sync(&published), Ok(2));
+        assert_eq!(follower.state().root(), state.root());
+        assert_eq!(follower.state().balance(bob_id, TokenId(0)), Some(Amount(16)));
+
+        // The second transaction of a batch is signed with the wrong key.
+        let mut forged = published[0].clone();
+        forged.transactions[1] =
+            Transaction::create(&pp, bob_id, alice_id, TokenId(0), Amount(1), &alice_sk, &alice_pk, &mut rng);
+        let mut follower = Follower::new(pp.clone(), genesis.clone());
+        let divergence = follower.ingest(&forged).unwrap_err();
+        assert_eq!(divergence, Divergence::Rejected { batch: 1, operation: Operation::Transaction(1) });
+        assert_eq!(divergence.to_string(), "transaction 1 of batch 1 is invalid");
+        assert_eq!(follower.state().root(), genesis.root());
+
+        // The published transactions are valid, but not the ones that were proven.
+        let mut swapped = published[0].clone();
+        swapped.transactions = published[1].transactions.clone();
+        assert!(matches!(follower.ingest(&swapped), Err(Divergence::FinalRoot { batch: 1, .. })));
+
+        // A batch that does not start from the root of the previous one.
+        follower.ingest(&published[0]).unwrap();
+        let mut stale = published[1].clone();
+        stale.initial_root = genesis.root();
+        assert!(matches!(follower.ingest(&stale), Err(Divergence::InitialRoot { batch: 2, .. })));
+        assert_eq!(follower.latest_batch(), 1);
+    }
+
+    #[test]
+    fn follower_snark_applies_proven_diffs_and_reports_divergence() {
+        let mut rng = StdRng::seed_from_u64(ark_std::test_rng().next_u64());
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, alice_pk, alice_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, TokenId(0), Amount(20)).unwrap();
+        let (bob_id, bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(bob_id, TokenId(0), Amount(10)).unwrap();
+        let genesis = state.clone();
+
+        // The operator sequences and proves two batches.
+        let transfers = |amount, rng: &mut StdRng| {
+            [
+                Transaction::create(&pp, alice_id, bob_id, TokenId(0), Amount(amount), &alice_sk, &alice_pk, rng),
+                Transaction::create(&pp, bob_id, alice_id, TokenId(0), Amount(1), &bob_sk, &bob_pk, rng),
+            ]
+        };
+        let setup_txs = transfers(1, &mut rng);
+        let setup_circuit =
+            Rollup::<2>::with_state_and_transactions(pp.clone(), &setup_txs, &mut state.clone(), true).unwrap();
+        let (pk, vk) = Groth16::<Bls12_381>::circuit_specific_setup(setup_circuit, &mut rng).unwrap();
+        let mut published = Vec::new();
+        for (batch, amount) in [(1, 5), (2, 3)] {
+            let txs = transfers(amount, &mut rng);
+            let rollup = Rollup::<2>::with_state_and_transactions(pp.clone(), &txs, &mut state, true).unwrap();
+            let submission = BatchSubmission {
+                batch,
+                initial_root: rollup.initial_root.unwrap(),
+                final_root: rollup.final_root.unwrap(),
+                state_diffs: da::encode(rollup.state_diffs.as_ref().unwrap()),
+                proof: Groth16::<Bls12_381>::prove(&pk, rollup, &mut rng).unwrap(),
+                num_deposits: 0,
+                withdrawals: Vec::new(),
+            };
+            published.push(ProvenBatch { submission, deposits: Vec::new() });
+        }
+
+        let mut follower = Follower::new(pp.clone(), genesis.clone());
+        assert_eq!(
+            follower.ingest_proven(&vk, &published[1]),
+            Err(Divergence::UnexpectedBatch { expected: 1, published: 2 })
+        );
+        assert_eq!(follower.sync_proven(&vk, &published), Ok(2));
+        assert_eq!(follower.state().root(), state.root());
+        assert_eq!(follower.state().balance(bob_id, TokenId(0)), Some(Amount(16)));
+
+        // The published diffs credit Bob more than the proven batch did.
+        let mut forged = published[0].clone();
+        let mut diffs = da::decode(&forged.submission.state_diffs).unwrap();
+        for diff in &mut diffs {
+            if let StateDiff::Balance { account, balance, .. } = diff {
+                if *account == bob_id {
+                    balance.0 += 100;
+                }
+            }
+        }
+        forged.submission.state_diffs = da::encode(&diffs);
+        let mut follower = Follower::new(pp.clone(), genesis.clone());
+        let divergence = follower.ingest_proven(&vk, &forged).unwrap_err();
+        assert_eq!(divergence, Divergence::InvalidProof { batch: 1 });
+        assert_eq!(divergence.to_string(), "the proof of batch 1 does not verify");
+        assert_eq!(follower.state().root(), genesis.root());
+
+        // The published diffs are those of another batch.
+        let mut swapped = published[0].clone();
+        swapped.submission.state_diffs = published[1].submission.state_diffs.clone();
+        assert_eq!(follower.ingest_proven(&vk, &swapped), Err(Divergence::InvalidProof { batch: 1 }));
+        let mut truncated = published[0].clone();
+        truncated.submission.state_diffs = vec![2];
+        assert_eq!(
+            follower.ingest_proven(&vk, &truncated),
+            Err(Divergence::MalformedStateDiffs { batch: 1 })
+        );
+
+        // A batch that does not start from the root of the previous one.
+        follower.ingest_proven(&vk, &published[0]).unwrap();
+        let mut stale = published[1].clone();
+        stale.submission.initial_root = genesis.root();
+        assert!(matches!(
+            follower.ingest_proven(&vk, &stale),
+            Err(Divergence::InitialRoot { batch: 2, .. })
+        ));
+        assert_eq!(follower.latest_batch(), 1);
+    }
+}
@@ -1,21 +1,33 @@
+use crate::account::{AccountPublicKeyVar, AccountSignatureVar};
 use crate::ConstraintF;
-use ark_crypto_primitives::crh::injective_map::constraints::{
-    PedersenCRHCompressorGadget, PedersenTwoToOneCRHCompressorGadget, TECompressorGadget,
-};
-use ark_crypto_primitives::crh::{pedersen, TwoToOneCRHScheme, CRHScheme, CRHSchemeGadget, TwoToOneCRHSchemeGadget};
-use ark_crypto_primitives::crh::injective_map::{PedersenCRHCompressor, PedersenTwoToOneCRHCompressor, TECompressor};
-use ark_crypto_primitives::merkle_tree::constraints::PathVar;
-use ark_ed_on_bls12_381::{constraints::EdwardsVar, EdwardsProjective, Fq};
+use ark_crypto_primitives::crh::{pedersen, CRHSchemeGadget};
+use ark_ed_on_bls12_381::{constraints::EdwardsVar, EdwardsProjective};
+use ark_ff::{BigInteger, One, PrimeField};
 use ark_r1cs_std::bits::uint64::UInt64;
+use ark_r1cs_std::fields::fp::FpVar;
 use ark_r1cs_std::prelude::*;
 use ark_relations::r1cs::{Namespace, SynthesisError};
 use ark_simple_payments_scp::ledger::*;
-use ark_simple_payments_scp::ledger::JubJubMerkleTreeParamsVar as JubJubMTP;
-use ark_simple_payments_scp::signature::schnorr::constraints::ParametersVar as SchnorrParamsVar;
-use ark_crypto_primitives::merkle_tree::constraints::ConfigGadget as MTConfigGad;
+use ark_simple_payments_scp::account::AccountSignatureScheme;
+use ark_simple_payments_scp::da::{DiffHash, DiffWindow};
+use ark_simple_payments_scp::commitment::{
+    pedersen::{constraints::{CommGadget, RandomnessVar}, Randomness},
+    CommitmentGadget,
+};
+use ark_simple_payments_scp::signature::eddsa::constraints::{EdDSAVerifyGadget, ParametersVar as EdDSAParamsVar};
+use ark_simple_payments_scp::signature::schnorr::constraints::{
+    ParametersVar as SchnorrParamsVar, SchnorrSignatureVerifyGadget,
+};
+use merkle_groth::constraints::{DigestVar, InnerParamVar, PathVar};
+pub use merkle_groth::jubjub::LeafHashParamsVar;
 use std::borrow::Borrow;
 
 /// Represents transaction amounts and account balances.
+///
+/// Amounts are 64-bit integers, and the field is much larger than `2^128`, so
+/// sums and products of two amounts never wrap around the field modulus. The
+/// checked operations therefore compute the exact result as a field element and
+/// range-check it to 64 bits by decomposing it into bits.
 #[derive(Clone, Debug)]
 pub struct AmountVar(pub UInt64<ConstraintF>);
 
@@ -25,44 +37,64 @@ impl AmountVar {
         self.0.to_bytes().unwrap()
     }
 
+    /// The amount as a field element.
+    #[tracing::instrument(target = "r1cs", skip(self))]
+    pub fn to_fp_var(&self) -> Result<FpVar<ConstraintF>, SynthesisError> {
+        Boolean::le_bits_to_fp_var(&self.0.to_bits_le())
+    }
+
+    /// Decompose `value` into `num_bits` little-endian bits, enforcing that it
+    /// is smaller than `2^num_bits`. Witnesses of out-of-range values are
+    /// assigned their low bits, which leaves the constraints unsatisfied.
+    #[tracing::instrument(target = "r1cs", skip(value))]
+    fn range_check(value: &FpVar<ConstraintF>, num_bits: usize) -> Result<Vec<Boolean<ConstraintF>>, SynthesisError> {
+        let cs = value.cs();
+        let mode = if cs.is_none() {
+            AllocationMode::Constant
+        } else {
+            AllocationMode::Witness
+        };
+        let bits = (0..num_bits)
+            .map(|i| {
+                Boolean::new_variable(cs.clone(), || Ok(value.value()?.into_bigint().get_bit(i)), mode)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Boolean::le_bits_to_fp_var(&bits)?.enforce_equal(value)?;
+        Ok(bits)
+    }
+
+    /// Convert `value` to an amount, enforcing that it is smaller than `2^64`.
+    #[tracing::instrument(target = "r1cs", skip(value))]
+    pub fn from_fp_var_checked(value: &FpVar<ConstraintF>) -> Result<Self, SynthesisError> {
+        Self::range_check(value, 64).map(|bits| Self(UInt64::from_bits_le(&bits)))
+    }
+
+    /// `self + other`, enforcing that the sum does not overflow 64 bits.
     #[tracing::instrument(target = "r1cs", skip(self, other))]
     pub fn checked_add(&self, other: &Self) -> Result<Self, SynthesisError> {
-        // To do a checked add, we add two uint64's directly.
-        // We also check for overflow, by casting them to field elements,
-        // adding the field element representation
-        // converting the field elements to bits
-        // and then checking if the 65th bit is 0.
-        let self_bits = self.0.to_bits_le();
-        let self_fe = Boolean::le_bits_to_fp_var(&self_bits)?;
-        let other_bits = other.0.to_bits_le();
-        let other_fe = Boolean::le_bits_to_fp_var(&other_bits)?;
-        let res_fe = self_fe + other_fe;
-        let res_bz = res_fe.to_bytes()?;
-        // Ensure 65th bit is 0
-        // implies 8th word (0-indexed) is 0
-        res_bz[8].enforce_equal(&UInt8::<ConstraintF>::constant(0))?;
-        // Add sum
-        let result = UInt64::addmany(&[self.0.clone(), other.0.clone()])?;
-        Ok(AmountVar(result))
+        Self::from_fp_var_checked(&(self.to_fp_var()? + other.to_fp_var()?))
     }
 
+    /// `self - other`, enforcing that `other <= self`. Otherwise the difference
+    /// wraps around the field modulus to a value far above `2^64`.
     #[tracing::instrument(target = "r1cs", skip(self, other))]
     pub fn checked_sub(&self, other: &Self) -> Result<Self, SynthesisError> {
-        // To do a checked sub, we convert the uints to a field element.
-        // We do the sub on the field element.
-        // We then cast the field element to bits, and ensure the top bits are 0.
-        // We then convert these bits to a field element
-        let self_bits = self.0.to_bits_le();
-        let self_fe = Boolean::le_bits_to_fp_var(&self_bits)?;
-        let other_bits = other.0.to_bits_le();
-        let other_fe = Boolean::le_bits_to_fp_var(&other_bits)?;
-        let res_fe = self_fe - other_fe;
-        let res_bz = res_fe.to_bytes()?;
-        // Ensure top bit is 0
-        res_bz[res_bz.len() - 1].enforce_equal(&UInt8::<ConstraintF>::constant(0))?;
-        // Convert to UInt64
-        let res = UInt64::from_bits_le(&res_fe.to_bits_le()?[..64]);
-        Ok(AmountVar(res))
+        Self::from_fp_var_checked(&(self.to_fp_var()? - other.to_fp_var()?))
+    }
+
+    /// `self * other`, enforcing that the product does not overflow 64 bits.
+    #[tracing::instrument(target = "r1cs", skip(self, other))]
+    pub fn checked_mul(&self, other: &Self) -> Result<Self, SynthesisError> {
+        Self::from_fp_var_checked(&(self.to_fp_var()? * other.to_fp_var()?))
+    }
+
+    /// Whether `self <= other`. This is never unsatisfiable: `other - self + 2^64`
+    /// lies in `[1, 2^65)`, and its bit 64 is set exactly if `self <= other`.
+    #[tracing::instrument(target = "r1cs", skip(self, other))]
+    pub fn is_less_or_equal(&self, other: &Self) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        let two_to_64 = FpVar::constant(ConstraintF::from(u64::MAX) + ConstraintF::one());
+        let shifted = other.to_fp_var()? - self.to_fp_var()? + two_to_64;
+        Ok(Self::range_check(&shifted, 65)?.pop().unwrap())
     }
 }
 
@@ -77,35 +109,158 @@ impl AllocVar<Amount, ConstraintF> for AmountVar {
     }
 }
 
-pub type TwoToOneHashGadget = PedersenTwoToOneCRHCompressorGadget<
-    EdwardsProjective,
-    TECompressor,
-    Window4x256,
-    EdwardsVar,
-    TECompressorGadget,
->;
-
-pub type LeafHashGadget = PedersenCRHCompressorGadget<
-    EdwardsProjective,
-    TECompressor,
-    Window4x256,
-    EdwardsVar,
-    TECompressorGadget,
->;
-
-pub type LeafHash = PedersenCRHCompressor<EdwardsProjective, TECompressor, Window4x256>;
-pub type TwoToOneHash = PedersenTwoToOneCRHCompressor<EdwardsProjective, TECompressor, Window4x256>;
-
-pub type AccRootVar = <JubJubMTP as MTConfigGad<JubJubMerkleTreeParams,ConstraintF>>::InnerDigest;
-pub type AccPathVar = PathVar<JubJubMerkleTreeParams, Fq, JubJubMerkleTreeParamsVar>;
-pub type LeafHashParamsVar = <LeafHashGadget as CRHSchemeGadget<LeafHash, ConstraintF>>::ParametersVar;
-pub type TwoToOneHashParamsVar = <TwoToOneHashGadget as TwoToOneCRHSchemeGadget<TwoToOneHash, ConstraintF>>::ParametersVar;
+/// Gadget for `AmountCommitmentScheme`.
+pub type AmountCommitmentGadget = CommGadget<EdwardsProjective, EdwardsVar, AmountWindow>;
+pub type AmountCommitmentParamsVar =
+    <AmountCommitmentGadget as CommitmentGadget<AmountCommitmentScheme, ConstraintF>>::ParametersVar;
+/// The little-endian bytes of the blinding of an `AmountCommitment`.
+pub type AmountBlindingVar = RandomnessVar<ConstraintF>;
+
+/// A commitment to an amount; see `AmountCommitment`.
+#[derive(Clone)]
+pub struct AmountCommitmentVar {
+    pub commitment: EdwardsVar,
+    /// The blinding, if known in-circuit. Commitments that are computed from
+    /// other commitments do not carry one, since the blindings are not field
+    /// elements of `ConstraintF`.
+    pub blinding: Option<AmountBlindingVar>,
+}
+
+impl AmountCommitmentVar {
+    /// Whether this commitment opens to `amount`, mirroring `AmountCommitment::opens_to`.
+    /// Commitments without a blinding never open.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, amount))]
+    pub fn opens_to(
+        &self,
+        parameters: &ParametersVar,
+        amount: &AmountVar,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        match &self.blinding {
+            Some(blinding) => AmountCommitmentGadget::commit(
+                &parameters.amount_commitment_params,
+                &amount.to_bytes_le(),
+                blinding,
+            )?
+            .is_eq(&self.commitment),
+            None => Ok(Boolean::FALSE),
+        }
+    }
+
+    /// A commitment to the sum of the committed amounts.
+    #[tracing::instrument(target = "r1cs", skip(self, other))]
+    pub fn add(&self, other: &Self) -> Self {
+        Self {
+            commitment: self.commitment.clone() + &other.commitment,
+            blinding: None,
+        }
+    }
+
+    /// A commitment to the difference of the committed amounts.
+    #[tracing::instrument(target = "r1cs", skip(self, other))]
+    pub fn sub(&self, other: &Self) -> Self {
+        Self {
+            commitment: self.commitment.clone() - &other.commitment,
+            blinding: None,
+        }
+    }
+}
+
+impl AllocVar<AmountCommitment, ConstraintF> for AmountCommitmentVar {
+    #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
+    fn new_variable<T: Borrow<AmountCommitment>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        let cs = cs.into();
+        f().and_then(|commitment| {
+            let commitment = commitment.borrow();
+            Ok(Self {
+                commitment: EdwardsVar::new_variable(cs.clone(), || Ok(commitment.commitment), mode)?,
+                blinding: Some(AmountBlindingVar::new_variable(
+                    cs.clone(),
+                    || Ok(Randomness::<EdwardsProjective>(commitment.blinding)),
+                    mode,
+                )?),
+            })
+        })
+    }
+}
+
+/// The root of the account Merkle tree, in the circuit.
+pub type AccRootVar = DigestVar<AccTreeParams, ConstraintF, AccTreeParamsVar>;
+/// A membership proof for an `(account, token)` leaf, in the circuit.
+pub type AccPathVar = PathVar<AccTreeParams, ConstraintF, AccTreeParamsVar>;
+pub type InnerHashParamsVar = InnerParamVar<AccTreeParams, ConstraintF, AccTreeParamsVar>;
+/// Check that `path` opens the leaf whose index has the little-endian bits
+/// `index_bits`. Bits missing on either side are treated as zero, so an index
+/// that does not fit in the tree never matches.
+#[tracing::instrument(target = "r1cs", skip(path, index_bits))]
+pub fn path_is_at_index(
+    path: &AccPathVar,
+    index_bits: &[Boolean<ConstraintF>],
+) -> Result<Boolean<ConstraintF>, SynthesisError> {
+    let position = &path.leaf_index_bits;
+    let len = position.len().max(index_bits.len());
+    let mut matches = Vec::with_capacity(len);
+    for i in 0..len {
+        let position_bit = position.get(i).cloned().unwrap_or(Boolean::FALSE);
+        let index_bit = index_bits.get(i).cloned().unwrap_or(Boolean::FALSE);
+        matches.push(position_bit.is_eq(&index_bit)?);
+    }
+    Boolean::kary_and(&matches)
+}
+
+/// Gadget for `PolicyH`, deriving a multisig account's key from its policy.
+pub type PolicyHashGadget = pedersen::constraints::CRHGadget<EdwardsProjective, EdwardsVar, PolicyWindow>;
+
+pub type PolicyHashParamsVar = <PolicyHashGadget as CRHSchemeGadget<PolicyH, ConstraintF>>::ParametersVar;
+
+/// Gadget for `DiffHash`, committing to the state diffs of a batch.
+pub type DiffHashGadget = pedersen::constraints::CRHGadget<EdwardsProjective, EdwardsVar, DiffWindow>;
+
+pub type DiffHashParamsVar = <DiffHashGadget as CRHSchemeGadget<DiffHash, ConstraintF>>::ParametersVar;
 
 /// The parameters that are used in transaction creation and validation.
 pub struct ParametersVar {
     pub sig_params: SchnorrParamsVar<EdwardsProjective, EdwardsVar>,
     pub leaf_crh_params: LeafHashParamsVar,
-    pub two_to_one_crh_params: TwoToOneHashParamsVar,
+    pub inner_crh_params: InnerHashParamsVar,
+    /// The signing domain is fixed per deployment, so it is baked into the
+    /// circuit as constant bytes.
+    pub domain: SigningDomain,
+    pub policy_crh_params: PolicyHashParamsVar,
+    /// Like the domain, the signature scheme is fixed per deployment, so the
+    /// circuit only contains the gadget for that scheme.
+    pub signature_scheme: AccountSignatureScheme,
+    pub eddsa_params: EdDSAParamsVar,
+    pub amount_commitment_params: AmountCommitmentParamsVar,
+    /// Whether leaves hold balance commitments. This is fixed per deployment too.
+    pub confidential_amounts: bool,
+    pub diff_crh_params: DiffHashParamsVar,
+}
+
+impl ParametersVar {
+    /// Verify `signature` on `message` under `public_key`, mirroring
+    /// `Parameters::verify_signature`. A signature of another scheme than
+    /// `signature_scheme` yields the constant `false`.
+    #[tracing::instrument(target = "r1cs", skip(self, public_key, message, signature))]
+    pub fn verify_signature(
+        &self,
+        public_key: &AccountPublicKeyVar,
+        message: &[UInt8<ConstraintF>],
+        signature: &AccountSignatureVar,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        match (self.signature_scheme, signature) {
+            (AccountSignatureScheme::Schnorr, AccountSignatureVar::Schnorr(signature)) => {
+                SchnorrSignatureVerifyGadget::verify(&self.sig_params, public_key, message, signature)
+            }
+            (AccountSignatureScheme::EdDSA, AccountSignatureVar::EdDSA(signature)) => {
+                EdDSAVerifyGadget::verify(&self.eddsa_params, &public_key.pub_key, message, signature)
+            }
+            _ => Ok(Boolean::FALSE),
+        }
+    }
 }
 
 impl AllocVar<Parameters, ConstraintF> for ParametersVar {
@@ -121,13 +276,121 @@ impl AllocVar<Parameters, ConstraintF> for ParametersVar {
             let sig_params = SchnorrParamsVar::new_constant(cs.clone(), &params.sig_params)?;
             let leaf_crh_params =
                 LeafHashParamsVar::new_constant(cs.clone(), &params.leaf_crh_params)?;
-            let two_to_one_crh_params =
-                TwoToOneHashParamsVar::new_constant(cs.clone(), &params.two_to_one_crh_params)?;
+            let inner_crh_params =
+                InnerHashParamsVar::new_constant(cs.clone(), &params.inner_crh_params)?;
+            let policy_crh_params =
+                PolicyHashParamsVar::new_constant(cs.clone(), &params.policy_crh_params)?;
+            let eddsa_params = EdDSAParamsVar::new_constant(cs.clone(), &params.eddsa_params)?;
+            let amount_commitment_params =
+                AmountCommitmentParamsVar::new_constant(cs.clone(), &params.amount_commitment_params)?;
+            let diff_crh_params = DiffHashParamsVar::new_constant(cs.clone(), &params.diff_crh_params)?;
             Ok(Self {
                 sig_params,
                 leaf_crh_params,
-                two_to_one_crh_params,
+                inner_crh_params,
+                domain: params.domain,
+                policy_crh_params,
+                signature_scheme: params.signature_scheme,
+                eddsa_params,
+                amount_commitment_params,
+                confidential_amounts: params.confidential_amounts,
+                diff_crh_params,
             })
         })
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::AmountVar;
+    use crate::ConstraintF;
+    use ark_r1cs_std::prelude::*;
+    use ark_relations::r1cs::{ConstraintSystem, ConstraintSystemRef, Variable};
+    use ark_simple_payments_scp::ledger::Amount;
+
+    const SAMPLES: [u64; 6] = [0, 1, 2, 1 << 32, u64::MAX - 1, u64::MAX];
+
+    type GadgetOp = fn(&AmountVar, &AmountVar) -> AmountVar;
+    type NativeOp = fn(u64, u64) -> Option<u64>;
+
+    fn amount(cs: &ConstraintSystemRef<ConstraintF>, value: u64) -> AmountVar {
+        AmountVar::new_witness(cs.clone(), || Ok(Amount(value))).unwrap()
+    }
+
+    /// Replace the witness of every bit of `result` by the bits of `value`.
+    fn assign_bits(cs: &ConstraintSystemRef<ConstraintF>, result: &AmountVar, value: u64) {
+        let mut cs = cs.borrow_mut().unwrap();
+        for (i, bit) in result.0.to_bits_le().iter().enumerate() {
+            match bit {
+                Boolean::Is(bit) => match bit.variable() {
+                    Variable::Witness(index) => cs.witness_assignment[index] = ((value >> i) & 1).into(),
+                    _ => unreachable!("bits of a range check are witnesses"),
+                },
+                _ => unreachable!("bits of a range check are witnesses"),
+            }
+        }
+    }
+
+    #[test]
+    fn amount_arithmetic_matches_native() {
+        let ops: [(GadgetOp, NativeOp); 3] = [
+            (|a, b| a.checked_add(b).unwrap(), u64::checked_add),
+            (|a, b| a.checked_sub(b).unwrap(), u64::checked_sub),
+            (|a, b| a.checked_mul(b).unwrap(), u64::checked_mul),
+        ];
+        for a in SAMPLES {
+            for b in SAMPLES {
+                for (gadget, native) in ops {
+                    let cs = ConstraintSystem::new_ref();
+                    let result = gadget(&amount(&cs, a), &amount(&cs, b));
+                    let expected = native(a, b);
+                    assert_eq!(cs.is_satisfied().unwrap(), expected.is_some(), "{a} {b}");
+                    if let Some(expected) = expected {
+                        assert_eq!(result.0.value().unwrap(), expected);
+                    }
+                }
+
+                let cs = ConstraintSystem::new_ref();
+                let is_le = amount(&cs, a).is_less_or_equal(&amount(&cs, b)).unwrap();
+                assert_eq!(is_le.value().unwrap(), a <= b, "{a} {b}");
+                assert!(cs.is_satisfied().unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn amount_arithmetic_rejects_wrapped_witnesses() {
+        // Results that are correct modulo 2^64, but not over the integers.
+        let cases: [(u64, u64, GadgetOp, u64); 3] = [
+            (3, 5, |a, b| a.checked_sub(b).unwrap(), 3u64.wrapping_sub(5)),
+            (u64::MAX, 2, |a, b| a.checked_add(b).unwrap(), u64::MAX.wrapping_add(2)),
+            (1 << 32, 1 << 33, |a, b| a.checked_mul(b).unwrap(), 0),
+        ];
+        for (a, b, gadget, wrapped) in cases {
+            let cs = ConstraintSystem::new_ref();
+            let result = gadget(&amount(&cs, a), &amount(&cs, b));
+            assign_bits(&cs, &result, wrapped);
+            assert_eq!(result.0.value().unwrap(), wrapped);
+            assert!(!cs.is_satisfied().unwrap());
+        }
+
+        // In range, the honest result is the only satisfying one. The constraint
+        // system caches evaluations, so it is only checked after the assignment.
+        for (claimed, satisfied) in [(2, true), (3, false)] {
+            let cs = ConstraintSystem::new_ref();
+            let result = amount(&cs, 5).checked_sub(&amount(&cs, 3)).unwrap();
+            assign_bits(&cs, &result, claimed);
+            assert_eq!(cs.is_satisfied().unwrap(), satisfied);
+        }
+    }
+
+    #[test]
+    fn constant_amounts_need_no_witnesses() {
+        let cs = ConstraintSystem::<ConstraintF>::new_ref();
+        let a = AmountVar::new_constant(cs.clone(), Amount(7)).unwrap();
+        let b = AmountVar::new_constant(cs.clone(), Amount(5)).unwrap();
+        assert_eq!(a.checked_sub(&b).unwrap().0.value().unwrap(), 2);
+        assert!(!a.is_less_or_equal(&b).unwrap().value().unwrap());
+        assert_eq!(cs.num_witness_variables(), 0);
+    }
+}
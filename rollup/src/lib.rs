@@ -1,6 +1,12 @@
 pub type ConstraintF = ark_bls12_381::Fr;
 
 pub mod account;
+pub mod bridge;
+pub mod da;
+pub mod diagnostics;
+pub mod exit;
+pub mod export;
+pub mod follower;
 pub mod ledger;
 pub mod transaction;
 
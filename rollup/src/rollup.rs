@@ -1,21 +1,166 @@
 use crate::account::AccountInformationVar;
+use crate::bridge::{BridgeTransfers, BridgeTransfersVar};
+use crate::da::{self, DiffCommitmentVar};
+use crate::diagnostics::{all_hold, Check, ConstraintReport, Diagnosis, Operation, OperationTrace};
+use crate::export;
 use crate::ledger::*;
-use crate::transaction::TransactionVar;
+use crate::transaction::{
+    KeyRotationVar, LeafUpdateVar, MultisigTransactionVar, SwapVar, TransactionVar, TransferLegVar,
+};
 use crate::ConstraintF;
-use ark_bls12_381::Fr;
 use ark_r1cs_std::prelude::*;
-use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+use ark_crypto_primitives::Error;
+use ark_relations::r1cs::{
+    ConstraintLayer, ConstraintSynthesizer, ConstraintSystem, ConstraintSystemRef, SynthesisError, SynthesisMode,
+};
 use ark_simple_payments_scp::{
-    account::AccountInformation,
-    ledger::{AccPath, AccRoot, Parameters, State},
-    transaction::Transaction,
+    account::{AccountId, AccountInformation, TokenId, NUM_TOKENS},
+    da::{DiffCommitment, StateDiff},
+    ledger::{AccPath, AccRoot, Amount, AmountCommitment, AmountOpening, Parameters, State},
+    transaction::{KeyRotation, MultisigTransaction, Swap, Transaction},
 };
-use ark_std::rand::{SeedableRng, RngCore};
-use ark_ec::AffineRepr;
-use ark_relations::r1cs::ToConstraintField;
-use ark_groth16::r1cs_to_qap::LibsnarkReduction;
+use tracing_subscriber::layer::SubscriberExt;
+
+/// An r1cs span whose name carries the index `i` of a loop iteration, e.g.
+/// `transaction[3]`. The witnesses and checks of the iteration are created in
+/// it, so that the constraint paths of different iterations, such as those
+/// returned by `ConstraintSystemRef::which_is_unsatisfied`, differ. Span names
+/// are static, so indices from 32 on share the name `transaction[..]` and
+/// only carry the index as a field.
+macro_rules! indexed_span {
+    ($name:literal, $i:expr) => {
+        indexed_span!(@ $name, $i, [
+            0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25 26 27 28 29 30 31
+        ])
+    };
+    (@ $name:literal, $i:expr, [$($n:literal)*]) => {
+        match $i {
+            $($n => tracing::info_span!(target: "r1cs", concat!($name, "[", $n, "]")),)*
+            i => tracing::info_span!(target: "r1cs", concat!($name, "[..]"), index = i),
+        }
+    };
+}
+
+/// The witness for one transfer of funds between two `(account, token)` leaves;
+/// see `TransferLegVar`.
+#[derive(Clone)]
+pub struct TransferLeg {
+    /// The sender's account information and authentication path before the transfer.
+    pub sender_pre_info_and_path: (AccountInformation, AccPath),
+    /// The recipient's account information and authentication path once the
+    /// sender's leaf is updated. For a self-transfer, this is the sender's
+    /// leaf after the debit.
+    pub recipient_pre_info_and_path: (AccountInformation, AccPath),
+    /// The state root before the transfer.
+    pub pre_root: AccRoot,
+    /// The state root after the transfer.
+    pub post_root: AccRoot,
+}
+
+impl TransferLeg {
+    /// Record the witness for updating the `(sender, token)` and `(recipient, token)`
+    /// leaves of `state` with `apply`, which moves `amount`, committed to by
+    /// `amount_commitment` if the ledger has confidential amounts. If `apply`
+    /// fails, the witness still describes the untouched state (so that invalid
+    /// batches can be tested), unless `validate` is set, in which case `None` is
+    /// returned.
+    #[allow(clippy::too_many_arguments)]
+    fn record(
+        state: &mut State,
+        sender: AccountId,
+        recipient: AccountId,
+        token: TokenId,
+        amount: Amount,
+        amount_commitment: Option<&AmountCommitment>,
+        validate: bool,
+        apply: impl FnOnce(&mut State) -> Option<()>,
+    ) -> Option<Self> {
+        let sender_index = sender.leaf_index(token);
+        let recipient_index = recipient.leaf_index(token);
+        let pre_root = state.root();
+        let sender_pre_acc_info = *state.account_info(sender, token)?;
+        let sender_pre_path = state
+            .account_merkle_tree
+            .generate_proof(sender_index)
+            .unwrap();
+        // The circuit opens the recipient's leaf once the sender's leaf is
+        // updated, so a self-transfer credits the debited leaf.
+        let mut recipient_pre_acc_info = *state.account_info(recipient, token)?;
+        if recipient_index == sender_index {
+            if let Some(balance) = sender_pre_acc_info.balance.checked_sub(amount) {
+                recipient_pre_acc_info.balance = balance;
+                recipient_pre_acc_info.balance_commitment = sender_pre_acc_info
+                    .balance_commitment
+                    .zip(amount_commitment)
+                    .map(|(balance, amount)| balance - *amount);
+            }
+        }
+
+        if apply(state).is_none() && validate {
+            return None;
+        }
+        let post_root = state.root();
+        // The siblings of the recipient's leaf do not depend on that leaf, so
+        // they are the same as in the tree in which only the sender's leaf is updated.
+        let recipient_path = state
+            .account_merkle_tree
+            .generate_proof(recipient_index)
+            .unwrap();
+        Some(Self {
+            sender_pre_info_and_path: (sender_pre_acc_info, sender_pre_path),
+            recipient_pre_info_and_path: (recipient_pre_acc_info, recipient_path),
+            pre_root,
+            post_root,
+        })
+    }
+}
+
+/// The witness for an update of a single `(account, token)` leaf; see `LeafUpdateVar`.
+#[derive(Clone)]
+pub struct LeafUpdate {
+    /// The account information and authentication path before the update.
+    pub pre_info_and_path: (AccountInformation, AccPath),
+    /// The state root before the update.
+    pub pre_root: AccRoot,
+    /// The state root after the update.
+    pub post_root: AccRoot,
+}
+
+impl LeafUpdate {
+    /// Record the witness for updating the `(account, token)` leaf of `state` with
+    /// `apply`. If `apply` fails, the witness still describes the untouched state,
+    /// unless `validate` is set, in which case `None` is returned.
+    fn record(
+        state: &mut State,
+        account: AccountId,
+        token: TokenId,
+        validate: bool,
+        apply: impl FnOnce(&mut State) -> Option<()>,
+    ) -> Option<Self> {
+        let index = account.leaf_index(token);
+        let pre_root = state.root();
+        let pre_acc_info = *state.account_info(account, token)?;
+        let pre_path = state.account_merkle_tree.generate_proof(index).unwrap();
+        if apply(state).is_none() && validate {
+            return None;
+        }
+        let post_root = state.root();
+        Some(Self {
+            pre_info_and_path: (pre_acc_info, pre_path),
+            pre_root,
+            post_root,
+        })
+    }
+}
 
-pub struct Rollup<const NUM_TX: usize> {
+/// A batch of `NUM_TX` transactions, followed by `NUM_SWAPS` swaps, followed by
+/// `NUM_ROTATIONS` key rotations, followed by `NUM_MULTISIG_TX` multisig transactions.
+pub struct Rollup<
+    const NUM_TX: usize,
+    const NUM_SWAPS: usize = 0,
+    const NUM_ROTATIONS: usize = 0,
+    const NUM_MULTISIG_TX: usize = 0,
+> {
     /// The ledger parameters.
     pub ledger_params: Parameters,
     /// The Merkle tree root before applying this batch of transactions.
@@ -24,39 +169,70 @@ pub struct Rollup<const NUM_TX: usize> {
     pub final_root: Option<AccRoot>,
     /// The current batch of transactions.
     pub transactions: Option<Vec<Transaction>>,
+    /// The opening of the amount commitment of each transaction that carries
+    /// one, which the prover receives privately from the sender.
+    pub amount_openings: Option<Vec<Option<AmountOpening>>>,
     /// The sender's account information and corresponding authentication path,
     /// *before* applying the transactions.
     pub sender_pre_tx_info_and_paths: Option<Vec<(AccountInformation, AccPath)>>,
-    /// The authentication path corresponding to the sender's account information
-    /// *after* applying the transactions.
-    pub sender_post_paths: Option<Vec<AccPath>>,
-    /// The recipient's account information and corresponding authentication path,
-    /// *before* applying the transactions.
+    /// The recipient's account information *before* applying the transactions,
+    /// and its authentication path once the sender's leaf is updated.
     pub recv_pre_tx_info_and_paths: Option<Vec<(AccountInformation, AccPath)>>,
-    /// The authentication path corresponding to the recipient's account information
-    /// *after* applying the transactions.
-    pub recv_post_paths: Option<Vec<AccPath>>,
     /// List of state roots, so that the i-th root is the state roots before applying
     /// the i-th transaction. This means that `pre_tx_roots[0] == initial_root`.
     pub pre_tx_roots: Option<Vec<AccRoot>>,
     /// List of state roots, so that the i-th root is the state root after applying
-    /// the i-th transaction. This means that `post_tx_roots[NUM_TX - 1] == final_root`.
+    /// the i-th transaction. This means that `post_tx_roots[NUM_TX - 1] == final_root`
+    /// if there are no swaps.
     pub post_tx_roots: Option<Vec<AccRoot>>,
+    /// The swaps, applied after all transactions.
+    pub swaps: Option<Vec<Swap>>,
+    /// The witnesses for the two legs of each swap, in the order of `SwapTerms::legs`.
+    pub swap_legs: Option<Vec<[TransferLeg; 2]>>,
+    /// The key rotations, applied after all swaps.
+    pub key_rotations: Option<Vec<KeyRotation>>,
+    /// The witnesses for the update of each leaf touched by a key rotation, in
+    /// `TokenId::all()` order.
+    pub key_rotation_updates: Option<Vec<[LeafUpdate; NUM_TOKENS]>>,
+    /// The multisig transactions, applied after all key rotations.
+    pub multisig_transactions: Option<Vec<MultisigTransaction>>,
+    /// The witness for the transfer of each multisig transaction.
+    pub multisig_legs: Option<Vec<TransferLeg>>,
+    /// The state diffs that the batch publishes: the `da::last_writes` of the
+    /// diffs of its operations, in order. The circuit takes their
+    /// `da::commit`ment as a public input.
+    pub state_diffs: Option<Vec<StateDiff>>,
+    /// The bridge account, whose transfers the circuit commits to as deposits
+    /// and withdrawals; see `bridge`.
+    pub bridge_account: Option<AccountId>,
 }
 
-impl<const NUM_TX: usize> Rollup<NUM_TX> {
+impl<
+        const NUM_TX: usize,
+        const NUM_SWAPS: usize,
+        const NUM_ROTATIONS: usize,
+        const NUM_MULTISIG_TX: usize,
+    > Rollup<NUM_TX, NUM_SWAPS, NUM_ROTATIONS, NUM_MULTISIG_TX>
+{
     pub fn new_empty(ledger_params: Parameters) -> Self {
         Self {
             ledger_params,
             initial_root: None,
             final_root: None,
             transactions: None,
+            amount_openings: None,
             sender_pre_tx_info_and_paths: None,
-            sender_post_paths: None,
             recv_pre_tx_info_and_paths: None,
-            recv_post_paths: None,
             pre_tx_roots: None,
             post_tx_roots: None,
+            swaps: None,
+            swap_legs: None,
+            key_rotations: None,
+            key_rotation_updates: None,
+            multisig_transactions: None,
+            multisig_legs: None,
+            state_diffs: None,
+            bridge_account: None,
         }
     }
 
@@ -70,12 +246,19 @@ impl<const NUM_TX: usize> Rollup<NUM_TX> {
             initial_root: Some(initial_root),
             final_root: Some(final_root),
             transactions: None,
+            amount_openings: None,
             sender_pre_tx_info_and_paths: None,
-            sender_post_paths: None,
             recv_pre_tx_info_and_paths: None,
-            recv_post_paths: None,
             pre_tx_roots: None,
             post_tx_roots: None,
+            swaps: None,
+            swap_legs: None,
+            key_rotations: None,
+            key_rotation_updates: None,
+            multisig_transactions: None,
+            multisig_legs: None,
+            state_diffs: None,
+            bridge_account: None,
         }
     }
 
@@ -84,55 +267,138 @@ impl<const NUM_TX: usize> Rollup<NUM_TX> {
         transactions: &[Transaction],
         state: &mut State,
         validate_transactions: bool,
+    ) -> Option<Self> {
+        Self::with_state_and_operations(ledger_params, transactions, &[], &[], &[], state, validate_transactions)
+    }
+
+    pub fn with_state_and_operations(
+        ledger_params: Parameters,
+        transactions: &[Transaction],
+        swaps: &[Swap],
+        key_rotations: &[KeyRotation],
+        multisig_transactions: &[MultisigTransaction],
+        state: &mut State,
+        validate_transactions: bool,
+    ) -> Option<Self> {
+        Self::with_state_operations_and_openings(
+            ledger_params,
+            transactions,
+            &vec![None; transactions.len()],
+            swaps,
+            key_rotations,
+            multisig_transactions,
+            state,
+            validate_transactions,
+        )
+    }
+
+    /// Like `with_state_and_operations`, where `amount_openings[i]` opens the
+    /// amount commitment of `transactions[i]` if the ledger has confidential amounts.
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_state_operations_and_openings(
+        ledger_params: Parameters,
+        transactions: &[Transaction],
+        amount_openings: &[Option<AmountOpening>],
+        swaps: &[Swap],
+        key_rotations: &[KeyRotation],
+        multisig_transactions: &[MultisigTransaction],
+        state: &mut State,
+        validate_transactions: bool,
     ) -> Option<Self> {
         assert_eq!(transactions.len(), NUM_TX);
+        assert_eq!(amount_openings.len(), NUM_TX);
+        assert_eq!(swaps.len(), NUM_SWAPS);
+        assert_eq!(key_rotations.len(), NUM_ROTATIONS);
+        assert_eq!(multisig_transactions.len(), NUM_MULTISIG_TX);
         let initial_root = Some(state.root());
+        let mut state_diffs = Vec::new();
         let mut sender_pre_tx_info_and_paths = Vec::with_capacity(NUM_TX);
         let mut recipient_pre_tx_info_and_paths = Vec::with_capacity(NUM_TX);
-        let mut sender_post_paths = Vec::with_capacity(NUM_TX);
-        let mut recipient_post_paths = Vec::with_capacity(NUM_TX);
         let mut pre_tx_roots = Vec::with_capacity(NUM_TX);
         let mut post_tx_roots = Vec::with_capacity(NUM_TX);
-        for tx in transactions {
-            if !tx.validate(&ledger_params, &*state) && validate_transactions {
+        for (tx, opening) in transactions.iter().zip(amount_openings) {
+            if !tx.validate_with_opening(&ledger_params, &*state, opening.as_ref()) && validate_transactions {
                 return None;
             }
         }
-        for tx in transactions {
-            let sender_id = tx.sender;
-            let recipient_id = tx.recipient;
-            let pre_tx_root = state.root();
-            let sender_pre_acc_info = state.id_to_account_info.get(&sender_id)?.clone();
-            let sender_pre_path = state
-                .account_merkle_tree
-                .generate_proof(sender_id.0 as usize)
-                .unwrap();
-            let recipient_pre_acc_info = state.id_to_account_info.get(&recipient_id)?.clone();
-            let recipient_pre_path = state
-                .account_merkle_tree
-                .generate_proof(recipient_id.0 as usize)
-                .unwrap();
-
-            if validate_transactions {
-                state.apply_transaction(&ledger_params, tx)?;
-            } else {
-                let _ = state.apply_transaction(&ledger_params, tx);
+        for (tx, opening) in transactions.iter().zip(amount_openings) {
+            let (amount, amount_commitment) = tx.amount_with_opening(opening.as_ref())?;
+            let leg = TransferLeg::record(
+                state,
+                tx.sender,
+                tx.recipient,
+                tx.token,
+                amount,
+                amount_commitment.as_ref(),
+                validate_transactions,
+                |state| state.apply_transaction_with_opening(&ledger_params, tx, opening.as_ref()),
+            )?;
+            state_diffs.extend(leg_diffs(&ledger_params, state, tx.sender, tx.recipient, tx.token));
+            sender_pre_tx_info_and_paths.push(leg.sender_pre_info_and_path);
+            recipient_pre_tx_info_and_paths.push(leg.recipient_pre_info_and_path);
+            pre_tx_roots.push(leg.pre_root);
+            post_tx_roots.push(leg.post_root);
+        }
+
+        let mut swap_legs = Vec::with_capacity(NUM_SWAPS);
+        for swap in swaps {
+            // Either both legs are applied or neither is.
+            let valid = swap.validate(&ledger_params, &*state);
+            if !valid && validate_transactions {
+                return None;
+            }
+            let [leg_a, leg_b] = swap.terms.legs().map(|(from, to, token, amount)| {
+                let leg = TransferLeg::record(state, from, to, token, amount, None, validate_transactions, |state| {
+                    valid.then(|| state.transfer(from, to, token, amount)).flatten()
+                });
+                state_diffs.extend(leg_diffs(&ledger_params, state, from, to, token));
+                leg
+            });
+            swap_legs.push([leg_a?, leg_b?]);
+        }
+
+        let mut key_rotation_updates = Vec::with_capacity(NUM_ROTATIONS);
+        for rotation in key_rotations {
+            let valid = rotation.validate(&ledger_params, &*state);
+            if !valid && validate_transactions {
+                return None;
+            }
+            // `State::rotate_key` updates all leaves at once, so record the
+            // intermediate states on a scratch copy.
+            let mut scratch = state.clone();
+            let updates = TokenId::all()
+                .map(|token| {
+                    LeafUpdate::record(&mut scratch, rotation.account, token, validate_transactions, |scratch| {
+                        valid
+                            .then(|| scratch.update_public_key(rotation.account, token, rotation.new_public_key))
+                            .flatten()
+                    })
+                })
+                .collect::<Option<Vec<_>>>()?;
+            if valid {
+                state.apply_key_rotation(&ledger_params, rotation)?;
             }
-            let post_tx_root = state.root();
-            let sender_post_path = state
-                .account_merkle_tree
-                .generate_proof(sender_id.0 as usize)
-                .unwrap();
-            let recipient_post_path = state
-                .account_merkle_tree
-                .generate_proof(recipient_id.0 as usize)
-                .unwrap();
-            sender_pre_tx_info_and_paths.push((sender_pre_acc_info, sender_pre_path));
-            recipient_pre_tx_info_and_paths.push((recipient_pre_acc_info, recipient_pre_path));
-            sender_post_paths.push(sender_post_path);
-            recipient_post_paths.push(recipient_post_path);
-            pre_tx_roots.push(pre_tx_root);
-            post_tx_roots.push(post_tx_root);
+            debug_assert_eq!(state.root(), scratch.root());
+            state_diffs.push(StateDiff::Key {
+                account: rotation.account,
+                public_key: rotation.new_public_key,
+            });
+            key_rotation_updates.push(updates.try_into().ok()?);
+        }
+
+        let mut multisig_legs = Vec::with_capacity(NUM_MULTISIG_TX);
+        for tx in multisig_transactions {
+            multisig_legs.push(TransferLeg::record(
+                state,
+                tx.sender,
+                tx.recipient,
+                tx.token,
+                tx.amount,
+                None,
+                validate_transactions,
+                |state| state.apply_multisig_transaction(&ledger_params, tx),
+            )?);
+            state_diffs.extend(leg_diffs(&ledger_params, state, tx.sender, tx.recipient, tx.token));
         }
 
         Some(Self {
@@ -140,21 +406,150 @@ impl<const NUM_TX: usize> Rollup<NUM_TX> {
             initial_root,
             final_root: Some(state.root()),
             transactions: Some(transactions.to_vec()),
+            amount_openings: Some(amount_openings.to_vec()),
             sender_pre_tx_info_and_paths: Some(sender_pre_tx_info_and_paths),
             recv_pre_tx_info_and_paths: Some(recipient_pre_tx_info_and_paths),
-            sender_post_paths: Some(sender_post_paths),
-            recv_post_paths: Some(recipient_post_paths),
             pre_tx_roots: Some(pre_tx_roots),
             post_tx_roots: Some(post_tx_roots),
+            swaps: Some(swaps.to_vec()),
+            swap_legs: Some(swap_legs),
+            key_rotations: Some(key_rotations.to_vec()),
+            key_rotation_updates: Some(key_rotation_updates),
+            multisig_transactions: Some(multisig_transactions.to_vec()),
+            multisig_legs: Some(multisig_legs),
+            state_diffs: Some(ark_simple_payments_scp::da::last_writes(&state_diffs)),
+            bridge_account: None,
         })
     }
+
+    /// Commit to the transfers from and to `bridge_account` as deposits and
+    /// withdrawals. This changes the circuit, so the proving key has to be
+    /// generated with the same bridge account. Swaps with the bridge account do
+    /// not satisfy the circuit, as no signed destination pays out their
+    /// withdrawal leg.
+    pub fn with_bridge_account(mut self, bridge_account: AccountId) -> Self {
+        self.bridge_account = Some(bridge_account);
+        self
+    }
+
+    /// The deposits that the batch credits and the withdrawals that it makes,
+    /// from its transactions and multisig transactions in the order in which
+    /// the circuit applies them. Empty without a bridge account.
+    pub fn bridge_transfers(&self) -> BridgeTransfers {
+        let mut transfers = BridgeTransfers::default();
+        let Some(bridge_account) = self.bridge_account else {
+            return transfers;
+        };
+        let amount_openings = self.amount_openings.iter().flatten();
+        let transactions = self.transactions.iter().flatten().zip(amount_openings).filter_map(|(tx, opening)| {
+            let (amount, _) = tx.amount_with_opening(opening.as_ref())?;
+            Some((tx.sender, tx.recipient, tx.token, amount, tx.destination))
+        });
+        let multisig_transactions = self
+            .multisig_transactions
+            .iter()
+            .flatten()
+            .map(|tx| (tx.sender, tx.recipient, tx.token, tx.amount, tx.destination));
+        for (sender, recipient, token, amount, destination) in transactions.chain(multisig_transactions) {
+            transfers.record(bridge_account, sender, recipient, token, amount, destination);
+        }
+        transfers
+    }
+
+    /// The commitment to `state_diffs`, which follows the roots in the public inputs.
+    pub fn diff_commitment(&self) -> Option<DiffCommitment> {
+        self.state_diffs.as_ref().map(|diffs| ark_simple_payments_scp::da::commit(&self.ledger_params, diffs))
+    }
 }
 
-impl<const NUM_TX: usize> ConstraintSynthesizer<ConstraintF> for Rollup<NUM_TX> {
-    #[tracing::instrument(target = "r1cs", skip(self, cs))]
+/// The diffs that a transfer leg publishes, read from `state` after the leg: the
+/// balances of `(sender, token)` and `(recipient, token)`, or their balance
+/// commitments if the ledger has confidential amounts. Like the witness, they
+/// describe the untouched state if the transfer failed.
+fn leg_diffs(
+    parameters: &Parameters,
+    state: &State,
+    sender: AccountId,
+    recipient: AccountId,
+    token: TokenId,
+) -> Vec<StateDiff> {
+    [sender, recipient]
+        .into_iter()
+        .filter_map(|account| {
+            let info = state.account_info(account, token)?;
+            Some(if parameters.confidential_amounts {
+                let commitment = info.balance_commitment?.commitment;
+                StateDiff::BalanceCommitment { account, token, commitment }
+            } else {
+                StateDiff::Balance { account, token, balance: info.balance }
+            })
+        })
+        .collect()
+}
+
+impl<
+        const NUM_TX: usize,
+        const NUM_SWAPS: usize,
+        const NUM_ROTATIONS: usize,
+        const NUM_MULTISIG_TX: usize,
+    > ConstraintSynthesizer<ConstraintF> for Rollup<NUM_TX, NUM_SWAPS, NUM_ROTATIONS, NUM_MULTISIG_TX>
+{
     fn generate_constraints(
         self,
         cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        self.synthesize(cs, None)
+    }
+}
+
+/// Append the trace of the operation whose constraints end at the current
+/// constraint count, if a trace is being recorded. `values` are checks that are
+/// enforced directly instead of through a `Boolean`. In setup mode only the
+/// constraints are recorded.
+fn record(
+    trace: &mut Option<&mut Vec<OperationTrace>>,
+    cs: &ConstraintSystemRef<ConstraintF>,
+    operation: Operation,
+    values: impl FnOnce() -> Result<Vec<(Check, bool)>, SynthesisError>,
+    checks: &[(Check, Boolean<ConstraintF>)],
+) -> Result<(), SynthesisError> {
+    if let Some(trace) = trace {
+        let mut check_values = Vec::new();
+        if !cs.is_in_setup_mode() {
+            check_values = values()?;
+            for (check, holds) in checks {
+                check_values.push((*check, holds.value()?));
+            }
+        }
+        let start = trace.last().map_or(0, |t| t.constraints.end);
+        trace.push(OperationTrace {
+            operation,
+            constraints: start..cs.num_constraints(),
+            checks: check_values,
+        });
+    }
+    Ok(())
+}
+
+/// Whether two roots are equal. Fails in setup mode, where roots have no value.
+fn roots_equal(a: &AccRootVar, b: &AccRootVar) -> Result<bool, SynthesisError> {
+    Ok(a.value()? == b.value()?)
+}
+
+impl<
+        const NUM_TX: usize,
+        const NUM_SWAPS: usize,
+        const NUM_ROTATIONS: usize,
+        const NUM_MULTISIG_TX: usize,
+    > Rollup<NUM_TX, NUM_SWAPS, NUM_ROTATIONS, NUM_MULTISIG_TX>
+{
+    /// Generate the constraints of the batch, and record the constraints and
+    /// check values of each operation in `trace`, if it is given.
+    #[tracing::instrument(target = "r1cs", skip(self, cs, trace))]
+    fn synthesize(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+        mut trace: Option<&mut Vec<OperationTrace>>,
     ) -> Result<(), SynthesisError> {
         // Declare the parameters as constants.
         let ledger_params = ParametersVar::new_constant(
@@ -170,19 +565,35 @@ impl<const NUM_TX: usize> ConstraintSynthesizer<ConstraintF> for Rollup<NUM_TX>
         let final_root = AccRootVar::new_input(ark_relations::ns!(cs, "Final root"), || {
             self.final_root.ok_or(SynthesisError::AssignmentMissing)
         })?;
+
+        // Declare the commitment to the published state diffs as a public input.
+        let diff_commitment = DiffCommitmentVar::new_input(ark_relations::ns!(cs, "Diff commitment"), || {
+            self.diff_commitment().ok_or(SynthesisError::AssignmentMissing)
+        })?;
+
+        // Declare the commitments to the deposits and withdrawals as public inputs.
+        let (deposit_commitment, withdrawal_commitment) = self.bridge_transfers().commitments(&self.ledger_params);
+        let deposit_commitment =
+            DiffCommitmentVar::new_input(ark_relations::ns!(cs, "Deposit commitment"), || Ok(deposit_commitment))?;
+        let withdrawal_commitment =
+            DiffCommitmentVar::new_input(ark_relations::ns!(cs, "Withdrawal commitment"), || {
+                Ok(withdrawal_commitment)
+            })?;
+        record(&mut trace, &cs, Operation::PublicInputs, || Ok(Vec::new()), &[])?;
         let mut prev_root = initial_root;
+        let mut state_diffs = Vec::new();
+        let mut bridge_transfers = BridgeTransfersVar::new(self.bridge_account);
 
         for i in 0..NUM_TX {
+            let _span = indexed_span!("transaction", i).entered();
             let tx = self.transactions.as_ref().and_then(|t| t.get(i));
+            let opening = self.amount_openings.as_ref().and_then(|o| o.get(i)).copied().flatten();
 
             let sender_acc_info = self.sender_pre_tx_info_and_paths.as_ref().map(|t| t[i].0);
             let sender_pre_path = self.sender_pre_tx_info_and_paths.as_ref().map(|t| &t[i].1);
 
             let recipient_acc_info = self.recv_pre_tx_info_and_paths.as_ref().map(|t| t[i].0);
-            let recipient_pre_path = self.recv_pre_tx_info_and_paths.as_ref().map(|t| &t[i].1);
-
-            let sender_post_path = self.sender_post_paths.as_ref().map(|t| &t[i]);
-            let recipient_post_path = self.recv_post_paths.as_ref().map(|t| &t[i]);
+            let recipient_path = self.recv_pre_tx_info_and_paths.as_ref().map(|t| &t[i].1);
 
             let pre_tx_root = self.pre_tx_roots.as_ref().map(|t| t[i]);
             let post_tx_root = self.post_tx_roots.as_ref().map(|t| t[i]);
@@ -190,38 +601,27 @@ impl<const NUM_TX: usize> ConstraintSynthesizer<ConstraintF> for Rollup<NUM_TX>
             // Let's declare all these things!
 
             let tx = TransactionVar::new_witness(ark_relations::ns!(cs, "Transaction"), || {
-                tx.ok_or(SynthesisError::AssignmentMissing)
+                tx.map(|tx| (tx.clone(), opening)).ok_or(SynthesisError::AssignmentMissing)
             })?;
             // Declare the sender's initial account balance...
             let sender_acc_info = AccountInformationVar::new_witness(
                 ark_relations::ns!(cs, "Sender Account Info"),
                 || sender_acc_info.ok_or(SynthesisError::AssignmentMissing),
             )?;
-            // ..., corresponding authentication path, ...
+            // ... and corresponding authentication path.
             let sender_pre_path =
                 AccPathVar::new_witness(ark_relations::ns!(cs, "Sender Pre-Path"), || {
                     sender_pre_path.ok_or(SynthesisError::AssignmentMissing)
                 })?;
-            // ... and authentication path after the update.
-            let sender_post_path =
-                AccPathVar::new_witness(ark_relations::ns!(cs, "Sender Post-Path"), || {
-                    sender_post_path.ok_or(SynthesisError::AssignmentMissing)
-                })?;
             // Declare the recipient's initial account balance...
             let recipient_acc_info = AccountInformationVar::new_witness(
                 ark_relations::ns!(cs, "Recipient Account Info"),
                 || recipient_acc_info.ok_or(SynthesisError::AssignmentMissing),
             )?;
-            // ..., corresponding authentication path, ...
-            let recipient_pre_path =
-                AccPathVar::new_witness(ark_relations::ns!(cs, "Recipient Pre-Path"), || {
-                    recipient_pre_path.ok_or(SynthesisError::AssignmentMissing)
-                })?;
-
-            // ... and authentication path after the update.
-            let recipient_post_path =
-                AccPathVar::new_witness(ark_relations::ns!(cs, "Recipient Post-Path"), || {
-                    recipient_post_path.ok_or(SynthesisError::AssignmentMissing)
+            // ... and authentication path once the sender's leaf is updated.
+            let recipient_path =
+                AccPathVar::new_witness(ark_relations::ns!(cs, "Recipient Path"), || {
+                    recipient_path.ok_or(SynthesisError::AssignmentMissing)
                 })?;
             // Declare the state root before the transaction...
             let pre_tx_root =
@@ -234,45 +634,231 @@ impl<const NUM_TX: usize> ConstraintSynthesizer<ConstraintF> for Rollup<NUM_TX>
                     post_tx_root.ok_or(SynthesisError::AssignmentMissing)
                 })?;
 
+            let leg = TransferLegVar {
+                pre_sender_acc_info: sender_acc_info,
+                sender_path: sender_pre_path,
+                pre_recipient_acc_info: recipient_acc_info,
+                recipient_path,
+                pre_root: pre_tx_root,
+                post_root: post_tx_root,
+            };
+
             // Enforce that the state root after the previous transaction equals
             // the starting state root for this transaction
-            prev_root.enforce_equal(&pre_tx_root)?;
+            prev_root.enforce_equal(&leg.pre_root)?;
 
             // Validate that the transaction signature and amount is correct.
-            tx.validate(
-                &ledger_params,
-                &sender_acc_info,
-                &sender_pre_path,
-                &sender_post_path,
-                &recipient_acc_info,
-                &recipient_pre_path,
-                &recipient_post_path,
-                &pre_tx_root,
-                &post_tx_root,
-            )?
-            .enforce_equal(&Boolean::TRUE)?;
+            let checks = tx.checks(&ledger_params, &leg)?;
+            all_hold(&checks)?.enforce_equal(&Boolean::TRUE)?;
+            state_diffs.extend(tx.state_diffs(&ledger_params, &leg)?);
+            bridge_transfers.record(&ledger_params, &tx.sender, &tx.recipient, &tx.token, &tx.amount, &tx.destination)?;
+            let values = || {
+                Ok(vec![
+                    (Check::RootChain, roots_equal(&prev_root, &leg.pre_root)?),
+                    (Check::Balance, tx.amount.0.value()? <= leg.pre_sender_acc_info.balance.0.value()?),
+                ])
+            };
+            record(&mut trace, &cs, Operation::Transaction(i), values, &checks)?;
 
             // Set the root for the next transaction.
-            prev_root = post_tx_root;
+            prev_root = leg.post_root;
+        }
+
+        for i in 0..NUM_SWAPS {
+            let _span = indexed_span!("swap", i).entered();
+            let swap = self.swaps.as_ref().and_then(|s| s.get(i));
+            let legs = self.swap_legs.as_ref().and_then(|l| l.get(i));
+
+            let swap = SwapVar::new_witness(ark_relations::ns!(cs, "Swap"), || {
+                swap.ok_or(SynthesisError::AssignmentMissing)
+            })?;
+            // Declare the leaves and paths touched by either leg.
+            let legs = [
+                TransferLegVar::new_witness(ark_relations::ns!(cs, "Swap Leg A"), || {
+                    legs.map(|l| &l[0]).ok_or(SynthesisError::AssignmentMissing)
+                })?,
+                TransferLegVar::new_witness(ark_relations::ns!(cs, "Swap Leg B"), || {
+                    legs.map(|l| &l[1]).ok_or(SynthesisError::AssignmentMissing)
+                })?,
+            ];
+
+            // The swap starts from the state left by the previous operation.
+            prev_root.enforce_equal(&legs[0].pre_root)?;
+
+            // Validate both signatures and both legs together, and that neither
+            // party is the bridge account.
+            let valid = Boolean::kary_and(&[
+                swap.validate(&ledger_params, &legs)?,
+                bridge_transfers.excludes(&swap.party_a)?,
+                bridge_transfers.excludes(&swap.party_b)?,
+            ])?;
+            valid.enforce_equal(&Boolean::TRUE)?;
+            state_diffs.extend(swap.state_diffs(&ledger_params, &legs)?);
+            let values = || Ok(vec![(Check::RootChain, roots_equal(&prev_root, &legs[0].pre_root)?)]);
+            record(&mut trace, &cs, Operation::Swap(i), values, &[(Check::Validity, valid)])?;
+
+            let [_, leg_b] = legs;
+            prev_root = leg_b.post_root;
+        }
+
+        for i in 0..NUM_ROTATIONS {
+            let _span = indexed_span!("key_rotation", i).entered();
+            let rotation = self.key_rotations.as_ref().and_then(|r| r.get(i));
+            let updates = self.key_rotation_updates.as_ref().and_then(|u| u.get(i));
+
+            let rotation = KeyRotationVar::new_witness(ark_relations::ns!(cs, "Key Rotation"), || {
+                rotation.ok_or(SynthesisError::AssignmentMissing)
+            })?;
+            // Declare the update of every leaf of the account.
+            let mut update_vars = Vec::with_capacity(NUM_TOKENS);
+            for j in 0..NUM_TOKENS {
+                let _span = indexed_span!("leaf_update", j).entered();
+                update_vars.push(LeafUpdateVar::new_witness(
+                    ark_relations::ns!(cs, "Key Rotation Leaf Update"),
+                    || updates.map(|u| &u[j]).ok_or(SynthesisError::AssignmentMissing),
+                )?);
+            }
+            let updates: [LeafUpdateVar; NUM_TOKENS] =
+                update_vars.try_into().unwrap_or_else(|_| unreachable!());
+
+            // The rotation starts from the state left by the previous operation.
+            prev_root.enforce_equal(&updates[0].pre_root)?;
+
+            let valid = rotation.validate(&ledger_params, &updates)?;
+            valid.enforce_equal(&Boolean::TRUE)?;
+            state_diffs.push(rotation.state_diff());
+            let values = || Ok(vec![(Check::RootChain, roots_equal(&prev_root, &updates[0].pre_root)?)]);
+            record(&mut trace, &cs, Operation::KeyRotation(i), values, &[(Check::Validity, valid)])?;
+
+            let [.., last_update] = updates;
+            prev_root = last_update.post_root;
+        }
+
+        for i in 0..NUM_MULTISIG_TX {
+            let _span = indexed_span!("multisig_transaction", i).entered();
+            let tx = self.multisig_transactions.as_ref().and_then(|t| t.get(i));
+            let leg = self.multisig_legs.as_ref().and_then(|l| l.get(i));
+
+            let tx = MultisigTransactionVar::new_witness(ark_relations::ns!(cs, "Multisig Transaction"), || {
+                tx.ok_or(SynthesisError::AssignmentMissing)
+            })?;
+            let leg = TransferLegVar::new_witness(ark_relations::ns!(cs, "Multisig Transfer Leg"), || {
+                leg.ok_or(SynthesisError::AssignmentMissing)
+            })?;
+
+            // The transaction starts from the state left by the previous operation.
+            prev_root.enforce_equal(&leg.pre_root)?;
+
+            // Validate the policy, the signature threshold, and the transfer.
+            let valid = tx.validate(&ledger_params, &leg)?;
+            valid.enforce_equal(&Boolean::TRUE)?;
+            state_diffs.extend(tx.state_diffs(&ledger_params, &leg)?);
+            bridge_transfers.record(&ledger_params, &tx.sender, &tx.recipient, &tx.token, &tx.amount, &tx.destination)?;
+            let values = || Ok(vec![(Check::RootChain, roots_equal(&prev_root, &leg.pre_root)?)]);
+            record(&mut trace, &cs, Operation::MultisigTransaction(i), values, &[(Check::Validity, valid)])?;
+
+            prev_root = leg.post_root;
         }
         // Check that the final root is consistent with the root computed after
         // applying all state transitions
         prev_root.enforce_equal(&final_root)?;
+        let values = || Ok(vec![(Check::RootChain, roots_equal(&prev_root, &final_root)?)]);
+        record(&mut trace, &cs, Operation::FinalRoot, values, &[])?;
+
+        // Check that the diffs of all operations hash to the public diff
+        // commitment, so that the published diffs match the proven batch.
+        let committed_diffs = da::commit(&ledger_params, &state_diffs)?;
+        committed_diffs.enforce_equal(&diff_commitment)?;
+        let values = || Ok(vec![(Check::DiffCommitment, committed_diffs.value()? == diff_commitment.value()?)]);
+        record(&mut trace, &cs, Operation::StateDiffs, values, &[])?;
+
+        // Check that the transfers from and to the bridge account hash to the
+        // public deposit and withdrawal commitments.
+        bridge_transfers.deposits.enforce_equal(&deposit_commitment)?;
+        bridge_transfers.withdrawals.enforce_equal(&withdrawal_commitment)?;
+        let values = || {
+            Ok(vec![(
+                Check::BridgeTransfers,
+                bridge_transfers.deposits.value()? == deposit_commitment.value()?
+                    && bridge_transfers.withdrawals.value()? == withdrawal_commitment.value()?,
+            )])
+        };
+        record(&mut trace, &cs, Operation::BridgeTransfers, values, &[])?;
         Ok(())
     }
+
+    /// Check the batch against the circuit and explain the first unsatisfied
+    /// constraint: the operation that generated it and the checks of that
+    /// operation that fail. The batch is also replayed natively from `state`,
+    /// the state before the batch, and the operations on which the native
+    /// ledger and the circuit disagree are reported.
+    pub fn diagnose(self, state: &State) -> Result<Diagnosis, Error> {
+        let pp = &self.ledger_params;
+        let mut native = state.clone();
+        let mut native_verdicts = Vec::new();
+        let amount_openings = self.amount_openings.iter().flatten();
+        for (i, (tx, opening)) in self.transactions.iter().flatten().zip(amount_openings).enumerate() {
+            let applied = native.apply_transaction_with_opening(pp, tx, opening.as_ref()).is_some();
+            native_verdicts.push((Operation::Transaction(i), applied));
+        }
+        for (i, swap) in self.swaps.iter().flatten().enumerate() {
+            native_verdicts.push((Operation::Swap(i), native.apply_swap(pp, swap).is_some()));
+        }
+        for (i, rotation) in self.key_rotations.iter().flatten().enumerate() {
+            native_verdicts.push((Operation::KeyRotation(i), native.apply_key_rotation(pp, rotation).is_some()));
+        }
+        for (i, tx) in self.multisig_transactions.iter().flatten().enumerate() {
+            native_verdicts.push((
+                Operation::MultisigTransaction(i),
+                native.apply_multisig_transaction(pp, tx).is_some(),
+            ));
+        }
+        native_verdicts.push((Operation::FinalRoot, self.final_root == Some(native.root())));
+
+        let cs = ConstraintSystem::new_ref();
+        let mut trace = Vec::new();
+        self.synthesize(cs.clone(), Some(&mut trace))?;
+        let (matrices, assignment) = export::matrices_and_assignment(&cs)?;
+        let first_unsatisfied = export::first_unsatisfied(&matrices, &assignment);
+        Ok(Diagnosis::new(&trace, &native_verdicts, first_unsatisfied))
+    }
+
+    /// Count the constraints of the circuit in each namespace; see
+    /// `ConstraintReport`. The batch is synthesized in setup mode, as for
+    /// generating proving keys, so its checks do not need to hold.
+    pub fn constraint_report(self) -> Result<ConstraintReport, Error> {
+        let cs = ConstraintSystem::new_ref();
+        cs.set_mode(SynthesisMode::Setup);
+        let mut trace = Vec::new();
+        // Constraints only record their namespaces while a `ConstraintLayer` is active.
+        let subscriber = tracing_subscriber::Registry::default().with(ConstraintLayer::default());
+        tracing::subscriber::with_default(subscriber, || self.synthesize(cs.clone(), Some(&mut trace)))?;
+        let constraint_names = cs.constraint_names().ok_or("constraints have no namespaces")?;
+        Ok(ConstraintReport::new(&trace, &constraint_names))
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use ark_relations::r1cs::{
-        ConstraintLayer, ConstraintSynthesizer, ConstraintSystem, TracingMode::OnlyConstraints,
-    };
-    use ark_simple_payments_scp::account::AccountId;
-    use ark_simple_payments_scp::ledger::{Amount, Parameters, State};
-    use ark_simple_payments_scp::transaction::Transaction;
+    use ark_ec::AffineRepr;
+    use ark_groth16::r1cs_to_qap::LibsnarkReduction;
+    use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystem, ToConstraintField};
+    use ark_simple_payments_scp::account::{AccountId, AccountSignatureScheme, TokenId};
+    use ark_simple_payments_scp::ledger::{Amount, Parameters, SigningDomain, State};
+    use ark_simple_payments_scp::signature::schnorr::Schnorr;
+    use ark_simple_payments_scp::account::MultisigPolicy;
+    use ark_simple_payments_scp::transaction::{KeyRotation, MultisigTransaction, SwapTerms, Transaction};
+    use ark_std::rand::{RngCore, SeedableRng};
 
-    fn test_cs<const NUM_TX: usize>(rollup: Rollup<NUM_TX>) -> bool {
+    fn test_cs<
+        const NUM_TX: usize,
+        const NUM_SWAPS: usize,
+        const NUM_ROTATIONS: usize,
+        const NUM_MULTISIG_TX: usize,
+    >(
+        rollup: Rollup<NUM_TX, NUM_SWAPS, NUM_ROTATIONS, NUM_MULTISIG_TX>,
+    ) -> bool {
         let cs = ConstraintSystem::new_ref();
         rollup.generate_constraints(cs.clone()).unwrap();
         let result = cs.is_satisfied().unwrap();
@@ -284,6 +870,7 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::redundant_pattern_matching, clippy::cloned_ref_to_slice_refs)]
     fn single_tx_validity_test() {
         let mut rng = ark_std::test_rng();
         let pp = Parameters::sample(&mut rng);
@@ -293,14 +880,14 @@ mod test {
             state.sample_keys_and_register(&pp, &mut rng).unwrap();
         // Let's give her some initial balance to start with.
         state
-            .update_balance(alice_id, Amount(20))
+            .update_balance(alice_id, TokenId(0), Amount(20))
             .expect("Alice's account should exist");
         // Let's make an account for Bob.
         let (bob_id, bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
 
         // Alice wants to transfer 5 units to Bob.
         let mut temp_state = state.clone();
-        let tx1 = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &alice_pk, &mut rng);
+        let tx1 = Transaction::create(&pp, alice_id, bob_id, TokenId(0), Amount(5), &alice_sk, &alice_pk, &mut rng);
         assert!(tx1.validate(&pp, &temp_state));
         let rollup = Rollup::<1>::with_state_and_transactions(
             pp.clone(),
@@ -311,7 +898,7 @@ mod test {
         .unwrap();
         assert!(test_cs(rollup));
 
-        let bad_tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &bob_sk, &bob_pk, &mut rng);
+        let bad_tx = Transaction::create(&pp, alice_id, bob_id, TokenId(0), Amount(5), &bob_sk, &bob_pk, &mut rng);
         assert!(!bad_tx.validate(&pp, &temp_state));
         assert!(matches!(temp_state.apply_transaction(&pp, &bad_tx), None));
         let rollup = Rollup::<1>::with_state_and_transactions(
@@ -325,6 +912,7 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::redundant_pattern_matching, clippy::cloned_ref_to_slice_refs)]
     fn end_to_end() {
         let mut rng = ark_std::test_rng();
         let pp = Parameters::sample(&mut rng);
@@ -334,14 +922,14 @@ mod test {
             state.sample_keys_and_register(&pp, &mut rng).unwrap();
         // Let's give her some initial balance to start with.
         state
-            .update_balance(alice_id, Amount(20))
+            .update_balance(alice_id, TokenId(0), Amount(20))
             .expect("Alice's account should exist");
         // Let's make an account for Bob.
         let (bob_id, bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
 
         // Alice wants to transfer 5 units to Bob.
         let mut temp_state = state.clone();
-        let tx1 = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &alice_pk, &mut rng);
+        let tx1 = Transaction::create(&pp, alice_id, bob_id, TokenId(0), Amount(5), &alice_sk, &alice_pk, &mut rng);
         assert!(tx1.validate(&pp, &temp_state));
         let rollup = Rollup::<1>::with_state_and_transactions(
             pp.clone(),
@@ -362,22 +950,18 @@ mod test {
         .unwrap();
         assert!(test_cs(rollup));
         assert_eq!(
-            temp_state
-                .id_to_account_info
-                .get(&alice_id)
-                .unwrap()
-                .balance,
-            Amount(10)
+            temp_state.balance(alice_id, TokenId(0)),
+            Some(Amount(10))
         );
         assert_eq!(
-            temp_state.id_to_account_info.get(&bob_id).unwrap().balance,
-            Amount(10)
+            temp_state.balance(bob_id, TokenId(0)),
+            Some(Amount(10))
         );
 
         // Let's try creating invalid transactions:
         // First, let's try a transaction where the amount is larger than Alice's balance.
         let mut temp_state = state.clone();
-        let bad_tx = Transaction::create(&pp, alice_id, bob_id, Amount(21), &alice_sk, &alice_pk, &mut rng);
+        let bad_tx = Transaction::create(&pp, alice_id, bob_id, TokenId(0), Amount(21), &alice_sk, &alice_pk, &mut rng);
         assert!(!bad_tx.validate(&pp, &temp_state));
         assert!(matches!(temp_state.apply_transaction(&pp, &bad_tx), None));
         let rollup = Rollup::<1>::with_state_and_transactions(
@@ -391,7 +975,7 @@ mod test {
 
         // Next, let's try a transaction where the signature is incorrect:
         let mut temp_state = state.clone();
-        let bad_tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &bob_sk, &bob_pk, &mut rng);
+        let bad_tx = Transaction::create(&pp, alice_id, bob_id, TokenId(0), Amount(5), &bob_sk, &bob_pk, &mut rng);
         assert!(!bad_tx.validate(&pp, &temp_state));
         assert!(matches!(temp_state.apply_transaction(&pp, &bad_tx), None));
         let rollup = Rollup::<1>::with_state_and_transactions(
@@ -405,11 +989,538 @@ mod test {
 
         // Finally, let's try a transaction to an non-existant account:
         let bad_tx =
-            Transaction::create(&pp, alice_id, AccountId(10), Amount(5), &alice_sk, &alice_pk, &mut rng);
+            Transaction::create(&pp, alice_id, AccountId(10), TokenId(0), Amount(5), &alice_sk, &alice_pk, &mut rng);
         assert!(!bad_tx.validate(&pp, &state));
         assert!(matches!(temp_state.apply_transaction(&pp, &bad_tx), None));
     }
 
+    #[test]
+    fn token_leaves_are_bound() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (usd, eur, gbp) = (TokenId(0), TokenId(1), TokenId(3));
+        let (alice_id, alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_balance(alice_id, usd, Amount(20))
+            .expect("Alice's account should exist");
+        state
+            .update_balance(alice_id, gbp, Amount(7))
+            .expect("Alice's account should exist");
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        // Transfers of tokens other than the first one go through.
+        let mut temp_state = state.clone();
+        let gbp_tx = Transaction::create(&pp, alice_id, bob_id, gbp, Amount(5), &alice_sk, &alice_pk, &mut rng);
+        let rollup = Rollup::<1>::with_state_and_transactions(
+            pp.clone(),
+            std::slice::from_ref(&gbp_tx),
+            &mut temp_state,
+            true,
+        )
+        .unwrap();
+        assert!(test_cs(rollup));
+        assert_eq!(temp_state.balance(bob_id, gbp), Some(Amount(5)));
+        assert_eq!(temp_state.balance(bob_id, usd), Some(Amount(0)));
+
+        let tx = Transaction::create(&pp, alice_id, bob_id, usd, Amount(5), &alice_sk, &alice_pk, &mut rng);
+
+        // Alice has no EUR. A prover debiting her USD leaf for an EUR transfer
+        // produces valid paths and roots, but at the wrong leaf.
+        let eur_tx = Transaction::create(&pp, alice_id, bob_id, eur, Amount(5), &alice_sk, &alice_pk, &mut rng);
+        assert!(!eur_tx.validate(&pp, &state));
+        let mut temp_state = state.clone();
+        let mut rollup = Rollup::<1>::with_state_and_transactions(
+            pp.clone(),
+            std::slice::from_ref(&tx),
+            &mut temp_state,
+            true,
+        )
+        .unwrap();
+        rollup.transactions = Some(vec![eur_tx]);
+        assert!(!test_cs(rollup));
+    }
+
+    #[test]
+    fn cross_domain_signature_rejected() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample_for_domain(SigningDomain { chain_id: 1, rollup_id: 1 }, &mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state
+            .update_balance(alice_id, TokenId(0), Amount(20))
+            .expect("Alice's account should exist");
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        // Alice signs a transfer for a different rollup on the same chain.
+        let mut other_pp = pp.clone();
+        other_pp.domain.rollup_id = 2;
+        let tx = Transaction::create(&other_pp, alice_id, bob_id, TokenId(0), Amount(5), &alice_sk, &alice_pk, &mut rng);
+        assert!(tx.validate(&other_pp, &state));
+        assert!(!tx.validate(&pp, &state));
+
+        let mut temp_state = state.clone();
+        let rollup = Rollup::<1>::with_state_and_transactions(
+            pp.clone(),
+            std::slice::from_ref(&tx),
+            &mut temp_state,
+            false,
+        )
+        .unwrap();
+        assert!(!test_cs(rollup));
+    }
+
+    #[test]
+    fn eddsa_signed_transactions() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample_for_domain_and_scheme(
+            SigningDomain::default(),
+            AccountSignatureScheme::EdDSA,
+            &mut rng,
+        );
+        let mut state = State::new(32, &pp);
+        let (alice_id, alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, TokenId(0), Amount(20)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        let tx = Transaction::create(&pp, alice_id, bob_id, TokenId(0), Amount(5), &alice_sk, &alice_pk, &mut rng);
+        let mut temp_state = state.clone();
+        let rollup = Rollup::<1>::with_state_and_transactions(
+            pp.clone(),
+            std::slice::from_ref(&tx),
+            &mut temp_state,
+            true,
+        )
+        .unwrap();
+        assert!(test_cs(rollup));
+
+        // A Schnorr signature by the same key is not accepted by the deployment.
+        let mut schnorr_pp = pp.clone();
+        schnorr_pp.signature_scheme = AccountSignatureScheme::Schnorr;
+        schnorr_pp.sig_params.generator = pp.eddsa_params.generator;
+        let tx = Transaction::create(&schnorr_pp, alice_id, bob_id, TokenId(0), Amount(5), &alice_sk, &alice_pk, &mut rng);
+        assert!(tx.validate(&schnorr_pp, &state));
+        assert!(!tx.validate(&pp, &state));
+        let mut temp_state = state.clone();
+        let rollup = Rollup::<1>::with_state_and_transactions(
+            pp.clone(),
+            std::slice::from_ref(&tx),
+            &mut temp_state,
+            false,
+        )
+        .unwrap();
+        // The circuit has no gadget for the other scheme, so it cannot even be synthesized.
+        assert!(rollup.generate_constraints(ConstraintSystem::new_ref()).is_err());
+    }
+
+    #[test]
+    fn confidential_transfers() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng).with_confidential_amounts();
+        let mut state = State::new(32, &pp).with_sampled_blinding_key(&mut rng);
+        let (alice_id, alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, TokenId(0), Amount(20)).unwrap();
+        let (bob_id, bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        // Two transfers followed by a rotation of Bob's key, which keeps his
+        // balance commitment.
+        let (tx1, opening1) =
+            Transaction::create_confidential(&pp, alice_id, bob_id, TokenId(0), Amount(5), &alice_sk, &alice_pk, &mut rng);
+        let (tx2, opening2) =
+            Transaction::create_confidential(&pp, alice_id, bob_id, TokenId(0), Amount(3), &alice_sk, &alice_pk, &mut rng);
+        let (new_bob_pk, _) = pp.keygen(&mut rng);
+        let rotation = KeyRotation::create(&pp, bob_id, new_bob_pk, &bob_sk, &bob_pk, &mut rng);
+        let batch = |temp_state: &mut State| {
+            Rollup::<2, 0, 1>::with_state_operations_and_openings(
+                pp.clone(),
+                &[tx1.clone(), tx2.clone()],
+                &[Some(opening1), Some(opening2)],
+                &[],
+                std::slice::from_ref(&rotation),
+                &[],
+                temp_state,
+                true,
+            )
+            .unwrap()
+        };
+        let mut temp_state = state.clone();
+        let rollup = batch(&mut temp_state);
+        assert_eq!(temp_state.balance(alice_id, TokenId(0)), Some(Amount(12)));
+        assert_eq!(temp_state.balance(bob_id, TokenId(0)), Some(Amount(8)));
+
+        // The batch publishes the final commitment of each leaf once, and the
+        // new key; replaying them yields the final root.
+        let commitment_diff = |state: &State, account| {
+            let commitment = state.account_info(account, TokenId(0)).unwrap().balance_commitment.unwrap().commitment;
+            StateDiff::BalanceCommitment { account, token: TokenId(0), commitment }
+        };
+        let diffs = vec![
+            commitment_diff(&temp_state, alice_id),
+            commitment_diff(&temp_state, bob_id),
+            StateDiff::Key { account: bob_id, public_key: new_bob_pk },
+        ];
+        assert_eq!(rollup.state_diffs, Some(diffs.clone()));
+        let mut replayed = state.clone();
+        for diff in &diffs {
+            replayed.apply_state_diff(diff).unwrap();
+        }
+        assert_eq!(replayed.root(), temp_state.root());
+        assert!(test_cs(rollup));
+
+        // Publishing the commitments after the first transfer as well breaks the proof.
+        let mut after_tx1 = state.clone();
+        after_tx1.apply_transaction_with_opening(&pp, &tx1, Some(&opening1)).unwrap();
+        let mut rollup = batch(&mut state.clone());
+        let mut all_writes = vec![commitment_diff(&after_tx1, alice_id), commitment_diff(&after_tx1, bob_id)];
+        all_writes.extend(diffs);
+        rollup.state_diffs = Some(all_writes);
+        assert!(!test_cs(rollup));
+
+        // The prover needs the opening of the amount ...
+        let mut temp_state = state.clone();
+        assert!(Rollup::<1>::with_state_and_transactions(pp.clone(), std::slice::from_ref(&tx1), &mut temp_state, false)
+            .is_none());
+
+        // ... the amount has to be the one committed to ...
+        let bad_opening = AmountOpening { amount: Amount(4), ..opening1 };
+        assert!(!tx1.validate_with_opening(&pp, &state, Some(&bad_opening)));
+        let mut temp_state = state.clone();
+        let rollup = Rollup::<1>::with_state_operations_and_openings(
+            pp.clone(),
+            std::slice::from_ref(&tx1),
+            &[Some(bad_opening)],
+            &[],
+            &[],
+            &[],
+            &mut temp_state,
+            false,
+        )
+        .unwrap();
+        assert!(!test_cs(rollup));
+
+        // ... and the committed amount cannot exceed the committed balance.
+        let (bad_tx, bad_opening) =
+            Transaction::create_confidential(&pp, alice_id, bob_id, TokenId(0), Amount(21), &alice_sk, &alice_pk, &mut rng);
+        let mut temp_state = state.clone();
+        let rollup = Rollup::<1>::with_state_operations_and_openings(
+            pp.clone(),
+            std::slice::from_ref(&bad_tx),
+            &[Some(bad_opening)],
+            &[],
+            &[],
+            &[],
+            &mut temp_state,
+            false,
+        )
+        .unwrap();
+        assert!(!test_cs(rollup));
+
+        // A transfer with a plain amount is not accepted by the deployment.
+        let plain_tx = Transaction::create(&pp, alice_id, bob_id, TokenId(0), Amount(5), &alice_sk, &alice_pk, &mut rng);
+        let mut temp_state = state.clone();
+        let rollup = Rollup::<1>::with_state_and_transactions(
+            pp.clone(),
+            std::slice::from_ref(&plain_tx),
+            &mut temp_state,
+            false,
+        )
+        .unwrap();
+        assert!(rollup.generate_constraints(ConstraintSystem::new_ref()).is_err());
+    }
+
+    #[test]
+    fn self_transfers_are_no_ops_natively_and_in_the_circuit() {
+        let mut rng = ark_std::test_rng();
+        for pp in [Parameters::sample(&mut rng), Parameters::sample(&mut rng).with_confidential_amounts()] {
+            let mut state = State::new(32, &pp).with_sampled_blinding_key(&mut rng);
+            let (alice_id, alice_pk, alice_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+            state.update_balance(alice_id, TokenId(0), Amount(20)).unwrap();
+            let leaf = *state.account_info(alice_id, TokenId(0)).unwrap();
+            let mut self_transfer = |amount| {
+                if pp.confidential_amounts {
+                    let (tx, opening) = Transaction::create_confidential(
+                        &pp, alice_id, alice_id, TokenId(0), amount, &alice_sk, &alice_pk, &mut rng,
+                    );
+                    (tx, Some(opening))
+                } else {
+                    (Transaction::create(&pp, alice_id, alice_id, TokenId(0), amount, &alice_sk, &alice_pk, &mut rng), None)
+                }
+            };
+            let batch = |(tx, opening): (Transaction, Option<AmountOpening>), state: &mut State, validate| {
+                Rollup::<1>::with_state_operations_and_openings(pp.clone(), &[tx], &[opening], &[], &[], &[], state, validate)
+            };
+
+            let mut temp_state = state.clone();
+            let rollup = batch(self_transfer(Amount(5)), &mut temp_state, true).unwrap();
+            assert_eq!(temp_state.root(), state.root());
+            assert_eq!(temp_state.balance(alice_id, TokenId(0)), Some(Amount(20)));
+            // The leaf is published once, unchanged.
+            let diff = if pp.confidential_amounts {
+                StateDiff::BalanceCommitment { account: alice_id, token: TokenId(0), commitment: leaf.balance_commitment.unwrap().commitment }
+            } else {
+                StateDiff::Balance { account: alice_id, token: TokenId(0), balance: Amount(20) }
+            };
+            assert_eq!(rollup.state_diffs, Some(vec![diff]));
+            let diagnosis = rollup.diagnose(&state).unwrap();
+            assert!(diagnosis.unsatisfied.is_none());
+            assert!(diagnosis.disagreements.is_empty());
+
+            // A self-transfer of the bridge account is neither a deposit nor a withdrawal.
+            let rollup = batch(self_transfer(Amount(5)), &mut state.clone(), true).unwrap().with_bridge_account(alice_id);
+            assert_eq!(rollup.bridge_transfers(), BridgeTransfers::default());
+            assert!(test_cs(rollup));
+
+            // Sending oneself more than the balance is rejected by both.
+            let (bad_tx, opening) = self_transfer(Amount(21));
+            assert!(state.clone().apply_transaction_with_opening(&pp, &bad_tx, opening.as_ref()).is_none());
+            let rollup = batch((bad_tx, opening), &mut state.clone(), false).unwrap();
+            assert!(!test_cs(rollup));
+        }
+    }
+
+    #[test]
+    fn swap_applies_both_legs_or_neither() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (usd, eur) = (TokenId(0), TokenId(1));
+        let (alice_id, alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, usd, Amount(20)).unwrap();
+        let (bob_id, bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(bob_id, eur, Amount(9)).unwrap();
+
+        // A transfer followed by a swap in which Alice sells 4 USD to Bob for 3 EUR.
+        let tx = Transaction::create(&pp, alice_id, bob_id, usd, Amount(5), &alice_sk, &alice_pk, &mut rng);
+        let terms = SwapTerms {
+            party_a: alice_id,
+            token_a: usd,
+            amount_a: Amount(4),
+            party_b: bob_id,
+            token_b: eur,
+            amount_b: Amount(3),
+        };
+        let swap = Swap::create(&pp, terms, (&alice_sk, &alice_pk), (&bob_sk, &bob_pk), &mut rng);
+        let mut temp_state = state.clone();
+        let rollup = Rollup::<1, 1>::with_state_and_operations(
+            pp.clone(),
+            std::slice::from_ref(&tx),
+            std::slice::from_ref(&swap),
+            &[],
+            &[],
+            &mut temp_state,
+            true,
+        )
+        .unwrap();
+        assert!(test_cs(rollup));
+        assert_eq!(temp_state.balance(alice_id, usd), Some(Amount(11)));
+        assert_eq!(temp_state.balance(alice_id, eur), Some(Amount(3)));
+        assert_eq!(temp_state.balance(bob_id, usd), Some(Amount(9)));
+        assert_eq!(temp_state.balance(bob_id, eur), Some(Amount(6)));
+
+        // A party to a swap signs no L1 destination, so it cannot be the bridge account.
+        let rollup = Rollup::<0, 1>::with_state_and_operations(
+            pp.clone(),
+            &[],
+            std::slice::from_ref(&swap),
+            &[],
+            &[],
+            &mut state.clone(),
+            true,
+        )
+        .unwrap();
+        assert!(!test_cs(rollup.with_bridge_account(bob_id)));
+
+        // Only one party signed the swap.
+        let mut bad_swap = swap.clone();
+        bad_swap.signature_b = terms.sign(&pp, &alice_sk, &alice_pk, &mut rng);
+        let mut temp_state = state.clone();
+        assert!(Rollup::<0, 1>::with_state_and_operations(
+            pp.clone(),
+            &[],
+            std::slice::from_ref(&bad_swap),
+            &[],
+            &[],
+            &mut temp_state,
+            true,
+        )
+        .is_none());
+        let rollup = Rollup::<0, 1>::with_state_and_operations(
+            pp.clone(),
+            &[],
+            std::slice::from_ref(&bad_swap),
+            &[],
+            &[],
+            &mut temp_state,
+            false,
+        )
+        .unwrap();
+        assert!(!test_cs(rollup));
+
+        // A prover applying only Alice's leg of the swap is caught.
+        let mut temp_state = state.clone();
+        let leg_a = TransferLeg::record(&mut temp_state, alice_id, bob_id, usd, Amount(4), None, true, |state| {
+            state.transfer(alice_id, bob_id, usd, Amount(4))
+        })
+        .unwrap();
+        let leg_b =
+            TransferLeg::record(&mut temp_state, bob_id, alice_id, eur, Amount(3), None, true, |_| Some(())).unwrap();
+        let mut rollup = Rollup::<0, 1>::only_initial_and_final_roots(pp.clone(), state.root(), temp_state.root());
+        rollup.swaps = Some(vec![swap]);
+        rollup.swap_legs = Some(vec![[leg_a, leg_b]]);
+        // The prover publishes the diffs of both legs.
+        rollup.state_diffs = Some(vec![
+            StateDiff::Balance { account: alice_id, token: usd, balance: Amount(16) },
+            StateDiff::Balance { account: bob_id, token: usd, balance: Amount(4) },
+            StateDiff::Balance { account: bob_id, token: eur, balance: Amount(6) },
+            StateDiff::Balance { account: alice_id, token: eur, balance: Amount(3) },
+        ]);
+        assert!(!test_cs(rollup));
+    }
+
+    #[test]
+    fn key_rotation_moves_control() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (usd, eur) = (TokenId(0), TokenId(1));
+        let (alice_id, alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, usd, Amount(20)).unwrap();
+        state.update_balance(alice_id, eur, Amount(2)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (new_pk, new_sk) = Schnorr::keygen(&pp.sig_params, &mut rng).unwrap();
+
+        // A rotation signed with a key other than the current one is rejected.
+        let bad_rotation = KeyRotation::create(&pp, alice_id, new_pk, &new_sk, &new_pk, &mut rng);
+        let mut temp_state = state.clone();
+        let rollup = Rollup::<0, 0, 1>::with_state_and_operations(
+            pp.clone(),
+            &[],
+            &[],
+            std::slice::from_ref(&bad_rotation),
+            &[],
+            &mut temp_state,
+            false,
+        )
+        .unwrap();
+        assert!(!test_cs(rollup));
+
+        // A prover rotating the key in only some of the leaves is caught.
+        let rotation = KeyRotation::create(&pp, alice_id, new_pk, &alice_sk, &alice_pk, &mut rng);
+        let mut temp_state = state.clone();
+        let updates = TokenId::all()
+            .map(|token| {
+                LeafUpdate::record(&mut temp_state, alice_id, token, true, |state| {
+                    if token == usd {
+                        state.update_public_key(alice_id, token, new_pk)
+                    } else {
+                        Some(())
+                    }
+                })
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+        let mut rollup =
+            Rollup::<0, 0, 1>::only_initial_and_final_roots(pp.clone(), state.root(), temp_state.root());
+        rollup.key_rotations = Some(vec![rotation.clone()]);
+        rollup.key_rotation_updates = Some(vec![updates.try_into().ok().unwrap()]);
+        rollup.state_diffs = Some(vec![StateDiff::Key { account: alice_id, public_key: new_pk }]);
+        assert!(!test_cs(rollup));
+
+        let rollup = Rollup::<0, 0, 1>::with_state_and_operations(
+            pp.clone(),
+            &[],
+            &[],
+            std::slice::from_ref(&rotation),
+            &[],
+            &mut state,
+            true,
+        )
+        .unwrap();
+        assert!(test_cs(rollup));
+        assert_eq!(state.balance(alice_id, usd), Some(Amount(20)));
+        assert_eq!(state.balance(alice_id, eur), Some(Amount(2)));
+
+        // Afterwards only the new key can spend any of the account's tokens.
+        for token in [usd, eur] {
+            let tx = Transaction::create(&pp, alice_id, bob_id, token, Amount(1), &new_sk, &new_pk, &mut rng);
+            let rollup = Rollup::<1>::with_state_and_transactions(
+                pp.clone(),
+                std::slice::from_ref(&tx),
+                &mut state.clone(),
+                true,
+            )
+            .unwrap();
+            assert!(test_cs(rollup));
+            let tx = Transaction::create(&pp, alice_id, bob_id, token, Amount(1), &alice_sk, &alice_pk, &mut rng);
+            let rollup = Rollup::<1>::with_state_and_transactions(
+                pp.clone(),
+                std::slice::from_ref(&tx),
+                &mut state.clone(),
+                false,
+            )
+            .unwrap();
+            assert!(!test_cs(rollup));
+        }
+    }
+
+    #[test]
+    fn multisig_transfer_needs_threshold() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let usd = TokenId(0);
+        let keys = (0..3)
+            .map(|_| Schnorr::keygen(&pp.sig_params, &mut rng).unwrap())
+            .collect::<Vec<_>>();
+        let policy = MultisigPolicy {
+            threshold: 2,
+            public_keys: keys.iter().map(|(pk, _)| *pk).collect(),
+        };
+        let treasury_id = state.register_multisig(&pp, &policy).unwrap();
+        state.update_balance(treasury_id, usd, Amount(10)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        let prove = |tx: MultisigTransaction, validate: bool| {
+            let rollup = Rollup::<0, 0, 0, 1>::with_state_and_operations(
+                pp.clone(),
+                &[],
+                &[],
+                &[],
+                std::slice::from_ref(&tx),
+                &mut state.clone(),
+                validate,
+            )
+            .unwrap();
+            test_cs(rollup)
+        };
+
+        // One signature is not enough.
+        let mut tx = MultisigTransaction::new(treasury_id, bob_id, usd, Amount(4), policy.clone());
+        tx.sign(&pp, &keys[1].1, &keys[1].0, &mut rng).unwrap();
+        assert!(!prove(tx.clone(), false));
+
+        // Two are.
+        tx.sign(&pp, &keys[2].1, &keys[2].0, &mut rng).unwrap();
+        assert!(prove(tx.clone(), true));
+
+        // A valid signature in the wrong slot does not count.
+        let mut bad_tx = tx.clone();
+        bad_tx.signatures.swap(0, 1);
+        assert!(!prove(bad_tx, false));
+
+        // A weaker policy over the same keys does not control the account.
+        let weak_policy = MultisigPolicy { threshold: 1, ..policy };
+        let mut bad_tx = MultisigTransaction::new(treasury_id, bob_id, usd, Amount(4), weak_policy);
+        bad_tx.sign(&pp, &keys[0].1, &keys[0].0, &mut rng).unwrap();
+        assert!(!prove(bad_tx, false));
+    }
+
     // Builds a circuit with two txs, using different pubkeys & amounts every time.
     // It returns this circuit
     fn build_two_tx_circuit() -> Rollup<2> {
@@ -422,7 +1533,7 @@ mod test {
             state.sample_keys_and_register(&pp, &mut rng).unwrap();
         // Let's give her some initial balance to start with.
         state
-            .update_balance(alice_id, Amount(1000))
+            .update_balance(alice_id, TokenId(0), Amount(1000))
             .expect("Alice's account should exist");
         // Let's make an account for Bob.
         let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
@@ -435,19 +1546,145 @@ mod test {
             &pp,
             alice_id,
             bob_id,
+            TokenId(0),
             Amount(amount_to_send),
             &alice_sk,
             &alice_pk,
             &mut rng,
         );
-        let rollup = Rollup::<2>::with_state_and_transactions(
+        Rollup::<2>::with_state_and_transactions(
             pp.clone(),
             &[tx1.clone(), tx1.clone()],
             &mut temp_state,
             true,
         )
+        .unwrap()
+    }
+
+
+    #[test]
+    fn diagnosis_maps_failures_to_operations_and_checks() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, alice_pk, alice_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, TokenId(0), Amount(20)).unwrap();
+        let (bob_id, bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let build = |txs: &[Transaction]| {
+            Rollup::<2>::with_state_and_transactions(pp.clone(), txs, &mut state.clone(), false).unwrap()
+        };
+        let tx0 = Transaction::create(&pp, alice_id, bob_id, TokenId(0), Amount(5), &alice_sk, &alice_pk, &mut rng);
+
+        // A valid batch.
+        let tx1 = Transaction::create(&pp, bob_id, alice_id, TokenId(0), Amount(2), &bob_sk, &bob_pk, &mut rng);
+        let diagnosis = build(&[tx0.clone(), tx1]).diagnose(&state).unwrap();
+        assert_eq!(diagnosis.unsatisfied, None);
+        assert!(diagnosis.disagreements.is_empty());
+
+        // The second transaction is signed with the wrong key.
+        let forged = Transaction::create(&pp, alice_id, bob_id, TokenId(0), Amount(5), &bob_sk, &bob_pk, &mut rng);
+        let diagnosis = build(&[tx0.clone(), forged]).diagnose(&state).unwrap();
+        let unsatisfied = diagnosis.unsatisfied.clone().unwrap();
+        assert_eq!(unsatisfied.operation, Operation::Transaction(1));
+        // The ledger skips the transaction, so neither the recipient's path nor
+        // the root after the transaction shows the transfer.
+        assert_eq!(
+            unsatisfied.failed_checks,
+            vec![Check::RecipientPath, Check::PostRoot, Check::Signature]
+        );
+        assert!(diagnosis.disagreements.is_empty());
+        assert!(diagnosis.to_string().starts_with(&format!("constraint {} of transaction 1", unsatisfied.index)));
+
+        // The second transaction overdraws Alice's account.
+        let overdraft = Transaction::create(&pp, alice_id, bob_id, TokenId(0), Amount(16), &alice_sk, &alice_pk, &mut rng);
+        let diagnosis = build(&[tx0.clone(), overdraft]).diagnose(&state).unwrap();
+        let unsatisfied = diagnosis.unsatisfied.unwrap();
+        assert_eq!(unsatisfied.operation, Operation::Transaction(1));
+        assert!(unsatisfied.failed_checks.contains(&Check::Balance));
+        assert!(diagnosis.disagreements.is_empty());
+
+        // A valid transaction with a wrong witness: the ledger accepts it, the circuit does not.
+        let mut rollup = build(&[tx0.clone(), tx0.clone()]);
+        let recipient_paths = rollup.recv_pre_tx_info_and_paths.as_mut().unwrap();
+        recipient_paths[0].1 = recipient_paths[1].1.clone();
+        let diagnosis = rollup.diagnose(&state).unwrap();
+        let unsatisfied = diagnosis.unsatisfied.clone().unwrap();
+        assert_eq!(unsatisfied.operation, Operation::Transaction(0));
+        assert_eq!(unsatisfied.failed_checks, vec![Check::RecipientPath, Check::PostRoot]);
+        assert_eq!(diagnosis.disagreements, vec![(Operation::Transaction(0), true)]);
+        assert!(diagnosis.to_string().contains("transaction 0 is valid natively but invalid in the circuit"));
+    }
+
+    #[test]
+    fn diff_commitment_binds_the_published_diffs() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, alice_pk, alice_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, TokenId(0), Amount(20)).unwrap();
+        let (bob_id, _, _) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (new_pk, _) = pp.keygen(&mut rng);
+        let tx = Transaction::create(&pp, alice_id, bob_id, TokenId(0), Amount(5), &alice_sk, &alice_pk, &mut rng);
+        let rotation = KeyRotation::create(&pp, alice_id, new_pk, &alice_sk, &alice_pk, &mut rng);
+        let mut temp_state = state.clone();
+        let mut rollup = Rollup::<1, 0, 1>::with_state_and_operations(
+            pp.clone(),
+            std::slice::from_ref(&tx),
+            &[],
+            std::slice::from_ref(&rotation),
+            &[],
+            &mut temp_state,
+            true,
+        )
         .unwrap();
-        rollup
+        let diffs = vec![
+            StateDiff::Balance { account: alice_id, token: TokenId(0), balance: Amount(15) },
+            StateDiff::Balance { account: bob_id, token: TokenId(0), balance: Amount(5) },
+            StateDiff::Key { account: alice_id, public_key: new_pk },
+        ];
+        assert_eq!(rollup.state_diffs, Some(diffs.clone()));
+        let mut replayed = state.clone();
+        for diff in &diffs {
+            replayed.apply_state_diff(diff).unwrap();
+        }
+        assert_eq!(replayed.root(), temp_state.root());
+
+        // Publishing any other diffs breaks the proof.
+        rollup.state_diffs = Some(vec![diffs[1], diffs[0], diffs[2]]);
+        let diagnosis = rollup.diagnose(&state).unwrap();
+        let unsatisfied = diagnosis.unsatisfied.unwrap();
+        assert_eq!(unsatisfied.operation, Operation::StateDiffs);
+        assert_eq!(unsatisfied.failed_checks, vec![Check::DiffCommitment]);
+        assert!(diagnosis.disagreements.is_empty());
+    }
+
+    #[test]
+    fn constraint_report_counts_every_constraint() {
+        let cs = ConstraintSystem::new_ref();
+        build_two_tx_circuit().generate_constraints(cs.clone()).unwrap();
+
+        let report = build_two_tx_circuit().constraint_report().unwrap();
+        assert_eq!(report.total(), cs.num_constraints());
+        let operations = report.truncated(1);
+        assert_eq!(
+            operations.0.keys().collect::<Vec<_>>(),
+            ["bridge_transfers", "final_root", "public_inputs", "state_diffs", "transaction[0]", "transaction[1]"]
+        );
+        assert_eq!(operations.0["transaction[0]"], operations.0["transaction[1]"]);
+        assert!(report.0.keys().any(|path| path.starts_with("transaction[1]/checks/verify_signature/")));
+        assert!(report.to_string().lines().all(|line| line.split_once(' ').is_some()));
+    }
+
+    #[test]
+    fn unsatisfied_constraints_name_the_index_of_their_operation() {
+        let mut rollup = build_two_tx_circuit();
+        let recipient_paths = rollup.recv_pre_tx_info_and_paths.as_mut().unwrap();
+        recipient_paths[1].1 = recipient_paths[0].1.clone();
+        let cs = ConstraintSystem::new_ref();
+        let subscriber = tracing_subscriber::Registry::default().with(ConstraintLayer::default());
+        tracing::subscriber::with_default(subscriber, || rollup.generate_constraints(cs.clone())).unwrap();
+        let unsatisfied = cs.which_is_unsatisfied().unwrap().unwrap();
+        assert!(unsatisfied.contains("::transaction[1]\n"), "{unsatisfied}");
     }
 
     #[test]
@@ -469,6 +1706,10 @@ mod test {
         let mut public_input = Vec::new();
         public_input.extend_from_slice(&circuit_to_verify_against.initial_root.unwrap().into_group().to_field_elements().unwrap());
         public_input.extend_from_slice(&circuit_to_verify_against.final_root.unwrap().into_group().to_field_elements().unwrap());
+        public_input.extend_from_slice(&circuit_to_verify_against.diff_commitment().unwrap().into_group().to_field_elements().unwrap());
+        let (deposits, withdrawals) = circuit_to_verify_against.bridge_transfers().commitments(&circuit_to_verify_against.ledger_params);
+        public_input.extend_from_slice(&deposits.into_group().to_field_elements().unwrap());
+        public_input.extend_from_slice(&withdrawals.into_group().to_field_elements().unwrap());
 
         let proof = Groth16::<Bls12_381, LibsnarkReduction>::prove(&pk, circuit_to_verify_against, &mut rng).unwrap();
         let valid_proof = Groth16::<Bls12_381, LibsnarkReduction>::verify(&vk, &public_input, &proof).unwrap();
@@ -480,6 +1721,10 @@ mod test {
         let mut public_input = Vec::new();
         public_input.extend_from_slice(&circuit_to_verify_against.final_root.unwrap().into_group().to_field_elements().unwrap());
         public_input.extend_from_slice(&circuit_to_verify_against.final_root.unwrap().into_group().to_field_elements().unwrap());
+        public_input.extend_from_slice(&circuit_to_verify_against.diff_commitment().unwrap().into_group().to_field_elements().unwrap());
+        let (deposits, withdrawals) = circuit_to_verify_against.bridge_transfers().commitments(&circuit_to_verify_against.ledger_params);
+        public_input.extend_from_slice(&deposits.into_group().to_field_elements().unwrap());
+        public_input.extend_from_slice(&withdrawals.into_group().to_field_elements().unwrap());
 
         let proof = Groth16::<Bls12_381, LibsnarkReduction>::prove(&pk, circuit_to_verify_against, &mut rng).unwrap();
         let valid_proof = Groth16::<Bls12_381, LibsnarkReduction>::verify(&vk, &public_input, &proof).unwrap();
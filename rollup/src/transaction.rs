@@ -1,29 +1,51 @@
-use crate::account::{AccountIdVar, AccountInformationVar, AccountPublicKeyVar};
-use crate::ledger::{self, AccPathVar, AccRootVar, AmountVar};
+use crate::account::{
+    AccountIdVar, AccountInformationVar, AccountPublicKeyVar, AccountSignatureVar, MultisigPolicyVar,
+    TokenIdVar,
+};
+use crate::da::StateDiffVar;
+use crate::diagnostics::{all_hold, Check};
+use crate::ledger::{self, path_is_at_index, AccPathVar, AccRootVar, AmountCommitmentVar, AmountVar};
+use crate::rollup::{LeafUpdate, TransferLeg};
 use crate::ConstraintF;
-use ark_ed_on_bls12_381::{constraints::EdwardsVar, EdwardsProjective};
 use ark_r1cs_std::prelude::*;
 use ark_relations::r1cs::{Namespace, SynthesisError};
-use ark_simple_payments_scp::commitment::blake2s::constraints::ParametersVar as B2SParamsVar;
-use ark_simple_payments_scp::signature::schnorr::constraints::{
-    ParametersVar as SchnorrParamsVar, SchnorrSignatureVerifyGadget, SignatureVar,
+use ark_simple_payments_scp::account::{
+    AccountSignature, AccountSignatureScheme, TokenId, MAX_MULTISIG_KEYS, NUM_TOKENS,
+};
+use ark_simple_payments_scp::ledger::AmountOpening;
+use ark_simple_payments_scp::transaction::{
+    KeyRotation, MultisigTransaction, OperationKind, SigningPayload, Swap, Transaction,
 };
-use ark_simple_payments_scp::transaction::Transaction;
 use std::borrow::Borrow;
-use blake2::Blake2b;
-use blake2::digest::generic_array::typenum::U32;
 
-/// Transaction transferring some amount from one account to another.
+/// The in-circuit counterpart of `SigningPayload`: the constant header for
+/// `kind` in the deployment's domain, followed by the `body` variables.
+pub fn signing_payload_bytes(
+    parameters: &ledger::ParametersVar,
+    kind: OperationKind,
+    body: Vec<UInt8<ConstraintF>>,
+) -> Vec<UInt8<ConstraintF>> {
+    let mut message = UInt8::constant_vec(&SigningPayload::header(&parameters.domain, kind));
+    message.extend(body);
+    message
+}
+
+/// Transaction transferring some amount of a token from one account to another.
 pub struct TransactionVar {
     /// The account information of the sender.
     pub sender: AccountIdVar,
     /// The account information of the recipient.
     pub recipient: AccountIdVar,
+    /// The token being transferred.
+    pub token: TokenIdVar,
     /// The amount being transferred from the sender to the receiver.
     pub amount: AmountVar,
-    /// The spend authorization is a signature over the sender, the recipient,
-    /// and the amount.
-    pub signature: SignatureVar<EdwardsProjective, EdwardsVar>,
+    /// The commitment to `amount`, if the ledger has confidential amounts.
+    pub amount_commitment: Option<AmountCommitmentVar>,
+    /// The L1 address that a withdrawal pays out to.
+    pub destination: Vec<UInt8<ConstraintF>>,
+    /// The spend authorization is a signature over the transfer `SigningPayload`.
+    pub signature: AccountSignatureVar,
 }
 
 impl TransactionVar {
@@ -31,123 +53,664 @@ impl TransactionVar {
     #[tracing::instrument(target = "r1cs", skip(self, pp, pub_key))]
     fn verify_signature(
         &self,
-        pp: &SchnorrParamsVar<EdwardsProjective, EdwardsVar>,
+        pp: &ledger::ParametersVar,
         pub_key: &AccountPublicKeyVar,
     ) -> Result<Boolean<ConstraintF>, SynthesisError> {
-        // The authorized message consists of
-        // (SenderAccId || SenderPubKey || RecipientAccId || RecipientPubKey || Amount)
-        let mut message = self.sender.to_bytes_le();
-        message.extend(self.recipient.to_bytes_le());
-        message.extend(self.amount.to_bytes_le());
-        SchnorrSignatureVerifyGadget::verify(&pp, &pub_key, &message, &self.signature)
+        // The body matches `SigningPayload::transfer`:
+        // (SenderAccId || RecipientAccId || TokenId || Amount || Destination),
+        // or `SigningPayload::confidential_transfer`, which has the amount
+        // commitment in place of the amount.
+        let mut body = self.sender.to_bytes_le();
+        body.extend(self.recipient.to_bytes_le());
+        body.extend(self.token.to_bytes_le());
+        let kind = match &self.amount_commitment {
+            Some(amount_commitment) => {
+                body.extend(amount_commitment.commitment.to_bytes()?);
+                OperationKind::ConfidentialTransfer
+            }
+            None => {
+                body.extend(self.amount.to_bytes_le());
+                OperationKind::Transfer
+            }
+        };
+        body.extend_from_slice(&self.destination);
+        let message = signing_payload_bytes(pp, kind, body);
+        pp.verify_signature(pub_key, &message, &self.signature)
     }
 
     /// Check that the transaction is valid for the given ledger state. This checks
     /// the following conditions:
     /// 1. Verify that the signature is valid with respect to the public key
     /// corresponding to `self.sender`.
-    /// 2. Verify that the sender's account has sufficient balance to finance
-    /// the transaction.
-    /// 3. Verify that the recipient's account exists.
-    #[tracing::instrument(
-        target = "r1cs",
-        skip(
-            self,
+    /// 2. Verify that `leg` moves `self.amount` of `self.token` from the sender to
+    /// the recipient, along with `self.amount_commitment` if the ledger has
+    /// confidential amounts; see `TransferLegVar::validate`.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, leg))]
+    pub fn validate(
+        &self,
+        parameters: &ledger::ParametersVar,
+        leg: &TransferLegVar,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        all_hold(&self.checks(parameters, leg)?)
+    }
+
+    /// The checks of `validate`, each labelled with what it checks.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, leg))]
+    pub fn checks(
+        &self,
+        parameters: &ledger::ParametersVar,
+        leg: &TransferLegVar,
+    ) -> Result<Vec<(Check, Boolean<ConstraintF>)>, SynthesisError> {
+        // Verify the signature against the sender pubkey.
+        let sig_verifies = self.verify_signature(parameters, &leg.pre_sender_acc_info.public_key)?;
+        let mut checks = leg.checks(
             parameters,
-            pre_sender_acc_info,
-            pre_sender_path,
-            post_sender_path,
-            pre_recipient_acc_info,
-            pre_recipient_path,
-            post_recipient_path,
-            pre_root,
-            post_root
+            &self.sender,
+            &self.recipient,
+            &self.token,
+            &self.amount,
+            self.amount_commitment.as_ref(),
+        )?;
+        checks.push((Check::Signature, sig_verifies));
+        Ok(checks)
+    }
+
+    /// The diffs that the transaction publishes; see `TransferLegVar::state_diffs`.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, leg))]
+    pub fn state_diffs(
+        &self,
+        parameters: &ledger::ParametersVar,
+        leg: &TransferLegVar,
+    ) -> Result<Vec<StateDiffVar>, SynthesisError> {
+        leg.state_diffs(
+            parameters,
+            &self.sender,
+            &self.recipient,
+            &self.token,
+            &self.amount,
+            self.amount_commitment.as_ref(),
         )
-    )]
+    }
+}
+
+/// A transaction is allocated together with the opening of its amount
+/// commitment, if it carries one; see `Transaction::amount_with_opening`.
+impl AllocVar<(Transaction, Option<AmountOpening>), ConstraintF> for TransactionVar {
+    #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
+    fn new_variable<T: Borrow<(Transaction, Option<AmountOpening>)>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        let cs = cs.into();
+        f().and_then(|tx| {
+            let (tx, opening) = tx.borrow();
+            let (amount, amount_commitment) =
+                tx.amount_with_opening(opening.as_ref()).ok_or(SynthesisError::AssignmentMissing)?;
+            let sender = AccountIdVar::new_variable(cs.clone(), || Ok(&tx.sender), mode)?;
+            let recipient = AccountIdVar::new_variable(cs.clone(), || Ok(&tx.recipient), mode)?;
+            let token = TokenIdVar::new_variable(cs.clone(), || Ok(&tx.token), mode)?;
+            let amount = AmountVar::new_variable(cs.clone(), || Ok(amount), mode)?;
+            let amount_commitment = amount_commitment
+                .map(|c| AmountCommitmentVar::new_variable(cs.clone(), || Ok(c), mode))
+                .transpose()?;
+            let destination = Vec::new_variable(cs.clone(), || Ok(&tx.destination[..]), mode)?;
+            let signature = AccountSignatureVar::new_variable(cs.clone(), || Ok(&tx.signature), mode)?;
+            Ok(Self {
+                sender,
+                recipient,
+                token,
+                amount,
+                amount_commitment,
+                destination,
+                signature,
+            })
+        })
+    }
+}
+
+/// The leaves and paths witnessing one transfer of funds between two
+/// `(account, token)` leaves: both leaves' information before the transfer and
+/// one path per leaf. The sender's leaf is updated first, so the recipient's
+/// path is one in the tree in which only the sender's leaf is updated.
+pub struct TransferLegVar {
+    pub pre_sender_acc_info: AccountInformationVar,
+    pub sender_path: AccPathVar,
+    pub pre_recipient_acc_info: AccountInformationVar,
+    pub recipient_path: AccPathVar,
+    /// The state root before the transfer.
+    pub pre_root: AccRootVar,
+    /// The state root after the transfer.
+    pub post_root: AccRootVar,
+}
+
+impl TransferLegVar {
+    /// Check that this leg moves `amount` of `token` from `sender` to `recipient`.
+    /// This checks the following conditions:
+    /// 1. Verify that the sender's account has sufficient balance of `token`
+    /// to finance the transfer.
+    /// 2. Verify that the recipient's account exists.
+    /// 3. Verify that both leaves are updated correctly from `pre_root` to `post_root`.
+    /// 4. Verify that all paths open the `(sender, token)` and `(recipient, token)`
+    /// leaves, so that exactly the transferred token is debited and credited.
+    /// 5. If the ledger has confidential amounts, verify that `amount_commitment`
+    /// and both balance commitments open to `amount` and the balances checked
+    /// above, and that `amount_commitment` is moved from the sender's
+    /// commitment to the recipient's. Without an amount commitment, the leg is
+    /// invalid in such a ledger.
+    ///
+    /// No authorization is checked here; that is up to the operation the leg belongs to.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, sender, recipient, token, amount, amount_commitment))]
     pub fn validate(
         &self,
         parameters: &ledger::ParametersVar,
-        pre_sender_acc_info: &AccountInformationVar,
-        pre_sender_path: &AccPathVar,
-        post_sender_path: &AccPathVar,
-        pre_recipient_acc_info: &AccountInformationVar,
-        pre_recipient_path: &AccPathVar,
-        post_recipient_path: &AccPathVar,
-        pre_root: &AccRootVar,
-        post_root: &AccRootVar,
+        sender: &AccountIdVar,
+        recipient: &AccountIdVar,
+        token: &TokenIdVar,
+        amount: &AmountVar,
+        amount_commitment: Option<&AmountCommitmentVar>,
     ) -> Result<Boolean<ConstraintF>, SynthesisError> {
-        // Verify the signature against the sender pubkey.
-        let sig_verifies = self.verify_signature(&parameters.sig_params, &pre_sender_acc_info.public_key)?; 
+        all_hold(&self.checks(parameters, sender, recipient, token, amount, amount_commitment)?)
+    }
+
+    /// The checks of `validate`, each labelled with what it checks. Condition 1
+    /// is enforced directly by the range checks of the new balances.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, sender, recipient, token, amount, amount_commitment))]
+    pub fn checks(
+        &self,
+        parameters: &ledger::ParametersVar,
+        sender: &AccountIdVar,
+        recipient: &AccountIdVar,
+        token: &TokenIdVar,
+        amount: &AmountVar,
+        amount_commitment: Option<&AmountCommitmentVar>,
+    ) -> Result<Vec<(Check, Boolean<ConstraintF>)>, SynthesisError> {
+        let (post_sender_balance, post_recipient_balance) = self.post_balances(amount)?;
+        let mut post_sender_acc_info = self.pre_sender_acc_info.clone();
+        post_sender_acc_info.balance = post_sender_balance;
+        let mut post_recipient_acc_info = self.pre_recipient_acc_info.clone();
+        post_recipient_acc_info.balance = post_recipient_balance;
 
-        // Compute the new sender balance.
-        let mut post_sender_acc_info = pre_sender_acc_info.clone();
-        post_sender_acc_info.balance = post_sender_acc_info.balance.checked_sub(&self.amount)?; 
-        // Compute the new receiver balance.
-        let mut post_recipient_acc_info = pre_recipient_acc_info.clone();
-        post_recipient_acc_info.balance = post_recipient_acc_info.balance.checked_add(&self.amount)?; 
+        // Open the commitments, so that the checks on the balances above apply to
+        // the committed values, and move the amount commitment. As the same
+        // commitment is subtracted and added, the transfer conserves the token.
+        let commitments_valid = match (
+            parameters.confidential_amounts,
+            amount_commitment,
+            &self.pre_sender_acc_info.balance_commitment,
+            &self.pre_recipient_acc_info.balance_commitment,
+        ) {
+            (true, Some(amount_commitment), Some(sender_commitment), Some(recipient_commitment)) => {
+                post_sender_acc_info.balance_commitment = Some(sender_commitment.sub(amount_commitment));
+                post_recipient_acc_info.balance_commitment = Some(recipient_commitment.add(amount_commitment));
+                Boolean::kary_and(&[
+                    amount_commitment.opens_to(parameters, amount)?,
+                    sender_commitment.opens_to(parameters, &self.pre_sender_acc_info.balance)?,
+                    recipient_commitment.opens_to(parameters, &self.pre_recipient_acc_info.balance)?,
+                ])?
+            }
+            (false, None, None, None) => Boolean::TRUE,
+            _ => Boolean::FALSE,
+        };
 
         // Check that the pre-tx sender account information is correct with
-        // respect to `pre_tx_root`, and that the post-tx sender account
-        // information is correct with respect to `post_tx_root`.
-        let sender_exists = pre_sender_path.verify_membership(
+        // respect to `pre_tx_root`, and compute the root with the post-tx sender
+        // account information along the same path.
+        let (sender_exists, sender_updated_root) = self.sender_path.update_leaf(
             &parameters.leaf_crh_params,
-            &parameters.two_to_one_crh_params,
-            &pre_root,
-            &pre_sender_acc_info.to_bytes_le().as_slice(),
-        )?; 
+            &parameters.inner_crh_params,
+            &self.pre_root,
+            &self.pre_sender_acc_info.to_bytes_le(),
+            &post_sender_acc_info.to_bytes_le(),
+        )?;
 
-        let sender_updated_correctly = post_sender_path.verify_membership(
-            &parameters.leaf_crh_params,
-            &parameters.two_to_one_crh_params,
-            &post_root,
-            &post_sender_acc_info.to_bytes_le().as_slice(),
-        )?; 
-
-        // Check that the pre-tx recipient account information is correct with
-        // respect to `pre_tx_root`, and that the post-tx recipient account
-        // information is correct with respect to `post_tx_root`.
-        let recipient_exists = pre_recipient_path.verify_membership(
+        // Do the same for the recipient, starting from the root in which the
+        // sender's leaf is updated, and check that this leads to `post_tx_root`.
+        let (recipient_exists, post_root) = self.recipient_path.update_leaf(
             &parameters.leaf_crh_params,
-            &parameters.two_to_one_crh_params,
-            &pre_root,
-            &pre_recipient_acc_info.to_bytes_le().as_slice(),
-        )?; 
+            &parameters.inner_crh_params,
+            &sender_updated_root,
+            &self.pre_recipient_acc_info.to_bytes_le(),
+            &post_recipient_acc_info.to_bytes_le(),
+        )?;
+        let reaches_post_root = post_root.is_eq(&self.post_root)?;
+
+        // Check that the sender path opens the (sender, token) leaf and the
+        // recipient path opens the (recipient, token) leaf.
+        let token_supported = token.is_supported()?;
+        let sender_index = sender.leaf_index_bits_le(token)?;
+        let recipient_index = recipient.leaf_index_bits_le(token)?;
+        let paths_at_token_leaves = Boolean::kary_and(&[
+            token_supported,
+            path_is_at_index(&self.sender_path, &sender_index)?,
+            path_is_at_index(&self.recipient_path, &recipient_index)?,
+        ])?;
+
+        Ok(vec![
+            (Check::SenderPath, sender_exists),
+            (Check::AmountCommitments, commitments_valid),
+            (Check::LeafIndices, paths_at_token_leaves),
+            (Check::RecipientPath, recipient_exists),
+            (Check::PostRoot, reaches_post_root),
+        ])
+    }
+
+    /// The balances of the sender and the recipient after moving `amount`.
+    #[tracing::instrument(target = "r1cs", skip(self, amount))]
+    fn post_balances(&self, amount: &AmountVar) -> Result<(AmountVar, AmountVar), SynthesisError> {
+        Ok((
+            self.pre_sender_acc_info.balance.checked_sub(amount)?,
+            self.pre_recipient_acc_info.balance.checked_add(amount)?,
+        ))
+    }
+
+    /// The diffs that this leg publishes: the new balances of `(sender, token)`
+    /// and `(recipient, token)`, mirroring the ledger. A ledger with
+    /// confidential amounts publishes the new balance commitments instead, and
+    /// nothing if the leg lacks commitments, as it is then invalid.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, sender, recipient, token, amount, amount_commitment))]
+    pub fn state_diffs(
+        &self,
+        parameters: &ledger::ParametersVar,
+        sender: &AccountIdVar,
+        recipient: &AccountIdVar,
+        token: &TokenIdVar,
+        amount: &AmountVar,
+        amount_commitment: Option<&AmountCommitmentVar>,
+    ) -> Result<Vec<StateDiffVar>, SynthesisError> {
+        if !parameters.confidential_amounts {
+            let (sender_balance, recipient_balance) = self.post_balances(amount)?;
+            return Ok(vec![
+                StateDiffVar::Balance {
+                    account: sender.clone(),
+                    token: token.clone(),
+                    balance: sender_balance,
+                },
+                StateDiffVar::Balance {
+                    account: recipient.clone(),
+                    token: token.clone(),
+                    balance: recipient_balance,
+                },
+            ]);
+        }
+        let (Some(amount_commitment), Some(sender_commitment), Some(recipient_commitment)) = (
+            amount_commitment,
+            &self.pre_sender_acc_info.balance_commitment,
+            &self.pre_recipient_acc_info.balance_commitment,
+        ) else {
+            return Ok(Vec::new());
+        };
+        Ok(vec![
+            StateDiffVar::BalanceCommitment {
+                account: sender.clone(),
+                token: token.clone(),
+                commitment: sender_commitment.sub(amount_commitment).commitment,
+            },
+            StateDiffVar::BalanceCommitment {
+                account: recipient.clone(),
+                token: token.clone(),
+                commitment: recipient_commitment.add(amount_commitment).commitment,
+            },
+        ])
+    }
+}
+
+impl AllocVar<TransferLeg, ConstraintF> for TransferLegVar {
+    #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
+    fn new_variable<T: Borrow<TransferLeg>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        let cs = cs.into();
+        f().and_then(|leg| {
+            let leg: &TransferLeg = leg.borrow();
+            let (pre_sender_acc_info, sender_path) = &leg.sender_pre_info_and_path;
+            let (pre_recipient_acc_info, recipient_path) = &leg.recipient_pre_info_and_path;
+            Ok(Self {
+                pre_sender_acc_info: AccountInformationVar::new_variable(cs.clone(), || Ok(pre_sender_acc_info), mode)?,
+                sender_path: AccPathVar::new_variable(cs.clone(), || Ok(sender_path), mode)?,
+                pre_recipient_acc_info: AccountInformationVar::new_variable(cs.clone(), || Ok(pre_recipient_acc_info), mode)?,
+                recipient_path: AccPathVar::new_variable(cs.clone(), || Ok(recipient_path), mode)?,
+                pre_root: AccRootVar::new_variable(cs.clone(), || Ok(leg.pre_root), mode)?,
+                post_root: AccRootVar::new_variable(cs.clone(), || Ok(leg.post_root), mode)?,
+            })
+        })
+    }
+}
 
-        let recipient_updated_correctly = post_recipient_path.verify_membership(
+/// Atomic two-leg swap signed by both parties; see `Swap`.
+pub struct SwapVar {
+    pub party_a: AccountIdVar,
+    pub token_a: TokenIdVar,
+    pub amount_a: AmountVar,
+    pub party_b: AccountIdVar,
+    pub token_b: TokenIdVar,
+    pub amount_b: AmountVar,
+    pub signature_a: AccountSignatureVar,
+    pub signature_b: AccountSignatureVar,
+}
+
+impl SwapVar {
+    /// Check that the swap is valid for the given ledger state. This checks
+    /// the following conditions:
+    /// 1. Verify that `signature_a` and `signature_b` are valid with respect to
+    /// the public keys in the leaves that `party_a` and `party_b` are debited from.
+    /// 2. Verify that `legs[0]` moves `amount_a` of `token_a` from `party_a` to
+    /// `party_b`, and `legs[1]` moves `amount_b` of `token_b` back.
+    /// 3. Verify that the second leg starts from the root the first leg ends in,
+    /// so that the swap goes from `legs[0].pre_root` to `legs[1].post_root`
+    /// with both transfers applied.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, legs))]
+    pub fn validate(
+        &self,
+        parameters: &ledger::ParametersVar,
+        legs: &[TransferLegVar; 2],
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        // The body matches `SigningPayload::swap`:
+        // (PartyA || TokenA || AmountA || PartyB || TokenB || AmountB)
+        let mut body = self.party_a.to_bytes_le();
+        body.extend(self.token_a.to_bytes_le());
+        body.extend(self.amount_a.to_bytes_le());
+        body.extend(self.party_b.to_bytes_le());
+        body.extend(self.token_b.to_bytes_le());
+        body.extend(self.amount_b.to_bytes_le());
+        let message = signing_payload_bytes(parameters, OperationKind::Swap, body);
+        let sig_a_verifies = parameters.verify_signature(
+            &legs[0].pre_sender_acc_info.public_key,
+            &message,
+            &self.signature_a,
+        )?;
+        let sig_b_verifies = parameters.verify_signature(
+            &legs[1].pre_sender_acc_info.public_key,
+            &message,
+            &self.signature_b,
+        )?;
+
+        let leg_a_valid =
+            legs[0].validate(parameters, &self.party_a, &self.party_b, &self.token_a, &self.amount_a, None)?;
+        let leg_b_valid =
+            legs[1].validate(parameters, &self.party_b, &self.party_a, &self.token_b, &self.amount_b, None)?;
+        let legs_chained = legs[0].post_root.is_eq(&legs[1].pre_root)?;
+
+        Boolean::kary_and(&[sig_a_verifies, sig_b_verifies, leg_a_valid, leg_b_valid, legs_chained])
+    }
+
+    /// The diffs that the swap publishes: those of `legs[0]`, then those of `legs[1]`.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, legs))]
+    pub fn state_diffs(
+        &self,
+        parameters: &ledger::ParametersVar,
+        legs: &[TransferLegVar; 2],
+    ) -> Result<Vec<StateDiffVar>, SynthesisError> {
+        let mut diffs =
+            legs[0].state_diffs(parameters, &self.party_a, &self.party_b, &self.token_a, &self.amount_a, None)?;
+        diffs.extend(legs[1].state_diffs(parameters, &self.party_b, &self.party_a, &self.token_b, &self.amount_b, None)?);
+        Ok(diffs)
+    }
+}
+
+impl AllocVar<Swap, ConstraintF> for SwapVar {
+    #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
+    fn new_variable<T: Borrow<Swap>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        let cs = cs.into();
+        f().and_then(|swap| {
+            let swap: &Swap = swap.borrow();
+            let terms = &swap.terms;
+            Ok(Self {
+                party_a: AccountIdVar::new_variable(cs.clone(), || Ok(&terms.party_a), mode)?,
+                token_a: TokenIdVar::new_variable(cs.clone(), || Ok(&terms.token_a), mode)?,
+                amount_a: AmountVar::new_variable(cs.clone(), || Ok(&terms.amount_a), mode)?,
+                party_b: AccountIdVar::new_variable(cs.clone(), || Ok(&terms.party_b), mode)?,
+                token_b: TokenIdVar::new_variable(cs.clone(), || Ok(&terms.token_b), mode)?,
+                amount_b: AmountVar::new_variable(cs.clone(), || Ok(&terms.amount_b), mode)?,
+                signature_a: AccountSignatureVar::new_variable(cs.clone(), || Ok(&swap.signature_a), mode)?,
+                signature_b: AccountSignatureVar::new_variable(cs.clone(), || Ok(&swap.signature_b), mode)?,
+            })
+        })
+    }
+}
+
+/// The path witnessing an update of a single `(account, token)` leaf: the leaf's
+/// information before the update, and its path.
+pub struct LeafUpdateVar {
+    pub pre_acc_info: AccountInformationVar,
+    pub path: AccPathVar,
+    /// The state root before the update.
+    pub pre_root: AccRootVar,
+    /// The state root after the update.
+    pub post_root: AccRootVar,
+}
+
+impl LeafUpdateVar {
+    /// Check that this update replaces `self.pre_acc_info` by `post_acc_info` in the
+    /// `(account, token)` leaf, going from `self.pre_root` to `self.post_root`.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, account, token, post_acc_info))]
+    pub fn validate(
+        &self,
+        parameters: &ledger::ParametersVar,
+        account: &AccountIdVar,
+        token: &TokenIdVar,
+        post_acc_info: &AccountInformationVar,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        let (leaf_existed, post_root) = self.path.update_leaf(
             &parameters.leaf_crh_params,
-            &parameters.two_to_one_crh_params,
-            &post_root,
-            &post_recipient_acc_info.to_bytes_le().as_slice(),
-        )?; 
+            &parameters.inner_crh_params,
+            &self.pre_root,
+            &self.pre_acc_info.to_bytes_le(),
+            &post_acc_info.to_bytes_le(),
+        )?;
+        let index = account.leaf_index_bits_le(token)?;
+        Boolean::kary_and(&[
+            token.is_supported()?,
+            path_is_at_index(&self.path, &index)?,
+            leaf_existed,
+            post_root.is_eq(&self.post_root)?,
+        ])
+    }
+}
+
+impl AllocVar<LeafUpdate, ConstraintF> for LeafUpdateVar {
+    #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
+    fn new_variable<T: Borrow<LeafUpdate>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        let cs = cs.into();
+        f().and_then(|update| {
+            let update: &LeafUpdate = update.borrow();
+            let (pre_acc_info, path) = &update.pre_info_and_path;
+            Ok(Self {
+                pre_acc_info: AccountInformationVar::new_variable(cs.clone(), || Ok(pre_acc_info), mode)?,
+                path: AccPathVar::new_variable(cs.clone(), || Ok(path), mode)?,
+                pre_root: AccRootVar::new_variable(cs.clone(), || Ok(update.pre_root), mode)?,
+                post_root: AccRootVar::new_variable(cs.clone(), || Ok(update.post_root), mode)?,
+            })
+        })
+    }
+}
+
+/// Replacement of an account's public key; see `KeyRotation`.
+pub struct KeyRotationVar {
+    pub account: AccountIdVar,
+    pub new_public_key: AccountPublicKeyVar,
+    pub signature: AccountSignatureVar,
+}
+
+impl KeyRotationVar {
+    /// Check that the rotation is valid for the given ledger state. This checks
+    /// the following conditions:
+    /// 1. Verify that all leaves of the account hold the same public key, and that
+    /// the signature is valid with respect to it.
+    /// 2. Verify that `updates[i]` replaces that key by `self.new_public_key` in the
+    /// leaf of the i-th token, keeping its balance.
+    /// 3. Verify that the updates are chained, so that the rotation goes from
+    /// `updates[0].pre_root` to `updates[NUM_TOKENS - 1].post_root`.
+    ///
+    /// That the new key is not used by another account is not checked.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, updates))]
+    pub fn validate(
+        &self,
+        parameters: &ledger::ParametersVar,
+        updates: &[LeafUpdateVar; NUM_TOKENS],
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        let current_public_key = &updates[0].pre_acc_info.public_key;
+        // The body matches `SigningPayload::rotate_key`: (AccId || CurrentPublicKey || NewPublicKey)
+        let mut body = self.account.to_bytes_le();
+        body.extend(current_public_key.to_bytes()?);
+        body.extend(self.new_public_key.to_bytes()?);
+        let message = signing_payload_bytes(parameters, OperationKind::RotateKey, body);
+        let mut checks = vec![parameters.verify_signature(current_public_key, &message, &self.signature)?];
+
+        for (token, update) in TokenId::all().zip(updates) {
+            let token = TokenIdVar(UInt8::constant(token.0));
+            let post_acc_info = AccountInformationVar {
+                public_key: self.new_public_key.clone(),
+                balance: update.pre_acc_info.balance.clone(),
+                balance_commitment: update.pre_acc_info.balance_commitment.clone(),
+            };
+            checks.push(update.pre_acc_info.public_key.is_eq(current_public_key)?);
+            checks.push(update.validate(parameters, &self.account, &token, &post_acc_info)?);
+        }
+        for pair in updates.windows(2) {
+            checks.push(pair[0].post_root.is_eq(&pair[1].pre_root)?);
+        }
+        Boolean::kary_and(&checks)
+    }
+
+    /// The diff that the rotation publishes: the new key of the account.
+    pub fn state_diff(&self) -> StateDiffVar {
+        StateDiffVar::Key {
+            account: self.account.clone(),
+            public_key: self.new_public_key.clone(),
+        }
+    }
+}
+
+impl AllocVar<KeyRotation, ConstraintF> for KeyRotationVar {
+    #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
+    fn new_variable<T: Borrow<KeyRotation>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        let cs = cs.into();
+        f().and_then(|rotation| {
+            let rotation: &KeyRotation = rotation.borrow();
+            Ok(Self {
+                account: AccountIdVar::new_variable(cs.clone(), || Ok(&rotation.account), mode)?,
+                new_public_key: AccountPublicKeyVar::new_variable(
+                    cs.clone(),
+                    || Ok(&rotation.new_public_key),
+                    mode,
+                )?,
+                signature: AccountSignatureVar::new_variable(cs.clone(), || Ok(&rotation.signature), mode)?,
+            })
+        })
+    }
+}
+
+/// Transaction transferring some amount of a token from a multisig account;
+/// see `MultisigTransaction`.
+pub struct MultisigTransactionVar {
+    pub sender: AccountIdVar,
+    pub recipient: AccountIdVar,
+    pub token: TokenIdVar,
+    pub amount: AmountVar,
+    pub destination: Vec<UInt8<ConstraintF>>,
+    pub policy: MultisigPolicyVar,
+    /// One signature per key slot of the policy. Missing signatures are
+    /// allocated as `AccountSignature::placeholder`, which never verifies.
+    pub signatures: Vec<AccountSignatureVar>,
+}
 
-        sender_exists
-            .and(&sender_updated_correctly)?
-            .and(&recipient_exists)?
-            .and(&recipient_updated_correctly)?
-            .and(&sig_verifies)
+impl MultisigTransactionVar {
+    /// The message that the policy keys sign, matching `SigningPayload::multisig_transfer`.
+    fn signing_payload(&self, parameters: &ledger::ParametersVar) -> Vec<UInt8<ConstraintF>> {
+        // (SenderAccId || RecipientAccId || TokenId || Amount || Destination)
+        let mut body = self.sender.to_bytes_le();
+        body.extend(self.recipient.to_bytes_le());
+        body.extend(self.token.to_bytes_le());
+        body.extend(self.amount.to_bytes_le());
+        body.extend_from_slice(&self.destination);
+        signing_payload_bytes(parameters, OperationKind::MultisigTransfer, body)
+    }
+
+    /// Check that the transaction is valid for the given ledger state. This checks
+    /// the following conditions:
+    /// 1. Verify that `self.policy` hashes to the public key of `self.sender`.
+    /// 2. Verify that the signatures of at least `threshold` of the policy keys
+    /// are valid.
+    /// 3. Verify that `leg` moves `self.amount` of `self.token` from the sender to
+    /// the recipient; see `TransferLegVar::validate`.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, leg))]
+    pub fn validate(
+        &self,
+        parameters: &ledger::ParametersVar,
+        leg: &TransferLegVar,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        let policy_matches = self
+            .policy
+            .account_key(parameters)?
+            .is_eq(&leg.pre_sender_acc_info.public_key.pub_key)?;
+
+        let message = self.signing_payload(parameters);
+        let threshold_met = self.policy.threshold_met(parameters, &message, &self.signatures)?;
+
+        let leg_valid = leg.validate(parameters, &self.sender, &self.recipient, &self.token, &self.amount, None)?;
+        Boolean::kary_and(&[policy_matches, threshold_met, leg_valid])
+    }
+
+    /// The diffs that the transaction publishes; see `TransferLegVar::state_diffs`.
+    #[tracing::instrument(target = "r1cs", skip(self, parameters, leg))]
+    pub fn state_diffs(
+        &self,
+        parameters: &ledger::ParametersVar,
+        leg: &TransferLegVar,
+    ) -> Result<Vec<StateDiffVar>, SynthesisError> {
+        leg.state_diffs(parameters, &self.sender, &self.recipient, &self.token, &self.amount, None)
     }
 }
 
-impl AllocVar<Transaction, ConstraintF> for TransactionVar {
+impl AllocVar<MultisigTransaction, ConstraintF> for MultisigTransactionVar {
     #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
-    fn new_variable<T: Borrow<Transaction>>(
+    fn new_variable<T: Borrow<MultisigTransaction>>(
         cs: impl Into<Namespace<ConstraintF>>,
         f: impl FnOnce() -> Result<T, SynthesisError>,
         mode: AllocationMode,
     ) -> Result<Self, SynthesisError> {
         let cs = cs.into();
         f().and_then(|tx| {
-            let tx: &Transaction = tx.borrow();
-            let sender = AccountIdVar::new_variable(cs.clone(), || Ok(&tx.sender), mode)?;
-            let recipient = AccountIdVar::new_variable(cs.clone(), || Ok(&tx.recipient), mode)?;
-            let amount = AmountVar::new_variable(cs.clone(), || Ok(&tx.amount), mode)?;
-            let signature = SignatureVar::new_variable(cs.clone(), || Ok(&tx.signature), mode)?;
+            let tx: &MultisigTransaction = tx.borrow();
+            // Pad with placeholders of the scheme the signers used, so that the
+            // circuit does not depend on which slots are missing.
+            let scheme = tx
+                .signatures
+                .iter()
+                .flatten()
+                .next()
+                .map_or(AccountSignatureScheme::default(), AccountSignature::scheme);
+            let missing = AccountSignature::placeholder(scheme);
+            let signatures = (0..MAX_MULTISIG_KEYS)
+                .map(|i| {
+                    let signature = tx.signatures.get(i).and_then(Option::as_ref).unwrap_or(&missing);
+                    AccountSignatureVar::new_variable(cs.clone(), || Ok(signature), mode)
+                })
+                .collect::<Result<_, _>>()?;
             Ok(Self {
-                sender,
-                recipient,
-                amount,
-                signature,
+                sender: AccountIdVar::new_variable(cs.clone(), || Ok(&tx.sender), mode)?,
+                recipient: AccountIdVar::new_variable(cs.clone(), || Ok(&tx.recipient), mode)?,
+                token: TokenIdVar::new_variable(cs.clone(), || Ok(&tx.token), mode)?,
+                amount: AmountVar::new_variable(cs.clone(), || Ok(&tx.amount), mode)?,
+                destination: Vec::new_variable(cs.clone(), || Ok(&tx.destination[..]), mode)?,
+                policy: MultisigPolicyVar::new_variable(cs.clone(), || Ok(&tx.policy), mode)?,
+                signatures,
             })
         })
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,42 @@
+//! Load a genesis file and print the root of its ledger.
+//!
+//! Run `cargo run --release --example genesis <genesis.json> [genesis.bin]` to
+//! print the root that the first batch has to start from, and optionally write
+//! the canonical binary form of the genesis to `genesis.bin`. Files that do not
+//! end in `.json` are read in the binary form. Without arguments, a sample
+//! genesis with two fresh accounts is printed as JSON.
+//!
+//! The parameters are sampled from `test_rng`, so the root is only meaningful
+//! for deployments that use the same test parameters.
+use ark_simple_payments_scp::genesis::{root_to_hex, Genesis, GenesisAccount};
+use ark_simple_payments_scp::ledger::Parameters;
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let mut rng = ark_std::test_rng();
+    let pp = Parameters::sample(&mut rng);
+
+    let Some(path) = args.next() else {
+        let accounts = [vec![1000, 0, 5], vec![]]
+            .into_iter()
+            .map(|balances| GenesisAccount { public_key: pp.keygen(&mut rng).0, balances })
+            .collect();
+        let genesis = Genesis { num_accounts: 32, accounts };
+        println!("{}", genesis.to_json());
+        return;
+    };
+
+    let genesis = if path.ends_with(".json") {
+        let json = std::fs::read_to_string(&path).expect("the genesis file should be readable");
+        Genesis::from_json(&json).expect("the genesis file should be valid JSON")
+    } else {
+        let bytes = std::fs::read(&path).expect("the genesis file should be readable");
+        Genesis::from_bytes(&bytes).expect("the genesis file should be a canonical genesis")
+    };
+    let root = genesis.root(&pp).expect("the genesis should describe a valid ledger");
+    println!("{}", root_to_hex(&root));
+
+    if let Some(out) = args.next() {
+        std::fs::write(&out, genesis.to_bytes()).expect("the binary genesis should be writable");
+    }
+}
@@ -1,14 +1,52 @@
 use crate::ledger::*;
-use crate::signature::schnorr;
+use crate::signature::{eddsa, schnorr};
+use ark_crypto_primitives::crh::CRHScheme;
 use ark_ed_on_bls12_381::EdwardsProjective;
 use ark_serialize::{CanonicalSerialize, CanonicalDeserialize};
-use std::borrow::Borrow;
 
 /// Account public key used to verify transaction signatures.
 pub type AccountPublicKey = schnorr::PublicKey<EdwardsProjective>;
 /// Account secret key used to create transaction signatures.
 pub type AccountSecretKey = schnorr::SecretKey<EdwardsProjective>;
 
+/// The signature scheme that account keys sign with. It is fixed per deployment
+/// by `Parameters::signature_scheme`.
+#[derive(Hash, Eq, PartialEq, Copy, Clone, Debug, Default)]
+pub enum AccountSignatureScheme {
+    /// The Schnorr signature of `signature::schnorr`.
+    #[default]
+    Schnorr,
+    /// RedJubjub as specified by Zcash, see `signature::eddsa`. Existing wallets
+    /// and libraries can produce these signatures.
+    EdDSA,
+}
+
+/// A signature by an account key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AccountSignature {
+    Schnorr(schnorr::Signature<EdwardsProjective>),
+    EdDSA(eddsa::Signature),
+}
+
+impl AccountSignature {
+    /// The scheme this signature belongs to.
+    pub fn scheme(&self) -> AccountSignatureScheme {
+        match self {
+            Self::Schnorr(_) => AccountSignatureScheme::Schnorr,
+            Self::EdDSA(_) => AccountSignatureScheme::EdDSA,
+        }
+    }
+
+    /// A signature of `scheme` that does not verify under any key other than the
+    /// identity, for padding unused signature slots.
+    pub fn placeholder(scheme: AccountSignatureScheme) -> Self {
+        match scheme {
+            AccountSignatureScheme::Schnorr => Self::Schnorr(Default::default()),
+            AccountSignatureScheme::EdDSA => Self::EdDSA(Default::default()),
+        }
+    }
+}
+
 /// Account identifier. This prototype supports only 256 accounts at a time.
 #[derive(Hash, Eq, PartialEq, Copy, Clone, Ord, PartialOrd, Debug)]
 pub struct AccountId(pub u8);
@@ -21,28 +59,122 @@ impl AccountId {
 }
 
 impl AccountId {
+    /// The index of the leaf holding this account's balance of `token`.
+    /// The token identifier forms the low `TOKEN_ID_BITS` bits of the index.
+    pub fn leaf_index(&self, token: TokenId) -> usize {
+        ((self.0 as usize) << TOKEN_ID_BITS) | token.0 as usize
+    }
+
     /// Increment the identifier in place.
     pub(crate) fn checked_increment(&mut self) -> Option<()> {
         self.0.checked_add(1).map(|result| self.0 = result)
     }
 }
 
-/// Information about the account, such as the balance and the associated public key.
+/// Number of leaf-index bits taken up by the token identifier.
+pub const TOKEN_ID_BITS: usize = 2;
+/// Number of distinct tokens that every account can hold.
+pub const NUM_TOKENS: usize = 1 << TOKEN_ID_BITS;
+
+/// Token identifier. Every account holds a separate balance of each token,
+/// stored in its own `(account, token)` leaf.
+#[derive(Hash, Eq, PartialEq, Copy, Clone, Ord, PartialOrd, Debug, Default)]
+pub struct TokenId(pub u8);
+
+impl TokenId {
+    /// Convert the token identifier to bytes.
+    pub fn to_bytes_le(&self) -> Vec<u8> {
+        vec![self.0]
+    }
+
+    /// All token identifiers supported by the ledger.
+    pub fn all() -> impl Iterator<Item = TokenId> {
+        (0..NUM_TOKENS as u8).map(TokenId)
+    }
+}
+
+/// Information stored in an `(account, token)` leaf: the account's public key
+/// and its balance of that token, or a commitment to that balance if the ledger
+/// has confidential amounts.
 #[derive(Hash, Eq, PartialEq, Copy, Clone, CanonicalSerialize, CanonicalDeserialize)]
 pub struct AccountInformation {
     /// The account public key.
     pub public_key: AccountPublicKey,
-    /// The balance associated with this this account.
+    /// The balance of the leaf's token held by this account.
     pub balance: Amount,
+    /// If the ledger has confidential amounts, the commitment to `balance` that
+    /// the leaf holds in place of the balance.
+    pub balance_commitment: Option<AmountCommitment>,
 }
 
 impl AccountInformation {
-    /// Convert the account information to bytes.
+    /// Convert the account information to bytes: (PublicKey || Balance), or
+    /// (PublicKey || BalanceCommitment) with both points uncompressed.
     pub fn to_bytes_le(&self) -> Vec<u8> {
         let mut bytes = Vec::new();
-        
-        self.public_key.serialize_uncompressed(&mut bytes);
-        bytes.extend_from_slice(&self.balance.to_bytes_le());
+        self.public_key
+            .serialize_uncompressed(&mut bytes)
+            .expect("serialization into a vector should not fail");
+        match &self.balance_commitment {
+            Some(balance_commitment) => balance_commitment
+                .commitment
+                .serialize_uncompressed(&mut bytes)
+                .expect("serialization into a vector should not fail"),
+            None => bytes.extend_from_slice(&self.balance.to_bytes_le()),
+        }
+        bytes
+    }
+}
+
+/// Maximum number of keys controlling a multisig account.
+pub const MAX_MULTISIG_KEYS: usize = 3;
+/// Length of an encoded `MultisigPolicy`: the threshold byte followed by
+/// `MAX_MULTISIG_KEYS` uncompressed 64-byte public keys.
+pub const MULTISIG_POLICY_BYTES: usize = 1 + 64 * MAX_MULTISIG_KEYS;
+
+/// The k-of-n policy controlling a multisig account.
+///
+/// A multisig account is registered under `account_key`, a Pedersen hash of the
+/// policy whose discrete logarithm nobody knows. It can therefore never produce a
+/// single-key signature; instead, its transfers carry signatures of at least
+/// `threshold` of `public_keys`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MultisigPolicy {
+    /// The number of signatures required to authorize a transfer.
+    pub threshold: u8,
+    /// The keys that may sign.
+    pub public_keys: Vec<AccountPublicKey>,
+}
+
+impl MultisigPolicy {
+    /// Whether `1 <= threshold <= public_keys.len() <= MAX_MULTISIG_KEYS`, and the
+    /// keys are distinct and not the identity, which marks unused slots.
+    pub fn is_well_formed(&self) -> bool {
+        let keys = &self.public_keys;
+        self.threshold >= 1
+            && self.threshold as usize <= keys.len()
+            && keys.len() <= MAX_MULTISIG_KEYS
+            && keys.iter().all(|key| !key.is_zero())
+            && keys.iter().enumerate().all(|(i, key)| !keys[..i].contains(key))
+    }
+
+    /// Convert the policy to bytes: (Threshold || PublicKey_0 || ... || PublicKey_{MAX-1}),
+    /// with the keys uncompressed and unused slots holding the identity.
+    pub fn to_bytes_le(&self) -> Vec<u8> {
+        let mut bytes = vec![self.threshold];
+        let padding = AccountPublicKey::zero();
+        for i in 0..MAX_MULTISIG_KEYS {
+            self.public_keys
+                .get(i)
+                .unwrap_or(&padding)
+                .serialize_uncompressed(&mut bytes)
+                .expect("serialization into a vector should not fail");
+        }
         bytes
     }
+
+    /// The key that an account controlled by this policy is registered under.
+    pub fn account_key(&self, parameters: &Parameters) -> AccountPublicKey {
+        PolicyH::evaluate(&parameters.policy_crh_params, self.to_bytes_le()).unwrap()
+    }
 }
@@ -16,16 +16,9 @@ pub struct RandomnessVar<F: Field>(pub Vec<UInt8<F>>);
 
 pub struct CommGadget;
 
-impl<F: PrimeField> CommitmentGadget<blake2s::Commitment, F> for CommGadget {
-    type OutputVar = OutputVar<F>;
-    type ParametersVar = ParametersVar;
-    type RandomnessVar = RandomnessVar<F>;
-
-    // #[tracing::instrument(target = "r1cs", skip(input, r))]
-    fn commit(
-        _: &Self::ParametersVar,
-        input: &[UInt8<F>],
-    ) -> Result<Self::OutputVar, SynthesisError> {
+impl CommGadget {
+    /// The in-circuit counterpart of `Commitment::hash`.
+    pub fn hash<F: PrimeField>(input: &[UInt8<F>]) -> Result<OutputVar<F>, SynthesisError> {
         let mut input_bits = Vec::with_capacity(512);
         for byte in input.iter() {
             input_bits.extend_from_slice(&byte.to_bits_le()?);
@@ -39,6 +32,23 @@ impl<F: PrimeField> CommitmentGadget<blake2s::Commitment, F> for CommGadget {
     }
 }
 
+impl<F: PrimeField> CommitmentGadget<blake2s::Commitment, F> for CommGadget {
+    type OutputVar = OutputVar<F>;
+    type ParametersVar = ParametersVar;
+    type RandomnessVar = RandomnessVar<F>;
+
+    // #[tracing::instrument(target = "r1cs", skip(input, r))]
+    fn commit(
+        _: &Self::ParametersVar,
+        input: &[UInt8<F>],
+        r: &Self::RandomnessVar,
+    ) -> Result<Self::OutputVar, SynthesisError> {
+        let mut hash_input = input.to_vec();
+        hash_input.extend_from_slice(&r.0);
+        Self::hash(&hash_input)
+    }
+}
+
 impl<ConstraintF: Field> AllocVar<(), ConstraintF> for ParametersVar {
     // #[tracing::instrument(target = "r1cs", skip(_cs, _f))]
     fn new_variable<T: Borrow<()>>(
@@ -95,18 +105,18 @@ mod test {
         rng.fill(&mut randomness);
 
         let parameters = ();
-        let primitive_result = Commitment::commit(&parameters, &input).unwrap();
+        let primitive_result = Commitment::commit(&parameters, &input, &randomness).unwrap();
 
         let mut input_var = vec![];
         for byte in &input {
             input_var.push(UInt8::new_witness(cs.clone(), || Ok(*byte)).unwrap());
         }
 
-        let mut randomness_var = vec![];
-        for r_byte in randomness.iter() {
-            randomness_var.push(UInt8::new_witness(cs.clone(), || Ok(r_byte)).unwrap());
-        }
-        let randomness_var = RandomnessVar(randomness_var);
+        let randomness_var = RandomnessVar::new_witness(
+            ark_relations::ns!(cs, "gadget_randomness"),
+            || Ok(randomness),
+        )
+        .unwrap();
 
         let parameters_var =
             <TestCOMMGadget as CommitmentGadget<TestCOMM, Fr>>::ParametersVar::new_witness(
@@ -117,12 +127,26 @@ mod test {
         let result_var = <TestCOMMGadget as CommitmentGadget<TestCOMM, Fr>>::commit(
             &parameters_var,
             &input_var,
+            &randomness_var,
         )
         .unwrap();
 
-        for i in 0..32 {
-            assert_eq!(primitive_result[i], result_var.0[i].value().unwrap());
+        for (expected, byte_var) in primitive_result.iter().zip(&result_var.0) {
+            assert_eq!(*expected, byte_var.value().unwrap());
         }
         assert!(cs.is_satisfied().unwrap());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn commitment_depends_on_randomness() {
+        let rng = &mut ark_std::test_rng();
+        let input = [7u8; 16];
+        let r1: [u8; 32] = rng.gen();
+        let r2: [u8; 32] = rng.gen();
+
+        let c1 = Commitment::commit(&(), &input, &r1).unwrap();
+        assert_eq!(c1, Commitment::commit(&(), &input, &r1).unwrap());
+        assert_ne!(c1, Commitment::commit(&(), &input, &r2).unwrap());
+        assert_ne!(c1, Commitment::hash(&input));
+    }
+}
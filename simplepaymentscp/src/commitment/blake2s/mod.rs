@@ -4,11 +4,25 @@ use ark_std::rand::Rng;
 use blake2::Blake2s256 as b2s;
 use digest::Digest;
 
+/// The BLAKE2s commitment `H(input || randomness)`. It hides `input` as long as
+/// the 32 bytes of randomness are sampled uniformly and never reused.
 pub struct Commitment;
 
 // #[cfg(feature = "r1cs")]
 pub mod constraints;
 
+impl Commitment {
+    /// The plain BLAKE2s digest of `input`, without any blinding. Use this where
+    /// the value only has to be bound, not hidden, e.g. for Fiat-Shamir challenges.
+    pub fn hash(input: &[u8]) -> [u8; 32] {
+        let mut h = b2s::new();
+        h.update(input);
+        let mut result = [0u8; 32];
+        result.copy_from_slice(&h.finalize());
+        result
+    }
+}
+
 impl CommitmentScheme for Commitment {
     type Parameters = ();
     type Randomness = [u8; 32];
@@ -21,11 +35,13 @@ impl CommitmentScheme for Commitment {
     fn commit(
         _: &Self::Parameters,
         input: &[u8],
+        randomness: &Self::Randomness,
     ) -> Result<Self::Output, Error> {
         let mut h = b2s::new();
         h.update(input);
+        h.update(randomness);
         let mut result = [0u8; 32];
         result.copy_from_slice(&h.finalize());
         Ok(result)
     }
-}
\ No newline at end of file
+}
@@ -4,6 +4,8 @@ use ark_r1cs_std::prelude::*;
 use ark_relations::r1cs::SynthesisError;
 use core::fmt::Debug;
 
+/// The in-circuit counterpart of a `CommitmentScheme`; `commit` computes the same
+/// output as the native scheme for the same input and randomness.
 pub trait CommitmentGadget<C: CommitmentScheme, ConstraintF: Field> {
     type OutputVar: EqGadget<ConstraintF>
         + ToBytesGadget<ConstraintF>
@@ -18,5 +20,6 @@ pub trait CommitmentGadget<C: CommitmentScheme, ConstraintF: Field> {
     fn commit(
         parameters: &Self::ParametersVar,
         input: &[UInt8<ConstraintF>],
+        randomness: &Self::RandomnessVar,
     ) -> Result<Self::OutputVar, SynthesisError>;
 }
\ No newline at end of file
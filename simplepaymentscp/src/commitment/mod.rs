@@ -4,12 +4,18 @@ use ark_std::rand::Rng;
 use ark_std::{fmt::Debug, hash::Hash};
 
 pub mod blake2s;
+pub mod pedersen;
 
 pub mod constraints;
 pub use constraints::*;
 
 use ark_crypto_primitives::Error;
 
+/// A hiding and binding commitment to a byte string.
+///
+/// `commit` is deterministic in `(input, randomness)`; the randomness has to be
+/// sampled uniformly for every commitment, otherwise the output does not hide
+/// the input.
 pub trait CommitmentScheme {
     type Output: CanonicalSerialize + Clone + Default + Eq + Hash + Debug;
     type Parameters: Clone;
@@ -20,5 +26,6 @@ pub trait CommitmentScheme {
     fn commit(
         parameters: &Self::Parameters,
         input: &[u8],
+        randomness: &Self::Randomness,
     ) -> Result<Self::Output, Error>;
 }
\ No newline at end of file
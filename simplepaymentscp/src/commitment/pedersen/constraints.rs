@@ -0,0 +1,204 @@
+use ark_ec::CurveGroup;
+use ark_ff::{Field, PrimeField, Zero};
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{Namespace, SynthesisError};
+use ark_serialize::CanonicalSerialize;
+use ark_std::vec::Vec;
+
+use crate::commitment::{
+    pedersen::{Commitment, Parameters, Randomness, Window},
+    CommitmentGadget,
+};
+
+use core::{borrow::Borrow, marker::PhantomData};
+use derivative::Derivative;
+
+type ConstraintF<C> = <<C as CurveGroup>::BaseField as Field>::BasePrimeField;
+
+/// The bases are public constants of the circuit, so they are kept natively.
+#[derive(Derivative)]
+#[derivative(Clone(bound = "C: CurveGroup, GG: CurveVar<C, ConstraintF<C>>"))]
+pub struct ParametersVar<C: CurveGroup, GG: CurveVar<C, ConstraintF<C>>> {
+    params: Parameters<C>,
+    #[doc(hidden)]
+    _group_var: PhantomData<GG>,
+}
+
+/// The little-endian bytes of the randomness scalar.
+#[derive(Clone, Debug)]
+pub struct RandomnessVar<F: Field>(pub Vec<UInt8<F>>);
+
+pub struct CommGadget<C: CurveGroup, GG: CurveVar<C, ConstraintF<C>>, W: Window> {
+    #[doc(hidden)]
+    _curve: PhantomData<*const C>,
+    #[doc(hidden)]
+    _group_var: PhantomData<*const GG>,
+    #[doc(hidden)]
+    _window: PhantomData<*const W>,
+}
+
+impl<C, GG, W> CommitmentGadget<Commitment<C, W>, ConstraintF<C>> for CommGadget<C, GG, W>
+where
+    C: CurveGroup,
+    GG: CurveVar<C, ConstraintF<C>>,
+    W: Window,
+    ConstraintF<C>: PrimeField,
+{
+    type OutputVar = GG;
+    type ParametersVar = ParametersVar<C, GG>;
+    type RandomnessVar = RandomnessVar<ConstraintF<C>>;
+
+    fn commit(
+        parameters: &Self::ParametersVar,
+        input: &[UInt8<ConstraintF<C>>],
+        r: &Self::RandomnessVar,
+    ) -> Result<Self::OutputVar, SynthesisError> {
+        // Inputs have a fixed length, as in `Commitment::commit`.
+        if input.len() * 8 != W::WINDOW_SIZE * W::NUM_WINDOWS {
+            return Err(SynthesisError::Unsatisfiable);
+        }
+
+        let input_bits = input.to_bits_le()?;
+        let mut result = GG::precomputed_base_multiscalar_mul_le(
+            &parameters.params.generators,
+            input_bits.chunks(W::WINDOW_SIZE),
+        )?;
+
+        // Add [r] h.
+        let rand_bits = r.0.to_bits_le()?;
+        result.precomputed_base_scalar_mul_le(
+            rand_bits.iter().zip(&parameters.params.randomness_generator),
+        )?;
+        Ok(result)
+    }
+}
+
+impl<C, GG> AllocVar<Parameters<C>, ConstraintF<C>> for ParametersVar<C, GG>
+where
+    C: CurveGroup,
+    GG: CurveVar<C, ConstraintF<C>>,
+{
+    fn new_variable<T: Borrow<Parameters<C>>>(
+        _cs: impl Into<Namespace<ConstraintF<C>>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        _mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        let params = f()?.borrow().clone();
+        Ok(ParametersVar {
+            params,
+            _group_var: PhantomData,
+        })
+    }
+}
+
+impl<C, F> AllocVar<Randomness<C>, F> for RandomnessVar<F>
+where
+    C: CurveGroup,
+    F: PrimeField,
+{
+    fn new_variable<T: Borrow<Randomness<C>>>(
+        cs: impl Into<Namespace<F>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        let mut bytes = Vec::new();
+        f().map(|r| r.borrow().0)
+            .unwrap_or(C::ScalarField::zero())
+            .serialize_uncompressed(&mut bytes)
+            .map_err(|_| SynthesisError::AssignmentMissing)?;
+        match mode {
+            AllocationMode::Constant => Ok(Self(UInt8::constant_vec(&bytes))),
+            AllocationMode::Input => UInt8::new_input_vec(cs, &bytes).map(Self),
+            AllocationMode::Witness => UInt8::new_witness_vec(cs, &bytes).map(Self),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::commitment::{
+        pedersen::{constraints::CommGadget, Commitment, Randomness, Window},
+        CommitmentGadget, CommitmentScheme,
+    };
+    use ark_ed_on_bls12_381::{constraints::EdwardsVar, EdwardsProjective as JubJub, Fq, Fr};
+    use ark_r1cs_std::prelude::*;
+    use ark_relations::r1cs::ConstraintSystem;
+    use ark_std::UniformRand;
+
+    #[derive(Clone, PartialEq, Eq, Hash)]
+    struct TestWindow;
+
+    impl Window for TestWindow {
+        const WINDOW_SIZE: usize = 4;
+        const NUM_WINDOWS: usize = 16;
+    }
+
+    type TestCOMM = Commitment<JubJub, TestWindow>;
+    type TestCOMMGadget = CommGadget<JubJub, EdwardsVar, TestWindow>;
+
+    #[test]
+    fn commitment_gadget_test() {
+        let cs = ConstraintSystem::<Fq>::new_ref();
+        let rng = &mut ark_std::test_rng();
+
+        let input = [1u8; 8];
+        let randomness = Randomness(Fr::rand(rng));
+        let parameters = TestCOMM::setup(rng).unwrap();
+        let primitive_result = TestCOMM::commit(&parameters, &input, &randomness).unwrap();
+
+        let input_var = UInt8::new_witness_vec(cs.clone(), &input).unwrap();
+        let randomness_var =
+            <TestCOMMGadget as CommitmentGadget<TestCOMM, Fq>>::RandomnessVar::new_witness(
+                ark_relations::ns!(cs, "gadget_randomness"),
+                || Ok(&randomness),
+            )
+            .unwrap();
+        let parameters_var =
+            <TestCOMMGadget as CommitmentGadget<TestCOMM, Fq>>::ParametersVar::new_constant(
+                ark_relations::ns!(cs, "gadget_parameters"),
+                &parameters,
+            )
+            .unwrap();
+        let result_var = <TestCOMMGadget as CommitmentGadget<TestCOMM, Fq>>::commit(
+            &parameters_var,
+            &input_var,
+            &randomness_var,
+        )
+        .unwrap();
+
+        assert_eq!(primitive_result, result_var.value().unwrap());
+        assert!(cs.is_satisfied().unwrap());
+    }
+
+    #[test]
+    fn commitment_hides_and_rejects_inputs_of_other_lengths() {
+        let rng = &mut ark_std::test_rng();
+        let parameters = TestCOMM::setup(rng).unwrap();
+        let input = [3u8; 8];
+        let r1 = Randomness(Fr::rand(rng));
+        let r2 = Randomness(Fr::rand(rng));
+
+        let c1 = TestCOMM::commit(&parameters, &input, &r1).unwrap();
+        assert_eq!(c1, TestCOMM::commit(&parameters, &input, &r1).unwrap());
+        assert_ne!(c1, TestCOMM::commit(&parameters, &input, &r2).unwrap());
+        assert!(TestCOMM::commit(&parameters, &[0u8; 9], &r1).is_err());
+
+        // Only inputs of the full length are accepted, so `m` and `m || 0` do
+        // not commit to the same point.
+        let short = [3u8; 7];
+        let padded = [3, 3, 3, 3, 3, 3, 3, 0];
+        assert!(TestCOMM::commit(&parameters, &short, &r1).is_err());
+        assert_ne!(TestCOMM::commit(&parameters, &padded, &r1).unwrap(), c1);
+
+        let cs = ConstraintSystem::<Fq>::new_ref();
+        let parameters_var =
+            <TestCOMMGadget as CommitmentGadget<TestCOMM, Fq>>::ParametersVar::new_constant(cs.clone(), &parameters)
+                .unwrap();
+        let randomness_var =
+            <TestCOMMGadget as CommitmentGadget<TestCOMM, Fq>>::RandomnessVar::new_witness(cs.clone(), || Ok(&r1))
+                .unwrap();
+        let short_var = UInt8::new_witness_vec(cs, &short).unwrap();
+        assert!(<TestCOMMGadget as CommitmentGadget<TestCOMM, Fq>>::commit(&parameters_var, &short_var, &randomness_var)
+            .is_err());
+    }
+}
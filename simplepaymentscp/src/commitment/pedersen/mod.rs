@@ -0,0 +1,89 @@
+use super::CommitmentScheme;
+use ark_crypto_primitives::{
+    crh::{pedersen, CRHScheme},
+    Error,
+};
+use ark_ec::CurveGroup;
+use ark_ff::{BitIteratorLE, PrimeField, UniformRand};
+use ark_serialize::CanonicalSerialize;
+use ark_std::rand::Rng;
+use ark_std::{marker::PhantomData, vec::Vec};
+use derivative::Derivative;
+
+pub use ark_crypto_primitives::crh::pedersen::Window;
+
+// #[cfg(feature = "r1cs")]
+pub mod constraints;
+
+/// Bases for the Pedersen commitment: the windowed bases of the Pedersen hash
+/// for the input, and the powers of an independent generator `h` for the
+/// randomness.
+#[derive(Clone)]
+pub struct Parameters<C: CurveGroup> {
+    pub randomness_generator: Vec<C>,
+    pub generators: Vec<Vec<C>>,
+}
+
+/// The Pedersen commitment `PedersenHash(input) + [r] h`. It is perfectly
+/// hiding, and binding as long as discrete logs between the bases are unknown.
+/// As the hash does not encode the length of its input, inputs have a fixed
+/// length of `W::WINDOW_SIZE * W::NUM_WINDOWS` bits.
+pub struct Commitment<C: CurveGroup, W: Window> {
+    _group: PhantomData<C>,
+    _window: PhantomData<W>,
+}
+
+#[derive(Derivative, CanonicalSerialize)]
+#[derivative(Clone, PartialEq, Debug, Eq, Default)]
+pub struct Randomness<C: CurveGroup>(pub C::ScalarField);
+
+impl<C: CurveGroup> UniformRand for Randomness<C> {
+    #[inline]
+    fn rand<R: Rng + ?Sized>(rng: &mut R) -> Self {
+        Randomness(UniformRand::rand(rng))
+    }
+}
+
+impl<C: CurveGroup, W: Window> CommitmentScheme for Commitment<C, W> {
+    type Parameters = Parameters<C>;
+    type Randomness = Randomness<C>;
+    type Output = C::Affine;
+
+    fn setup<R: Rng>(rng: &mut R) -> Result<Self::Parameters, Error> {
+        let num_powers = <C::ScalarField as PrimeField>::MODULUS_BIT_SIZE as usize;
+        let randomness_generator = pedersen::CRH::<C, W>::generator_powers(num_powers, rng);
+        let generators = pedersen::CRH::<C, W>::create_generators(rng);
+        Ok(Parameters {
+            randomness_generator,
+            generators,
+        })
+    }
+
+    /// Inputs that are not exactly `W::WINDOW_SIZE * W::NUM_WINDOWS` bits long
+    /// are rejected. Padding shorter inputs would commit `m` and `m || 0` to
+    /// the same point.
+    fn commit(
+        parameters: &Self::Parameters,
+        input: &[u8],
+        randomness: &Self::Randomness,
+    ) -> Result<Self::Output, Error> {
+        if input.len() * 8 != W::WINDOW_SIZE * W::NUM_WINDOWS {
+            return Err(format!("incorrect input length: {:?}", input.len()).into());
+        }
+
+        let crh_parameters = pedersen::Parameters {
+            generators: parameters.generators.clone(),
+        };
+        let mut result: C = pedersen::CRH::<C, W>::evaluate(&crh_parameters, input)?.into();
+
+        // Add [r] h, using the precomputed powers of h.
+        for (bit, power) in BitIteratorLE::new(randomness.0.into_bigint())
+            .zip(&parameters.randomness_generator)
+        {
+            if bit {
+                result += power
+            }
+        }
+        Ok(result.into())
+    }
+}
@@ -0,0 +1,392 @@
+//! Data availability: what the operator publishes per batch so that anyone can
+//! recompute the state. The proof already attests to the validity of a batch,
+//! so it suffices to publish the leaves it changes as a list of `StateDiff`s,
+//! without the transactions and their signatures. A leaf that a batch writes
+//! several times is published once, with its final contents; see `last_writes`.
+//!
+//! `encode` compresses the list: the diff count and each balance are LEB128
+//! varints, the token shares a tag byte with the kind of diff, and points are
+//! compressed. `commit` hashes the fixed-width encodings of the diffs into the
+//! `DiffCommitment` that the rollup circuit takes as a public input.
+use crate::account::{AccountId, AccountPublicKey, TokenId};
+use crate::ledger::{Amount, Parameters, State};
+use ark_crypto_primitives::crh::{pedersen, CRHScheme};
+use ark_ed_on_bls12_381::{EdwardsAffine, EdwardsProjective as JubJub};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+
+/// A leaf changed by a batch, described by its new contents.
+#[derive(Hash, Eq, PartialEq, Copy, Clone, Debug)]
+pub enum StateDiff {
+    /// The `(account, token)` leaf holds `balance` after the batch.
+    Balance {
+        account: AccountId,
+        token: TokenId,
+        balance: Amount,
+    },
+    /// All leaves of `account` hold `public_key` after the batch.
+    Key {
+        account: AccountId,
+        public_key: AccountPublicKey,
+    },
+    /// In a ledger with confidential amounts, the `(account, token)` leaf holds
+    /// the balance commitment `commitment` after the batch.
+    BalanceCommitment {
+        account: AccountId,
+        token: TokenId,
+        commitment: EdwardsAffine,
+    },
+}
+
+/// The length of `StateDiff::to_bytes_le` for a balance diff.
+pub const BALANCE_DIFF_BYTES: usize = 1 + 1 + 1 + 8;
+/// The length of `StateDiff::to_bytes_le` for a key diff.
+pub const KEY_DIFF_BYTES: usize = 1 + 1 + 64;
+/// The length of `StateDiff::to_bytes_le` for a balance commitment diff, the
+/// longest kind of diff.
+pub const COMMITMENT_DIFF_BYTES: usize = 1 + 1 + 1 + 64;
+
+impl StateDiff {
+    const BALANCE_TAG: u8 = 0;
+    const KEY_TAG: u8 = 1;
+    const COMMITMENT_TAG: u8 = 2;
+
+    /// Whether `later`, a diff of the same batch that comes after this one,
+    /// overwrites what this diff writes: the balance or balance commitment of
+    /// the same leaf, or the key of the same account.
+    pub fn is_overwritten_by(&self, later: &StateDiff) -> bool {
+        match (self.balance_leaf(), later.balance_leaf()) {
+            (Some(leaf), Some(later_leaf)) => leaf == later_leaf,
+            (None, None) => self.account() == later.account(),
+            _ => false,
+        }
+    }
+
+    /// The account whose leaves the diff writes.
+    pub fn account(&self) -> AccountId {
+        match *self {
+            StateDiff::Balance { account, .. }
+            | StateDiff::Key { account, .. }
+            | StateDiff::BalanceCommitment { account, .. } => account,
+        }
+    }
+
+    /// The leaf whose balance or balance commitment the diff writes, if any.
+    fn balance_leaf(&self) -> Option<(AccountId, TokenId)> {
+        match *self {
+            StateDiff::Balance { account, token, .. } | StateDiff::BalanceCommitment { account, token, .. } => {
+                Some((account, token))
+            }
+            StateDiff::Key { .. } => None,
+        }
+    }
+
+    /// The fixed-width encoding that `commit` hashes: (0 || AccId || TokenId ||
+    /// Balance) for a balance diff, (1 || AccId || PublicKey) for a key diff,
+    /// and (2 || AccId || TokenId || Commitment) for a balance commitment
+    /// diff, with the points uncompressed.
+    pub fn to_bytes_le(&self) -> Vec<u8> {
+        match self {
+            StateDiff::Balance { account, token, balance } => {
+                let mut bytes = vec![Self::BALANCE_TAG];
+                bytes.extend(account.to_bytes_le());
+                bytes.extend(token.to_bytes_le());
+                bytes.extend(balance.to_bytes_le());
+                bytes
+            }
+            StateDiff::Key { account, public_key } => {
+                let mut bytes = vec![Self::KEY_TAG];
+                bytes.extend(account.to_bytes_le());
+                public_key
+                    .serialize_uncompressed(&mut bytes)
+                    .expect("serialization into a vector succeeds");
+                bytes
+            }
+            StateDiff::BalanceCommitment { account, token, commitment } => {
+                let mut bytes = vec![Self::COMMITMENT_TAG];
+                bytes.extend(account.to_bytes_le());
+                bytes.extend(token.to_bytes_le());
+                commitment
+                    .serialize_uncompressed(&mut bytes)
+                    .expect("serialization into a vector succeeds");
+                bytes
+            }
+        }
+    }
+}
+
+/// The diffs of `diffs` that no later diff overwrites, in order. This is what a
+/// batch publishes: each leaf it writes once, with its contents after the batch.
+pub fn last_writes(diffs: &[StateDiff]) -> Vec<StateDiff> {
+    diffs
+        .iter()
+        .enumerate()
+        .filter(|(i, diff)| !diffs[i + 1..].iter().any(|later| diff.is_overwritten_by(later)))
+        .map(|(_, diff)| *diff)
+        .collect()
+}
+
+/// Hash chaining the diffs of a batch into a `DiffCommitment`.
+pub type DiffHash = pedersen::CRH<JubJub, DiffWindow>;
+
+/// The input of a step of `commit`: the previous commitment, uncompressed, and
+/// the longest diff.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct DiffWindow;
+impl pedersen::Window for DiffWindow {
+    const WINDOW_SIZE: usize = 4;
+    const NUM_WINDOWS: usize = (64 + COMMITMENT_DIFF_BYTES) * 8 / 4;
+}
+
+/// The commitment to the diffs of a batch; see `commit`.
+pub type DiffCommitment = EdwardsAffine;
+
+/// Commit to `diffs`, in order. The commitment starts out as the identity, and
+/// each diff replaces it by `DiffHash(commitment || diff)`. As each kind of
+/// diff has a fixed width and starts with its tag, distinct lists of diffs
+/// hash distinct inputs.
+pub fn commit(parameters: &Parameters, diffs: &[StateDiff]) -> DiffCommitment {
+    diffs
+        .iter()
+        .fold(DiffCommitment::default(), |commitment, diff| chain(parameters, &commitment, &diff.to_bytes_le()))
+}
+
+/// One step of a hash chain like `commit`: `DiffHash(commitment || bytes)`,
+/// with the commitment uncompressed. `bytes` are at most
+/// `COMMITMENT_DIFF_BYTES` long.
+pub fn chain(parameters: &Parameters, commitment: &DiffCommitment, bytes: &[u8]) -> DiffCommitment {
+    let mut input = Vec::with_capacity(64 + COMMITMENT_DIFF_BYTES);
+    commitment
+        .serialize_uncompressed(&mut input)
+        .expect("serialization into a vector succeeds");
+    input.extend(bytes);
+    DiffHash::evaluate(&parameters.diff_crh_params, input).unwrap()
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+/// Read a varint from the front of `bytes`. Encodings that are longer than
+/// necessary or overflow 64 bits are rejected, so every value has exactly one
+/// encoding.
+fn read_varint(bytes: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = bytes.split_first()?;
+        *bytes = rest;
+        let bits = u64::from(byte & 0x7f);
+        if bits << shift >> shift != bits || (byte == 0 && shift > 0) {
+            return None;
+        }
+        value |= bits << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn read_byte(bytes: &mut &[u8]) -> Option<u8> {
+    let (&byte, rest) = bytes.split_first()?;
+    *bytes = rest;
+    Some(byte)
+}
+
+/// Encode the diffs of a batch for publication. The encoding consists of the
+/// number of diffs followed by each diff: (Token << 1 || AccId || Balance) for
+/// a balance diff, (1 || AccId || PublicKey) for a key diff, and
+/// (Token << 2 | 3 || AccId || Commitment) for a balance commitment diff, with
+/// the points compressed. The count and the balances are varints.
+pub fn encode(diffs: &[StateDiff]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    write_varint(&mut bytes, diffs.len() as u64);
+    for diff in diffs {
+        match diff {
+            StateDiff::Balance { account, token, balance } => {
+                bytes.push(token.0 << 1);
+                bytes.push(account.0);
+                write_varint(&mut bytes, balance.0);
+            }
+            StateDiff::Key { account, public_key } => {
+                bytes.push(1);
+                bytes.push(account.0);
+                public_key
+                    .serialize_compressed(&mut bytes)
+                    .expect("serialization into a vector succeeds");
+            }
+            StateDiff::BalanceCommitment { account, token, commitment } => {
+                bytes.push(token.0 << 2 | 3);
+                bytes.push(account.0);
+                commitment
+                    .serialize_compressed(&mut bytes)
+                    .expect("serialization into a vector succeeds");
+            }
+        }
+    }
+    bytes
+}
+
+/// Decode the output of `encode`. Returns `None` if `bytes` is not a valid
+/// encoding, e.g. if it is truncated, has trailing bytes, or contains a point
+/// that is not in the prime-order subgroup.
+pub fn decode(mut bytes: &[u8]) -> Option<Vec<StateDiff>> {
+    let bytes = &mut bytes;
+    let num_diffs = read_varint(bytes)?;
+    let mut diffs = Vec::new();
+    for _ in 0..num_diffs {
+        let tag = read_byte(bytes)?;
+        let account = AccountId(read_byte(bytes)?);
+        let diff = match tag {
+            1 => StateDiff::Key {
+                account,
+                public_key: AccountPublicKey::deserialize_compressed(&mut *bytes).ok()?,
+            },
+            tag if tag & 1 == 0 => StateDiff::Balance {
+                account,
+                token: TokenId(tag >> 1),
+                balance: Amount(read_varint(bytes)?),
+            },
+            tag if tag & 3 == 3 => StateDiff::BalanceCommitment {
+                account,
+                token: TokenId(tag >> 2),
+                commitment: EdwardsAffine::deserialize_compressed(&mut *bytes).ok()?,
+            },
+            _ => return None,
+        };
+        diffs.push(diff);
+    }
+    bytes.is_empty().then_some(diffs)
+}
+
+/// Reconstruct the state after `batches` by applying the decoded diffs of each
+/// batch to `genesis` and finalizing it; see `State::apply_state_diff`.
+/// Returns `None` if a batch does not decode or touches a leaf that does not
+/// exist.
+pub fn replay<'a>(genesis: &State, batches: impl IntoIterator<Item = &'a [u8]>) -> Option<State> {
+    let mut state = genesis.clone();
+    for batch in batches {
+        for diff in decode(batch)? {
+            state.apply_state_diff(&diff)?;
+        }
+        state.finalize_batch();
+    }
+    Some(state)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transaction::{KeyRotation, Transaction};
+
+    #[test]
+    fn replaying_published_diffs_reconstructs_the_state() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, alice_pk, alice_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, TokenId(0), Amount(1000)).unwrap();
+        let (bob_id, _, _) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let genesis = state.clone();
+
+        // Batch 1 is a transfer, batch 2 rotates Alice's key.
+        let tx = Transaction::create(&pp, alice_id, bob_id, TokenId(0), Amount(300), &alice_sk, &alice_pk, &mut rng);
+        state.apply_transaction(&pp, &tx).unwrap();
+        state.finalize_batch();
+        let batch_1 = vec![
+            StateDiff::Balance { account: alice_id, token: TokenId(0), balance: Amount(700) },
+            StateDiff::Balance { account: bob_id, token: TokenId(0), balance: Amount(300) },
+        ];
+        let (new_pk, _) = pp.keygen(&mut rng);
+        let rotation = KeyRotation::create(&pp, alice_id, new_pk, &alice_sk, &alice_pk, &mut rng);
+        state.apply_key_rotation(&pp, &rotation).unwrap();
+        state.finalize_batch();
+        let batch_2 = vec![StateDiff::Key { account: alice_id, public_key: new_pk }];
+
+        let published = [encode(&batch_1), encode(&batch_2)];
+        // The count, then 2 bytes of tag and account and a 2-byte varint each.
+        assert_eq!(published[0].len(), 1 + 2 * 4);
+        assert_eq!(published[1].len(), 1 + 2 + 32);
+        assert_eq!(decode(&published[0]), Some(batch_1.clone()));
+        assert_eq!(decode(&published[1]), Some(batch_2));
+
+        let replayed = replay(&genesis, published.iter().map(Vec::as_slice)).unwrap();
+        assert_eq!(replayed.root(), state.root());
+        assert_eq!(replayed.root_at(1), state.root_at(1));
+        assert_eq!(replayed.pub_key_to_id.get(&new_pk), Some(&alice_id));
+
+        // Commitments bind the order and the contents of the diffs.
+        let reversed = [batch_1[1], batch_1[0]];
+        assert_ne!(commit(&pp, &batch_1), commit(&pp, &reversed));
+        assert_ne!(commit(&pp, &batch_1), commit(&pp, &batch_1[..1]));
+
+        // Malformed encodings and diffs of missing leaves are rejected.
+        let mut truncated = published[0].clone();
+        truncated.pop();
+        assert_eq!(decode(&truncated), None);
+        let mut trailing = published[0].clone();
+        trailing.push(0);
+        assert_eq!(decode(&trailing), None);
+        assert_eq!(decode(&[1, 0, 1, 0x80, 0]), None);
+        let unknown = encode(&[StateDiff::Balance { account: AccountId(9), token: TokenId(0), balance: Amount(1) }]);
+        assert!(replay(&genesis, [unknown.as_slice()]).is_none());
+    }
+
+    #[test]
+    fn confidential_ledgers_publish_the_last_commitment_of_each_leaf() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng).with_confidential_amounts();
+        let mut state = State::new(32, &pp).with_sampled_blinding_key(&mut rng);
+        let (alice_id, alice_pk, alice_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, TokenId(1), Amount(1000)).unwrap();
+        let (bob_id, _, _) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let genesis = state.clone();
+
+        // Alice pays Bob twice in one batch; each leaf is published once.
+        let mut diffs = Vec::new();
+        for amount in [300, 200] {
+            let (tx, opening) =
+                Transaction::create_confidential(&pp, alice_id, bob_id, TokenId(1), Amount(amount), &alice_sk, &alice_pk, &mut rng);
+            state.apply_transaction_with_opening(&pp, &tx, Some(&opening)).unwrap();
+            for account in [alice_id, bob_id] {
+                let commitment = state.account_info(account, TokenId(1)).unwrap().balance_commitment.unwrap();
+                diffs.push(StateDiff::BalanceCommitment { account, token: TokenId(1), commitment: commitment.commitment });
+            }
+        }
+        state.finalize_batch();
+        let batch = last_writes(&diffs);
+        assert_eq!(batch, diffs[2..]);
+        let published = encode(&batch);
+        // The count, then 2 bytes of tag and account and a compressed point each.
+        assert_eq!(published.len(), 1 + 2 * (2 + 32));
+        assert_eq!(decode(&published), Some(batch.clone()));
+
+        // Replaying yields the same leaves, though not the openings.
+        let mut replayed = replay(&genesis, [published.as_slice()]).unwrap();
+        assert_eq!(replayed.root(), state.root());
+        assert_eq!(replayed.balance(bob_id, TokenId(1)), None);
+        assert_eq!(replayed.balance(bob_id, TokenId(0)), Some(Amount(0)));
+        // Setting the balance recommits to it, with a known opening.
+        replayed.update_balance(bob_id, TokenId(1), Amount(500)).unwrap();
+        assert_eq!(replayed.balance(bob_id, TokenId(1)), Some(Amount(500)));
+
+        // Balances and commitments of a leaf overwrite each other, keys only keys.
+        let balance = StateDiff::Balance { account: bob_id, token: TokenId(1), balance: Amount(5) };
+        let other_token = StateDiff::Balance { account: bob_id, token: TokenId(2), balance: Amount(5) };
+        let key = StateDiff::Key { account: bob_id, public_key: alice_pk };
+        assert!(balance.is_overwritten_by(&batch[1]) && batch[1].is_overwritten_by(&balance));
+        assert!(!balance.is_overwritten_by(&other_token) && !balance.is_overwritten_by(&key));
+        assert_eq!(last_writes(&[key, balance, key]), vec![balance, key]);
+
+        // A commitment diff does not apply to a ledger with plain amounts.
+        let plain_pp = Parameters { confidential_amounts: false, ..pp.clone() };
+        let mut plain_state = State::new(32, &plain_pp);
+        for _ in 0..2 {
+            plain_state.register(plain_pp.keygen(&mut rng).0).unwrap();
+        }
+        assert!(replay(&plain_state, [published.as_slice()]).is_none());
+    }
+}
@@ -0,0 +1,231 @@
+//! The initial ledger of a deployment. A `Genesis` lists the accounts with
+//! their public keys and initial balances. It is written by hand as JSON, e.g.
+//!
+//! ```json
+//! {
+//!   "num_accounts": 32,
+//!   "accounts": [
+//!     { "public_key": "<hex of the compressed key>", "balances": [1000, 0, 5] }
+//!   ]
+//! }
+//! ```
+//!
+//! and distributed in its canonical binary encoding, from which every node
+//! derives the same `State` and root.
+use crate::account::{AccountId, AccountPublicKey, TokenId, NUM_TOKENS};
+use crate::ledger::{AccRoot, Amount, Parameters, State};
+use ark_crypto_primitives::Error;
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// An account of the genesis ledger.
+#[derive(Clone, Debug, PartialEq, Eq, CanonicalSerialize, CanonicalDeserialize, Serialize, Deserialize)]
+pub struct GenesisAccount {
+    #[serde(with = "hex_public_key")]
+    pub public_key: AccountPublicKey,
+    /// The balance of each token, in `TokenId::all()` order. Missing balances
+    /// are zero.
+    #[serde(default)]
+    pub balances: Vec<u64>,
+}
+
+/// The initial ledger; see the module documentation.
+#[derive(Clone, Debug, PartialEq, Eq, CanonicalSerialize, CanonicalDeserialize, Serialize, Deserialize)]
+pub struct Genesis {
+    /// The number of accounts that the ledger supports, as in `State::new`.
+    pub num_accounts: u64,
+    /// The accounts, which are registered in order, starting with identifier 1.
+    pub accounts: Vec<GenesisAccount>,
+}
+
+impl Genesis {
+    /// Parse the JSON form of a genesis. Fails if a list of balances ends in
+    /// a zero balance, which is written by leaving it out.
+    pub fn from_json(json: &str) -> Result<Self, Error> {
+        serde_json::from_str::<Self>(json)?.canonical()
+    }
+
+    /// The JSON form of the genesis.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("a genesis serializes to JSON")
+    }
+
+    /// Decode the canonical binary form of a genesis. Fails on trailing bytes
+    /// and on lists of balances that end in a zero balance, so that every
+    /// genesis has exactly one binary form.
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self, Error> {
+        let genesis = Self::deserialize_compressed(&mut bytes)?;
+        if !bytes.is_empty() {
+            return Err("trailing bytes after the genesis".into());
+        }
+        genesis.canonical()
+    }
+
+    /// The canonical binary form of the genesis.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.serialize_compressed(&mut bytes)
+            .expect("serialization into a vector succeeds");
+        bytes
+    }
+
+    /// The genesis, if no list of balances ends in a zero balance.
+    fn canonical(self) -> Result<Self, Error> {
+        if self.accounts.iter().any(|account| account.balances.last() == Some(&0)) {
+            return Err("trailing zero balances are left out".into());
+        }
+        Ok(self)
+    }
+
+    /// Build the ledger described by the genesis. Fails if there are more
+    /// accounts than the ledger supports, an account has more balances than
+    /// `NUM_TOKENS`, or two accounts share a public key. Also fails if the
+    /// ledger has confidential amounts, since its leaves then depend on the
+    /// blindings of the balance commitments and not just on the genesis.
+    pub fn state(&self, parameters: &Parameters) -> Result<State, Error> {
+        if parameters.confidential_amounts {
+            return Err("the genesis of a ledger with confidential amounts depends on its blindings".into());
+        }
+        let num_accounts = usize::try_from(self.num_accounts)?;
+        if num_accounts > u8::MAX as usize {
+            return Err("account identifiers are a single byte".into());
+        }
+        if self.accounts.len() > num_accounts {
+            return Err("more accounts than the ledger supports".into());
+        }
+        let mut public_keys = HashSet::new();
+        let mut state = State::new(num_accounts, parameters);
+        for account in &self.accounts {
+            if account.balances.len() > NUM_TOKENS {
+                return Err("more balances than tokens".into());
+            }
+            if !public_keys.insert(account.public_key) {
+                return Err("two accounts share a public key".into());
+            }
+            let id: AccountId = state.register(account.public_key).ok_or("no account identifier left")?;
+            for (token, balance) in TokenId::all().zip(&account.balances) {
+                state.update_balance(id, token, Amount(*balance)).expect("the account was just registered");
+            }
+        }
+        Ok(state)
+    }
+
+    /// The root of the ledger described by the genesis, which the first batch
+    /// has to start from.
+    pub fn root(&self, parameters: &Parameters) -> Result<AccRoot, Error> {
+        Ok(self.state(parameters)?.root())
+    }
+}
+
+/// Lowercase hex of `bytes`.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// The bytes of a lowercase hex string, as written by `to_hex`, or `None` if
+/// it is not one.
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    let digits = hex
+        .chars()
+        .map(|digit| digit.to_digit(16).filter(|_| !digit.is_ascii_uppercase()))
+        .collect::<Option<Vec<_>>>()?;
+    let pairs = digits.chunks_exact(2);
+    if !pairs.remainder().is_empty() {
+        return None;
+    }
+    Some(pairs.map(|pair| (pair[0] << 4 | pair[1]) as u8).collect())
+}
+
+/// The hex string of a compressed root, for printing and comparing roots.
+pub fn root_to_hex(root: &AccRoot) -> String {
+    let mut bytes = Vec::new();
+    root.serialize_compressed(&mut bytes)
+        .expect("serialization into a vector succeeds");
+    to_hex(&bytes)
+}
+
+/// Serde adapter writing public keys as the hex of their compressed encoding.
+mod hex_public_key {
+    use super::{from_hex, to_hex, AccountPublicKey};
+    use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(public_key: &AccountPublicKey, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut bytes = Vec::new();
+        public_key
+            .serialize_compressed(&mut bytes)
+            .expect("serialization into a vector succeeds");
+        serializer.serialize_str(&to_hex(&bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<AccountPublicKey, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        let bytes = from_hex(&hex).ok_or_else(|| de::Error::custom("public keys are hex strings"))?;
+        AccountPublicKey::deserialize_compressed(bytes.as_slice()).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn genesis_round_trips_and_builds_the_ledger() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let (alice_pk, _) = pp.keygen(&mut rng);
+        let (bob_pk, _) = pp.keygen(&mut rng);
+        let genesis = Genesis {
+            num_accounts: 32,
+            accounts: vec![
+                GenesisAccount { public_key: alice_pk, balances: vec![1000, 0, 5] },
+                GenesisAccount { public_key: bob_pk, balances: vec![] },
+            ],
+        };
+
+        // The same ledger as setting it up by hand.
+        let mut expected = State::new(32, &pp);
+        let alice_id = expected.register(alice_pk).unwrap();
+        expected.update_balance(alice_id, TokenId(0), Amount(1000)).unwrap();
+        expected.update_balance(alice_id, TokenId(2), Amount(5)).unwrap();
+        expected.register(bob_pk).unwrap();
+        let state = genesis.state(&pp).unwrap();
+        assert_eq!(state.root(), expected.root());
+        assert_eq!(genesis.root(&pp).unwrap(), expected.root());
+        assert_eq!(state.balance(AccountId(1), TokenId(2)), Some(Amount(5)));
+        assert_eq!(state.pub_key_to_id.get(&bob_pk), Some(&AccountId(2)));
+
+        let json = genesis.to_json();
+        assert_eq!(Genesis::from_json(&json).unwrap(), genesis);
+        let bytes = genesis.to_bytes();
+        assert_eq!(Genesis::from_bytes(&bytes).unwrap(), genesis);
+        assert_eq!(root_to_hex(&state.root()).len(), 64);
+
+        // Malformed files and impossible ledgers are rejected.
+        assert!(Genesis::from_json(r#"{"num_accounts": 2, "accounts": [{"public_key": "zz"}]}"#).is_err());
+        assert!(Genesis::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+
+        // Every genesis has a single encoding.
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(Genesis::from_bytes(&trailing).is_err());
+        let mut padded = genesis.clone();
+        padded.accounts[0].balances.push(0);
+        assert!(Genesis::from_bytes(&padded.to_bytes()).is_err());
+        assert!(Genesis::from_json(&padded.to_json()).is_err());
+        let alice_hex = to_hex(&bytes[8 + 8..8 + 8 + 32]);
+        assert!(json.contains(&alice_hex));
+        assert!(Genesis::from_json(&json.replace(&alice_hex, &alice_hex.to_uppercase())).is_err());
+        assert_eq!(from_hex("0a1"), None);
+        assert_eq!(from_hex("+a"), None);
+        assert_eq!(from_hex("0aff"), Some(vec![0x0a, 0xff]));
+        let duplicate = Genesis { accounts: vec![genesis.accounts[0].clone(); 2], ..genesis.clone() };
+        assert!(duplicate.state(&pp).is_err());
+        let crowded = Genesis { num_accounts: 1, ..genesis.clone() };
+        assert!(crowded.state(&pp).is_err());
+        let mut many_tokens = genesis.clone();
+        many_tokens.accounts[1].balances = vec![0; NUM_TOKENS + 1];
+        assert!(many_tokens.state(&pp).is_err());
+    }
+}
@@ -1,15 +1,24 @@
-use crate::account::{AccountId, AccountInformation, AccountPublicKey, AccountSecretKey};
-use crate::signature::schnorr;
-use crate::transaction::Transaction;
-use ark_crypto_primitives::crh::{pedersen, TwoToOneCRHScheme, TwoToOneCRHSchemeGadget, CRHScheme, CRHSchemeGadget};
-use ark_crypto_primitives::crh::{injective_map::{PedersenCRHCompressor, TECompressor}};
-use ark_crypto_primitives::merkle_tree::constraints::{BytesVarDigestConverter, ConfigGadget};
-use ark_crypto_primitives::merkle_tree::{self, MerkleTree, ByteDigestConverter, Config, Path};
-use ark_ed_on_bls12_381::{constraints::EdwardsVar, EdwardsProjective as JubJub, Fq};
-use ark_r1cs_std::uint8::UInt8;
+use crate::account::{
+    AccountId, AccountInformation, AccountPublicKey, AccountSecretKey, AccountSignature,
+    AccountSignatureScheme, MultisigPolicy, TokenId, MULTISIG_POLICY_BYTES, NUM_TOKENS, TOKEN_ID_BITS,
+};
+use crate::da::{DiffHash, StateDiff};
+use crate::commitment::{pedersen::{self as pedersen_commitment, Randomness}, CommitmentScheme};
+use crate::signature::{eddsa, schnorr};
+use crate::transaction::{KeyRotation, MultisigTransaction, Swap, Transaction};
+use ark_crypto_primitives::crh::{pedersen, CRHScheme};
+use ark_ed_on_bls12_381::{EdwardsAffine, EdwardsProjective as JubJub, Fr};
+use ark_ff::{PrimeField, UniformRand};
+use blake2::{Blake2b512, Digest as _};
 use ark_std::rand::Rng;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::ops::{Add, Sub};
+use std::sync::Arc;
 use ark_serialize::{CanonicalSerialize, CanonicalDeserialize};
+use merkle_groth::history::MerkleTreeHistory;
+use merkle_groth::jubjub::LeafHash;
+use merkle_groth::{Config, Digest, InnerParam, MerkleTree, Path};
+pub use merkle_groth::jubjub::{JubJubMerkleTreeParams, JubJubMerkleTreeParamsVar, Window4x256};
 
 /// Represents transaction amounts and account balances.
 #[derive(Hash, Eq, PartialEq, Copy, Clone, PartialOrd, Ord, Debug, CanonicalSerialize, CanonicalDeserialize)]
@@ -29,128 +38,478 @@ impl Amount {
     }
 }
 
+/// Pedersen commitment to an `Amount`. A single 64-bit window makes it
+/// `[amount] G + [blinding] H`, which is additively homomorphic.
+pub type AmountCommitmentScheme = pedersen_commitment::Commitment<JubJub, AmountWindow>;
+pub type AmountCommitmentParameters = <AmountCommitmentScheme as CommitmentScheme>::Parameters;
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct AmountWindow;
+impl pedersen::Window for AmountWindow {
+    const WINDOW_SIZE: usize = 64;
+    const NUM_WINDOWS: usize = 1;
+}
+
+/// A commitment to an amount together with its blinding. The amount itself is
+/// kept next to it, e.g. in `AccountInformation::balance` or `AmountOpening::amount`.
+///
+/// Commitments add and subtract like the amounts they hide, so a transfer
+/// subtracts its amount commitment from the sender's balance commitment and adds
+/// it to the recipient's, and whoever knows the transfer's blinding can keep
+/// track of the new openings.
+#[derive(Hash, Eq, PartialEq, Copy, Clone, Debug, CanonicalSerialize, CanonicalDeserialize)]
+pub struct AmountCommitment {
+    /// The commitment, which is what leaves and signed payloads contain.
+    pub commitment: EdwardsAffine,
+    /// The blinding of the commitment. It is private to the parties and the prover.
+    pub blinding: Fr,
+}
+
+impl AmountCommitment {
+    /// Commit to `amount` with `blinding`.
+    pub fn new(parameters: &AmountCommitmentParameters, amount: Amount, blinding: Fr) -> Self {
+        let commitment =
+            AmountCommitmentScheme::commit(parameters, &amount.to_bytes_le(), &Randomness(blinding))
+                .expect("an amount fits into the commitment window");
+        Self { commitment, blinding }
+    }
+
+    /// Whether this commitment opens to `amount`.
+    pub fn opens_to(&self, parameters: &AmountCommitmentParameters, amount: Amount) -> bool {
+        *self == Self::new(parameters, amount, self.blinding)
+    }
+}
+
+impl Add for AmountCommitment {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            commitment: (self.commitment + other.commitment).into(),
+            blinding: self.blinding + other.blinding,
+        }
+    }
+}
+
+impl Sub for AmountCommitment {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Self {
+            commitment: (self.commitment - other.commitment).into(),
+            blinding: self.blinding - other.blinding,
+        }
+    }
+}
+
+/// The opening of a published amount commitment, such as the one in
+/// `TransferAmount::Committed`. It is not published: the sender shares it with
+/// the recipient, who needs it to open the new balance, and with the prover.
+#[derive(Hash, Eq, PartialEq, Copy, Clone, Debug)]
+pub struct AmountOpening {
+    pub amount: Amount,
+    pub blinding: Fr,
+}
+
+impl AmountOpening {
+    /// Open a commitment to `amount` with a fresh blinding.
+    pub fn sample<R: Rng>(amount: Amount, rng: &mut R) -> Self {
+        Self {
+            amount,
+            blinding: Fr::rand(rng),
+        }
+    }
+
+    /// The commitment that this opens, together with its blinding.
+    pub fn commit(&self, parameters: &AmountCommitmentParameters) -> AmountCommitment {
+        AmountCommitment::new(parameters, self.amount, self.blinding)
+    }
+}
+
+/// Identifies the deployment that a signature is valid for. It is part of every
+/// signed payload, so a signature cannot be replayed on another chain or rollup.
+#[derive(Hash, Eq, PartialEq, Copy, Clone, Default, Debug)]
+pub struct SigningDomain {
+    /// The chain the rollup settles on.
+    pub chain_id: u64,
+    /// The rollup instance on that chain.
+    pub rollup_id: u64,
+}
+
+impl SigningDomain {
+    pub fn to_bytes_le(&self) -> Vec<u8> {
+        let mut bytes = self.chain_id.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&self.rollup_id.to_le_bytes());
+        bytes
+    }
+}
+
 /// The parameters that are used in transaction creation and validation.
 #[derive(Clone)]
 pub struct Parameters {
     pub sig_params: schnorr::Parameters<JubJub>,
-    pub leaf_crh_params: <LeafH as CRHScheme>::Parameters,
-    pub two_to_one_crh_params: <CompressH as TwoToOneCRHScheme>::Parameters,
+    pub leaf_crh_params: <LeafHash as CRHScheme>::Parameters,
+    pub inner_crh_params: InnerParam<AccTreeParams>,
+    pub domain: SigningDomain,
+    /// Parameters of the hash that derives a multisig account's key from its policy.
+    pub policy_crh_params: <PolicyH as CRHScheme>::Parameters,
+    /// The scheme that account keys sign with.
+    pub signature_scheme: AccountSignatureScheme,
+    pub eddsa_params: eddsa::Parameters,
+    /// Parameters of the commitments that hide balances and transfer amounts.
+    pub amount_commitment_params: AmountCommitmentParameters,
+    /// Whether leaves hold commitments to their balances instead of the balances;
+    /// see `Parameters::with_confidential_amounts`.
+    pub confidential_amounts: bool,
+    /// Parameters of the hash that commits to the state diffs of a batch; see
+    /// `da::commit`.
+    pub diff_crh_params: <DiffHash as CRHScheme>::Parameters,
 }
 
 impl Parameters {
+    /// Sample parameters for the default signing domain.
     pub fn sample<R: Rng>(rng: &mut R) -> Self {
+        Self::sample_for_domain(SigningDomain::default(), rng)
+    }
+
+    /// Sample parameters whose signatures are only valid within `domain`.
+    pub fn sample_for_domain<R: Rng>(domain: SigningDomain, rng: &mut R) -> Self {
+        Self::sample_for_domain_and_scheme(domain, AccountSignatureScheme::default(), rng)
+    }
+
+    /// Sample parameters whose accounts sign with `signature_scheme`, and whose
+    /// signatures are only valid within `domain`.
+    pub fn sample_for_domain_and_scheme<R: Rng>(
+        domain: SigningDomain,
+        signature_scheme: AccountSignatureScheme,
+        rng: &mut R,
+    ) -> Self {
         let sig_params = schnorr::Schnorr::setup(rng).unwrap();
-        let leaf_crh_params = <LeafH as CRHScheme>::setup(rng).unwrap();
-        let two_to_one_crh_params = <CompressH as TwoToOneCRHScheme>::setup(rng).unwrap();
+        let leaf_crh_params = LeafHash::setup(rng).unwrap();
+        let inner_crh_params = <AccTreeParams as Config>::InnerHash::setup(rng).unwrap();
+        let policy_crh_params = <PolicyH as CRHScheme>::setup(rng).unwrap();
+        let amount_commitment_params = AmountCommitmentScheme::setup(rng).unwrap();
+        let diff_crh_params = DiffHash::setup(rng).unwrap();
         Self {
             sig_params,
             leaf_crh_params,
-            two_to_one_crh_params,
+            inner_crh_params,
+            domain,
+            policy_crh_params,
+            signature_scheme,
+            eddsa_params: eddsa::EdDSA::setup().unwrap(),
+            amount_commitment_params,
+            confidential_amounts: false,
+            diff_crh_params,
         }
     }
-}
 
-type LeafH = pedersen::CRH<JubJub, Window4x256>;
-type LeafHG = pedersen::constraints::CRHGadget<JubJub, EdwardsVar, Window4x256>;
+    /// Switch to confidential amounts. Leaves then hold an `AmountCommitment` to
+    /// their balance instead of the balance, and transfers are signed over a
+    /// commitment to the amount. Swaps and multisig transfers sign plain amounts,
+    /// so they are rejected in such deployments.
+    pub fn with_confidential_amounts(mut self) -> Self {
+        self.confidential_amounts = true;
+        self
+    }
 
-type CompressH = pedersen::TwoToOneCRH<JubJub, Window4x256>;
-type CompressHG = pedersen::constraints::TwoToOneCRHGadget<JubJub, EdwardsVar, Window4x256>;
+    /// Sample an account key pair for `signature_scheme`.
+    pub fn keygen<R: Rng>(&self, rng: &mut R) -> (AccountPublicKey, AccountSecretKey) {
+        match self.signature_scheme {
+            AccountSignatureScheme::Schnorr => schnorr::Schnorr::keygen(&self.sig_params, rng).unwrap(),
+            AccountSignatureScheme::EdDSA => {
+                let (pk, sk) = eddsa::EdDSA::keygen(&self.eddsa_params, rng);
+                (pk, schnorr::SecretKey(sk.0))
+            }
+        }
+    }
 
-type LeafVar<ConstraintF> = [UInt8<ConstraintF>];
+    /// Sign `message` with `sk`, whose public key is `pk`, using `signature_scheme`.
+    pub fn sign<R: Rng>(
+        &self,
+        sk: &AccountSecretKey,
+        pk: &AccountPublicKey,
+        message: &[u8],
+        rng: &mut R,
+    ) -> AccountSignature {
+        match self.signature_scheme {
+            AccountSignatureScheme::Schnorr => AccountSignature::Schnorr(
+                schnorr::Schnorr::sign(&self.sig_params, sk, pk, message, rng).unwrap(),
+            ),
+            AccountSignatureScheme::EdDSA => AccountSignature::EdDSA(eddsa::EdDSA::sign(
+                &self.eddsa_params,
+                &eddsa::SecretKey(sk.0),
+                message,
+                rng,
+            )),
+        }
+    }
 
-#[derive(Clone, PartialEq, Eq, Hash)]
-pub struct Window4x256;
-impl pedersen::Window for Window4x256 {
-    const WINDOW_SIZE: usize = 4;
-    const NUM_WINDOWS: usize = 256;
+    /// Verify `signature` on `message` under `pk`. Signatures of another scheme
+    /// than `signature_scheme` are rejected.
+    pub fn verify_signature(
+        &self,
+        pk: &AccountPublicKey,
+        message: &[u8],
+        signature: &AccountSignature,
+    ) -> bool {
+        match (self.signature_scheme, signature) {
+            (AccountSignatureScheme::Schnorr, AccountSignature::Schnorr(signature)) => {
+                schnorr::Schnorr::verify(&self.sig_params, pk, message, signature).unwrap()
+            }
+            (AccountSignatureScheme::EdDSA, AccountSignature::EdDSA(signature)) => {
+                eddsa::EdDSA::verify(&self.eddsa_params, pk, message, signature)
+            }
+            _ => false,
+        }
+    }
 }
 
-#[derive(Clone)]
-pub struct JubJubMerkleTreeParams;
-
-impl Config for JubJubMerkleTreeParams {
-    type Leaf = [u8];
-    type LeafDigest = <LeafH as CRHScheme>::Output;
-    type LeafInnerDigestConverter = ByteDigestConverter<Self::LeafDigest>;
+/// Hash from an encoded `MultisigPolicy` to the key its account is registered under.
+pub type PolicyH = pedersen::CRH<JubJub, PolicyWindow>;
 
-    type InnerDigest = <CompressH as TwoToOneCRHScheme>::Output;
-    type LeafHash = LeafH;
-    type TwoToOneHash = CompressH;
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct PolicyWindow;
+impl pedersen::Window for PolicyWindow {
+    const WINDOW_SIZE: usize = 4;
+    const NUM_WINDOWS: usize = MULTISIG_POLICY_BYTES * 8 / 4;
 }
 
-type ConstraintF = Fq;
-pub struct JubJubMerkleTreeParamsVar;
-impl ConfigGadget<JubJubMerkleTreeParams, ConstraintF> for JubJubMerkleTreeParamsVar {
-    type Leaf = LeafVar<ConstraintF>;
-    type LeafDigest = <LeafHG as CRHSchemeGadget<LeafH, ConstraintF>>::OutputVar;
-    type LeafInnerConverter = BytesVarDigestConverter<Self::LeafDigest, ConstraintF>;
-    type InnerDigest =
-    <CompressHG as TwoToOneCRHSchemeGadget<CompressH, ConstraintF>>::OutputVar;
-    type LeafHash = LeafHG;
-    type TwoToOneHash = CompressHG;
-}
+/// The configuration of the account Merkle tree. It is binary, unless the
+/// `account-tree-arity-4` or the `account-tree-arity-8` feature is enabled.
+/// If both are, as features are additive, the larger arity wins.
+#[cfg(not(any(feature = "account-tree-arity-4", feature = "account-tree-arity-8")))]
+pub use merkle_groth::jubjub::{JubJubMerkleTreeParams as AccTreeParams, JubJubMerkleTreeParamsVar as AccTreeParamsVar};
+#[cfg(all(feature = "account-tree-arity-4", not(feature = "account-tree-arity-8")))]
+pub use merkle_groth::jubjub::{
+    JubJub4aryMerkleTreeParams as AccTreeParams, JubJub4aryMerkleTreeParamsVar as AccTreeParamsVar,
+};
+#[cfg(feature = "account-tree-arity-8")]
+pub use merkle_groth::jubjub::{
+    JubJub8aryMerkleTreeParams as AccTreeParams, JubJub8aryMerkleTreeParamsVar as AccTreeParamsVar,
+};
 
-/// A Merkle tree containing account information.
-pub type AccMerkleTree = MerkleTree<JubJubMerkleTreeParams>;
+/// A Merkle tree containing one leaf of account information per `(account, token)` pair.
+pub type AccMerkleTree = MerkleTree<AccTreeParams>;
 /// The root of the account Merkle tree.
-pub type AccRoot = <CompressH as TwoToOneCRHScheme>::Output;
-/// A membership proof for a given account.
-pub type AccPath = Path<JubJubMerkleTreeParams>;
+pub type AccRoot = Digest<AccTreeParams>;
+/// A membership proof for a given `(account, token)` leaf.
+pub type AccPath = Path<AccTreeParams>;
+/// The account Merkle tree as of each finalized batch.
+pub type AccTreeHistory = MerkleTreeHistory<AccTreeParams>;
+
+/// The batches in which a leaf's information changed, in increasing order, each
+/// with the information from that batch on.
+type AccountInfoChanges = Vec<(usize, AccountInformation)>;
 
 #[derive(Clone)]
 pub struct State {
     /// What is the next available account identifier?
     pub next_available_account: Option<AccountId>,
-    /// A merkle tree mapping where the i-th leaf corresponds to the i-th account's
-    /// information (= balance and public key).
+    /// A merkle tree whose leaf at `id.leaf_index(token)` holds the information of
+    /// account `id` for `token` (= balance of that token and public key).
     pub account_merkle_tree: AccMerkleTree,
-    /// A mapping from an account's identifier to its information (= balance and public key).
-    pub id_to_account_info: HashMap<AccountId, AccountInformation>,
-    /// A mapping from a public key to an account's identifier.
+    /// A mapping from an account's identifier and a token to the information stored
+    /// in the corresponding leaf (= balance of that token and public key).
+    pub id_to_account_info: HashMap<(AccountId, TokenId), AccountInformation>,
+    /// A mapping from every public key that an account has held to the account's
+    /// identifier. Keys retired by a rotation stay reserved, so that no account
+    /// returns to a key whose rotation signatures could be replayed.
     pub pub_key_to_id: HashMap<schnorr::PublicKey<JubJub>, AccountId>,
+    /// The commitment parameters if the ledger has confidential amounts, for
+    /// recommitting to balances that are set directly.
+    pub amount_commitment_params: Option<AmountCommitmentParameters>,
+    /// The secret from which the blindings of those recommitments are derived;
+    /// see `State::with_sampled_blinding_key`.
+    pub blinding_key: Option<[u8; 32]>,
+    /// The leaves whose balance commitment was set by `update_balance_commitment`,
+    /// so that the state does not know its opening.
+    pub unopened_balances: HashSet<(AccountId, TokenId)>,
+    /// The account Merkle tree as of each batch; see `State::finalize_batch`.
+    /// The history grows with the chain, so clones of the state share it until
+    /// one of them finalizes a batch.
+    pub account_tree_history: Arc<AccTreeHistory>,
+    /// The changes of the information of each `(account, token)` leaf, as of
+    /// each batch. Shared between clones like `account_tree_history`.
+    pub account_info_history: Arc<HashMap<(AccountId, TokenId), AccountInfoChanges>>,
 }
 
 impl State {
-    /// Create an empty ledger that supports `num_accounts` accounts.
+    /// Create an empty ledger that supports `num_accounts` accounts, each holding
+    /// `NUM_TOKENS` tokens.
     pub fn new(num_accounts: usize, parameters: &Parameters) -> Self {
-        let height = ark_std::log2(num_accounts);
+        // Identifiers start at 1, so the tree also holds the leaves of identifier 0.
+        let num_leaves = (num_accounts + 1) * NUM_TOKENS;
+        let mut depth = 0;
+        while AccTreeParams::ARITY.pow(depth) < num_leaves {
+            depth += 1;
+        }
         let account_merkle_tree = AccMerkleTree::blank(
             &parameters.leaf_crh_params,
-            &parameters.two_to_one_crh_params,
-            height as usize,
+            &parameters.inner_crh_params,
+            depth as usize,
         )
         .unwrap();
         let pub_key_to_id = HashMap::with_capacity(num_accounts);
-        let id_to_account_info = HashMap::with_capacity(num_accounts);
+        let id_to_account_info = HashMap::with_capacity(num_accounts * NUM_TOKENS);
+        let amount_commitment_params = parameters
+            .confidential_amounts
+            .then(|| parameters.amount_commitment_params.clone());
         Self {
             next_available_account: Some(AccountId(1)),
+            account_tree_history: Arc::new(AccTreeHistory::new(&account_merkle_tree)),
             account_merkle_tree,
             id_to_account_info,
             pub_key_to_id,
+            amount_commitment_params,
+            blinding_key: None,
+            unopened_balances: HashSet::new(),
+            account_info_history: Arc::default(),
         }
     }
 
+    /// Sample the secret from which the state derives the blindings of the
+    /// balance commitments that it makes itself, when registering accounts and
+    /// in `update_balance`. A ledger with confidential amounts needs one for
+    /// either. Each blinding is derived from the leaf, its current commitment
+    /// and the new balance, so every commitment gets a fresh blinding, and
+    /// clones of the state make the same commitments.
+    pub fn with_sampled_blinding_key<R: Rng>(mut self, rng: &mut R) -> Self {
+        self.blinding_key = Some(rng.gen());
+        self
+    }
+
+    /// Commit to `amount` as the new balance of the `(id, token)` leaf, whose
+    /// current balance commitment is `current`, with a blinding derived from
+    /// the blinding key. Returns `None` if the ledger has no confidential
+    /// amounts or the state has no blinding key.
+    fn commit_to_balance(
+        &self,
+        id: AccountId,
+        token: TokenId,
+        current: &EdwardsAffine,
+        amount: Amount,
+    ) -> Option<AmountCommitment> {
+        let mut input = self.blinding_key?.to_vec();
+        input.extend((id.leaf_index(token) as u64).to_le_bytes());
+        current
+            .serialize_uncompressed(&mut input)
+            .expect("serialization into a vector should not fail");
+        input.extend(amount.to_bytes_le());
+        let blinding = Fr::from_le_bytes_mod_order(&Blake2b512::digest(&input));
+        Some(AmountCommitment::new(self.amount_commitment_params.as_ref()?, amount, blinding))
+    }
+
     /// Return the root of the account Merkle tree.
     pub fn root(&self) -> AccRoot {
         self.account_merkle_tree.root()
     }
 
+    /// Record the current state as the state after the next batch, and return
+    /// the number of that batch. Batch 0 is the empty ledger created by
+    /// `State::new`, so a batch consists of everything applied since the
+    /// previous one, registrations included.
+    ///
+    /// If a clone of the state shares the history, the history is copied
+    /// first, so finalize batches on states that are not cloned.
+    pub fn finalize_batch(&mut self) -> usize {
+        let changed_leaves = Arc::make_mut(&mut self.account_tree_history)
+            .commit(&self.account_merkle_tree)
+            .expect("the account tree keeps its depth");
+        let batch = self.latest_batch();
+        let account_info_history = Arc::make_mut(&mut self.account_info_history);
+        for index in changed_leaves {
+            // Account identifiers fit in a byte, so a leaf beyond the last of
+            // them belongs to no account.
+            let Ok(id) = u8::try_from(index >> TOKEN_ID_BITS).map(AccountId) else {
+                continue;
+            };
+            let token = TokenId((index % NUM_TOKENS) as u8);
+            if let Some(account_info) = self.id_to_account_info.get(&(id, token)).copied() {
+                account_info_history
+                    .entry((id, token))
+                    .or_default()
+                    .push((batch, account_info));
+            }
+        }
+        batch
+    }
+
+    /// Return the number of the most recently finalized batch.
+    pub fn latest_batch(&self) -> usize {
+        self.account_tree_history.latest_version()
+    }
+
+    /// Return the root of the account Merkle tree after `batch`, or `None` if
+    /// `batch` has not been finalized.
+    pub fn root_at(&self, batch: usize) -> Option<AccRoot> {
+        self.account_tree_history.root_at(batch)
+    }
+
+    /// Return the information of account `id` for `token` after `batch`, and the
+    /// authentication path of its leaf with respect to `root_at(batch)`.
+    /// Returns `None` if `batch` has not been finalized or the account did not
+    /// exist after it.
+    pub fn generate_proof_at(
+        &self,
+        batch: usize,
+        id: AccountId,
+        token: TokenId,
+    ) -> Option<(AccountInformation, AccPath)> {
+        let changes = self.account_info_history.get(&(id, token))?;
+        let num_earlier = changes.partition_point(|(changed_in, _)| *changed_in <= batch);
+        let account_info = changes[num_earlier.checked_sub(1)?].1;
+        let path = self
+            .account_tree_history
+            .generate_proof_at(batch, id.leaf_index(token))
+            .ok()?;
+        Some((account_info, path))
+    }
+
+    /// Return the information stored in the leaf of account `id` for `token`.
+    pub fn account_info(&self, id: AccountId, token: TokenId) -> Option<&AccountInformation> {
+        self.id_to_account_info.get(&(id, token))
+    }
+
+    /// Return the balance of `token` held by account `id`, or `None` if the
+    /// account does not exist or the state does not know the opening of the
+    /// leaf's balance commitment.
+    pub fn balance(&self, id: AccountId, token: TokenId) -> Option<Amount> {
+        if self.unopened_balances.contains(&(id, token)) {
+            return None;
+        }
+        self.account_info(id, token).map(|info| info.balance)
+    }
+
     /// Create a new account with public key `pub_key`. Returns a fresh account identifier
     /// if there is space for a new account, and returns `None` otherwise.
-    /// The initial balance of the new account is 0.
+    /// The initial balance of every token in the new account is 0. If the ledger has
+    /// confidential amounts, every leaf commits to it with its own blinding, and
+    /// `None` is returned if the state has no blinding key.
     pub fn register(&mut self, public_key: AccountPublicKey) -> Option<AccountId> {
         self.next_available_account.and_then(|id| {
-            // Construct account information for the new account.
-            let account_info = AccountInformation {
-                public_key,
-                balance: Amount(0),
-            };
+            // Construct account information for each leaf of the new account.
+            let account_infos = TokenId::all()
+                .map(|token| {
+                    let balance_commitment = match self.amount_commitment_params {
+                        Some(_) => Some(self.commit_to_balance(id, token, &EdwardsAffine::zero(), Amount(0))?),
+                        None => None,
+                    };
+                    Some(AccountInformation {
+                        public_key,
+                        balance: Amount(0),
+                        balance_commitment,
+                    })
+                })
+                .collect::<Option<Vec<_>>>()?;
             // Insert information into the relevant accounts.
             self.pub_key_to_id.insert(public_key, id);
-            self.account_merkle_tree
-                .update(id.0 as usize, &account_info.to_bytes_le())
-                .expect("should exist");
-            self.id_to_account_info.insert(id, account_info);
+            for (token, account_info) in TokenId::all().zip(account_infos) {
+                self.account_merkle_tree
+                    .update(id.leaf_index(token), &account_info.to_bytes_le())
+                    .expect("should exist");
+                self.id_to_account_info.insert((id, token), account_info);
+            }
             // Increment the next account identifier.
             self.next_available_account
                 .as_mut()
@@ -159,38 +518,248 @@ impl State {
         })
     }
 
+    /// Create a new account controlled by `policy`; see `MultisigPolicy`.
+    /// Returns `None` if the policy is not well-formed or no identifier is available.
+    pub fn register_multisig(
+        &mut self,
+        parameters: &Parameters,
+        policy: &MultisigPolicy,
+    ) -> Option<AccountId> {
+        if policy.is_well_formed() {
+            self.register(policy.account_key(parameters))
+        } else {
+            None
+        }
+    }
+
     /// Samples keys and registers these in the ledger.
     pub fn sample_keys_and_register<R: Rng>(
         &mut self,
         ledger_params: &Parameters,
         rng: &mut R,
     ) -> Option<(AccountId, AccountPublicKey, AccountSecretKey)> {
-        let (pub_key, secret_key) =
-            schnorr::Schnorr::keygen(&ledger_params.sig_params, rng).unwrap();
+        let (pub_key, secret_key) = ledger_params.keygen(rng);
         self.register(pub_key).map(|id| (id, pub_key, secret_key))
     }
 
-    /// Update the balance of `id` to `new_amount`.
+    /// Update the balance of `token` held by `id` to `new_amount`. If the ledger has
+    /// confidential amounts, the leaf recommits to the new balance with a fresh
+    /// blinding; see `State::with_sampled_blinding_key`.
+    /// Returns `Some(())` if an account with identifier `id` exists already, and `None`
+    /// otherwise, or if the state lacks the blinding key that it needs.
+    pub fn update_balance(&mut self, id: AccountId, token: TokenId, new_amount: Amount) -> Option<()> {
+        self.set_balance(id, token, new_amount, None)
+    }
+
+    /// Replace the balance commitment of `token` held by `id` by `commitment`,
+    /// whose opening is unknown, as when replaying the published diffs of a
+    /// ledger with confidential amounts. Until `update_balance` sets the
+    /// balance again, `balance` returns `None` for the leaf, and the balance
+    /// and blinding in its account information do not open the commitment.
+    /// Returns `None` if the leaf does not exist or the ledger has no
+    /// confidential amounts.
+    pub fn update_balance_commitment(&mut self, id: AccountId, token: TokenId, commitment: EdwardsAffine) -> Option<()> {
+        let account_info = *self.account_info(id, token)?;
+        let balance_commitment = AmountCommitment {
+            commitment,
+            ..account_info.balance_commitment?
+        };
+        self.set_balance(id, token, account_info.balance, Some(balance_commitment))?;
+        self.unopened_balances.insert((id, token));
+        Some(())
+    }
+
+    /// Set the balance of `token` held by `id` to `new_amount`, and its commitment
+    /// to `new_commitment`, or to a recommitment if that is `None`, whose opening
+    /// the state then knows.
+    fn set_balance(
+        &mut self,
+        id: AccountId,
+        token: TokenId,
+        new_amount: Amount,
+        new_commitment: Option<AmountCommitment>,
+    ) -> Option<()> {
+        let account_info = *self.account_info(id, token)?;
+        let balance_commitment = match (new_commitment, account_info.balance_commitment) {
+            (Some(new_commitment), _) => Some(new_commitment),
+            (None, Some(current)) => {
+                let recommitment = self.commit_to_balance(id, token, &current.commitment, new_amount)?;
+                self.unopened_balances.remove(&(id, token));
+                Some(recommitment)
+            }
+            (None, None) => None,
+        };
+        let account_info = AccountInformation {
+            balance: new_amount,
+            balance_commitment,
+            ..account_info
+        };
+        self.account_merkle_tree
+            .update(id.leaf_index(token), &account_info.to_bytes_le())
+            .expect("should exist");
+        self.id_to_account_info.insert((id, token), account_info);
+        Some(())
+    }
+
+    /// Replace the public key in the `(id, token)` leaf by `new_public_key`.
     /// Returns `Some(())` if an account with identifier `id` exists already, and `None`
     /// otherwise.
-    pub fn update_balance(&mut self, id: AccountId, new_amount: Amount) -> Option<()> {
+    ///
+    /// This touches a single leaf and leaves `pub_key_to_id` alone, so on its own it
+    /// breaks the invariant that all leaves of an account hold the same key. It is
+    /// exposed to let provers record the intermediate states of `rotate_key`.
+    pub fn update_public_key(
+        &mut self,
+        id: AccountId,
+        token: TokenId,
+        new_public_key: AccountPublicKey,
+    ) -> Option<()> {
         let tree = &mut self.account_merkle_tree;
-        self.id_to_account_info.get_mut(&id).map(|account_info| {
-            account_info.balance = new_amount;
-            tree.update(id.0 as usize, &account_info.to_bytes_le())
+        self.id_to_account_info.get_mut(&(id, token)).map(|account_info| {
+            account_info.public_key = new_public_key;
+            tree.update(id.leaf_index(token), &account_info.to_bytes_le())
                 .expect("should exist");
         })
     }
 
+    /// Replace the public key of account `id` by `new_public_key` in all of its
+    /// leaves, in `TokenId::all()` order, without checking any authorization.
+    /// Returns `None`, leaving the state untouched, if the account does not exist
+    /// or `new_public_key` is registered or retired. The old key stays reserved
+    /// for the account; see `pub_key_to_id`.
+    pub fn rotate_key(&mut self, id: AccountId, new_public_key: AccountPublicKey) -> Option<()> {
+        self.account_info(id, TokenId::default())?;
+        if self.pub_key_to_id.contains_key(&new_public_key) {
+            return None;
+        }
+        for token in TokenId::all() {
+            self.update_public_key(id, token, new_public_key)?;
+        }
+        self.pub_key_to_id.insert(new_public_key, id);
+        Some(())
+    }
+
+    /// Move `amount` of `token` from `from` to `to`, without checking any
+    /// authorization. Returns `None`, leaving the state untouched, if either leaf
+    /// does not exist or a balance would under- or overflow.
+    pub fn transfer(
+        &mut self,
+        from: AccountId,
+        to: AccountId,
+        token: TokenId,
+        amount: Amount,
+    ) -> Option<()> {
+        self.move_funds(from, to, token, amount, None)
+    }
+
+    /// Like `transfer`, but moves `amount_commitment`, which has to open to `amount`,
+    /// between the balance commitments of a ledger with confidential amounts.
+    pub fn transfer_committed(
+        &mut self,
+        from: AccountId,
+        to: AccountId,
+        token: TokenId,
+        amount: Amount,
+        amount_commitment: &AmountCommitment,
+    ) -> Option<()> {
+        self.move_funds(from, to, token, amount, Some(amount_commitment))
+    }
+
+    fn move_funds(
+        &mut self,
+        from: AccountId,
+        to: AccountId,
+        token: TokenId,
+        amount: Amount,
+        amount_commitment: Option<&AmountCommitment>,
+    ) -> Option<()> {
+        let old_sender_info = *self.account_info(from, token)?;
+        let new_sender_bal = old_sender_info.balance.checked_sub(amount)?;
+        let new_sender_com = old_sender_info
+            .balance_commitment
+            .zip(amount_commitment)
+            .map(|(balance, amount)| balance - *amount);
+        self.set_balance(from, token, new_sender_bal, new_sender_com);
+        // Read the recipient balance only now, so that self-transfers are no-ops.
+        let old_receiver_info = *self.account_info(to, token)?;
+        match old_receiver_info.balance.checked_add(amount) {
+            Some(new_receiver_bal) => {
+                let new_receiver_com = old_receiver_info
+                    .balance_commitment
+                    .zip(amount_commitment)
+                    .map(|(balance, amount)| balance + *amount);
+                self.set_balance(to, token, new_receiver_bal, new_receiver_com)
+            }
+            None => self
+                .set_balance(from, token, old_sender_info.balance, old_sender_info.balance_commitment)
+                .and(None),
+        }
+    }
+
+    /// Update the state by applying a published `diff`, without checking any
+    /// authorization; the batch proof attests to it. Returns `None` if a leaf
+    /// of the diff does not exist, or a key diff assigns a key that is already
+    /// registered.
+    pub fn apply_state_diff(&mut self, diff: &StateDiff) -> Option<()> {
+        match *diff {
+            StateDiff::Balance { account, token, balance } => self.update_balance(account, token, balance),
+            StateDiff::Key { account, public_key } => self.rotate_key(account, public_key),
+            StateDiff::BalanceCommitment { account, token, commitment } => {
+                self.update_balance_commitment(account, token, commitment)
+            }
+        }
+    }
+
     /// Update the state by applying the transaction `tx`, if `tx` is valid.
+    /// Transactions in a ledger with confidential amounts are applied with
+    /// `apply_transaction_with_opening` instead.
     pub fn apply_transaction(&mut self, pp: &Parameters, tx: &Transaction) -> Option<()> {
+        self.apply_transaction_with_opening(pp, tx, None)
+    }
+
+    /// Update the state by applying the transaction `tx`, whose amount commitment
+    /// `opening` opens if the ledger has confidential amounts, if `tx` is valid;
+    /// see `Transaction::validate_with_opening`.
+    pub fn apply_transaction_with_opening(
+        &mut self,
+        pp: &Parameters,
+        tx: &Transaction,
+        opening: Option<&AmountOpening>,
+    ) -> Option<()> {
+        let (amount, amount_commitment) = tx.amount_with_opening(opening)?;
+        if !tx.validate_with_opening(pp, self, opening) {
+            None
+        } else if let Some(amount_commitment) = &amount_commitment {
+            self.transfer_committed(tx.sender, tx.recipient, tx.token, amount, amount_commitment)
+        } else {
+            self.transfer(tx.sender, tx.recipient, tx.token, amount)
+        }
+    }
+
+    /// Update the state by applying the key rotation `rotation`, if `rotation` is valid.
+    pub fn apply_key_rotation(&mut self, pp: &Parameters, rotation: &KeyRotation) -> Option<()> {
+        if rotation.validate(pp, self) {
+            self.rotate_key(rotation.account, rotation.new_public_key)
+        } else {
+            None
+        }
+    }
+
+    /// Update the state by applying the multisig transaction `tx`, if `tx` is valid.
+    pub fn apply_multisig_transaction(&mut self, pp: &Parameters, tx: &MultisigTransaction) -> Option<()> {
         if tx.validate(pp, self) {
-            let old_sender_bal = self.id_to_account_info.get(&tx.sender)?.balance;
-            let old_receiver_bal = self.id_to_account_info.get(&tx.recipient)?.balance;
-            let new_sender_bal = old_sender_bal.checked_sub(tx.amount)?;
-            let new_receiver_bal = old_receiver_bal.checked_add(tx.amount)?;
-            self.update_balance(tx.sender, new_sender_bal);
-            self.update_balance(tx.recipient, new_receiver_bal);
+            self.transfer(tx.sender, tx.recipient, tx.token, tx.amount)
+        } else {
+            None
+        }
+    }
+
+    /// Update the state by applying both legs of `swap`, if `swap` is valid.
+    pub fn apply_swap(&mut self, pp: &Parameters, swap: &Swap) -> Option<()> {
+        if swap.validate(pp, self) {
+            for (from, to, token, amount) in swap.terms.legs() {
+                self.transfer(from, to, token, amount)?;
+            }
             Some(())
         } else {
             None
@@ -200,10 +769,16 @@ impl State {
 
 #[cfg(test)]
 mod test {
-    use super::{AccountId, Amount, Parameters, State};
-    use crate::transaction::Transaction;
+    use super::{AccountId, Amount, AmountOpening, Parameters, SigningDomain, State, TokenId};
+    use crate::signature::schnorr::{self, Schnorr};
+    use crate::account::MultisigPolicy;
+    use crate::transaction::{
+        KeyRotation, MultisigTransaction, SigningPayload, Swap, SwapTerms, Transaction, TransferAmount,
+        NO_DESTINATION,
+    };
 
     #[test]
+    #[allow(clippy::redundant_pattern_matching)]
     fn end_to_end() {
         let mut rng = ark_std::test_rng();
         let pp = Parameters::sample(&mut rng);
@@ -213,29 +788,381 @@ mod test {
             state.sample_keys_and_register(&pp, &mut rng).unwrap();
         // Let's give her some initial balance to start with.
         state
-            .update_balance(alice_id, Amount(10))
+            .update_balance(alice_id, TokenId(0), Amount(10))
             .expect("Alice's account should exist");
         // Let's make an account for Bob.
         let (bob_id, bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
 
         // Alice wants to transfer 5 units to Bob.
-        let tx1 = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &alice_pk, &mut rng);
+        let tx1 = Transaction::create(&pp, alice_id, bob_id, TokenId(0), Amount(5), &alice_sk, &alice_pk, &mut rng);
         assert!(tx1.validate(&pp, &state));
         state.apply_transaction(&pp, &tx1).expect("should work");
         // Let's try creating invalid transactions:
         // First, let's try a transaction where the amount is larger than Alice's balance.
-        let bad_tx = Transaction::create(&pp, alice_id, bob_id, Amount(6), &alice_sk, &alice_pk, &mut rng);
+        let bad_tx = Transaction::create(&pp, alice_id, bob_id, TokenId(0), Amount(6), &alice_sk, &alice_pk, &mut rng);
         assert!(!bad_tx.validate(&pp, &state));
         assert!(matches!(state.apply_transaction(&pp, &bad_tx), None));
         // Next, let's try a transaction where the signature is incorrect:
-        let bad_tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &bob_sk, &bob_pk, &mut rng);
+        let bad_tx = Transaction::create(&pp, alice_id, bob_id, TokenId(0), Amount(5), &bob_sk, &bob_pk, &mut rng);
         assert!(!bad_tx.validate(&pp, &state));
         assert!(matches!(state.apply_transaction(&pp, &bad_tx), None));
 
         // Finally, let's try a transaction to an non-existant account:
         let bad_tx =
-            Transaction::create(&pp, alice_id, AccountId(10), Amount(5), &alice_sk, &alice_pk, &mut rng);
+            Transaction::create(&pp, alice_id, AccountId(10), TokenId(0), Amount(5), &alice_sk, &alice_pk, &mut rng);
         assert!(!bad_tx.validate(&pp, &state));
         assert!(matches!(state.apply_transaction(&pp, &bad_tx), None));
     }
+
+    #[test]
+    fn signatures_do_not_replay_across_domains() {
+        let mut rng = ark_std::test_rng();
+        let domain = SigningDomain { chain_id: 1, rollup_id: 7 };
+        let pp = Parameters::sample_for_domain(domain, &mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, TokenId(0), Amount(10)).unwrap();
+        let (bob_id, _, _) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        let tx = Transaction::create(&pp, alice_id, bob_id, TokenId(0), Amount(5), &alice_sk, &alice_pk, &mut rng);
+        assert!(tx.validate(&pp, &state));
+
+        // The same parameters deployed on another chain or as another rollup
+        // must reject the signature.
+        for other_domain in [
+            SigningDomain { chain_id: 2, rollup_id: 7 },
+            SigningDomain { chain_id: 1, rollup_id: 8 },
+        ] {
+            let mut other_pp = pp.clone();
+            other_pp.domain = other_domain;
+            assert!(!tx.validate(&other_pp, &state));
+        }
+    }
+
+    #[test]
+    fn eddsa_accounts_accept_wallet_signatures() {
+        use crate::account::{AccountSignature, AccountSignatureScheme};
+        use crate::signature::eddsa::{self, EdDSA};
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample_for_domain_and_scheme(
+            SigningDomain::default(),
+            AccountSignatureScheme::EdDSA,
+            &mut rng,
+        );
+        let mut state = State::new(32, &pp);
+        // A wallet only needs the RedJubjub key pair and the payload encoding.
+        let (alice_pk, alice_sk) = EdDSA::keygen(&pp.eddsa_params, &mut rng);
+        let alice_id = state.register(alice_pk).unwrap();
+        state.update_balance(alice_id, TokenId(0), Amount(10)).unwrap();
+        let (bob_id, _, _) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        let payload = SigningPayload::transfer(pp.domain, alice_id, bob_id, TokenId(0), Amount(4), &NO_DESTINATION);
+        let signature = EdDSA::sign(&pp.eddsa_params, &alice_sk, &payload.to_bytes_le(), &mut rng);
+        let tx = Transaction {
+            sender: alice_id,
+            recipient: bob_id,
+            token: TokenId(0),
+            amount: TransferAmount::Plain(Amount(4)),
+            destination: NO_DESTINATION,
+            signature: AccountSignature::EdDSA(eddsa::Signature::from_bytes(&signature.to_bytes())),
+        };
+        state.apply_transaction(&pp, &tx).expect("should work");
+        assert_eq!(state.balance(alice_id, TokenId(0)), Some(Amount(6)));
+
+        // Signatures of the other scheme are rejected, even with the same key.
+        let schnorr_sk = schnorr::SecretKey(alice_sk.0);
+        let mut schnorr_pp = pp.clone();
+        schnorr_pp.signature_scheme = AccountSignatureScheme::Schnorr;
+        schnorr_pp.sig_params.generator = pp.eddsa_params.generator;
+        let tx = Transaction::create(&schnorr_pp, alice_id, bob_id, TokenId(0), Amount(1), &schnorr_sk, &alice_pk, &mut rng);
+        assert!(tx.validate(&schnorr_pp, &state));
+        assert!(!tx.validate(&pp, &state));
+    }
+
+    #[test]
+    fn confidential_amounts_hide_balances() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng).with_confidential_amounts();
+        // Without a blinding key, the state cannot commit to the new balances.
+        assert!(State::new(32, &pp).sample_keys_and_register(&pp, &mut rng).is_none());
+        let mut state = State::new(32, &pp).with_sampled_blinding_key(&mut rng);
+        let (alice_id, alice_pk, alice_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (bob_id, _, _) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        // Empty leaves do not look alike.
+        let bob_zero = state.account_info(bob_id, TokenId(0)).unwrap().balance_commitment.unwrap();
+        assert!(bob_zero.opens_to(&pp.amount_commitment_params, Amount(0)));
+        assert_ne!(bob_zero.commitment, state.account_info(bob_id, TokenId(1)).unwrap().balance_commitment.unwrap().commitment);
+        assert_ne!(bob_zero.commitment, state.account_info(alice_id, TokenId(0)).unwrap().balance_commitment.unwrap().commitment);
+        let alice_zero = state.account_info(alice_id, TokenId(0)).unwrap().balance_commitment.unwrap();
+        state.update_balance(alice_id, TokenId(0), Amount(10)).unwrap();
+
+        // The leaf holds the key and a commitment, not the balance, and the
+        // commitment is not the old one plus a commitment to the difference.
+        let info = *state.account_info(alice_id, TokenId(0)).unwrap();
+        let commitment = info.balance_commitment.unwrap();
+        assert!(commitment.opens_to(&pp.amount_commitment_params, Amount(10)));
+        assert_ne!(commitment.blinding, alice_zero.blinding);
+        assert_eq!(info.to_bytes_le().len(), 128);
+        assert!(!info.to_bytes_le().ends_with(&Amount(10).to_bytes_le()));
+
+        // The published transaction carries neither the amount nor the blinding.
+        let (tx, opening) =
+            Transaction::create_confidential(&pp, alice_id, bob_id, TokenId(0), Amount(4), &alice_sk, &alice_pk, &mut rng);
+        let amount_commitment = opening.commit(&pp.amount_commitment_params);
+        assert_eq!(tx.amount, TransferAmount::Committed(amount_commitment.commitment));
+        assert!(state.clone().apply_transaction(&pp, &tx).is_none());
+        state.apply_transaction_with_opening(&pp, &tx, Some(&opening)).expect("should work");
+        assert_eq!(state.balance(alice_id, TokenId(0)), Some(Amount(6)));
+        assert_eq!(state.balance(bob_id, TokenId(0)), Some(Amount(4)));
+        // Both parties can open their new balances from the amount's blinding.
+        let alice_commitment = state.account_info(alice_id, TokenId(0)).unwrap().balance_commitment.unwrap();
+        assert_eq!(alice_commitment, commitment - amount_commitment);
+        assert!(alice_commitment.opens_to(&pp.amount_commitment_params, Amount(6)));
+        let bob_commitment = state.account_info(bob_id, TokenId(0)).unwrap().balance_commitment.unwrap();
+        assert_eq!(bob_commitment, bob_zero + amount_commitment);
+        assert!(bob_commitment.opens_to(&pp.amount_commitment_params, Amount(4)));
+
+        // An opening to another amount than the committed one is rejected ...
+        let (bad_tx, opening) =
+            Transaction::create_confidential(&pp, alice_id, bob_id, TokenId(0), Amount(1), &alice_sk, &alice_pk, &mut rng);
+        let bad_opening = AmountOpening { amount: Amount(2), ..opening };
+        assert!(!bad_tx.validate_with_opening(&pp, &state, Some(&bad_opening)));
+        // ... and so is a commitment to more than the balance.
+        let (bad_tx, opening) =
+            Transaction::create_confidential(&pp, alice_id, bob_id, TokenId(0), Amount(7), &alice_sk, &alice_pk, &mut rng);
+        assert!(!bad_tx.validate_with_opening(&pp, &state, Some(&opening)));
+        // Transfers with plain amounts are rejected, and vice versa.
+        let plain_tx = Transaction::create(&pp, alice_id, bob_id, TokenId(0), Amount(1), &alice_sk, &alice_pk, &mut rng);
+        assert!(!plain_tx.validate(&pp, &state));
+        let (tx, opening) =
+            Transaction::create_confidential(&pp, alice_id, bob_id, TokenId(0), Amount(1), &alice_sk, &alice_pk, &mut rng);
+        assert!(tx.validate_with_opening(&pp, &state, Some(&opening)));
+        let mut plain_pp = pp.clone();
+        plain_pp.confidential_amounts = false;
+        assert!(!tx.validate_with_opening(&plain_pp, &state, Some(&opening)));
+    }
+
+    #[test]
+    fn tokens_have_separate_balances() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (usd, eur) = (TokenId(0), TokenId(1));
+        let (alice_id, alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, usd, Amount(10)).unwrap();
+        state.update_balance(alice_id, eur, Amount(3)).unwrap();
+        let (bob_id, _, _) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        // Moving EUR leaves the USD balances untouched.
+        let tx = Transaction::create(&pp, alice_id, bob_id, eur, Amount(2), &alice_sk, &alice_pk, &mut rng);
+        state.apply_transaction(&pp, &tx).expect("should work");
+        assert_eq!(state.balance(alice_id, eur), Some(Amount(1)));
+        assert_eq!(state.balance(bob_id, eur), Some(Amount(2)));
+        assert_eq!(state.balance(alice_id, usd), Some(Amount(10)));
+        assert_eq!(state.balance(bob_id, usd), Some(Amount(0)));
+
+        // Alice's USD balance cannot fund an EUR transfer.
+        let bad_tx = Transaction::create(&pp, alice_id, bob_id, eur, Amount(5), &alice_sk, &alice_pk, &mut rng);
+        assert!(state.apply_transaction(&pp, &bad_tx).is_none());
+
+        // A signature over one token does not authorize a transfer of another.
+        let mut bad_tx = Transaction::create(&pp, alice_id, bob_id, eur, Amount(1), &alice_sk, &alice_pk, &mut rng);
+        bad_tx.token = usd;
+        assert!(!bad_tx.validate(&pp, &state));
+
+        // Unsupported tokens have no leaves.
+        let bad_tx =
+            Transaction::create(&pp, alice_id, bob_id, TokenId(4), Amount(0), &alice_sk, &alice_pk, &mut rng);
+        assert!(!bad_tx.validate(&pp, &state));
+    }
+
+    #[test]
+    fn swaps_apply_both_legs_or_neither() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (usd, eur) = (TokenId(0), TokenId(1));
+        let (alice_id, alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, usd, Amount(10)).unwrap();
+        let (bob_id, bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(bob_id, eur, Amount(8)).unwrap();
+
+        // Alice sells 4 USD to Bob for 3 EUR.
+        let terms = SwapTerms {
+            party_a: alice_id,
+            token_a: usd,
+            amount_a: Amount(4),
+            party_b: bob_id,
+            token_b: eur,
+            amount_b: Amount(3),
+        };
+        let swap = Swap::create(&pp, terms, (&alice_sk, &alice_pk), (&bob_sk, &bob_pk), &mut rng);
+        assert!(swap.validate(&pp, &state));
+        state.apply_swap(&pp, &swap).expect("should work");
+        assert_eq!(state.balance(alice_id, usd), Some(Amount(6)));
+        assert_eq!(state.balance(alice_id, eur), Some(Amount(3)));
+        assert_eq!(state.balance(bob_id, usd), Some(Amount(4)));
+        assert_eq!(state.balance(bob_id, eur), Some(Amount(5)));
+
+        // Bob cannot finance his leg, so Alice's leg is not applied either.
+        let root = state.root();
+        let terms = SwapTerms { amount_b: Amount(6), ..terms };
+        let swap = Swap::create(&pp, terms, (&alice_sk, &alice_pk), (&bob_sk, &bob_pk), &mut rng);
+        assert!(!swap.validate(&pp, &state));
+        assert!(state.apply_swap(&pp, &swap).is_none());
+        assert_eq!(state.root(), root);
+
+        // Both parties have to sign.
+        let mut swap = Swap::create(&pp, terms, (&alice_sk, &alice_pk), (&bob_sk, &bob_pk), &mut rng);
+        swap.terms.amount_b = Amount(1);
+        swap.signature_a = swap.terms.sign(&pp, &alice_sk, &alice_pk, &mut rng);
+        assert!(!swap.validate(&pp, &state));
+        swap.signature_b = swap.terms.sign(&pp, &alice_sk, &alice_pk, &mut rng);
+        assert!(!swap.validate(&pp, &state));
+        swap.signature_b = swap.terms.sign(&pp, &bob_sk, &bob_pk, &mut rng);
+        assert!(swap.validate(&pp, &state));
+
+        // A signature over a transfer does not authorize a swap.
+        let tx = Transaction::create(&pp, alice_id, bob_id, usd, Amount(1), &alice_sk, &alice_pk, &mut rng);
+        swap.signature_a = tx.signature;
+        assert!(!swap.validate(&pp, &state));
+    }
+
+    #[test]
+    fn key_rotation_moves_control_but_not_funds() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (usd, eur) = (TokenId(0), TokenId(1));
+        let (alice_id, alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, usd, Amount(10)).unwrap();
+        state.update_balance(alice_id, eur, Amount(4)).unwrap();
+        let (bob_id, bob_pk, _) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (new_pk, new_sk) = Schnorr::keygen(&pp.sig_params, &mut rng).unwrap();
+
+        // Rotations have to be signed with the current key.
+        let bad_rotation = KeyRotation::create(&pp, alice_id, new_pk, &new_sk, &new_pk, &mut rng);
+        assert!(!bad_rotation.validate(&pp, &state));
+        assert!(state.apply_key_rotation(&pp, &bad_rotation).is_none());
+        // Keys cannot be taken over from another account.
+        let bad_rotation = KeyRotation::create(&pp, alice_id, bob_pk, &alice_sk, &alice_pk, &mut rng);
+        assert!(!bad_rotation.validate(&pp, &state));
+
+        let rotation = KeyRotation::create(&pp, alice_id, new_pk, &alice_sk, &alice_pk, &mut rng);
+        assert!(rotation.validate(&pp, &state));
+        state.apply_key_rotation(&pp, &rotation).expect("should work");
+        for token in TokenId::all() {
+            assert_eq!(state.account_info(alice_id, token).unwrap().public_key, new_pk);
+        }
+        assert_eq!(state.balance(alice_id, usd), Some(Amount(10)));
+        assert_eq!(state.balance(alice_id, eur), Some(Amount(4)));
+        assert_eq!(state.pub_key_to_id.get(&new_pk), Some(&alice_id));
+        assert_eq!(state.pub_key_to_id.get(&alice_pk), Some(&alice_id));
+
+        // The old key no longer controls the account, and cannot rotate it back.
+        let tx = Transaction::create(&pp, alice_id, bob_id, usd, Amount(1), &alice_sk, &alice_pk, &mut rng);
+        assert!(!tx.validate(&pp, &state));
+        assert!(!rotation.validate(&pp, &state));
+        let tx = Transaction::create(&pp, alice_id, bob_id, usd, Amount(1), &new_sk, &new_pk, &mut rng);
+        assert!(tx.validate(&pp, &state));
+    }
+
+    #[test]
+    fn multisig_transfers_need_threshold_signatures() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let usd = TokenId(0);
+        let keys = (0..3)
+            .map(|_| Schnorr::keygen(&pp.sig_params, &mut rng).unwrap())
+            .collect::<Vec<_>>();
+        let policy = MultisigPolicy {
+            threshold: 2,
+            public_keys: keys.iter().map(|(pk, _)| *pk).collect(),
+        };
+        let treasury_id = state.register_multisig(&pp, &policy).unwrap();
+        state.update_balance(treasury_id, usd, Amount(10)).unwrap();
+        let (bob_id, _, _) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        // One signature is not enough, and neither is signing twice with one key.
+        let mut tx = MultisigTransaction::new(treasury_id, bob_id, usd, Amount(4), policy.clone());
+        tx.sign(&pp, &keys[2].1, &keys[2].0, &mut rng).unwrap();
+        assert!(!tx.validate(&pp, &state));
+        tx.sign(&pp, &keys[2].1, &keys[2].0, &mut rng).unwrap();
+        assert_eq!(tx.num_valid_signatures(&pp), 1);
+        assert!(!tx.validate(&pp, &state));
+        // Outsiders cannot sign.
+        let (outsider_pk, outsider_sk) = Schnorr::keygen(&pp.sig_params, &mut rng).unwrap();
+        assert!(tx.sign(&pp, &outsider_sk, &outsider_pk, &mut rng).is_none());
+
+        tx.sign(&pp, &keys[0].1, &keys[0].0, &mut rng).unwrap();
+        assert!(tx.validate(&pp, &state));
+        state.apply_multisig_transaction(&pp, &tx).expect("should work");
+        assert_eq!(state.balance(treasury_id, usd), Some(Amount(6)));
+        assert_eq!(state.balance(bob_id, usd), Some(Amount(4)));
+
+        // A weaker policy over the same keys does not control the account.
+        let weak_policy = MultisigPolicy { threshold: 1, ..policy.clone() };
+        let mut bad_tx = MultisigTransaction::new(treasury_id, bob_id, usd, Amount(1), weak_policy);
+        bad_tx.sign(&pp, &keys[0].1, &keys[0].0, &mut rng).unwrap();
+        assert!(!bad_tx.validate(&pp, &state));
+
+        // Policies with duplicate keys or unreachable thresholds cannot be registered.
+        let duplicate_keys = MultisigPolicy {
+            threshold: 2,
+            public_keys: vec![keys[0].0, keys[0].0],
+        };
+        assert!(state.register_multisig(&pp, &duplicate_keys).is_none());
+        let unreachable = MultisigPolicy { threshold: 4, ..policy };
+        assert!(state.register_multisig(&pp, &unreachable).is_none());
+    }
+
+    #[test]
+    fn finalized_batches_keep_their_roots_and_proofs() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let empty_root = state.root();
+        let (alice_id, _, alice_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (bob_id, _, _) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, TokenId(0), Amount(10)).unwrap();
+        assert_eq!(state.finalize_batch(), 1);
+        let alice_pk = state.account_info(alice_id, TokenId(0)).unwrap().public_key;
+        let tx = Transaction::create(&pp, alice_id, bob_id, TokenId(0), Amount(4), &alice_sk, &alice_pk, &mut rng);
+        state.apply_transaction(&pp, &tx).unwrap();
+        let root_after_batch_1 = state.root_at(1).unwrap();
+        assert_ne!(root_after_batch_1, state.root());
+        assert_eq!(state.finalize_batch(), 2);
+        assert_eq!(state.latest_batch(), 2);
+
+        assert_eq!(state.root_at(0), Some(empty_root));
+        assert_eq!(state.root_at(2), Some(state.root()));
+        assert_eq!(state.root_at(3), None);
+        for (batch, balance) in [(1, Amount(10)), (2, Amount(6))] {
+            let (info, path) = state.generate_proof_at(batch, alice_id, TokenId(0)).unwrap();
+            assert_eq!(info.balance, balance);
+            let root = state.root_at(batch).unwrap();
+            assert!(path.verify(&pp.leaf_crh_params, &pp.inner_crh_params, &root, info.to_bytes_le().as_slice()).unwrap());
+        }
+        // Bob's other tokens did not change in batch 2.
+        let (info, path) = state.generate_proof_at(2, bob_id, TokenId(1)).unwrap();
+        assert_eq!(info.balance, Amount(0));
+        assert!(path.verify(&pp.leaf_crh_params, &pp.inner_crh_params, &state.root(), info.to_bytes_le().as_slice()).unwrap());
+        assert!(state.generate_proof_at(0, alice_id, TokenId(0)).is_none());
+        assert!(state.generate_proof_at(3, alice_id, TokenId(0)).is_none());
+
+        // Clones share the history until one of them finalizes a batch.
+        let mut scratch = state.clone();
+        assert!(std::sync::Arc::ptr_eq(&scratch.account_tree_history, &state.account_tree_history));
+        assert!(std::sync::Arc::ptr_eq(&scratch.account_info_history, &state.account_info_history));
+        scratch.update_balance(bob_id, TokenId(1), Amount(1)).unwrap();
+        assert_eq!(scratch.finalize_batch(), 3);
+        assert!(!std::sync::Arc::ptr_eq(&scratch.account_tree_history, &state.account_tree_history));
+        assert_eq!(state.latest_batch(), 2);
+        assert_eq!(scratch.root_at(2), state.root_at(2));
+    }
 }
@@ -1,4 +1,6 @@
 pub mod account;
+pub mod da;
+pub mod genesis;
 pub mod ledger;
 pub mod transaction;
 
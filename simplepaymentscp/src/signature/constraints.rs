@@ -1,35 +1,34 @@
-use ark_ff::Field;
-use ark_r1cs_std::prelude::*;
-use ark_relations::r1cs::SynthesisError;
+pub use ark_crypto_primitives::signature::constraints::{SigRandomizePkGadget, SigVerifyGadget};
 
 #[cfg(test)]
 mod test {
-    use crate::signature::{schnorr, schnorr::constraints::*, *};
-    use crate::signature::schnorr::Schnorr;
-    use crate::commitment::blake2s::Commitment;
-    use crate::commitment::blake2s::constraints::CommGadget;
-    use crate::commitment::CommitmentGadget;
-    use ark_bls12_381::Fr;
-    use ark_ec::{CurveGroup};
-    use ark_ed_on_bls12_381::{constraints::EdwardsVar as JubJubVar, EdwardsProjective as JubJub, Fq};
-    use ark_ff::{Field, PrimeField};
+    use crate::signature::eddsa::{
+        self,
+        constraints::{blake2b_512, EdDSAVerifyGadget, ParametersVar as EdDSAParamsVar, SignatureVar as EdDSASignatureVar},
+        EdDSA,
+    };
+    use crate::signature::schnorr::musig::constraints::KeyAggGadget;
+    use crate::signature::schnorr::{constraints::*, PublicKey, Schnorr, Signature};
+    use crate::signature::{SigRandomizePkGadget, SigVerifyGadget, SignatureScheme};
+    use ark_ec::CurveGroup;
+    use ark_ed_on_bls12_381::{constraints::EdwardsVar as JubJubVar, EdwardsProjective as JubJub, Fq, Fr};
+    use ark_ff::{BigInteger, Field, PrimeField};
     use ark_r1cs_std::prelude::*;
     use ark_relations::r1cs::ConstraintSystem;
-    use ark_std::test_rng;
+    use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+    use ark_std::{test_rng, UniformRand};
 
 
     type ConstraintF<C> = <<C as CurveGroup>::BaseField as Field>::BasePrimeField;
 
-    type VG = SchnorrSignatureVerifyGadget<JubJub, JubJubVar>;
-
     fn sign_and_verify<C: CurveGroup, GC: CurveVar<C, ConstraintF<C>>>(
         message: &[u8],
     ) {
         let rng = &mut test_rng();
         let parameters = Schnorr::<C>::setup::<_>(rng).unwrap();
         let (pk, sk) = Schnorr::<C>::keygen(&parameters, rng).unwrap();
-        let sig = Schnorr::<C>::sign(&parameters, &sk, &pk, &message, rng).unwrap();
-        assert!(Schnorr::<C>::verify(&parameters, &pk, &message, &sig).unwrap());
+        let sig = Schnorr::<C>::sign(&parameters, &sk, &pk, message, rng).unwrap();
+        assert!(Schnorr::<C>::verify(&parameters, &pk, message, &sig).unwrap());
 
         let cs = ConstraintSystem::<ConstraintF<C>>::new_ref();
 
@@ -38,20 +37,9 @@ mod test {
         let parameters_var = ParametersVar::<C,GC>::new_constant(cs.clone(), parameters).unwrap();
         let signature_var = SignatureVar::<C,GC>::new_witness(cs.clone(), || Ok(&sig)).unwrap();
         let pk_var = PublicKeyVar::<C,GC>::new_witness(cs.clone(), || Ok(&pk)).unwrap();
-        let mut msg_var = Vec::new();
-        for i in 0..message.len() {
-            msg_var.push(UInt8::new_witness(cs.clone(), || Ok(&message[i])).unwrap())
-        }
-
-        type TestCOMM = Commitment;
-        type TestCOMMGadget = CommGadget;
-
-        let b2s_parameters_var = <TestCOMMGadget as CommitmentGadget<TestCOMM, Fr>>::ParametersVar::new_witness(
-            ark_relations::ns!(cs, "gadget_parameters"), 
-            || Ok(&()), 
-        ).unwrap();
+        let msg_var = UInt8::new_witness_vec(cs.clone(), message).unwrap();
 
-        let valid_sig_var = SchnorrSignatureVerifyGadget::<C,GC>::verify(&parameters_var, &pk_var, &msg_var, &signature_var, &b2s_parameters_var).unwrap();
+        let valid_sig_var = SchnorrSignatureVerifyGadget::<C,GC>::verify(&parameters_var, &pk_var, &msg_var, &signature_var).unwrap();
         valid_sig_var.enforce_equal(&Boolean::<ConstraintF<C>>::TRUE).unwrap();
         assert!(cs.is_satisfied().unwrap());
     }
@@ -63,4 +51,290 @@ mod test {
             JubJub, JubJubVar
         >(message.as_bytes());
     }
-}
\ No newline at end of file
+
+    type Params = crate::signature::schnorr::Parameters<JubJub>;
+
+    /// Allocate `sig` as a witness and run the gadget, optionally letting `tamper`
+    /// rewrite the allocated signature. Returns whether the circuit accepts.
+    fn verify_in_circuit_with(
+        parameters: &Params,
+        pk: &PublicKey<JubJub>,
+        message: &[u8],
+        sig: &Signature<JubJub>,
+        tamper: impl FnOnce(&mut SignatureVar<JubJub, JubJubVar>),
+    ) -> bool {
+        let cs = ConstraintSystem::new_ref();
+        let parameters_var =
+            ParametersVar::<JubJub, JubJubVar>::new_constant(cs.clone(), parameters).unwrap();
+        let pk_var = PublicKeyVar::<JubJub, JubJubVar>::new_witness(cs.clone(), || Ok(pk)).unwrap();
+        let mut signature_var =
+            SignatureVar::<JubJub, JubJubVar>::new_witness(cs.clone(), || Ok(sig)).unwrap();
+        tamper(&mut signature_var);
+        let msg_var = UInt8::new_witness_vec(cs.clone(), message).unwrap();
+        let valid = SchnorrSignatureVerifyGadget::verify(&parameters_var, &pk_var, &msg_var, &signature_var)
+            .unwrap();
+        valid.enforce_equal(&Boolean::TRUE).unwrap();
+        cs.is_satisfied().unwrap()
+    }
+
+    fn verify_in_circuit(
+        parameters: &Params,
+        pk: &PublicKey<JubJub>,
+        message: &[u8],
+        sig: &Signature<JubJub>,
+    ) -> bool {
+        verify_in_circuit_with(parameters, pk, message, sig, |_| {})
+    }
+
+    /// Every vector must be accepted natively iff it is accepted in-circuit.
+    #[test]
+    fn native_and_gadget_agree_on_test_vectors() {
+        let rng = &mut test_rng();
+        let parameters = Schnorr::<JubJub>::setup(rng).unwrap();
+        let (pk, sk) = Schnorr::<JubJub>::keygen(&parameters, rng).unwrap();
+        let (other_pk, _) = Schnorr::<JubJub>::keygen(&parameters, rng).unwrap();
+
+        let messages: [&[u8]; 4] = [b"", b"\x00", b"Hi, I am a Schnorr signature!", &[0xa5; 97]];
+        for message in messages {
+            let sig = Schnorr::sign(&parameters, &sk, &pk, message, rng).unwrap();
+
+            let mut bad_response = sig.clone();
+            bad_response.prover_response += Fr::from(1u8);
+            let mut bad_challenge = sig.clone();
+            bad_challenge.verifier_challenge += Fr::from(1u8);
+            let mut bad_com = sig.clone();
+            bad_com.prover_com = (bad_com.prover_com + parameters.generator).into_affine();
+            let mut forged = sig.clone();
+            forged.prover_response = Fr::rand(rng);
+            forged.prover_com = (parameters.generator * forged.prover_response
+                + pk * forged.verifier_challenge)
+                .into_affine();
+
+            let vectors = [
+                (pk, message.to_vec(), sig.clone(), true),
+                (pk, [message, b"!"].concat(), sig.clone(), false),
+                (other_pk, message.to_vec(), sig.clone(), false),
+                (pk, message.to_vec(), bad_response, false),
+                (pk, message.to_vec(), bad_challenge, false),
+                (pk, message.to_vec(), bad_com, false),
+                (pk, message.to_vec(), forged, false),
+                (pk, message.to_vec(), Signature::default(), false),
+            ];
+            for (i, (pk, message, sig, expected)) in vectors.iter().enumerate() {
+                let native = Schnorr::verify(&parameters, pk, message, sig).unwrap();
+                let circuit = verify_in_circuit(&parameters, pk, message, sig);
+                assert_eq!(native, *expected, "native result for vector {}", i);
+                assert_eq!(circuit, native, "native and circuit disagree on vector {}", i);
+            }
+        }
+    }
+
+    /// The canonical encoding round-trips, and a signature decoded from it is
+    /// still accepted by both verifiers.
+    #[test]
+    fn canonical_encoding_round_trip() {
+        let rng = &mut test_rng();
+        let parameters = Schnorr::<JubJub>::setup(rng).unwrap();
+        let (pk, sk) = Schnorr::<JubJub>::keygen(&parameters, rng).unwrap();
+        let message = b"round trip";
+        let sig = Schnorr::sign(&parameters, &sk, &pk, message, rng).unwrap();
+
+        let mut bytes = Vec::new();
+        sig.serialize_compressed(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), 96);
+        let decoded = Signature::<JubJub>::deserialize_compressed(bytes.as_slice()).unwrap();
+        assert_eq!(decoded, sig);
+        assert!(Schnorr::verify(&parameters, &pk, message, &decoded).unwrap());
+        assert!(verify_in_circuit(&parameters, &pk, message, &decoded));
+    }
+
+    /// `s + r` and `e + r` act like `s` and `e` on prime-order points, but are not
+    /// canonical scalar encodings, so natively they cannot even be decoded. The
+    /// gadget must reject them as well.
+    #[test]
+    fn gadget_rejects_non_canonical_scalars() {
+        let rng = &mut test_rng();
+        let parameters = Schnorr::<JubJub>::setup(rng).unwrap();
+        let (pk, sk) = Schnorr::<JubJub>::keygen(&parameters, rng).unwrap();
+        let message = b"non-canonical";
+        let sig = Schnorr::sign(&parameters, &sk, &pk, message, rng).unwrap();
+
+        let mut shifted = sig.prover_response.into_bigint();
+        assert!(!shifted.add_with_carry(&Fr::MODULUS));
+        let shifted_bytes = shifted.to_bytes_le();
+        assert!(Fr::deserialize_uncompressed(shifted_bytes.as_slice()).is_err());
+
+        assert!(verify_in_circuit(&parameters, &pk, message, &sig));
+        assert!(!verify_in_circuit_with(&parameters, &pk, message, &sig, |sig_var| {
+            for (byte_var, byte) in sig_var.prover_response.iter_mut().zip(&shifted_bytes) {
+                *byte_var = UInt8::new_witness(byte_var.cs(), || Ok(*byte)).unwrap();
+            }
+        }));
+    }
+
+    #[test]
+    fn musig_aggregate_key_gadget_test() {
+        let rng = &mut test_rng();
+        let parameters = Schnorr::<JubJub>::setup(rng).unwrap();
+        let signers: Vec<_> = (0..3)
+            .map(|_| Schnorr::<JubJub>::keygen(&parameters, rng).unwrap())
+            .collect();
+        let message = b"Hi, we are a MuSig signature!";
+        let (context, sig) = crate::signature::test::musig_sign(&parameters, &signers, message, rng);
+
+        let verify_aggregated = |public_keys: &[PublicKey<JubJub>]| {
+            let cs = ConstraintSystem::new_ref();
+            let parameters_var =
+                ParametersVar::<JubJub, JubJubVar>::new_constant(cs.clone(), &parameters).unwrap();
+            let pk_vars = public_keys
+                .iter()
+                .map(|pk| PublicKeyVar::<JubJub, JubJubVar>::new_witness(cs.clone(), || Ok(pk)).unwrap())
+                .collect::<Vec<_>>();
+            let aggregate_key = KeyAggGadget::aggregate(&pk_vars).unwrap();
+            let matches_native = aggregate_key.pub_key.value().unwrap() == context.aggregate_key;
+            let signature_var =
+                SignatureVar::<JubJub, JubJubVar>::new_witness(cs.clone(), || Ok(&sig)).unwrap();
+            let msg_var = UInt8::new_witness_vec(cs.clone(), message).unwrap();
+            let valid = SchnorrSignatureVerifyGadget::verify(&parameters_var, &aggregate_key, &msg_var, &signature_var)
+                .unwrap()
+                .value()
+                .unwrap()
+                && cs.is_satisfied().unwrap();
+            (matches_native, valid)
+        };
+        assert_eq!(verify_aggregated(&context.public_keys), (true, true));
+        // The individual keys are committed to in order.
+        let mut swapped = context.public_keys.clone();
+        swapped.swap(0, 1);
+        assert_eq!(verify_aggregated(&swapped), (false, false));
+    }
+
+    fn eddsa_verify_in_circuit(
+        parameters: &eddsa::Parameters,
+        pk: &eddsa::PublicKey,
+        message: &[u8],
+        sig: &eddsa::Signature,
+    ) -> bool {
+        let cs = ConstraintSystem::new_ref();
+        let parameters_var = EdDSAParamsVar::new_constant(cs.clone(), parameters).unwrap();
+        // Allocating a point keeps only its prime-order component, so keys and
+        // signatures with other points cannot be represented.
+        let pk_var = JubJubVar::new_witness(cs.clone(), || Ok(*pk)).unwrap();
+        if pk_var.value().unwrap() != *pk {
+            return false;
+        }
+        let Ok(signature_var) = EdDSASignatureVar::new_witness(cs.clone(), || Ok(sig)) else {
+            return false;
+        };
+        let msg_var = UInt8::new_witness_vec(cs.clone(), message).unwrap();
+        let valid = EdDSAVerifyGadget::verify(&parameters_var, &pk_var, &msg_var, &signature_var).unwrap();
+        valid.enforce_equal(&Boolean::TRUE).unwrap();
+        cs.is_satisfied().unwrap()
+    }
+
+    #[test]
+    fn blake2b_gadget_matches_native() {
+        for len in [0, 1, 64, 127, 128, 129, 300] {
+            let input: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let cs = ConstraintSystem::<Fq>::new_ref();
+            let input_var = UInt8::new_witness_vec(cs.clone(), &input).unwrap();
+            let output = blake2b_512(eddsa::HASH_PERSONALIZATION, &input_var).unwrap();
+            assert_eq!(output.value().unwrap()[..], eddsa::blake2b_512(eddsa::HASH_PERSONALIZATION, &input)[..]);
+            assert!(cs.is_satisfied().unwrap());
+        }
+    }
+
+    /// Every vector must be accepted natively iff it is accepted in-circuit.
+    #[test]
+    fn eddsa_native_and_gadget_agree_on_test_vectors() {
+        use crate::signature::eddsa::test_vectors::{bytes, order_4_point, small_order_key_and_signature, TEST_VECTORS};
+        let parameters = EdDSA::setup().unwrap();
+        let (small_order_vk, forged) = small_order_key_and_signature(&parameters);
+        for tv in &TEST_VECTORS[..3] {
+            let vk = eddsa::decode_point(&bytes(tv.vk)).unwrap();
+            let rvk = eddsa::decode_point(&bytes(tv.rvk)).unwrap();
+            let message = bytes::<32>(tv.m).to_vec();
+            let sig = eddsa::Signature::from_bytes(&bytes(tv.sig));
+            let rsig = eddsa::Signature::from_bytes(&bytes(tv.rsig));
+            let mut bad_response = sig;
+            bad_response.s_bytes[0] ^= 1;
+            let mut bad_com = sig;
+            bad_com.r_bytes[31] ^= 0x80;
+            let mut mixed_com = sig;
+            let r = eddsa::decode_point(&sig.r_bytes).unwrap();
+            mixed_com.r_bytes = eddsa::encode_point(&(r + order_4_point()).into_affine());
+            let mixed_vk = (vk + order_4_point()).into_affine();
+
+            let vectors = [
+                (vk, message.clone(), sig, true),
+                (rvk, message.clone(), rsig, true),
+                (rvk, message.clone(), sig, false),
+                (vk, [&message[..], b"!"].concat(), sig, false),
+                (vk, message.clone(), bad_response, false),
+                (vk, message.clone(), bad_com, false),
+                (vk, message.clone(), mixed_com, false),
+                (mixed_vk, message.clone(), sig, false),
+                (small_order_vk, message, forged, false),
+            ];
+            for (i, (pk, message, sig, expected)) in vectors.iter().enumerate() {
+                let native = EdDSA::verify(&parameters, pk, message, sig);
+                let circuit = eddsa_verify_in_circuit(&parameters, pk, message, sig);
+                assert_eq!(native, *expected, "native result for vector {}", i);
+                assert_eq!(circuit, native, "native and circuit disagree on vector {}", i);
+            }
+        }
+    }
+
+    /// Verification written only against the upstream traits.
+    fn verify_generically<S: SignatureScheme, G: SigVerifyGadget<S, Fq>>(
+        parameters: &S::Parameters,
+        pk: &S::PublicKey,
+        message: &[u8],
+        sig: &S::Signature,
+    ) -> bool {
+        let cs = ConstraintSystem::new_ref();
+        let parameters_var = G::ParametersVar::new_constant(cs.clone(), parameters).unwrap();
+        let pk_var = G::PublicKeyVar::new_witness(cs.clone(), || Ok(pk)).unwrap();
+        let signature_var = G::SignatureVar::new_witness(cs.clone(), || Ok(sig)).unwrap();
+        let msg_var = UInt8::new_witness_vec(cs.clone(), message).unwrap();
+        G::verify(&parameters_var, &pk_var, &msg_var, &signature_var)
+            .unwrap()
+            .enforce_equal(&Boolean::TRUE)
+            .unwrap();
+        cs.is_satisfied().unwrap()
+    }
+
+    #[test]
+    fn schnorr_gadgets_through_traits() {
+        type S = Schnorr<JubJub>;
+        type G = SchnorrSignatureVerifyGadget<JubJub, JubJubVar>;
+        let rng = &mut test_rng();
+        let parameters = <S as SignatureScheme>::setup(rng).unwrap();
+        let (pk, sk) = <S as SignatureScheme>::keygen(&parameters, rng).unwrap();
+        let message = b"generic";
+        let sig = <S as SignatureScheme>::sign(&parameters, &sk, message, rng).unwrap();
+        assert!(verify_generically::<S, G>(&parameters, &pk, message, &sig));
+        assert!(!verify_generically::<S, G>(&parameters, &pk, b"other", &sig));
+
+        let mut randomness = Vec::new();
+        Fr::rand(rng).serialize_compressed(&mut randomness).unwrap();
+        let randomized_pk = S::randomize_public_key(&parameters, &pk, &randomness).unwrap();
+        let cs = ConstraintSystem::new_ref();
+        let parameters_var =
+            ParametersVar::<JubJub, JubJubVar>::new_constant(cs.clone(), &parameters).unwrap();
+        let pk_var = PublicKeyVar::<JubJub, JubJubVar>::new_witness(cs.clone(), || Ok(pk)).unwrap();
+        let randomness_var = UInt8::new_witness_vec(cs.clone(), &randomness).unwrap();
+        let randomized_pk_var = SchnorrRandomizePkGadget::<JubJub, JubJubVar>::randomize(
+            &parameters_var,
+            &pk_var,
+            &randomness_var,
+        )
+        .unwrap();
+        assert_eq!(randomized_pk_var.pub_key.value().unwrap(), randomized_pk);
+        assert!(cs.is_satisfied().unwrap());
+
+        let randomized_sk = S::randomize_secret_key(&sk, &randomness);
+        let sig = <S as SignatureScheme>::sign(&parameters, &randomized_sk, message, rng).unwrap();
+        assert!(verify_generically::<S, G>(&parameters, &randomized_pk, message, &sig));
+    }
+}
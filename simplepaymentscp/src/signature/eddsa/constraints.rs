@@ -0,0 +1,217 @@
+use ark_ed_on_bls12_381::{constraints::EdwardsVar, Fq, Fr};
+use ark_ff::{BigInteger, PrimeField};
+use ark_r1cs_std::bits::uint64::UInt64;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{Namespace, SynthesisError};
+use ark_std::vec::Vec;
+
+use core::borrow::Borrow;
+
+use super::{decode_point, Parameters, Signature, HASH_PERSONALIZATION};
+
+#[derive(Clone)]
+pub struct ParametersVar {
+    pub generator: EdwardsVar,
+}
+
+/// A signature `R || S`, with `R` allocated as a point and `S` as its 32 bytes.
+#[derive(Clone, Debug)]
+pub struct SignatureVar {
+    pub prover_com: EdwardsVar,
+    pub prover_response: Vec<UInt8<Fq>>,
+}
+
+/// `repr_J(point)`, matching `eddsa::encode_point`.
+pub fn encode_point(point: &EdwardsVar) -> Result<Vec<UInt8<Fq>>, SynthesisError> {
+    let mut bits = point.y.to_bits_le()?;
+    bits.push(point.x.to_bits_le()?[0].clone());
+    Ok(bits.chunks(8).map(UInt8::from_bits_le).collect())
+}
+
+const SIGMA: [[usize; 16]; 10] = [
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+    [14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+    [11, 8, 12, 0, 5, 2, 15, 13, 10, 14, 3, 6, 7, 1, 9, 4],
+    [7, 9, 3, 1, 13, 12, 11, 14, 2, 6, 5, 10, 4, 0, 15, 8],
+    [9, 0, 5, 7, 2, 4, 10, 15, 14, 1, 11, 12, 6, 8, 3, 13],
+    [2, 12, 6, 10, 0, 11, 8, 3, 4, 13, 7, 5, 15, 14, 1, 9],
+    [12, 5, 1, 15, 14, 13, 4, 10, 0, 7, 6, 3, 9, 2, 8, 11],
+    [13, 11, 7, 14, 12, 1, 3, 9, 5, 0, 15, 4, 8, 6, 2, 10],
+    [6, 15, 14, 9, 11, 3, 0, 8, 12, 2, 13, 7, 1, 4, 10, 5],
+    [10, 2, 8, 4, 7, 6, 1, 5, 15, 11, 9, 14, 3, 12, 13, 0],
+];
+
+const IV: [u64; 8] = [
+    0x6a09e667f3bcc908,
+    0xbb67ae8584caa73b,
+    0x3c6ef372fe94f82b,
+    0xa54ff53a5f1d36f1,
+    0x510e527fade682d1,
+    0x9b05688c2b3e6c1f,
+    0x1f83d9abfb41bd6b,
+    0x5be0cd19137e2179,
+];
+
+fn mixing_g(
+    v: &mut [UInt64<Fq>],
+    (a, b, c, d): (usize, usize, usize, usize),
+    x: &UInt64<Fq>,
+    y: &UInt64<Fq>,
+) -> Result<(), SynthesisError> {
+    v[a] = UInt64::addmany(&[v[a].clone(), v[b].clone(), x.clone()])?;
+    v[d] = v[d].xor(&v[a])?.rotr(32);
+    v[c] = UInt64::addmany(&[v[c].clone(), v[d].clone()])?;
+    v[b] = v[b].xor(&v[c])?.rotr(24);
+    v[a] = UInt64::addmany(&[v[a].clone(), v[b].clone(), y.clone()])?;
+    v[d] = v[d].xor(&v[a])?.rotr(16);
+    v[c] = UInt64::addmany(&[v[c].clone(), v[d].clone()])?;
+    v[b] = v[b].xor(&v[c])?.rotr(63);
+    Ok(())
+}
+
+fn blake2b_compression(
+    h: &mut [UInt64<Fq>],
+    m: &[UInt64<Fq>],
+    t: u64,
+    f: bool,
+) -> Result<(), SynthesisError> {
+    let mut v = h.to_vec();
+    v.extend(IV.iter().map(|word| UInt64::constant(*word)));
+    // Inputs are far shorter than 2^64 bytes, so the high word of the counter is 0.
+    v[12] = v[12].xor(&UInt64::constant(t))?;
+    if f {
+        v[14] = v[14].xor(&UInt64::constant(u64::MAX))?;
+    }
+    for i in 0..12 {
+        let s = SIGMA[i % 10];
+        mixing_g(&mut v, (0, 4, 8, 12), &m[s[0]], &m[s[1]])?;
+        mixing_g(&mut v, (1, 5, 9, 13), &m[s[2]], &m[s[3]])?;
+        mixing_g(&mut v, (2, 6, 10, 14), &m[s[4]], &m[s[5]])?;
+        mixing_g(&mut v, (3, 7, 11, 15), &m[s[6]], &m[s[7]])?;
+        mixing_g(&mut v, (0, 5, 10, 15), &m[s[8]], &m[s[9]])?;
+        mixing_g(&mut v, (1, 6, 11, 12), &m[s[10]], &m[s[11]])?;
+        mixing_g(&mut v, (2, 7, 8, 13), &m[s[12]], &m[s[13]])?;
+        mixing_g(&mut v, (3, 4, 9, 14), &m[s[14]], &m[s[15]])?;
+    }
+    for i in 0..8 {
+        h[i] = h[i].xor(&v[i])?.xor(&v[i + 8])?;
+    }
+    Ok(())
+}
+
+/// BLAKE2b-512 with an empty key and salt and the given personalization.
+pub fn blake2b_512(
+    personalization: &[u8; 16],
+    input: &[UInt8<Fq>],
+) -> Result<Vec<UInt8<Fq>>, SynthesisError> {
+    let mut parameters = [0u64; 8];
+    parameters[0] = 0x0101_0000 ^ 64;
+    parameters[6] = u64::from_le_bytes(personalization[..8].try_into().unwrap());
+    parameters[7] = u64::from_le_bytes(personalization[8..].try_into().unwrap());
+    let mut h: Vec<_> = IV
+        .iter()
+        .zip(&parameters)
+        .map(|(iv, p)| UInt64::constant(iv ^ p))
+        .collect();
+
+    let bits = input.to_bits_le()?;
+    let mut blocks: Vec<Vec<UInt64<Fq>>> = bits
+        .chunks(1024)
+        .map(|block| {
+            let mut words: Vec<_> = block
+                .chunks(64)
+                .map(|word| {
+                    let mut word = word.to_vec();
+                    word.resize(64, Boolean::FALSE);
+                    UInt64::from_bits_le(&word)
+                })
+                .collect();
+            words.resize(16, UInt64::constant(0));
+            words
+        })
+        .collect();
+    if blocks.is_empty() {
+        blocks.push(vec![UInt64::constant(0); 16]);
+    }
+
+    let (last, init) = blocks.split_last().unwrap();
+    for (i, block) in init.iter().enumerate() {
+        blake2b_compression(&mut h, block, (i as u64 + 1) * 128, false)?;
+    }
+    blake2b_compression(&mut h, last, input.len() as u64, true)?;
+
+    let output_bits: Vec<_> = h.iter().flat_map(|word| word.to_bits_le()).collect();
+    Ok(output_bits.chunks(8).map(UInt8::from_bits_le).collect())
+}
+
+pub struct EdDSAVerifyGadget;
+
+impl EdDSAVerifyGadget {
+    /// Verify `signature` on `message` under `public_key`, mirroring `EdDSA::verify`:
+    /// `S` must be a canonical scalar, and
+    /// `[8] (-[S] P_G + R + [H*(R || vk || message)] vk)` must be the identity.
+    ///
+    /// The challenge is used as the unreduced 512-bit digest; this adds a multiple
+    /// of `r_J · vk` that the cofactor multiplication removes.
+    pub fn verify(
+        parameters: &ParametersVar,
+        public_key: &EdwardsVar,
+        message: &[UInt8<Fq>],
+        signature: &SignatureVar,
+    ) -> Result<Boolean<Fq>, SynthesisError> {
+        let response = signature.prover_response.to_bits_le()?;
+        let mut modulus_minus_one = Fr::MODULUS;
+        modulus_minus_one.sub_with_borrow(&1u64.into());
+        Boolean::enforce_smaller_or_equal_than_le(&response, modulus_minus_one)?;
+
+        let mut hash_input = encode_point(&signature.prover_com)?;
+        hash_input.extend(encode_point(public_key)?);
+        hash_input.extend_from_slice(message);
+        let challenge = blake2b_512(HASH_PERSONALIZATION, &hash_input)?.to_bits_le()?;
+
+        let mut check = signature.prover_com.clone()
+            + public_key.scalar_mul_le(challenge.iter())?
+            - parameters.generator.scalar_mul_le(response.iter())?;
+        for _ in 0..3 {
+            check.double_in_place()?;
+        }
+        check.is_zero()
+    }
+}
+
+impl AllocVar<Parameters, Fq> for ParametersVar {
+    fn new_variable<T: Borrow<Parameters>>(
+        cs: impl Into<Namespace<Fq>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        let generator = EdwardsVar::new_variable(cs, || f().map(|p| p.borrow().generator), mode)?;
+        Ok(Self { generator })
+    }
+}
+
+impl AllocVar<Signature, Fq> for SignatureVar {
+    /// Fails with `AssignmentMissing` if `R` is not the canonical encoding of a
+    /// point of the prime-order subgroup, since such a signature cannot be
+    /// represented in the circuit: allocating a point keeps only its
+    /// prime-order component.
+    fn new_variable<T: Borrow<Signature>>(
+        cs: impl Into<Namespace<Fq>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        f().and_then(|signature| {
+            let cs = cs.into();
+            let signature = signature.borrow();
+            let prover_com = decode_point(&signature.r_bytes)
+                .filter(|point| point.is_in_correct_subgroup_assuming_on_curve())
+                .ok_or(SynthesisError::AssignmentMissing)?;
+            let prover_com = EdwardsVar::new_variable(cs.clone(), || Ok(prover_com), mode)?;
+            let prover_response = Vec::new_variable(cs, || Ok(&signature.s_bytes[..]), mode)?;
+            Ok(Self {
+                prover_com,
+                prover_response,
+            })
+        })
+    }
+}
@@ -0,0 +1,220 @@
+//! EdDSA over JubJub, following RedJubjub as specified in section 5.4.7 of the
+//! Zcash protocol specification (version 2023.4.0 and later, including ZIP 216).
+//! Keys and signatures are therefore interchangeable with those of Zcash
+//! spend-authorization signatures, and existing RedJubjub implementations can sign
+//! for accounts using this scheme.
+//!
+//! * The generator `P_G` is `FindGroupHash^J*("Zcash_G_", "")`.
+//! * `H*(B)` is `BLAKE2b-512("Zcash_RedJubjubH", B)` read as a little-endian
+//!   integer, reduced modulo the group order `r_J`.
+//! * A signature is `R || S`, with `R = [r] P_G` and `S = r + H*(R || vk || M) · sk`.
+//! * Verification checks `[8] (-[S] P_G + R + [H*(R || vk || M)] vk) = O`.
+//!
+//! Unlike the specification, verification also requires `R` and `vk` to lie in
+//! the prime-order subgroup. The gadget can only hold such points, so accepting
+//! points with a small-order component would make native and in-circuit
+//! verification disagree. Honest signers never produce them.
+//!
+//! Points are encoded as in the specification: the 255-bit little-endian
+//! `v`-coordinate, with the low bit of the `u`-coordinate as bit 255. Only canonical
+//! encodings are accepted.
+use ark_crypto_primitives::Error;
+use ark_ec::{twisted_edwards::TECurveConfig, AffineRepr, CurveGroup};
+use ark_ed_on_bls12_381::{EdwardsAffine, Fq, Fr, JubjubConfig};
+use ark_ff::{BigInteger, Field, PrimeField, UniformRand, Zero};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use ark_std::rand::Rng;
+use ark_std::{ops::Mul, vec::Vec};
+use blake2::digest::core_api::{Buffer, UpdateCore, VariableOutputCore};
+use blake2::{Blake2bVarCore, Blake2sVarCore};
+
+pub mod constraints;
+#[cfg(test)]
+pub(crate) mod test_vectors;
+
+/// BLAKE2b personalization of the hash `H*`.
+pub const HASH_PERSONALIZATION: &[u8; 16] = b"Zcash_RedJubjubH";
+/// BLAKE2s personalization from which the generator `P_G` is derived.
+pub const GENERATOR_PERSONALIZATION: &[u8; 8] = b"Zcash_G_";
+/// The uniform random string prefixed to every input of `GroupHash^J*`.
+pub const GROUP_HASH_URS: &[u8; 64] =
+    b"096b36a5804bfacef1691e173c366a47ff5ba84a44f26ddd7e8d9f79d5b42df0";
+
+pub struct EdDSA;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Parameters {
+    pub generator: EdwardsAffine,
+}
+
+pub type PublicKey = EdwardsAffine;
+
+#[derive(Clone, Default, Debug)]
+pub struct SecretKey(pub Fr);
+
+/// A signature `R || S`, kept in its 64-byte wire encoding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Signature {
+    /// The encoding of the nonce commitment `R`.
+    pub r_bytes: [u8; 32],
+    /// The little-endian encoding of the response `S`.
+    pub s_bytes: [u8; 32],
+}
+
+/// The signature with `R = O` and `S = 0`, which verifies only under the
+/// identity key.
+impl Default for Signature {
+    fn default() -> Self {
+        Self {
+            r_bytes: encode_point(&EdwardsAffine::zero()),
+            s_bytes: [0u8; 32],
+        }
+    }
+}
+
+impl Signature {
+    pub fn to_bytes(&self) -> [u8; 64] {
+        let mut bytes = [0u8; 64];
+        bytes[..32].copy_from_slice(&self.r_bytes);
+        bytes[32..].copy_from_slice(&self.s_bytes);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; 64]) -> Self {
+        let mut signature = Self::default();
+        signature.r_bytes.copy_from_slice(&bytes[..32]);
+        signature.s_bytes.copy_from_slice(&bytes[32..]);
+        signature
+    }
+}
+
+/// BLAKE2 with an empty key and salt and the given personalization of at most
+/// 16 (BLAKE2b) or 8 (BLAKE2s) bytes.
+macro_rules! personalized_blake2 {
+    ($name:ident, $core:ty, $len:expr) => {
+        pub fn $name(personalization: &[u8], input: &[u8]) -> [u8; $len] {
+            let mut core = <$core>::new_with_params(&[], personalization, 0, $len);
+            let mut buffer = Buffer::<$core>::default();
+            buffer.digest_blocks(input, |blocks| core.update_blocks(blocks));
+            let mut output = Default::default();
+            core.finalize_variable_core(&mut buffer, &mut output);
+            output.into()
+        }
+    };
+}
+personalized_blake2!(blake2b_512, Blake2bVarCore, 64);
+personalized_blake2!(blake2s_256, Blake2sVarCore, 32);
+
+/// `H*(input)`.
+pub fn hash_to_scalar(input: &[u8]) -> Fr {
+    Fr::from_le_bytes_mod_order(&blake2b_512(HASH_PERSONALIZATION, input))
+}
+
+/// `repr_J(point)`.
+pub fn encode_point(point: &EdwardsAffine) -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    point
+        .y
+        .serialize_uncompressed(&mut bytes[..])
+        .expect("a base field element fits in 32 bytes");
+    bytes[31] |= (point.x.into_bigint().is_odd() as u8) << 7;
+    bytes
+}
+
+/// `abst_J(bytes)`: the point with the canonical encoding `bytes`, if any.
+pub fn decode_point(bytes: &[u8; 32]) -> Option<EdwardsAffine> {
+    let u_is_odd = bytes[31] >> 7 == 1;
+    let mut v_bytes = *bytes;
+    v_bytes[31] &= 0x7f;
+    // Rejects `v >= q`.
+    let v = Fq::deserialize_uncompressed(&v_bytes[..]).ok()?;
+    // a · u^2 + v^2 = 1 + d · u^2 · v^2, so u^2 = (1 - v^2) / (a - d · v^2).
+    let v2 = v.square();
+    let denominator = JubjubConfig::COEFF_A - JubjubConfig::COEFF_D * v2;
+    let u = ((Fq::ONE - v2) * denominator.inverse()?).sqrt()?;
+    if u.is_zero() && u_is_odd {
+        return None;
+    }
+    let u = if u.into_bigint().is_odd() == u_is_odd { u } else { -u };
+    Some(EdwardsAffine::new_unchecked(u, v))
+}
+
+/// `FindGroupHash^J*(personalization, message)`: the first point
+/// `[8] abst_J(BLAKE2s(personalization, URS || message || i))` for `i = 0, 1, ...`
+/// that is not the identity.
+pub fn find_group_hash(personalization: &[u8; 8], message: &[u8]) -> Option<EdwardsAffine> {
+    (0..=u8::MAX).find_map(|i| {
+        let mut input = GROUP_HASH_URS.to_vec();
+        input.extend_from_slice(message);
+        input.push(i);
+        let point = decode_point(&blake2s_256(personalization, &input))?.mul_by_cofactor();
+        (!point.is_zero()).then_some(point)
+    })
+}
+
+impl EdDSA {
+    /// The parameters of the specification, i.e. the spend-authorization generator.
+    pub fn setup() -> Result<Parameters, Error> {
+        let generator = find_group_hash(GENERATOR_PERSONALIZATION, b"")
+            .ok_or("no generator found for the personalization")?;
+        Ok(Parameters { generator })
+    }
+
+    pub fn keygen<R: Rng>(parameters: &Parameters, rng: &mut R) -> (PublicKey, SecretKey) {
+        let secret_key = Fr::rand(rng);
+        (parameters.generator.mul(secret_key).into_affine(), SecretKey(secret_key))
+    }
+
+    /// `H*(R || vk || message)`.
+    pub fn challenge(r_bytes: &[u8; 32], pk: &PublicKey, message: &[u8]) -> Fr {
+        let mut input = r_bytes.to_vec();
+        input.extend_from_slice(&encode_point(pk));
+        input.extend_from_slice(message);
+        hash_to_scalar(&input)
+    }
+
+    pub fn sign<R: Rng>(
+        parameters: &Parameters,
+        sk: &SecretKey,
+        message: &[u8],
+        rng: &mut R,
+    ) -> Signature {
+        let pk = parameters.generator.mul(sk.0).into_affine();
+        // r = H*(T || vk || M) for 80 random bytes T.
+        let mut nonce_input = (0..80).map(|_| rng.gen()).collect::<Vec<u8>>();
+        nonce_input.extend_from_slice(&encode_point(&pk));
+        nonce_input.extend_from_slice(message);
+        let nonce = hash_to_scalar(&nonce_input);
+
+        let r_bytes = encode_point(&parameters.generator.mul(nonce).into_affine());
+        let response = nonce + Self::challenge(&r_bytes, &pk, message) * sk.0;
+        let mut s_bytes = [0u8; 32];
+        response
+            .serialize_uncompressed(&mut s_bytes[..])
+            .expect("a scalar fits in 32 bytes");
+        Signature { r_bytes, s_bytes }
+    }
+
+    pub fn verify(
+        parameters: &Parameters,
+        pk: &PublicKey,
+        message: &[u8],
+        signature: &Signature,
+    ) -> bool {
+        let prover_com = match decode_point(&signature.r_bytes) {
+            Some(point) if point.is_in_correct_subgroup_assuming_on_curve() => point,
+            _ => return false,
+        };
+        if !pk.is_on_curve() || !pk.is_in_correct_subgroup_assuming_on_curve() {
+            return false;
+        }
+        // Rejects `S >= r_J`.
+        let response = match Fr::deserialize_uncompressed(&signature.s_bytes[..]) {
+            Ok(response) => response,
+            Err(_) => return false,
+        };
+        let challenge = Self::challenge(&signature.r_bytes, pk, message);
+        let check = prover_com + pk.mul(challenge) - parameters.generator.mul(response);
+        check.into_affine().mul_by_cofactor().is_zero()
+    }
+}
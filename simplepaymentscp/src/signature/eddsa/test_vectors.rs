@@ -0,0 +1,141 @@
+//! The RedJubjub test vectors of
+//! <https://github.com/zcash/zcash-test-vectors/blob/master/zcash_test_vectors/sapling/redjubjub.py>.
+//! `sig` is a signature on `m` under `vk = [sk] P_G`, and `rsig` is one on `m`
+//! under the key `rvk = vk + [alpha] P_G`, randomized as for spend authorization.
+
+use super::{decode_point, encode_point, Parameters, PublicKey, Signature};
+use ark_ec::{AffineRepr, CurveGroup};
+use ark_ed_on_bls12_381::{EdwardsAffine, Fq, Fr};
+use ark_ff::{Field, UniformRand};
+use ark_serialize::CanonicalSerialize;
+
+pub struct TestVector {
+    pub sk: &'static str,
+    pub vk: &'static str,
+    pub alpha: &'static str,
+    pub rvk: &'static str,
+    pub m: &'static str,
+    pub sig: &'static str,
+    pub rsig: &'static str,
+}
+
+/// The bytes of a hex string.
+pub fn bytes<const N: usize>(hex: &str) -> [u8; N] {
+    let bytes: Vec<u8> = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect();
+    bytes.try_into().unwrap()
+}
+
+pub const TEST_VECTORS: [TestVector; 10] = [
+    TestVector {
+        sk: "18e28dea5c11817aeeb21a19981d28368ec438afc25a8db94ebe08d7a0288e09",
+        vk: "9b0153b03d320fe23e2834d5d61dbb1f519b3f41f8f946152bf0c3f247d11807",
+        alpha: "ffd1a1273252b187f4ed326dfc98853e2917c2b36379b175da63b9ef6dda6c08",
+        rvk: "c1babcb6eae2b994ee6d65c10b9dad5940dc735b07504daed1e46b0709b45136",
+        m: "0000000000000000000000000000000000000000000000000000000000000000",
+        sig: "dca3bb2cb8f048ccab10aed77546c1dbb10cc4fb15ab02acaef944ddab8b6722545fda4c62046d69d98f922f4e8c210bc47b4fdde0a1947179804c1ace569005",
+        rsig: "70c284504e90f0008e8ed2208f4969727a415ec3102c299e398b6c16572bd9643ee1011766681e406ee6bee3d03ee8f27176e32fbabdded20b0d1786a4ee1801",
+    },
+    TestVector {
+        sk: "059654f961273dafda3b2677b35c18af6b11adfb9ee90b48935e557c8d5d9c04",
+        vk: "faf6c3b737e8e611aafea52f03bb2786e18353ebe0d3139e3c54498780c8c199",
+        alpha: "c30b96208da800e10af02542ce694b7ed76a28299f85998e5d610812681bf003",
+        rvk: "d524dce7734069758a91f007a869505dfc4aba1720594d4d74f007700e62ee00",
+        m: "0101010101010101010101010101010101010101010101010101010101010101",
+        sig: "b5a1f32d3d50fc738b5c3b4e9960729ce4316ba7721a12686604feba6bd748450070cb922406fdfc5d60dea9be3a526a16cfeb877779fb782d5d41395b455f04",
+        rsig: "5a5a20d200efddd498dfae2a9ef8cf01281a8919018a824cc7a4983b9a0d4a06ff172079e013d42a2a3a88a6520c86fce3b98e1efaa325832a6a5658d8dd7c0a",
+    },
+    TestVector {
+        sk: "ade7abb551c79d0f0e42ef7f1206b87712a84a61dea3f37b42496d7efd12520c",
+        vk: "369ea751762f839d25701a5eeb551ec4f06c1290b3b9c3a724402dec02739221",
+        alpha: "81922529a63ee743fc4fbbac45c4988316bc9b6e428b01a8d31fc1c2a6ca6205",
+        rvk: "0d92ad6d46edacd023d4d2ef703a6ca0a792cfc4b7da11c2353bc845a27a974d",
+        m: "0202020202020202020202020202020202020202020202020202020202020202",
+        sig: "1f3e8a94310c2071a70f9df5e79aa9e8485deccb178bdff9805fcbe6f7d551eee3c3542ca75c9d8d4adc54d72c3dbe28626d20785bb7f588c1a582b893dbb601",
+        rsig: "d136214c5d528ea3d4cb7b631a6bb036064973a108b733a5e3a452ab52a659e567cb55d2644e74b6e8426f2a7dd2a04d2dda4935cc3820b77a9c1ab619863c05",
+    },
+    TestVector {
+        sk: "c9d2ae1f6d32a675d09eb0823f467fa921b3284acb35fabdfc994de549b8590d",
+        vk: "2d2f316e5c369ae4dd2c825f3d86460058407184603b212cf3459f36c8697fd8",
+        alpha: "ebbc89031107c44f47889ed4d4375a4114cf8a75dd33b962f2d759d3f4c6df06",
+        rvk: "f0430e953be60bf438dbdcc2303f0e32a6f7ce2fbedfb13ac518f75a3fd10eb5",
+        m: "0303030303030303030303030303030303030303030303030303030303030303",
+        sig: "12c78ddd20d30a61f8930c6fe0850fd112bb7be88b1238ea33d6bef881c102d104aa36544a78471c9e2842e6fd42558346cff43127032666eb116f442a28480c",
+        rsig: "01baaa26274c149acf12e1ccf5507d56790482f067e5c92b3219ad6bf91118cc3fce8d2a23198a3b290a7bf68c2ac07b5d9062b9f868662bb2524912d4856e0c",
+    },
+    TestVector {
+        sk: "33bcd2864541b8bb7fdc77a19d970f924eaeecf4103c38c8d2b0668142f27d09",
+        vk: "741794e62cf9320c58bac594a2b90e340a6d8a68056f6ed5c7868c5ff3e4d616",
+        alpha: "7ce725a5fef61bd4a1e9c77328e8210eb7292d954c64e99e8bedd07ab3ab0e0d",
+        rvk: "6662ba09950accd2cea3c7a81290cd5978a62b5ac5bbc48d9f5819cdc9646f0a",
+        m: "0404040404040404040404040404040404040404040404040404040404040404",
+        sig: "774ac4673f09f3ac5789b286b5eecbedb257234e8cdfd93f02890978a6bba61169ed48f9e1c9fd1319bd330d2cf5b491010d69b043f4648bff554162c6a6dc09",
+        rsig: "7c6c498de001786109b303a4c5dcb7fd075750a0b9df5e1e2a8e7547b7ed70cc0b56a5bfa9657843efd89c66a84f41d2b1b50751196b1e8c0c4498600696a404",
+    },
+    TestVector {
+        sk: "ca3506d6af7767b5790ef0c5190fb3f3877c4aab40e0dd651abbdacb544ed005",
+        vk: "bab6cfb5c8ea3491251b46d52aca25d9e9af69faa9b4e40b03ad0086de59b51f",
+        alpha: "bea387203f43760ad37d61de0eb59fca6cab7560df64fabb9511579f6f682606",
+        rvk: "11267d14d5e0b2bb3ce099e8ef8449471cbcfc6939a4b348dea2c17356a1e8dd",
+        m: "0505050505050505050505050505050505050505050505050505050505050505",
+        sig: "9a25429f3efd9b2f7de29e45128dd7b760f0508cd9582182abaf53dd76c0342ce41b4acf8e0a4824e41108c2026573114b60beecb174012a2bdbeecbaa00b506",
+        rsig: "cff5835713be07fbe125bbf27a636add131c9081716c52fda875426d03982cd27ebd14b4227b839615fd0371bfdb8a30abddff74d795f3e27d1d47c629469b08",
+    },
+    TestVector {
+        sk: "bc27838de2a614cfba6c3e922a8f8424d9856f6816f3bc6102313b7faf5c3a0c",
+        vk: "d79be9ff229a2e35f5bca448e5eb4a8aa97fb418029125cfbaa78a91a382b094",
+        alpha: "21a7150e194fedfef90c5d10e420858bca4004040eb681d14e75c4471351cb02",
+        rvk: "54bf1be72e6d41208b8aec1161d3ba59519fb93da01a55e678e27520066036c9",
+        m: "0606060606060606060606060606060606060606060606060606060606060606",
+        sig: "bbe0235987c6e0ec686ddb8a657266ad605f7b75955bb0e802f88164a0ffe10c3b738504abb3d10562b927b3d29fe9b0d356286aeae5a2ac9e435f20791af800",
+        rsig: "6de32b5415d77a905f0903902a117eda793c708e23a54245ba8a8d1fe0267523231565e05709aed96c221fb1f3d042043503ff338585a9bb989c9dd430d6d60b",
+    },
+    TestVector {
+        sk: "b20859b88ee3338a64954f8a9e8e9bf3e7115acf7c6e7f01432c5f7696d2d005",
+        vk: "a81fe6846dbe0a75c0f49b213232beadd1f9a564673d25b91ee0f17ce9caa363",
+        alpha: "44d908e1c15e6bd9380a8b235ace02fac1c08794454bcdb4a6f48cea78a74a04",
+        rvk: "0b68d50f913cd1b78b59921e1656d576b0eb171ed3870d39fec69441b34b2538",
+        m: "0707070707070707070707070707070707070707070707070707070707070707",
+        sig: "446d677c4cfefd024b0aeb37a598cc2eb3d29b0294fe5bb6978e8b43d32b2e4f0956acd13e7e3a63a18fca32d6ab94b94ed033e9a10fc56928bc8a0f4f8e9500",
+        rsig: "8de041e709db624ae2be1648b662239cdedf85ecd382268b0e3554bfa0f2081cd641bca04078aa89f7dd2540587ced6b458916b13e4b6a3630da697646dbbf09",
+    },
+    TestVector {
+        sk: "3216ae47e9f53e8a52796f24b62460776bd5f205a78e1595bc8efedc519d360b",
+        vk: "df74bf047961cc5cdac82890c76ec675bd4e89ead280c952d7c33eeaf2b5a66b",
+        alpha: "c961f2dd93682adb93f5c05a73fdbc6d43c70e1b15e8d53e3f17a82494e3f209",
+        rvk: "0afbe406a891c3b8c310c215bc68a913de7cda06af29420056468d0c08855b28",
+        m: "0808080808080808080808080808080808080808080808080808080808080808",
+        sig: "993580ef93349a1c9ee960ca3e7cd04c13b4a0ec4fd18053a19cff7763620965fbee96c1647230e373cb82b81d00039223d30b393ed172c9b3c563c611792205",
+        rsig: "cc7aae1cedad2d7f6ce04c19c5a5b6b7a6a082785c540c14f6309b064d1ffa68172953fba0c2fcfb875ca7f7ea98ef55a0402fd529cfcddf996ca2b8ca89900a",
+    },
+    TestVector {
+        sk: "85836f9832b28de7c63613e2a6ed36fb1ab44fb0c13fa8798cd9cd3030d45503",
+        vk: "bfd5bc00c7c022aa8901ae083c12d54b82f0ddff8ed6db9a12d59a5ef6a5a2e0",
+        alpha: "a2e8b9e16d6ff3ca6c53d4e88abbb99be7af7e3659631f1eae1eff23874d8e0c",
+        rvk: "eb9297036cf517e15e9efe3975328db48ee7c2694e946db25f528788f6a1db14",
+        m: "0909090909090909090909090909090909090909090909090909090909090909",
+        sig: "ce90ddf4af21aac4d94193ea16ff35cd9379204e7d8ff4c0f54117abb16b7c85a0b197cf13ab14d7c3ba68010ab8051225913bdbc39a51f6037afc6ceecb0b06",
+        rsig: "a847742e9401cf2239213dc8813e9772e97af8d67adffeabc8e67f5d2d90d0b41bc25b05f94ace168aecc6583e18f7637492f37a9ca300202bc065abd380ec00",
+    },
+];
+
+/// A point of order 4: `v = 0` gives `u^2 = 1 / a = -1`.
+pub fn order_4_point() -> EdwardsAffine {
+    let point = decode_point(&[0u8; 32]).unwrap();
+    assert!(!point.is_zero() && point.mul_by_cofactor().is_zero());
+    point
+}
+
+/// The key `(0, -1)` of order 2, and a signature that the check
+/// `[8] (-[S] P_G + R + [c] vk) = O` alone accepts under it for every message:
+/// `[8] [c] vk` vanishes, so `R = [S] P_G` suffices.
+pub fn small_order_key_and_signature(parameters: &Parameters) -> (PublicKey, Signature) {
+    let key = EdwardsAffine::new_unchecked(Fq::ZERO, -Fq::ONE);
+    let s = Fr::rand(&mut ark_std::test_rng());
+    let mut s_bytes = [0u8; 32];
+    s.serialize_uncompressed(&mut s_bytes[..]).unwrap();
+    let r_bytes = encode_point(&(parameters.generator * s).into_affine());
+    (key, Signature { r_bytes, s_bytes })
+}
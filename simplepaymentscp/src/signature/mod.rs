@@ -1,66 +1,299 @@
-use ark_crypto_primitives::Error;
-use ark_serialize::CanonicalSerialize;
-use ark_std::hash::Hash;
-use ark_std::rand::Rng;
-
 //#[cfg(feature = "r1cs")]
 pub mod constraints;
 //#[cfg(feature = "r1cs")]
 pub use constraints::*;
+pub use ark_crypto_primitives::signature::SignatureScheme;
 
+pub mod eddsa;
 pub mod schnorr;
 
 #[cfg(test)]
 mod test {
-    use crate::signature::{schnorr, *};
-    use crate::signature::schnorr::Schnorr;
-    //use ark_ec::AdditiveGroup;
+    use crate::signature::eddsa::{self, EdDSA};
+    use crate::signature::schnorr::musig::{self, KeyAggContext, PublicNonce, SigningSession};
+    use crate::signature::schnorr::{Parameters, PublicKey, Schnorr, SecretKey, Signature};
+    use crate::signature::SignatureScheme;
     use ark_ec::CurveGroup;
-    use ark_ed_on_bls12_381::EdwardsProjective as JubJub;
-    use ark_std::{test_rng, vec::Vec, UniformRand};
+    use ark_ed_on_bls12_381::{EdwardsProjective as JubJub, Fr};
+    use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+    use ark_std::rand::Rng;
+    use ark_std::{hash::Hash, test_rng, UniformRand};
 
-    fn sign_and_verify<C: CurveGroup>(message: &[u8]) {
+    fn sign_and_verify<S: SignatureScheme>(message: &[u8]) {
         let rng = &mut test_rng();
-        let parameters = Schnorr::<C>::setup::<_>(rng).unwrap();
-        let (pk, sk) = Schnorr::<C>::keygen(&parameters, rng).unwrap();
-        let sig = Schnorr::<C>::sign(&parameters, &sk, &pk, &message, rng).unwrap();
-        assert!(Schnorr::<C>::verify(&parameters, &pk, &message, &sig).unwrap());
+        let parameters = S::setup::<_>(rng).unwrap();
+        let (pk, sk) = S::keygen(&parameters, rng).unwrap();
+        let sig = S::sign(&parameters, &sk, message, rng).unwrap();
+        assert!(S::verify(&parameters, &pk, message, &sig).unwrap());
     }
 
-    fn failed_verification<C: CurveGroup>(message: &[u8], bad_message: &[u8]) {
+    fn failed_verification<S: SignatureScheme>(message: &[u8], bad_message: &[u8]) {
         let rng = &mut test_rng();
-        let parameters = Schnorr::<C>::setup::<_>(rng).unwrap();
-        let (pk, sk) = Schnorr::<C>::keygen(&parameters, rng).unwrap();
-        let sig = Schnorr::<C>::sign(&parameters, &sk, &pk, message, rng).unwrap();
-        assert!(!Schnorr::<C>::verify(&parameters, &pk, bad_message, &sig).unwrap());
+        let parameters = S::setup::<_>(rng).unwrap();
+        let (pk, sk) = S::keygen(&parameters, rng).unwrap();
+        let sig = S::sign(&parameters, &sk, message, rng).unwrap();
+        assert!(!S::verify(&parameters, &pk, bad_message, &sig).unwrap());
+    }
+
+    /// A signature carries over to a randomized key, and the randomized secret
+    /// key signs for that key directly.
+    fn randomize_and_verify<C: CurveGroup + Hash>(message: &[u8], randomness: &[u8]) {
+        type S<C> = Schnorr<C>;
+        let rng = &mut test_rng();
+        let parameters = <S<C> as SignatureScheme>::setup::<_>(rng).unwrap();
+        let (pk, sk) = <S<C> as SignatureScheme>::keygen(&parameters, rng).unwrap();
+        let sig = <S<C> as SignatureScheme>::sign(&parameters, &sk, message, rng).unwrap();
+        assert!(<S<C> as SignatureScheme>::verify(&parameters, &pk, message, &sig).unwrap());
+        let randomized_pk = S::<C>::randomize_public_key(&parameters, &pk, randomness).unwrap();
+        assert!(!<S<C> as SignatureScheme>::verify(&parameters, &randomized_pk, message, &sig).unwrap());
+        let moved_sig = S::<C>::randomize_signature(&parameters, &sig, randomness).unwrap();
+        assert!(<S<C> as SignatureScheme>::verify(&parameters, &randomized_pk, message, &moved_sig).unwrap());
+        assert!(!<S<C> as SignatureScheme>::verify(&parameters, &pk, message, &moved_sig).unwrap());
+
+        let randomized_sk = S::<C>::randomize_secret_key(&sk, randomness);
+        let randomized_sig = <S<C> as SignatureScheme>::sign(&parameters, &randomized_sk, message, rng).unwrap();
+        assert!(<S<C> as SignatureScheme>::verify(&parameters, &randomized_pk, message, &randomized_sig).unwrap());
     }
 
-    // #[test]
-    // fn schnorr_signature_test() {
-    //     let message = "Hi, I am a Schnorr signature!";
-    //     let rng = &mut test_rng();
-    //     sign_and_verify::<schnorr::Schnorr<JubJub, Blake2s>>(message.as_bytes());
-    //     failed_verification::<schnorr::Schnorr<JubJub, Blake2s>>(
-    //         message.as_bytes(),
-    //         "Bad message".as_bytes(),
-    //     );
-    //     let mut random_scalar_bytes = Vec::new();
-    //     let random_scalar = <JubJub as AdditiveGroup>::Scalar::rand(rng);
-    //     random_scalar
-    //         .serialize_compressed(&mut random_scalar_bytes)
-    //         .unwrap();
-    //     randomize_and_verify::<schnorr::Schnorr<JubJub, Blake2s>>(
-    //         message.as_bytes(),
-    //         &random_scalar_bytes.as_slice(),
-    //     );
-    // }
     #[test]
     fn schnorr_signature_test() {
         let message = "Hi, I am a Schnorr signature!";
-        sign_and_verify::<JubJub>(message.as_bytes());
-        failed_verification::<JubJub>(
+        let rng = &mut test_rng();
+        sign_and_verify::<Schnorr<JubJub>>(message.as_bytes());
+        failed_verification::<Schnorr<JubJub>>(
             message.as_bytes(),
             "Bad message".as_bytes(),
         );
+        let mut random_scalar_bytes = Vec::new();
+        let random_scalar = Fr::rand(rng);
+        random_scalar
+            .serialize_compressed(&mut random_scalar_bytes)
+            .unwrap();
+        randomize_and_verify::<JubJub>(
+            message.as_bytes(),
+            random_scalar_bytes.as_slice(),
+        );
+    }
+
+    /// Run the two MuSig rounds between simulated signers that exchange their
+    /// nonces locally, checking every partial signature on the way.
+    pub(crate) fn musig_sign<R: Rng>(
+        parameters: &Parameters<JubJub>,
+        signers: &[(PublicKey<JubJub>, SecretKey<JubJub>)],
+        message: &[u8],
+        rng: &mut R,
+    ) -> (KeyAggContext<JubJub>, Signature<JubJub>) {
+        let context = KeyAggContext::new(signers.iter().map(|(pk, _)| *pk).collect()).unwrap();
+        loop {
+            // Round 1: every signer broadcasts a fresh pair of nonces.
+            let (secret_nonces, public_nonces): (Vec<_>, Vec<_>) = signers
+                .iter()
+                .map(|_| musig::nonce_gen(parameters, rng))
+                .unzip();
+            let aggregate_nonce = PublicNonce::aggregate(&public_nonces);
+            let session = match SigningSession::new(&context, &aggregate_nonce, message).unwrap() {
+                Some(session) => session,
+                None => continue,
+            };
+            // Round 2: every signer sends its share to the aggregator.
+            let partial_signatures: Vec<_> = signers
+                .iter()
+                .zip(secret_nonces)
+                .map(|((pk, sk), secret_nonce)| {
+                    session.partial_sign(&context, secret_nonce, sk, pk).unwrap()
+                })
+                .collect();
+            for ((pk, _), (public_nonce, partial)) in
+                signers.iter().zip(public_nonces.iter().zip(&partial_signatures))
+            {
+                assert!(session.partial_verify(parameters, &context, pk, public_nonce, partial));
+            }
+            return (context, session.aggregate(&partial_signatures));
+        }
+    }
+
+    #[test]
+    fn musig_signature_test() {
+        let rng = &mut test_rng();
+        let parameters = Schnorr::<JubJub>::setup(rng).unwrap();
+        let signers: Vec<_> = (0..3)
+            .map(|_| Schnorr::<JubJub>::keygen(&parameters, rng).unwrap())
+            .collect();
+        let message = b"Hi, we are a MuSig signature!";
+        let (context, sig) = musig_sign(&parameters, &signers, message, rng);
+
+        // The result is a plain Schnorr signature under the aggregated key only.
+        assert!(musig::verify(&parameters, &context, message, &sig).unwrap());
+        assert!(Schnorr::verify(&parameters, &context.aggregate_key, message, &sig).unwrap());
+        assert!(!Schnorr::verify(&parameters, &context.aggregate_key, b"Bad message", &sig).unwrap());
+        for (pk, _) in &signers {
+            assert!(!Schnorr::verify(&parameters, pk, message, &sig).unwrap());
+        }
+
+        // The aggregated key depends on the order of the keys, and is not their sum.
+        let reversed = KeyAggContext::<JubJub>::new(signers.iter().rev().map(|(pk, _)| *pk).collect()).unwrap();
+        assert_ne!(reversed.aggregate_key, context.aggregate_key);
+        let sum = signers.iter().map(|(pk, _)| *pk).sum::<JubJub>().into_affine();
+        assert_ne!(sum, context.aggregate_key);
+    }
+
+    #[test]
+    fn musig_rejects_bad_partial_signatures() {
+        let rng = &mut test_rng();
+        let parameters = Schnorr::<JubJub>::setup(rng).unwrap();
+        let signers: Vec<_> = (0..2)
+            .map(|_| Schnorr::<JubJub>::keygen(&parameters, rng).unwrap())
+            .collect();
+        let (outsider_pk, outsider_sk) = Schnorr::<JubJub>::keygen(&parameters, rng).unwrap();
+        let context = KeyAggContext::new(signers.iter().map(|(pk, _)| *pk).collect()).unwrap();
+        let message = b"partial";
+
+        let (session, secret_nonces, public_nonces) = loop {
+            let (secret_nonces, public_nonces): (Vec<_>, Vec<_>) =
+                (0..2).map(|_| musig::nonce_gen(&parameters, rng)).unzip();
+            let aggregate_nonce = PublicNonce::aggregate(&public_nonces);
+            if let Some(session) = SigningSession::new(&context, &aggregate_nonce, message).unwrap() {
+                break (session, secret_nonces, public_nonces);
+            }
+        };
+        let mut secret_nonces = secret_nonces.into_iter();
+        let (pk, sk) = &signers[0];
+        let partial = session
+            .partial_sign(&context, secret_nonces.next().unwrap(), sk, pk)
+            .unwrap();
+        assert!(session.partial_verify(&parameters, &context, pk, &public_nonces[0], &partial));
+        // A share is bound to its signer's key and nonce.
+        let (other_pk, _) = &signers[1];
+        assert!(!session.partial_verify(&parameters, &context, other_pk, &public_nonces[0], &partial));
+        assert!(!session.partial_verify(&parameters, &context, pk, &public_nonces[1], &partial));
+        let mut tampered = partial;
+        tampered.0 += Fr::from(1u8);
+        assert!(!session.partial_verify(&parameters, &context, pk, &public_nonces[0], &tampered));
+        // Keys outside the aggregated key cannot contribute.
+        assert!(session
+            .partial_sign(&context, secret_nonces.next().unwrap(), &outsider_sk, &outsider_pk)
+            .is_err());
+    }
+
+    /// The spend-authorization generator published in the Zcash protocol
+    /// specification, section 5.4.7.1, and `FindGroupHash^J*("Zcash_G_", "")`.
+    #[test]
+    fn eddsa_generator_matches_specification() {
+        use ark_ed_on_bls12_381::Fq;
+        use ark_ff::{BigInt, PrimeField};
+        let u = Fq::from_bigint(BigInt([
+            0x47bf_4692_0a95_a753,
+            0xd5b9_a7d3_ef8e_2827,
+            0xd418_a7ff_2675_3b6a,
+            0x0926_d4f3_2059_c712,
+        ]))
+        .unwrap();
+        let v = Fq::from_bigint(BigInt([
+            0x3056_32ad_aaf2_b530,
+            0x6d65_674d_cedb_ddbc,
+            0x53bb_37d0_c21c_fd05,
+            0x57a1_019e_6de9_b675,
+        ]))
+        .unwrap();
+        let generator = EdDSA::setup().unwrap().generator;
+        assert_eq!((generator.x, generator.y), (u, v));
+        assert!(generator.is_on_curve() && generator.is_in_correct_subgroup_assuming_on_curve());
+    }
+
+    #[test]
+    fn eddsa_personalized_blake2() {
+        use blake2::{Blake2b512, Blake2s256, Digest};
+        // Without a personalization, these are the plain hash functions.
+        for input in [&b""[..], b"abc", &[0x5a; 128], &[0x5a; 129]] {
+            assert_eq!(eddsa::blake2b_512(b"", input)[..], Blake2b512::digest(input)[..]);
+            assert_eq!(eddsa::blake2s_256(b"", input)[..], Blake2s256::digest(input)[..]);
+        }
+        assert_ne!(eddsa::blake2b_512(eddsa::HASH_PERSONALIZATION, b"abc")[..], Blake2b512::digest(b"abc")[..]);
+    }
+
+    #[test]
+    fn eddsa_point_encoding() {
+        use ark_ed_on_bls12_381::EdwardsAffine;
+        use ark_std::UniformRand;
+        let rng = &mut test_rng();
+        // The identity (0, 1), and (0, -1) of order 2.
+        let mut identity = [0u8; 32];
+        identity[0] = 1;
+        assert_eq!(eddsa::encode_point(&EdwardsAffine::zero()), identity);
+        assert_eq!(eddsa::decode_point(&identity), Some(EdwardsAffine::zero()));
+        // u = 0 with the sign bit set is not canonical (ZIP 216).
+        let mut negative_zero = identity;
+        negative_zero[31] |= 0x80;
+        assert_eq!(eddsa::decode_point(&negative_zero), None);
+        // Neither is v >= q: q + 1 encodes the same v-coordinate as the identity.
+        let mut overflowing = [0u8; 32];
+        let mut q_plus_one = <ark_ed_on_bls12_381::Fq as ark_ff::PrimeField>::MODULUS;
+        ark_ff::BigInteger::add_with_carry(&mut q_plus_one, &1u64.into());
+        overflowing.copy_from_slice(&ark_ff::BigInteger::to_bytes_le(&q_plus_one));
+        assert_eq!(eddsa::decode_point(&overflowing), None);
+
+        for _ in 0..16 {
+            let point = JubJub::rand(rng).into_affine();
+            let encoding = eddsa::encode_point(&point);
+            assert_eq!(eddsa::decode_point(&encoding), Some(point));
+            assert_eq!(eddsa::encode_point(&(-point)), {
+                let mut negated = encoding;
+                negated[31] ^= 0x80;
+                negated
+            });
+        }
+    }
+
+    #[test]
+    fn eddsa_test_vectors() {
+        use crate::signature::eddsa::test_vectors::{bytes, order_4_point, small_order_key_and_signature, TEST_VECTORS};
+        use ark_ff::{BigInteger, PrimeField};
+        let rng = &mut test_rng();
+        let parameters = EdDSA::setup().unwrap();
+        for tv in &TEST_VECTORS {
+            let sk = Fr::from_le_bytes_mod_order(&bytes::<32>(tv.sk));
+            let vk = eddsa::decode_point(&bytes(tv.vk)).unwrap();
+            assert_eq!((parameters.generator * sk).into_affine(), vk);
+            let alpha = Fr::from_le_bytes_mod_order(&bytes::<32>(tv.alpha));
+            let rvk = eddsa::decode_point(&bytes(tv.rvk)).unwrap();
+            assert_eq!((vk + parameters.generator * alpha).into_affine(), rvk);
+
+            let m = bytes::<32>(tv.m);
+            let sig = eddsa::Signature::from_bytes(&bytes(tv.sig));
+            let rsig = eddsa::Signature::from_bytes(&bytes(tv.rsig));
+            assert_eq!(eddsa::Signature::from_bytes(&sig.to_bytes()), sig);
+            assert!(EdDSA::verify(&parameters, &vk, &m, &sig));
+            assert!(EdDSA::verify(&parameters, &rvk, &m, &rsig));
+            assert!(!EdDSA::verify(&parameters, &rvk, &m, &sig));
+            assert!(!EdDSA::verify(&parameters, &vk, &m, &rsig));
+            assert!(!EdDSA::verify(&parameters, &vk, b"Bad message", &sig));
+
+            // Signatures are randomized, but any signature by `sk` verifies.
+            let fresh = EdDSA::sign(&parameters, &eddsa::SecretKey(sk), &m, rng);
+            assert_ne!(fresh, sig);
+            assert!(EdDSA::verify(&parameters, &vk, &m, &fresh));
+
+            // S + r_J acts like S on the group, but is not a canonical scalar.
+            let mut shifted = <Fr as CanonicalDeserialize>::deserialize_uncompressed(&sig.s_bytes[..])
+                .unwrap()
+                .into_bigint();
+            assert!(!shifted.add_with_carry(&Fr::MODULUS));
+            let mut malleated = sig;
+            malleated.s_bytes.copy_from_slice(&shifted.to_bytes_le());
+            assert!(!EdDSA::verify(&parameters, &vk, &m, &malleated));
+
+            // Adding a small-order point to R or vk does not change the check
+            // `[8] (...) = O`, but such points are rejected.
+            let small_order = order_4_point();
+            let r = eddsa::decode_point(&sig.r_bytes).unwrap();
+            let mut mixed_r = sig;
+            mixed_r.r_bytes = eddsa::encode_point(&(r + small_order).into_affine());
+            assert!(!EdDSA::verify(&parameters, &vk, &m, &mixed_r));
+            let mixed_vk = (vk + small_order).into_affine();
+            assert!(!mixed_vk.is_in_correct_subgroup_assuming_on_curve());
+            assert!(!EdDSA::verify(&parameters, &mixed_vk, &m, &sig));
+        }
+        let (small_order_vk, forged) = small_order_key_and_signature(&parameters);
+        assert!(!EdDSA::verify(&parameters, &small_order_vk, b"any message", &forged));
     }
 }
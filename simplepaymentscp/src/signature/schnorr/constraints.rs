@@ -1,33 +1,20 @@
-use ark_crypto_primitives::prf::blake2s::constraints::OutputVar;
 use ark_std::vec::Vec;
 use ark_ec::CurveGroup;
-use ark_bls12_381::Fr;
-use ark_ff::Field;
+use ark_ff::{BigInteger, Field, PrimeField};
 use ark_r1cs_std::prelude::*;
-use ark_relations::r1cs::{ConstraintSystem, Namespace, SynthesisError};
+use ark_relations::r1cs::{Namespace, SynthesisError};
 
-use crate::signature::{schnorr};
-use crate::signature::schnorr::Signature;
-use crate::serde_utils::*;
-use ark_crypto_primitives::prf::blake2s::constraints::Blake2sGadget;
-use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, SerializationError};
-use ark_std::{cfg_iter, fmt::Debug, io::Write, test_rng};
+use crate::signature::schnorr::{Schnorr, Signature};
+use ark_crypto_primitives::signature::constraints::{SigRandomizePkGadget, SigVerifyGadget};
+use ark_std::hash::Hash;
+use ark_serialize::CanonicalSerialize;
 
 use core::{borrow::Borrow, marker::PhantomData};
-use std::ops::Mul;
 
-use ark_relations::r1cs::ConstraintSystemRef;
-use crate::signature::schnorr::{Parameters, PublicKey, Schnorr};
+use crate::signature::schnorr::{Parameters, PublicKey};
 use derivative::Derivative;
-use digest::Digest;
 
-use crate::commitment::{
-    blake2s::{
-        constraints::{CommGadget, ParametersVar as B2SParamsVar},
-        Commitment,
-    },
-    CommitmentGadget,
-};
+use crate::commitment::blake2s::constraints::CommGadget;
 
 type ConstraintF<C> = <<C as CurveGroup>::BaseField as Field>::BasePrimeField;
 
@@ -59,8 +46,9 @@ pub struct SignatureVar<C: CurveGroup, GC: CurveVar<C, ConstraintF<C>>>
 {
     pub prover_response: Vec<UInt8<ConstraintF<C>>>,
     pub verifier_challenge: Vec<UInt8<ConstraintF<C>>>,
+    pub prover_com: GC,
     #[doc(hidden)]
-    _group: PhantomData<GC>,
+    _group: PhantomData<C>,
 }
 
 pub struct SchnorrSignatureVerifyGadget<C: CurveGroup, GC: CurveVar<C, ConstraintF<C>>>
@@ -76,41 +64,92 @@ where
     C: CurveGroup,
     GC: CurveVar<C, ConstraintF<C>>,
 {
+    /// Verify `signature` on `message` under `public_key`, mirroring `Schnorr::verify`:
+    /// both scalars must be canonical encodings, `s · G + e · pk` must equal the prover
+    /// commitment, and `e` must equal the hash of `(prover_com || message)`.
     pub fn verify(
         parameters: &ParametersVar<C,GC>,
         public_key: &PublicKeyVar<C,GC>,
         message: &[UInt8<ConstraintF<C>>],
         signature: &SignatureVar<C,GC>,
     ) -> Result<Boolean<ConstraintF<C>>, SynthesisError> {
-        let prover_response = signature.prover_response.clone();
-        let verifier_challenge = signature.verifier_challenge.clone();
+        let prover_response = signature.prover_response.to_bits_le()?;
+        let verifier_challenge = signature.verifier_challenge.to_bits_le()?;
+        // Natively the scalars are deserialized with `deserialize_uncompressed`, which
+        // rejects anything not smaller than the scalar field modulus.
+        let mut modulus_minus_one = C::ScalarField::MODULUS;
+        modulus_minus_one.sub_with_borrow(&1u64.into());
+        Boolean::enforce_smaller_or_equal_than_le(&prover_response, modulus_minus_one)?;
+        Boolean::enforce_smaller_or_equal_than_le(&verifier_challenge, modulus_minus_one)?;
+
         let mut claimed_prover_commitment = parameters
             .generator
-            .scalar_mul_le(prover_response.to_bits_le()?.iter())?;
+            .scalar_mul_le(prover_response.iter())?;
         let public_key_times_verifier_challenge = public_key
             .pub_key
-            .scalar_mul_le(verifier_challenge.to_bits_le()?.iter())?;
+            .scalar_mul_le(verifier_challenge.iter())?;
         claimed_prover_commitment += &public_key_times_verifier_challenge;
+        let commitment_opens = claimed_prover_commitment.is_eq(&signature.prover_com)?;
 
         let mut hash_input = Vec::new();
-        hash_input.extend_from_slice(&public_key.pub_key.to_bytes()?);
-        hash_input.extend_from_slice(&claimed_prover_commitment.to_bytes()?);
+        hash_input.extend_from_slice(&signature.prover_com.to_bytes()?);
         hash_input.extend_from_slice(message);
 
-        type TestCOMM = Commitment;
-        type TestCOMMGadget = CommGadget;
+        let obtained_verifier_challenge = CommGadget::hash(&hash_input)?;
+
+        obtained_verifier_challenge
+            .0
+            .is_eq(&signature.verifier_challenge)?
+            .and(&commitment_opens)
+    }
+}
+
+impl<C, GC> SigVerifyGadget<Schnorr<C>, ConstraintF<C>> for SchnorrSignatureVerifyGadget<C, GC>
+where
+    C: CurveGroup + Hash,
+    GC: CurveVar<C, ConstraintF<C>>,
+{
+    type ParametersVar = ParametersVar<C, GC>;
+    type PublicKeyVar = PublicKeyVar<C, GC>;
+    type SignatureVar = SignatureVar<C, GC>;
+
+    fn verify(
+        parameters: &Self::ParametersVar,
+        public_key: &Self::PublicKeyVar,
+        message: &[UInt8<ConstraintF<C>>],
+        signature: &Self::SignatureVar,
+    ) -> Result<Boolean<ConstraintF<C>>, SynthesisError> {
+        Self::verify(parameters, public_key, message, signature)
+    }
+}
 
-        let b2s_params = <TestCOMMGadget as CommitmentGadget<TestCOMM, Fr>>::ParametersVar::new_constant(
-            ConstraintSystemRef::<Fr>::None,
-            (),
-        )?;
+pub struct SchnorrRandomizePkGadget<C: CurveGroup, GC: CurveVar<C, ConstraintF<C>>>
+{
+    #[doc(hidden)]
+    _group: PhantomData<*const C>,
+    #[doc(hidden)]
+    _group_gadget: PhantomData<*const GC>,
+}
 
-        let obtained_verifier_challenge = <TestCOMMGadget as CommitmentGadget<TestCOMM, ConstraintF<C>>>::commit(
-            &b2s_params,
-            &hash_input,
-        ).unwrap();
+impl<C, GC> SigRandomizePkGadget<Schnorr<C>, ConstraintF<C>> for SchnorrRandomizePkGadget<C, GC>
+where
+    C: CurveGroup + Hash,
+    GC: CurveVar<C, ConstraintF<C>>,
+{
+    type ParametersVar = ParametersVar<C, GC>;
+    type PublicKeyVar = PublicKeyVar<C, GC>;
 
-        obtained_verifier_challenge.0.is_eq(&verifier_challenge)
+    /// Computes `pk + [ρ] G`, mirroring `Schnorr::randomize_public_key`. The
+    /// randomness bits are used unreduced, which yields the same point on the
+    /// prime-order subgroup.
+    fn randomize(
+        parameters: &Self::ParametersVar,
+        public_key: &Self::PublicKeyVar,
+        randomness: &[UInt8<ConstraintF<C>>],
+    ) -> Result<Self::PublicKeyVar, SynthesisError> {
+        let randomness = randomness.to_bits_le()?;
+        let offset = parameters.generator.scalar_mul_le(randomness.iter())?;
+        Ok((offset + &public_key.pub_key).into())
     }
 }
 
@@ -150,6 +189,20 @@ where
     }
 }
 
+impl<C, GC> From<GC> for PublicKeyVar<C, GC>
+where
+    C: CurveGroup,
+    GC: CurveVar<C, ConstraintF<C>>,
+{
+    /// Wrap a key computed in-circuit, such as an aggregated MuSig key.
+    fn from(pub_key: GC) -> Self {
+        Self {
+            pub_key,
+            _group: PhantomData,
+        }
+    }
+}
+
 impl<C, GC> AllocVar<Signature<C>, ConstraintF<C>> for SignatureVar<C, GC>
 where
     C: CurveGroup,
@@ -162,30 +215,22 @@ where
     ) -> Result<Self, SynthesisError> {
         f().and_then(|val| {
             let cs = cs.into();
+            let val = val.borrow();
             let mut response_bytes = Vec::new();
-            val.borrow().prover_response.serialize_uncompressed(&mut response_bytes);
-
+            val.prover_response
+                .serialize_uncompressed(&mut response_bytes)
+                .map_err(|_| SynthesisError::AssignmentMissing)?;
             let mut challenge_bytes = Vec::new();
-            val.borrow().verifier_challenge.serialize_uncompressed(&mut challenge_bytes);
-            let mut prover_response = Vec::<UInt8<ConstraintF<C>>>::new();
-            let mut verifier_challenge = Vec::<UInt8<ConstraintF<C>>>::new();
-            for byte in &response_bytes {
-                prover_response.push(UInt8::<ConstraintF<C>>::new_variable(
-                    cs.clone(),
-                    || Ok(byte),
-                    mode,
-                )?);
-            }
-            for byte in &challenge_bytes {
-                verifier_challenge.push(UInt8::<ConstraintF<C>>::new_variable(
-                    cs.clone(),
-                    || Ok(byte),
-                    mode,
-                )?);
-            }
+            val.verifier_challenge
+                .serialize_uncompressed(&mut challenge_bytes)
+                .map_err(|_| SynthesisError::AssignmentMissing)?;
+            let prover_response = Vec::new_variable(cs.clone(), || Ok(&response_bytes[..]), mode)?;
+            let verifier_challenge = Vec::new_variable(cs.clone(), || Ok(&challenge_bytes[..]), mode)?;
+            let prover_com = GC::new_variable(cs, || Ok(val.prover_com), mode)?;
             Ok(SignatureVar {
                 prover_response,
                 verifier_challenge,
+                prover_com,
                 _group: PhantomData,
             })
         })
@@ -232,3 +277,17 @@ where
         self.pub_key.to_bytes()
     }
 }
+
+impl<C, GC> ToBytesGadget<ConstraintF<C>> for SignatureVar<C, GC>
+where
+    C: CurveGroup,
+    GC: CurveVar<C, ConstraintF<C>>,
+{
+    /// `s || e || R`, with `R` uncompressed.
+    fn to_bytes(&self) -> Result<Vec<UInt8<ConstraintF<C>>>, SynthesisError> {
+        let mut bytes = self.prover_response.clone();
+        bytes.extend_from_slice(&self.verifier_challenge);
+        bytes.extend(self.prover_com.to_bytes()?);
+        Ok(bytes)
+    }
+}
@@ -1,20 +1,19 @@
-use ark_crypto_primitives::Error;
+use ark_crypto_primitives::{signature::SignatureScheme, Error};
 use ark_ec::{AffineRepr, CurveGroup};
 use ark_ff::{
     fields::{Field, PrimeField},
-    One, ToConstraintField, UniformRand, Zero,
+    ToConstraintField, UniformRand,
 };
 use ark_serialize::{CanonicalSerialize, CanonicalDeserialize};
 use ark_std::ops::Mul;
 use ark_std::rand::Rng;
-use ark_r1cs_std::prelude::*;
-use ark_r1cs_std::boolean::AllocatedBool;
 use ark_std::{hash::Hash, marker::PhantomData, vec::Vec};
-use crate::commitment::{blake2s::Commitment, CommitmentScheme};
+use crate::commitment::blake2s::Commitment;
 
 use derivative::Derivative;
 //#[cfg(feature = "r1cs")]
 pub mod constraints;
+pub mod musig;
 
 pub struct Schnorr<C: CurveGroup> {
     _group: PhantomData<C>,
@@ -42,7 +41,11 @@ impl<C: CurveGroup> DigestToScalarField<C> for Vec<u8> {
     }
 }
 
-#[derive(Clone, Default, Debug)]
+/// A Schnorr signature `(s, e, R)`.
+///
+/// The canonical encoding is `s || e || R` as produced by `CanonicalSerialize`;
+/// `SignatureVar` allocates the same uncompressed scalar bytes in-circuit.
+#[derive(Clone, Default, Debug, PartialEq, Eq, CanonicalSerialize, CanonicalDeserialize)]
 pub struct Signature<C: CurveGroup> {
     pub prover_response: C::ScalarField,
     pub verifier_challenge: C::ScalarField,
@@ -71,10 +74,32 @@ where
         Ok((public_key, SecretKey(secret_key)))
     }
 
+    /// Hash `(prover_com || message)` to the raw bytes of the verifier challenge.
+    /// This is the digest that `SchnorrSignatureVerifyGadget` recomputes in-circuit.
+    ///
+    /// The challenge does not commit to the public key, so that signatures can be
+    /// moved to randomized keys; see `randomize_signature`. Every payload that
+    /// accounts sign names the account it authorizes, which ties a signature to
+    /// the key in that account's leaf.
+    pub fn challenge_digest(prover_com: &C::Affine, message: &[u8]) -> Result<[u8; 32], Error> {
+        let mut hash_input = Vec::new();
+        prover_com.serialize_uncompressed(&mut hash_input)?;
+        hash_input.extend_from_slice(message);
+        Ok(Commitment::hash(&hash_input))
+    }
+
+    /// Derive the verifier challenge for `(prover_com, message)`.
+    /// Returns `None` if the digest is not the canonical encoding of a scalar,
+    /// in which case the signer has to pick a fresh nonce.
+    pub fn challenge(prover_com: &C::Affine, message: &[u8]) -> Result<Option<C::ScalarField>, Error> {
+        let digest = Self::challenge_digest(prover_com, message)?;
+        Ok(<Vec<u8> as DigestToScalarField<C>>::digest_to_scalar_field(&digest.to_vec()).ok())
+    }
+
     pub fn sign<R: Rng>(
         parameters: &Parameters<C>,
         sk: &SecretKey<C>,
-        pk: &PublicKey<C>,
+        _pk: &PublicKey<C>,
         message: &[u8],
         rng: &mut R,
     ) -> Result<Signature<C>, Error> {
@@ -87,15 +112,8 @@ where
             let prover_commitment = parameters.generator.mul(random_scalar).into_affine();
 
             // Hash everything to get verifier challenge.
-            let mut hash_input = Vec::new();
-            pk.serialize_uncompressed(&mut hash_input)?;
-            prover_commitment.serialize_uncompressed(&mut hash_input)?;
-            hash_input.extend_from_slice(message);
-
-            let verifier_challenge_hash = Commitment::commit(&(),&hash_input)?;
-
-            if let Ok(clean_verifier_challenge) = <Vec<u8> as DigestToScalarField<C>>::digest_to_scalar_field(&verifier_challenge_hash.to_vec()) {
-                break (random_scalar, clean_verifier_challenge, prover_commitment);
+            if let Some(verifier_challenge) = Self::challenge(&prover_commitment, message)? {
+                break (random_scalar, verifier_challenge, prover_commitment);
             };
         };
         // k - xe;
@@ -119,24 +137,98 @@ where
             verifier_challenge,
             prover_com,
         } = signature;
+        // s · G + e · pk has to reopen the prover commitment ...
         let mut claimed_prover_commitment = parameters.generator.mul(*prover_response);
         let public_key_times_verifier_challenge = pk.mul(*verifier_challenge);
         claimed_prover_commitment += &public_key_times_verifier_challenge;
-        let claimed_prover_commitment = claimed_prover_commitment.into_affine();
+        if claimed_prover_commitment.into_affine() != *prover_com {
+            return Ok(false);
+        }
 
-        let mut hash_input = Vec::new();
-        pk.serialize_uncompressed(&mut hash_input)?;
-        claimed_prover_commitment.serialize_uncompressed(&mut hash_input)?;
-        hash_input.extend_from_slice(message);
+        // ... and the challenge has to be the canonical hash of the commitment.
+        match Self::challenge(prover_com, message)? {
+            Some(obtained_verifier_challenge) => Ok(verifier_challenge == &obtained_verifier_challenge),
+            None => Ok(false),
+        }
+    }
+}
 
-        let obtained_verifier_challenge = if let Some(obtained_verifier_challenge) =
-            C::ScalarField::from_random_bytes(&Commitment::commit(&(),&hash_input)?)
-        {
-            obtained_verifier_challenge
-        } else {
-            return Ok(false);
-        };
-        Ok(verifier_challenge == &obtained_verifier_challenge)
+impl<C: CurveGroup + Hash> Schnorr<C>
+where
+    C::ScalarField: PrimeField,
+{
+    /// The secret key of the public key `randomize_public_key` returns for
+    /// `randomness`: `sk + ρ`.
+    pub fn randomize_secret_key(sk: &SecretKey<C>, randomness: &[u8]) -> SecretKey<C> {
+        SecretKey(sk.0 + C::ScalarField::from_le_bytes_mod_order(randomness))
+    }
+}
+
+impl<C: CurveGroup + Hash> SignatureScheme for Schnorr<C>
+where
+    C::ScalarField: PrimeField,
+{
+    type Parameters = Parameters<C>;
+    type PublicKey = PublicKey<C>;
+    type SecretKey = SecretKey<C>;
+    type Signature = Signature<C>;
+
+    fn setup<R: Rng>(rng: &mut R) -> Result<Self::Parameters, Error> {
+        Self::setup(rng)
+    }
+
+    fn keygen<R: Rng>(
+        parameters: &Self::Parameters,
+        rng: &mut R,
+    ) -> Result<(Self::PublicKey, Self::SecretKey), Error> {
+        Self::keygen(parameters, rng)
+    }
+
+    /// Like `Schnorr::sign`, with the public key derived from `sk`.
+    fn sign<R: Rng>(
+        parameters: &Self::Parameters,
+        sk: &Self::SecretKey,
+        message: &[u8],
+        rng: &mut R,
+    ) -> Result<Self::Signature, Error> {
+        let pk = parameters.generator.mul(sk.0).into_affine();
+        Self::sign(parameters, sk, &pk, message, rng)
+    }
+
+    fn verify(
+        parameters: &Self::Parameters,
+        pk: &Self::PublicKey,
+        message: &[u8],
+        signature: &Self::Signature,
+    ) -> Result<bool, Error> {
+        Self::verify(parameters, pk, message, signature)
+    }
+
+    /// Returns `pk + [ρ] G`, where `ρ` is `randomness` read as a little-endian
+    /// integer modulo the group order; see also `randomize_secret_key`.
+    fn randomize_public_key(
+        parameters: &Self::Parameters,
+        public_key: &Self::PublicKey,
+        randomness: &[u8],
+    ) -> Result<Self::PublicKey, Error> {
+        let randomness = C::ScalarField::from_le_bytes_mod_order(randomness);
+        Ok((parameters.generator.mul(randomness) + public_key).into_affine())
+    }
+
+    /// Returns `(s - e · ρ, e, R)`, which verifies under the key that
+    /// `randomize_public_key` returns for the same `randomness`: the challenge
+    /// only depends on `R` and the message, and `(s - e · ρ) · G + e · (pk + ρ · G)`
+    /// still opens `R`.
+    fn randomize_signature(
+        _parameters: &Self::Parameters,
+        signature: &Self::Signature,
+        randomness: &[u8],
+    ) -> Result<Self::Signature, Error> {
+        let randomness = C::ScalarField::from_le_bytes_mod_order(randomness);
+        Ok(Signature {
+            prover_response: signature.prover_response - signature.verifier_challenge * randomness,
+            ..signature.clone()
+        })
     }
 }
 
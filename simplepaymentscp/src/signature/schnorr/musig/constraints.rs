@@ -0,0 +1,60 @@
+use ark_std::vec::Vec;
+use ark_ec::CurveGroup;
+use ark_ff::Field;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::SynthesisError;
+
+use core::marker::PhantomData;
+
+use super::{KEY_COEFFICIENT_TAG, KEY_LIST_TAG};
+use crate::commitment::blake2s::constraints::CommGadget;
+use crate::signature::schnorr::constraints::PublicKeyVar;
+
+type ConstraintF<C> = <<C as CurveGroup>::BaseField as Field>::BasePrimeField;
+
+/// In-circuit key aggregation, mirroring `KeyAggContext::new`. The aggregated key
+/// can be passed to `SchnorrSignatureVerifyGadget::verify` like any other key.
+pub struct KeyAggGadget<C: CurveGroup, GC: CurveVar<C, ConstraintF<C>>> {
+    #[doc(hidden)]
+    _group: PhantomData<*const C>,
+    #[doc(hidden)]
+    _group_gadget: PhantomData<*const GC>,
+}
+
+impl<C, GC> KeyAggGadget<C, GC>
+where
+    C: CurveGroup,
+    GC: CurveVar<C, ConstraintF<C>>,
+{
+    fn hash(input: &[UInt8<ConstraintF<C>>]) -> Result<Vec<UInt8<ConstraintF<C>>>, SynthesisError> {
+        Ok(CommGadget::hash(input)?.0)
+    }
+
+    /// Aggregate `public_keys` in the given order.
+    ///
+    /// Natively each coefficient is the digest reduced modulo the group order;
+    /// here the unreduced 256-bit digest is used as the scalar, which yields the
+    /// same point as long as the keys lie in the prime-order subgroup.
+    pub fn aggregate(
+        public_keys: &[PublicKeyVar<C, GC>],
+    ) -> Result<PublicKeyVar<C, GC>, SynthesisError> {
+        let key_bytes = public_keys
+            .iter()
+            .map(|pk| pk.pub_key.to_bytes())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut list_input = UInt8::constant_vec(KEY_LIST_TAG);
+        key_bytes.iter().for_each(|bytes| list_input.extend_from_slice(bytes));
+        let list_digest = Self::hash(&list_input)?;
+
+        let mut aggregate_key = GC::zero();
+        for (public_key, bytes) in public_keys.iter().zip(&key_bytes) {
+            let mut coefficient_input = UInt8::constant_vec(KEY_COEFFICIENT_TAG);
+            coefficient_input.extend_from_slice(&list_digest);
+            coefficient_input.extend_from_slice(bytes);
+            let coefficient = Self::hash(&coefficient_input)?.to_bits_le()?;
+            aggregate_key += public_key.pub_key.scalar_mul_le(coefficient.iter())?;
+        }
+        Ok(aggregate_key.into())
+    }
+}
@@ -0,0 +1,238 @@
+//! MuSig2-style aggregation of Schnorr signatures.
+//!
+//! `n` signers holding keys `pk_1, ..., pk_n` jointly produce a single
+//! `Signature<C>` that verifies under the aggregated key
+//! `X = a_1 · pk_1 + ... + a_n · pk_n` with the plain `Schnorr::verify` and
+//! `SchnorrSignatureVerifyGadget`. The key coefficients `a_i` bind every key to the
+//! whole key list, which prevents rogue-key attacks.
+//!
+//! Signing takes two rounds:
+//! 1. Every signer runs `nonce_gen` and sends the `PublicNonce` to the others.
+//! 2. Everyone aggregates the nonces and opens a `SigningSession`. Every signer
+//!    then sends `SigningSession::partial_sign` to an aggregator, which can check each
+//!    share with `partial_verify` and combines them with `aggregate`.
+//!
+//! A signature is only valid if its challenge hash is a canonical scalar, and
+//! `Schnorr::sign` retries with a fresh nonce when it is not. Here the nonce is
+//! chosen jointly, so `SigningSession::new` returns `None` in that case, and the
+//! signers have to start over from round 1.
+use super::{Parameters, PublicKey, Schnorr, SecretKey, Signature};
+use crate::commitment::blake2s::Commitment;
+use ark_crypto_primitives::Error;
+use ark_ec::{AffineRepr, CurveGroup};
+use ark_ff::{PrimeField, UniformRand, Zero};
+use ark_serialize::CanonicalSerialize;
+use ark_std::rand::Rng;
+use ark_std::{hash::Hash, ops::Mul, vec::Vec};
+
+pub mod constraints;
+
+/// Tag of the hash committing to the list of keys.
+pub const KEY_LIST_TAG: &[u8] = b"ark-rollup/musig/key-list";
+/// Tag of the hash deriving a key's coefficient from the list digest.
+pub const KEY_COEFFICIENT_TAG: &[u8] = b"ark-rollup/musig/key-coefficient";
+/// Tag of the hash deriving the nonce coefficient `b`.
+pub const NONCE_COEFFICIENT_TAG: &[u8] = b"ark-rollup/musig/nonce-coefficient";
+
+fn serialize_points<P: CanonicalSerialize>(bytes: &mut Vec<u8>, points: &[&P]) -> Result<(), Error> {
+    for point in points {
+        point.serialize_uncompressed(&mut *bytes)?;
+    }
+    Ok(())
+}
+
+/// The digest `H(KEY_LIST_TAG || pk_1 || ... || pk_n)` of the ordered key list,
+/// with the keys uncompressed.
+pub fn key_list_digest<C: CurveGroup>(public_keys: &[PublicKey<C>]) -> Result<[u8; 32], Error> {
+    let mut input = KEY_LIST_TAG.to_vec();
+    serialize_points(&mut input, &public_keys.iter().collect::<Vec<_>>())?;
+    Ok(Commitment::hash(&input))
+}
+
+/// The coefficient `H(KEY_COEFFICIENT_TAG || key_list_digest || pk)` of `public_key`,
+/// reduced modulo the scalar field order.
+pub fn key_coefficient<C: CurveGroup>(
+    key_list_digest: &[u8; 32],
+    public_key: &PublicKey<C>,
+) -> Result<C::ScalarField, Error> {
+    let mut input = KEY_COEFFICIENT_TAG.to_vec();
+    input.extend_from_slice(key_list_digest);
+    public_key.serialize_uncompressed(&mut input)?;
+    let digest = Commitment::hash(&input);
+    Ok(C::ScalarField::from_le_bytes_mod_order(&digest))
+}
+
+/// The ordered list of signer keys, their coefficients, and the aggregated key.
+#[derive(Clone, Debug)]
+pub struct KeyAggContext<C: CurveGroup> {
+    pub public_keys: Vec<PublicKey<C>>,
+    pub coefficients: Vec<C::ScalarField>,
+    /// The key `a_1 · pk_1 + ... + a_n · pk_n` that the aggregated signature verifies under.
+    pub aggregate_key: PublicKey<C>,
+}
+
+impl<C: CurveGroup> KeyAggContext<C> {
+    /// Aggregate `public_keys`. All signers have to use the same order.
+    pub fn new(public_keys: Vec<PublicKey<C>>) -> Result<Self, Error> {
+        let list_digest = key_list_digest::<C>(&public_keys)?;
+        let coefficients = public_keys
+            .iter()
+            .map(|pk| key_coefficient::<C>(&list_digest, pk))
+            .collect::<Result<Vec<_>, _>>()?;
+        let aggregate_key = public_keys
+            .iter()
+            .zip(&coefficients)
+            .map(|(pk, a)| pk.mul(*a))
+            .sum::<C>()
+            .into_affine();
+        Ok(Self {
+            public_keys,
+            coefficients,
+            aggregate_key,
+        })
+    }
+
+    /// The coefficient of `public_key`, if it is part of the list.
+    fn coefficient(&self, public_key: &PublicKey<C>) -> Option<C::ScalarField> {
+        let i = self.public_keys.iter().position(|pk| pk == public_key)?;
+        Some(self.coefficients[i])
+    }
+}
+
+/// The two secret nonces of a signer. It is consumed by `partial_sign`, so that
+/// it cannot be used for two signatures.
+pub struct SecretNonce<C: CurveGroup>(C::ScalarField, C::ScalarField);
+
+/// The commitments `(k_1 · G, k_2 · G)` to a signer's secret nonces, or the sum of
+/// all signers' commitments.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PublicNonce<C: CurveGroup> {
+    pub r1: C::Affine,
+    pub r2: C::Affine,
+}
+
+impl<C: CurveGroup> PublicNonce<C> {
+    /// Sum the public nonces of all signers.
+    pub fn aggregate(nonces: &[Self]) -> Self {
+        let (r1, r2) = nonces.iter().fold((C::zero(), C::zero()), |(r1, r2), nonce| {
+            (r1 + nonce.r1, r2 + nonce.r2)
+        });
+        Self {
+            r1: r1.into_affine(),
+            r2: r2.into_affine(),
+        }
+    }
+}
+
+/// Sample a fresh pair of nonces for round 1.
+pub fn nonce_gen<C: CurveGroup, R: Rng>(
+    parameters: &Parameters<C>,
+    rng: &mut R,
+) -> (SecretNonce<C>, PublicNonce<C>) {
+    let (k1, k2) = (C::ScalarField::rand(rng), C::ScalarField::rand(rng));
+    let public_nonce = PublicNonce {
+        r1: parameters.generator.mul(k1).into_affine(),
+        r2: parameters.generator.mul(k2).into_affine(),
+    };
+    (SecretNonce(k1, k2), public_nonce)
+}
+
+/// A signer's share `s_i` of the aggregated response.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PartialSignature<C: CurveGroup>(pub C::ScalarField);
+
+/// The values that all signers derive from the aggregated key, the aggregated
+/// nonce and the message in round 2.
+#[derive(Clone, Debug)]
+pub struct SigningSession<C: CurveGroup> {
+    /// The nonce coefficient `b = H(NONCE_COEFFICIENT_TAG || X || R_1 || R_2 || m)`.
+    pub nonce_coefficient: C::ScalarField,
+    /// The effective nonce `R = R_1 + b · R_2`.
+    pub prover_com: C::Affine,
+    /// The Schnorr challenge `e` for `(R, m)`.
+    pub verifier_challenge: C::ScalarField,
+}
+
+impl<C: CurveGroup + Hash> SigningSession<C> {
+    /// Open a session for signing `message` under `context.aggregate_key`. Returns
+    /// `None` if the challenge is not canonical, in which case the signers have to
+    /// discard their nonces and run round 1 again.
+    pub fn new(
+        context: &KeyAggContext<C>,
+        aggregate_nonce: &PublicNonce<C>,
+        message: &[u8],
+    ) -> Result<Option<Self>, Error> {
+        let mut input = NONCE_COEFFICIENT_TAG.to_vec();
+        serialize_points(
+            &mut input,
+            &[&context.aggregate_key, &aggregate_nonce.r1, &aggregate_nonce.r2],
+        )?;
+        input.extend_from_slice(message);
+        let nonce_coefficient =
+            C::ScalarField::from_le_bytes_mod_order(&Commitment::hash(&input));
+        let prover_com = (aggregate_nonce.r1 + aggregate_nonce.r2.mul(nonce_coefficient)).into_affine();
+        Ok(Schnorr::<C>::challenge(&prover_com, message)?.map(
+            |verifier_challenge| Self {
+                nonce_coefficient,
+                prover_com,
+                verifier_challenge,
+            },
+        ))
+    }
+
+    /// Compute the share `s_i = k_1 + b · k_2 - e · a_i · sk_i` of the signer `pk`.
+    pub fn partial_sign(
+        &self,
+        context: &KeyAggContext<C>,
+        secret_nonce: SecretNonce<C>,
+        sk: &SecretKey<C>,
+        pk: &PublicKey<C>,
+    ) -> Result<PartialSignature<C>, Error> {
+        let coefficient = context
+            .coefficient(pk)
+            .ok_or("the signer's key is not part of the aggregated key")?;
+        let SecretNonce(k1, k2) = secret_nonce;
+        Ok(PartialSignature(
+            k1 + self.nonce_coefficient * k2 - self.verifier_challenge * coefficient * sk.0,
+        ))
+    }
+
+    /// Check the share of the signer `pk`, who sent `public_nonce` in round 1:
+    /// `s_i · G + e · a_i · pk == R_1,i + b · R_2,i`.
+    pub fn partial_verify(
+        &self,
+        parameters: &Parameters<C>,
+        context: &KeyAggContext<C>,
+        pk: &PublicKey<C>,
+        public_nonce: &PublicNonce<C>,
+        partial_signature: &PartialSignature<C>,
+    ) -> bool {
+        context.coefficient(pk).is_some_and(|coefficient| {
+            let lhs = parameters.generator.mul(partial_signature.0)
+                + pk.mul(self.verifier_challenge * coefficient);
+            let rhs = public_nonce.r1.into_group() + public_nonce.r2.mul(self.nonce_coefficient);
+            lhs == rhs
+        })
+    }
+
+    /// Combine the shares of all signers into a signature under the aggregated key.
+    pub fn aggregate(&self, partial_signatures: &[PartialSignature<C>]) -> Signature<C> {
+        Signature {
+            prover_response: partial_signatures
+                .iter()
+                .fold(C::ScalarField::zero(), |s, partial| s + partial.0),
+            verifier_challenge: self.verifier_challenge,
+            prover_com: self.prover_com,
+        }
+    }
+}
+
+/// Verify a signature under the aggregated key of `context`.
+pub fn verify<C: CurveGroup + Hash>(
+    parameters: &Parameters<C>,
+    context: &KeyAggContext<C>,
+    message: &[u8],
+    signature: &Signature<C>,
+) -> Result<bool, Error> {
+    Schnorr::<C>::verify(parameters, &context.aggregate_key, message, signature)
+}
@@ -1,96 +1,703 @@
-use crate::account::{AccountId, AccountPublicKey, AccountSecretKey};
-use crate::ledger::{self, Amount};
-use crate::signature::{schnorr::{self, Schnorr}};
-use ark_ed_on_bls12_381::EdwardsProjective;
+use crate::account::{
+    AccountId, AccountPublicKey, AccountSecretKey, AccountSignature, MultisigPolicy, TokenId,
+};
+use crate::ledger::{self, Amount, AmountCommitment, AmountOpening, SigningDomain};
+use ark_ed_on_bls12_381::EdwardsAffine;
+use ark_serialize::CanonicalSerialize;
 use ark_std::rand::Rng;
+use std::collections::HashMap;
 
-/// Transaction transferring some amount from one account to another.
+/// Tag prefixed to every signed payload, separating rollup signatures from
+/// signatures made with the same key for any other purpose.
+pub const SIGNING_DOMAIN_TAG: &[u8] = b"ark-rollup/signed-payload";
+/// Version of the signed payload layout. Bump it whenever the layout changes.
+pub const SIGNING_FORMAT_VERSION: u8 = 2;
+
+/// The kind of operation a signed payload authorizes.
+#[derive(Hash, Eq, PartialEq, Copy, Clone, Debug)]
+#[repr(u8)]
+pub enum OperationKind {
+    Transfer = 0,
+    Swap = 1,
+    RotateKey = 2,
+    MultisigTransfer = 3,
+    ConfidentialTransfer = 4,
+    Exit = 5,
+}
+
+/// An address on the L1 chain, to which exits and withdrawals pay out.
+pub type L1Address = [u8; 20];
+
+/// The destination that transfers which are not withdrawals sign.
+pub const NO_DESTINATION: L1Address = [0; 20];
+
+/// The message that is actually signed. It consists of
+/// (DomainTag || FormatVersion || OperationKind || ChainId || RollupId || Body),
+/// where the integers are little-endian and the body depends on the operation.
+#[derive(Clone, Debug)]
+pub struct SigningPayload {
+    pub domain: SigningDomain,
+    pub kind: OperationKind,
+    pub body: Vec<u8>,
+}
+
+impl SigningPayload {
+    /// The payload authorizing a transfer; its body is
+    /// (SenderAccId || RecipientAccId || TokenId || Amount || Destination),
+    /// where the destination is the L1 address that a withdrawal pays out to.
+    pub fn transfer(
+        domain: SigningDomain,
+        sender: AccountId,
+        recipient: AccountId,
+        token: TokenId,
+        amount: Amount,
+        destination: &L1Address,
+    ) -> Self {
+        let mut body = sender.to_bytes_le();
+        body.extend(recipient.to_bytes_le());
+        body.extend(token.to_bytes_le());
+        body.extend(amount.to_bytes_le());
+        body.extend(destination);
+        Self {
+            domain,
+            kind: OperationKind::Transfer,
+            body,
+        }
+    }
+
+    /// The payload authorizing a transfer in a ledger with confidential amounts;
+    /// its body is
+    /// (SenderAccId || RecipientAccId || TokenId || AmountCommitment || Destination),
+    /// with the commitment uncompressed.
+    pub fn confidential_transfer(
+        domain: SigningDomain,
+        sender: AccountId,
+        recipient: AccountId,
+        token: TokenId,
+        amount_commitment: &EdwardsAffine,
+        destination: &L1Address,
+    ) -> Self {
+        let mut body = sender.to_bytes_le();
+        body.extend(recipient.to_bytes_le());
+        body.extend(token.to_bytes_le());
+        amount_commitment
+            .serialize_uncompressed(&mut body)
+            .expect("serialization into a vector should not fail");
+        body.extend(destination);
+        Self {
+            domain,
+            kind: OperationKind::ConfidentialTransfer,
+            body,
+        }
+    }
+
+    /// The payload that the signers of a multisig account sign to authorize a
+    /// transfer; its body is the same as that of `transfer`.
+    pub fn multisig_transfer(
+        domain: SigningDomain,
+        sender: AccountId,
+        recipient: AccountId,
+        token: TokenId,
+        amount: Amount,
+        destination: &L1Address,
+    ) -> Self {
+        Self {
+            kind: OperationKind::MultisigTransfer,
+            ..Self::transfer(domain, sender, recipient, token, amount, destination)
+        }
+    }
+
+    /// The payload authorizing a swap, signed by both parties; its body is
+    /// (PartyA || TokenA || AmountA || PartyB || TokenB || AmountB).
+    pub fn swap(domain: SigningDomain, terms: &SwapTerms) -> Self {
+        let mut body = terms.party_a.to_bytes_le();
+        body.extend(terms.token_a.to_bytes_le());
+        body.extend(terms.amount_a.to_bytes_le());
+        body.extend(terms.party_b.to_bytes_le());
+        body.extend(terms.token_b.to_bytes_le());
+        body.extend(terms.amount_b.to_bytes_le());
+        Self {
+            domain,
+            kind: OperationKind::Swap,
+            body,
+        }
+    }
+
+    /// The payload authorizing a key rotation, signed with the current key;
+    /// its body is (AccId || CurrentPublicKey || NewPublicKey), with the keys
+    /// uncompressed. Binding the current key makes the signature authorize
+    /// leaving that key only.
+    pub fn rotate_key(
+        domain: SigningDomain,
+        account: AccountId,
+        current_public_key: &AccountPublicKey,
+        new_public_key: &AccountPublicKey,
+    ) -> Self {
+        let mut body = account.to_bytes_le();
+        current_public_key
+            .serialize_uncompressed(&mut body)
+            .expect("serialization into a vector should not fail");
+        new_public_key
+            .serialize_uncompressed(&mut body)
+            .expect("serialization into a vector should not fail");
+        Self {
+            domain,
+            kind: OperationKind::RotateKey,
+            body,
+        }
+    }
+
+    /// The payload authorizing the exit of the whole `token` balance of an
+    /// account to `destination` on L1; its body is
+    /// (AccId || TokenId || Amount || Destination).
+    pub fn exit(
+        domain: SigningDomain,
+        account: AccountId,
+        token: TokenId,
+        amount: Amount,
+        destination: &L1Address,
+    ) -> Self {
+        let mut body = account.to_bytes_le();
+        body.extend(token.to_bytes_le());
+        body.extend(amount.to_bytes_le());
+        body.extend(destination);
+        Self {
+            domain,
+            kind: OperationKind::Exit,
+            body,
+        }
+    }
+
+    /// The fixed prefix of every payload of `kind` in `domain`. The gadget
+    /// allocates these bytes as constants and appends the body variables.
+    pub fn header(domain: &SigningDomain, kind: OperationKind) -> Vec<u8> {
+        let mut header = SIGNING_DOMAIN_TAG.to_vec();
+        header.push(SIGNING_FORMAT_VERSION);
+        header.push(kind as u8);
+        header.extend(domain.to_bytes_le());
+        header
+    }
+
+    pub fn to_bytes_le(&self) -> Vec<u8> {
+        let mut bytes = Self::header(&self.domain, self.kind);
+        bytes.extend_from_slice(&self.body);
+        bytes
+    }
+}
+
+/// The amount of a `Transaction`, as it is signed and published.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransferAmount {
+    /// The amount itself.
+    Plain(Amount),
+    /// A commitment to the amount, which transactions in a ledger with
+    /// confidential amounts carry instead. Its `AmountOpening` is not part of
+    /// the transaction.
+    Committed(EdwardsAffine),
+}
+
+/// Transaction transferring some amount of a token from one account to another.
 #[derive(Clone, Debug)]
 pub struct Transaction {
     /// The account information of the sender.
     pub sender: AccountId,
     /// The account information of the recipient.
     pub recipient: AccountId,
+    /// The token being transferred.
+    pub token: TokenId,
     /// The amount being transferred from the sender to the receiver.
-    pub amount: Amount,
-    /// The spend authorization is a signature over the sender, the recipient,
-    /// and the amount.
-    pub signature: schnorr::Signature<EdwardsProjective>,
+    pub amount: TransferAmount,
+    /// The L1 address that the transfer pays out to if the recipient is the
+    /// rollup's bridge account, and `NO_DESTINATION` otherwise.
+    pub destination: L1Address,
+    /// The spend authorization is a signature over the transfer `SigningPayload`.
+    pub signature: AccountSignature,
 }
 
 impl Transaction {
+    /// The payload that the sender signs: the confidential transfer payload if the
+    /// transaction carries an amount commitment, and the transfer payload otherwise.
+    pub fn signing_payload(&self, parameters: &ledger::Parameters) -> SigningPayload {
+        match &self.amount {
+            TransferAmount::Committed(amount_commitment) => SigningPayload::confidential_transfer(
+                parameters.domain,
+                self.sender,
+                self.recipient,
+                self.token,
+                amount_commitment,
+                &self.destination,
+            ),
+            TransferAmount::Plain(amount) => SigningPayload::transfer(
+                parameters.domain,
+                self.sender,
+                self.recipient,
+                self.token,
+                *amount,
+                &self.destination,
+            ),
+        }
+    }
+
+    /// The amount being transferred, and the amount commitment with the blinding
+    /// from `opening` if the transaction carries one, without checking that
+    /// `opening` opens it. Returns `None` if the transaction carries an amount
+    /// commitment but there is no `opening`.
+    pub fn amount_with_opening(&self, opening: Option<&AmountOpening>) -> Option<(Amount, Option<AmountCommitment>)> {
+        match self.amount {
+            TransferAmount::Plain(amount) => Some((amount, None)),
+            TransferAmount::Committed(commitment) => opening.map(|opening| {
+                let amount_commitment = AmountCommitment {
+                    commitment,
+                    blinding: opening.blinding,
+                };
+                (opening.amount, Some(amount_commitment))
+            }),
+        }
+    }
+
     /// Verify just the signature in the transaction.
     fn verify_signature(
         &self,
-        pp: &schnorr::Parameters<EdwardsProjective>,
+        parameters: &ledger::Parameters,
         pub_key: &AccountPublicKey,
     ) -> bool {
-        // The authorized message consists of
-        // (SenderAccId || SenderPubKey || RecipientAccId || RecipientPubKey || Amount)
-        let mut message = self.sender.to_bytes_le();
-        message.extend(self.recipient.to_bytes_le());
-        message.extend(self.amount.to_bytes_le());
-        Schnorr::verify(pp, pub_key, &message, &self.signature).unwrap()
+        // The sender's public key is bound by the signature challenge, so the
+        // payload itself only carries the account identifiers.
+        let message = self.signing_payload(parameters).to_bytes_le();
+        parameters.verify_signature(pub_key, &message, &self.signature)
     }
 
-    /// Check that the transaction is valid for the given ledger state. This checks
-    /// the following conditions:
+    /// Check that the transaction is valid for the given ledger state. Transactions
+    /// in a ledger with confidential amounts are checked with
+    /// `validate_with_opening` instead.
+    pub fn validate(&self, parameters: &ledger::Parameters, state: &ledger::State) -> bool {
+        self.validate_with_opening(parameters, state, None)
+    }
+
+    /// Check that the transaction is valid for the given ledger state, where
+    /// `opening` opens the amount commitment if the ledger has confidential
+    /// amounts. This checks the following conditions:
     /// 1. Verify that the signature is valid with respect to the public key
-    /// corresponding to `self.sender`.
-    /// 2. Verify that the sender's account has sufficient balance to finance
-    /// the transaction.
+    ///    corresponding to `self.sender`.
+    /// 2. Verify that the sender's account has sufficient balance of `self.token`
+    ///    to finance the transaction.
     /// 3. Verify that the recipient's account exists.
-    pub fn validate(&self, parameters: &ledger::Parameters, state: &ledger::State) -> bool {
-        // Lookup public key corresponding to sender ID
-        if let Some(sender_acc_info) = state.id_to_account_info.get(&self.sender) {
-            let mut result = true;
+    /// 4. Verify that the transaction carries an amount commitment exactly if the
+    ///    ledger has confidential amounts, and that `opening` opens it.
+    pub fn validate_with_opening(
+        &self,
+        parameters: &ledger::Parameters,
+        state: &ledger::State,
+        opening: Option<&AmountOpening>,
+    ) -> bool {
+        // Lookup public key and balance corresponding to sender ID and token
+        if let (Some(sender_acc_info), Some((amount, amount_commitment))) =
+            (state.account_info(self.sender, self.token), self.amount_with_opening(opening))
+        {
+            let mut result = match &amount_commitment {
+                Some(amount_commitment) => {
+                    parameters.confidential_amounts
+                        && amount_commitment.opens_to(&parameters.amount_commitment_params, amount)
+                }
+                None => !parameters.confidential_amounts,
+            };
             // Check that the account_info exists in the Merkle tree.
             result &= {
                 let path = state
                     .account_merkle_tree
-                    .generate_proof(self.sender.0 as usize)
+                    .generate_proof(self.sender.leaf_index(self.token))
                     .expect("path should exist");
                 path.verify(
                     &parameters.leaf_crh_params,
-                    &parameters.two_to_one_crh_params,
+                    &parameters.inner_crh_params,
                     &state.account_merkle_tree.root(),
                     sender_acc_info.to_bytes_le(),
                 )
                 .unwrap()
             };
             // Verify the signature against the sender pubkey.
-            result &= self.verify_signature(&parameters.sig_params, &sender_acc_info.public_key);
+            result &= self.verify_signature(parameters, &sender_acc_info.public_key);
             // assert!(result, "signature verification failed");
             // Verify the amount is available in the sender account.
-            result &= self.amount <= sender_acc_info.balance;
+            result &= amount <= sender_acc_info.balance;
             // Verify that recipient account exists.
-            result &= state.id_to_account_info.get(&self.recipient).is_some();
+            result &= state.account_info(self.recipient, self.token).is_some();
             result
         } else {
             false
         }
     }
 
-    /// Create a (possibly invalid) transaction.
+    /// Create a (possibly invalid) transaction with a plain amount.
+    #[allow(clippy::too_many_arguments)]
     pub fn create<R: Rng>(
         parameters: &ledger::Parameters,
         sender: AccountId,
         recipient: AccountId,
+        token: TokenId,
         amount: Amount,
         sender_sk: &AccountSecretKey,
         sender_pk: &AccountPublicKey,
         rng: &mut R,
     ) -> Self {
-        // The authorized message consists of (SenderAccId || RecipientAccId || Amount)
-        let mut message = sender.to_bytes_le();
-        message.extend(recipient.to_bytes_le());
-        message.extend(amount.to_bytes_le());
-        let signature = Schnorr::sign(&parameters.sig_params, sender_sk, &sender_pk, &message, rng).unwrap();
-        Self {
+        let amount = TransferAmount::Plain(amount);
+        Self::signed(parameters, sender, recipient, token, amount, NO_DESTINATION, sender_sk, sender_pk, rng)
+    }
+
+    /// Create a (possibly invalid) transfer with a plain amount to the rollup's
+    /// `bridge_account`, which withdraws `amount` to `destination` on L1.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_withdrawal<R: Rng>(
+        parameters: &ledger::Parameters,
+        sender: AccountId,
+        bridge_account: AccountId,
+        token: TokenId,
+        amount: Amount,
+        destination: L1Address,
+        sender_sk: &AccountSecretKey,
+        sender_pk: &AccountPublicKey,
+        rng: &mut R,
+    ) -> Self {
+        let amount = TransferAmount::Plain(amount);
+        Self::signed(parameters, sender, bridge_account, token, amount, destination, sender_sk, sender_pk, rng)
+    }
+
+    /// Create a (possibly invalid) transaction for a ledger with confidential
+    /// amounts, which commits to `amount` with a fresh blinding. Returns the
+    /// transaction and the opening of its amount commitment.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_confidential<R: Rng>(
+        parameters: &ledger::Parameters,
+        sender: AccountId,
+        recipient: AccountId,
+        token: TokenId,
+        amount: Amount,
+        sender_sk: &AccountSecretKey,
+        sender_pk: &AccountPublicKey,
+        rng: &mut R,
+    ) -> (Self, AmountOpening) {
+        let opening = AmountOpening::sample(amount, rng);
+        let amount = TransferAmount::Committed(opening.commit(&parameters.amount_commitment_params).commitment);
+        let tx = Self::signed(parameters, sender, recipient, token, amount, NO_DESTINATION, sender_sk, sender_pk, rng);
+        (tx, opening)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn signed<R: Rng>(
+        parameters: &ledger::Parameters,
+        sender: AccountId,
+        recipient: AccountId,
+        token: TokenId,
+        amount: TransferAmount,
+        destination: L1Address,
+        sender_sk: &AccountSecretKey,
+        sender_pk: &AccountPublicKey,
+        rng: &mut R,
+    ) -> Self {
+        let mut tx = Self {
             sender,
             recipient,
+            token,
             amount,
+            destination,
+            signature: AccountSignature::placeholder(parameters.signature_scheme),
+        };
+        let message = tx.signing_payload(parameters).to_bytes_le();
+        tx.signature = parameters.sign(sender_sk, sender_pk, &message, rng);
+        tx
+    }
+}
+
+/// The terms of an atomic swap: `party_a` gives `amount_a` of `token_a` to
+/// `party_b`, and in exchange `party_b` gives `amount_b` of `token_b` to `party_a`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SwapTerms {
+    pub party_a: AccountId,
+    pub token_a: TokenId,
+    pub amount_a: Amount,
+    pub party_b: AccountId,
+    pub token_b: TokenId,
+    pub amount_b: Amount,
+}
+
+impl SwapTerms {
+    /// The two transfers making up the swap, as `(sender, recipient, token, amount)`,
+    /// in the order in which they are applied.
+    pub fn legs(&self) -> [(AccountId, AccountId, TokenId, Amount); 2] {
+        [
+            (self.party_a, self.party_b, self.token_a, self.amount_a),
+            (self.party_b, self.party_a, self.token_b, self.amount_b),
+        ]
+    }
+
+    /// Sign the terms on behalf of one of the parties.
+    pub fn sign<R: Rng>(
+        &self,
+        parameters: &ledger::Parameters,
+        sk: &AccountSecretKey,
+        pk: &AccountPublicKey,
+        rng: &mut R,
+    ) -> AccountSignature {
+        let message = SigningPayload::swap(parameters.domain, self).to_bytes_le();
+        parameters.sign(sk, pk, &message, rng)
+    }
+}
+
+/// Atomic two-leg swap. Either both legs are applied or neither is.
+#[derive(Clone, Debug)]
+pub struct Swap {
+    pub terms: SwapTerms,
+    /// Signature of `party_a` over the swap `SigningPayload`.
+    pub signature_a: AccountSignature,
+    /// Signature of `party_b` over the swap `SigningPayload`.
+    pub signature_b: AccountSignature,
+}
+
+impl Swap {
+    /// The payload that both parties sign.
+    pub fn signing_payload(&self, parameters: &ledger::Parameters) -> SigningPayload {
+        SigningPayload::swap(parameters.domain, &self.terms)
+    }
+
+    /// Verify that both parties signed the terms. Each signature is checked against
+    /// the public key in the leaf that the corresponding party is debited from.
+    fn verify_signatures(&self, parameters: &ledger::Parameters, state: &ledger::State) -> bool {
+        let message = self.signing_payload(parameters).to_bytes_le();
+        let terms = &self.terms;
+        [
+            (terms.party_a, terms.token_a, &self.signature_a),
+            (terms.party_b, terms.token_b, &self.signature_b),
+        ]
+        .iter()
+        .all(|(party, token, signature)| {
+            state.account_info(*party, *token).is_some_and(|info| {
+                parameters.verify_signature(&info.public_key, &message, signature)
+            })
+        })
+    }
+
+    /// Check that the swap is valid for the given ledger state. This checks
+    /// the following conditions:
+    /// 1. Verify that both signatures are valid with respect to the public keys
+    ///    of the two parties.
+    /// 2. Verify that applying both legs in order succeeds, i.e. all four leaves
+    ///    exist and each sender can finance its leg.
+    ///
+    /// The terms carry plain amounts, so swaps are invalid in ledgers with
+    /// confidential amounts.
+    pub fn validate(&self, parameters: &ledger::Parameters, state: &ledger::State) -> bool {
+        !parameters.confidential_amounts && self.verify_signatures(parameters, state) && self.legs_apply(state)
+    }
+
+    /// Whether both legs can be applied in order to `state`, like `State::transfer`.
+    /// The balances written by the first leg are kept aside, so that the second
+    /// leg is checked against them without copying the ledger.
+    fn legs_apply(&self, state: &ledger::State) -> bool {
+        let mut balances = HashMap::new();
+        self.terms.legs().iter().all(|&(from, to, token, amount)| {
+            let mut move_funds = || {
+                let sender = balances.get(&(from, token)).copied().or_else(|| state.balance(from, token))?;
+                balances.insert((from, token), sender.checked_sub(amount)?);
+                // Read the recipient balance only now, so that self-transfers are no-ops.
+                let recipient = balances.get(&(to, token)).copied().or_else(|| state.balance(to, token))?;
+                balances.insert((to, token), recipient.checked_add(amount)?);
+                Some(())
+            };
+            move_funds().is_some()
+        })
+    }
+
+    /// Create a swap signed by both parties.
+    pub fn create<R: Rng>(
+        parameters: &ledger::Parameters,
+        terms: SwapTerms,
+        (sk_a, pk_a): (&AccountSecretKey, &AccountPublicKey),
+        (sk_b, pk_b): (&AccountSecretKey, &AccountPublicKey),
+        rng: &mut R,
+    ) -> Self {
+        Self {
+            terms,
+            signature_a: terms.sign(parameters, sk_a, pk_a, rng),
+            signature_b: terms.sign(parameters, sk_b, pk_b, rng),
+        }
+    }
+}
+
+/// Replace the public key of an account, keeping its balances. This lets a user
+/// whose key is compromised move control of the account without moving funds.
+#[derive(Clone, Debug)]
+pub struct KeyRotation {
+    /// The account whose key is replaced.
+    pub account: AccountId,
+    /// The key controlling the account from now on.
+    pub new_public_key: AccountPublicKey,
+    /// Signature with the current key over the rotation `SigningPayload`.
+    pub signature: AccountSignature,
+}
+
+impl KeyRotation {
+    /// The payload that `current_public_key`, the account's key before the
+    /// rotation, signs.
+    pub fn signing_payload(
+        &self,
+        parameters: &ledger::Parameters,
+        current_public_key: &AccountPublicKey,
+    ) -> SigningPayload {
+        SigningPayload::rotate_key(parameters.domain, self.account, current_public_key, &self.new_public_key)
+    }
+
+    /// Check that the rotation is valid for the given ledger state. This checks
+    /// the following conditions:
+    /// 1. Verify that the account exists.
+    /// 2. Verify that the signature is valid with respect to the account's current
+    ///    public key.
+    /// 3. Verify that the new public key has never been held by any account.
+    ///    Like at registration, this is only enforced natively.
+    pub fn validate(&self, parameters: &ledger::Parameters, state: &ledger::State) -> bool {
+        // All leaves of an account hold the same key, so any of them will do.
+        if let Some(acc_info) = state.account_info(self.account, TokenId::default()) {
+            let message = self.signing_payload(parameters, &acc_info.public_key).to_bytes_le();
+            parameters.verify_signature(&acc_info.public_key, &message, &self.signature)
+                && !state.pub_key_to_id.contains_key(&self.new_public_key)
+        } else {
+            false
+        }
+    }
+
+    /// Create a (possibly invalid) key rotation signed with `current_sk`.
+    pub fn create<R: Rng>(
+        parameters: &ledger::Parameters,
+        account: AccountId,
+        new_public_key: AccountPublicKey,
+        current_sk: &AccountSecretKey,
+        current_pk: &AccountPublicKey,
+        rng: &mut R,
+    ) -> Self {
+        let message =
+            SigningPayload::rotate_key(parameters.domain, account, current_pk, &new_public_key).to_bytes_le();
+        let signature = parameters.sign(current_sk, current_pk, &message, rng);
+        Self {
+            account,
+            new_public_key,
             signature,
         }
     }
-}
\ No newline at end of file
+}
+
+/// Transaction transferring some amount of a token from a multisig account,
+/// authorized by a threshold of the keys in the account's `MultisigPolicy`.
+#[derive(Clone, Debug)]
+pub struct MultisigTransaction {
+    /// The multisig account the funds are taken from.
+    pub sender: AccountId,
+    /// The account information of the recipient.
+    pub recipient: AccountId,
+    /// The token being transferred.
+    pub token: TokenId,
+    /// The amount being transferred from the sender to the receiver.
+    pub amount: Amount,
+    /// The L1 address that the transfer pays out to if the recipient is the
+    /// rollup's bridge account, and `NO_DESTINATION` otherwise.
+    pub destination: L1Address,
+    /// The sender's policy. It has to hash to the key the sender is registered under.
+    pub policy: MultisigPolicy,
+    /// `signatures[i]` is the signature of `policy.public_keys[i]` over the multisig
+    /// transfer `SigningPayload`, if that key signed.
+    pub signatures: Vec<Option<AccountSignature>>,
+}
+
+impl MultisigTransaction {
+    /// Create a transaction that is not signed by any key yet.
+    pub fn new(
+        sender: AccountId,
+        recipient: AccountId,
+        token: TokenId,
+        amount: Amount,
+        policy: MultisigPolicy,
+    ) -> Self {
+        let signatures = vec![None; policy.public_keys.len()];
+        Self {
+            sender,
+            recipient,
+            token,
+            amount,
+            destination: NO_DESTINATION,
+            policy,
+            signatures,
+        }
+    }
+
+    /// Withdraw the transferred amount to `destination` on L1; the recipient
+    /// has to be the rollup's bridge account. The signatures cover the
+    /// destination, so this is set before signing.
+    pub fn with_destination(mut self, destination: L1Address) -> Self {
+        self.destination = destination;
+        self
+    }
+
+    /// The payload that the signers sign.
+    pub fn signing_payload(&self, parameters: &ledger::Parameters) -> SigningPayload {
+        SigningPayload::multisig_transfer(
+            parameters.domain,
+            self.sender,
+            self.recipient,
+            self.token,
+            self.amount,
+            &self.destination,
+        )
+    }
+
+    /// Add the signature of `pk`. Returns `None` if `pk` is not part of the policy.
+    pub fn sign<R: Rng>(
+        &mut self,
+        parameters: &ledger::Parameters,
+        sk: &AccountSecretKey,
+        pk: &AccountPublicKey,
+        rng: &mut R,
+    ) -> Option<()> {
+        let slot = self.policy.public_keys.iter().position(|key| key == pk)?;
+        let message = self.signing_payload(parameters).to_bytes_le();
+        let signature = parameters.sign(sk, pk, &message, rng);
+        self.signatures.resize(self.policy.public_keys.len(), None);
+        self.signatures[slot] = Some(signature);
+        Some(())
+    }
+
+    /// The number of policy keys that validly signed the transaction.
+    pub fn num_valid_signatures(&self, parameters: &ledger::Parameters) -> usize {
+        let message = self.signing_payload(parameters).to_bytes_le();
+        self.policy
+            .public_keys
+            .iter()
+            .zip(&self.signatures)
+            .filter(|(pk, signature)| {
+                signature.as_ref().is_some_and(|signature| {
+                    parameters.verify_signature(pk, &message, signature)
+                })
+            })
+            .count()
+    }
+
+    /// Check that the transaction is valid for the given ledger state. This checks
+    /// the following conditions:
+    /// 1. Verify that `self.policy` is well-formed and hashes to the public key of
+    ///    `self.sender`.
+    /// 2. Verify that at least `threshold` of the policy keys signed.
+    /// 3. Verify that the sender's account has sufficient balance of `self.token`
+    ///    to finance the transaction.
+    /// 4. Verify that the recipient's account exists.
+    ///
+    /// Like swaps, multisig transactions are invalid in ledgers with confidential amounts.
+    pub fn validate(&self, parameters: &ledger::Parameters, state: &ledger::State) -> bool {
+        if let Some(sender_acc_info) = state.account_info(self.sender, self.token) {
+            !parameters.confidential_amounts
+                && self.policy.is_well_formed()
+                && self.policy.account_key(parameters) == sender_acc_info.public_key
+                && self.num_valid_signatures(parameters) >= self.policy.threshold as usize
+                && self.amount <= sender_acc_info.balance
+                && state.account_info(self.recipient, self.token).is_some()
+        } else {
+            false
+        }
+    }
+}